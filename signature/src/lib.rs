@@ -294,6 +294,7 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                 let mut allowed_values = None;
                 let mut description = None;
                 let mut completion_command = quote! {None};
+                let mut column_types = quote! {None};
                 if !field.attrs.is_empty() {
                     for attr in &field.attrs {
                         if call_is_default(attr) {
@@ -307,6 +308,9 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                         } else if call_is_named(attr, "custom_completion") {
                             let name = call_value(attr)?;
                             completion_command = quote! {Some(#name)};
+                        } else if call_is_named(attr, "column") {
+                            let types = call_value(attr)?;
+                            column_types = quote! {Some(vec! #types)};
                         } else if call_is_named(attr, "description") {
                             description = Some(unescape(&(call_literal(attr)?.to_string())));
                         }
@@ -377,8 +381,9 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                         allowed: #allowed_values,
                         description: None,
                         complete: #completion_command,
-                        named: false,
-                        unnamed: false,
+                        named: #is_named_target,
+                        unnamed: #is_unnamed_target,
+                        column: #column_types,
                     },
                 };
             }
@@ -423,7 +428,7 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                     )
                 }
 
-                pub fn declare_method(_env: &mut ordered_map::OrderedMap<std::string::String, crate::lang::command::Command>) {
+                pub fn declare_method(_env: &mut crate::ordered_map::OrderedMap<std::string::String, crate::lang::command::Command>) {
                     let mut full = vec!["global", #path];
                     full.push(#command_name);
                     _env.insert(#command_name.to_string(),
@@ -442,7 +442,15 @@ fn option_type_data(
                 .to_lowercase()
         ),
         initialize: quote! { let mut #name = None; },
-        mappings: quote! { (Some(#name_literal), #value_type) => #name = Some(#mutator), },
+        mappings: match simple_type {
+            // Passing nothing to an optional parameter is the same as not passing it at all
+            SimpleSignature::Value | SimpleSignature::Stream =>
+                quote! { (Some(#name_literal), #value_type) => #name = Some(#mutator), },
+            _ => quote! {
+                (Some(#name_literal), #value_type) => #name = Some(#mutator),
+                (Some(#name_literal), crate::lang::value::Value::Empty) => #name = None,
+            },
+        },
         unnamed_mutate: Some(quote_spanned! { span =>
                     if #name.is_none() {
                         match _unnamed.pop_front() {
@@ -460,7 +468,10 @@ fn option_type_data(
                     }
                     }),
         assign: quote! { #name, },
-        crush_internal_type: simple_type.value_type(),
+        crush_internal_type: {
+            let sub_type = simple_type.value_type();
+            quote! {crate::lang::value::ValueType::optional(#sub_type)}
+        },
     })
 }
 
@@ -20,6 +20,7 @@ pub enum SimpleSignature {
     Stream,
     Value,
     Dict,
+    Set,
     PathBuf,
     Scope,
 }
@@ -46,6 +47,7 @@ impl TryFrom<&Ident> for SimpleSignature {
             "Stream" => Ok(SimpleSignature::Stream),
             "Value" => Ok(SimpleSignature::Value),
             "Dict" => Ok(SimpleSignature::Dict),
+            "Set" => Ok(SimpleSignature::Set),
             "PathBuf" => Ok(SimpleSignature::PathBuf),
             "Scope" => Ok(SimpleSignature::Scope),
             _ => Err("Unknown type".to_string()),
@@ -81,6 +83,7 @@ impl SimpleSignature {
             SimpleSignature::Stream => "Stream",
             SimpleSignature::Value => "Value",
             SimpleSignature::Dict => "Dict",
+            SimpleSignature::Set => "Set",
             SimpleSignature::PathBuf => "PathBuf",
             SimpleSignature::Scope => "Scope",
         }
@@ -103,6 +106,7 @@ impl SimpleSignature {
             SimpleSignature::Duration => quote! {crate::lang::value::Value::Duration(_value)},
             SimpleSignature::Struct => quote! {crate::lang::value::Value::Struct(_value)},
             SimpleSignature::Dict => quote! {crate::lang::value::Value::Dict(_value)},
+            SimpleSignature::Set => quote! {crate::lang::value::Value::Set(_value)},
             SimpleSignature::Stream => quote! {_value},
             SimpleSignature::Value => quote! {_value},
             SimpleSignature::PathBuf => quote! {crate::lang::value::Value::File(_value)},
@@ -127,6 +131,7 @@ impl SimpleSignature {
             SimpleSignature::Duration => quote! {crate::lang::value::ValueType::Duration},
             SimpleSignature::Struct => quote! {crate::lang::value::ValueType::Struct},
             SimpleSignature::Dict => quote! {crate::lang::value::ValueType::Struct},
+            SimpleSignature::Set => quote! {crate::lang::value::ValueType::Set(Box::from(crate::lang::value::ValueType::Any))},
             SimpleSignature::Stream => quote! {crate::lang::value::ValueType::Any},
             SimpleSignature::Value => quote! {crate::lang::value::ValueType::Any},
             SimpleSignature::PathBuf => quote! {crate::lang::value::ValueType::File},
@@ -148,6 +153,7 @@ impl SimpleSignature {
             SimpleSignature::Stream => "stream",
             SimpleSignature::Struct => "struct",
             SimpleSignature::Dict => "dict",
+            SimpleSignature::Set => "set",
             SimpleSignature::PathBuf => "file",
             SimpleSignature::Scope => "scope",
         }
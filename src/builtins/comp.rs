@@ -13,7 +13,7 @@ macro_rules! cmp {
             context.arguments.check_len(2)?;
             let l = context.arguments.value(0)?;
             let r = context.arguments.value(1)?;
-            match l.partial_cmp(&r) {
+            match l.compare(&r, &context.scope, &context.global_state)? {
                 Some(ordering) => context.output.send(Value::Bool($op(ordering))),
                 None => {
                     return argument_error_legacy(
@@ -39,14 +39,16 @@ pub fn eq(mut context: CommandContext) -> CrushResult<()> {
     context.arguments.check_len(2)?;
     let l = context.arguments.value(0)?;
     let r = context.arguments.value(1)?;
-    context.output.send(Value::Bool(l.eq(&r)))
+    let equal = l.equals(&r, &context.scope, &context.global_state)?;
+    context.output.send(Value::Bool(equal))
 }
 
 pub fn neq(mut context: CommandContext) -> CrushResult<()> {
     context.arguments.check_len(2)?;
     let l = context.arguments.value(0)?;
     let r = context.arguments.value(1)?;
-    context.output.send(Value::Bool(!l.eq(&r)))
+    let equal = l.equals(&r, &context.scope, &context.global_state)?;
+    context.output.send(Value::Bool(!equal))
 }
 
 pub fn not(mut context: CommandContext) -> CrushResult<()> {
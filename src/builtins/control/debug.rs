@@ -0,0 +1,40 @@
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::Known;
+use crate::lang::errors::CrushResult;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::value::ValueType;
+use signature::signature;
+
+#[signature(
+    control.debug,
+    short = "Run a closure in the debugger.",
+    long = "Execution pauses before the first command of the closure, and a debugger prompt is shown. At the prompt, step through the closure one command at a time, look at the call stack, or run any Crush code in the scope of the paused closure to inspect or modify its variables. Type help at the prompt for a list of debugger commands.",
+    example = "debug {files | where {$size > 1000}}"
+)]
+pub struct Debug {
+    #[description("the closure to debug.")]
+    closure: Command,
+}
+
+fn debug(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Debug = Debug::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let debugger = context.global_state.debugger().clone();
+    debugger.start();
+    let res = cfg.closure.eval(context.with_args(vec![], None));
+    debugger.stop();
+    res
+}
+
+#[signature(
+    control.breakpoint,
+    output = Known(ValueType::Empty),
+    short = "Pause execution and show the debugger prompt.",
+    long = "This works both when running under the debugger and when not, so it can be added anywhere a closure misbehaves.",
+    example = "for i=$(seq 10) {breakpoint; echo $i}"
+)]
+pub struct Breakpoint {}
+
+fn breakpoint(context: CommandContext) -> CrushResult<()> {
+    context.global_state.debugger().breakpoint(&context.scope, &context.global_state);
+    context.output.empty()
+}
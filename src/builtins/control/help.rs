@@ -1,5 +1,5 @@
 use signature::signature;
-use crate::lang::help::Help;
+use crate::lang::help::{help_text, Help};
 use crate::lang::value::Value;
 use crate::{CrushResult, Printer};
 use crate::lang::errors::error;
@@ -8,18 +8,21 @@ use crate::lang::command::OutputType::Known;
 use crate::lang::value::ValueType;
 
 fn halp(o: &dyn Help, printer: &Printer) {
-    printer.line(
-        match o.long_help() {
-            None => format!("{}\n\n    {}", o.signature(), o.short_help()),
-            Some(long_help) => format!(
-                "{}\n\n    {}\n\n{}",
-                o.signature(),
-                o.short_help(),
-                long_help
-            ),
+    printer.line(help_text(o).as_str());
+}
+
+/** List the methods added to the type using `types:extend` that are visible to the caller. */
+fn extensions(t: &ValueType, context: &CommandContext) -> CrushResult<()> {
+    let extensions = context.scope.extensions(t)?;
+    if !extensions.is_empty() {
+        let width = extensions.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let mut lines = vec!["    Extensions:\n".to_string()];
+        for (name, method) in &extensions {
+            lines.push(format!("    * {}  {}{}", name, " ".repeat(width - name.len()), method.help().short_help()));
         }
-            .as_str(),
-    );
+        context.global_state.printer().line(&lines.join("\n"));
+    }
+    Ok(())
 }
 
 #[signature(
@@ -65,8 +68,14 @@ members of a value, write "dir <value>".
                     Some(v) => halp(v, &context.global_state.printer()),
                 },
                 Value::Command(cmd) => halp(cmd.help(), &context.global_state.printer()),
-                Value::Type(t) => halp(&t, &context.global_state.printer()),
-                v => halp(&v, &context.global_state.printer()),
+                Value::Type(t) => {
+                    halp(&t, &context.global_state.printer());
+                    extensions(&t, &context)?;
+                }
+                v => {
+                    halp(&v, &context.global_state.printer());
+                    extensions(&v.value_type(), &context)?;
+                }
             }
             context.output.send(Value::Empty)
         }
@@ -0,0 +1,82 @@
+use signature::signature;
+use crate::lang::argument::Argument;
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::errors::{argument_error_legacy, CrushResult};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::value::Value;
+use crate::lang::ordered_string_map::OrderedStringMap;
+
+#[signature(
+    control.r#match,
+    can_block = true,
+    short = "Execute the command for the variant of an enum value.",
+    long = "Each named argument is the name of a variant of the enum, and the command to execute if the value is of that variant. The fields of the variant are passed to the command as named arguments.",
+    long = "The value to match is the first unnamed argument. It may be followed by an unnamed default command, to execute for variants without a command of their own. Unless a default command is given, there must be a command for every variant of the enum, so that adding a variant to an enum makes any code that doesn't handle it fail.",
+    output = Unknown,
+    example = "match $result Ok={|$value| echo $value} Err={|$reason| echo $reason}",
+    example = "match $state Done={echo \"done\"} {echo \"not done yet\"}",
+)]
+pub struct Match {
+    #[unnamed()]
+    #[description("the enum value to match, optionally followed by the default command.")]
+    unnamed: Vec<Value>,
+    #[named()]
+    #[description("the command to execute for each variant.")]
+    arms: OrderedStringMap<Command>,
+}
+
+fn r#match(mut context: CommandContext) -> CrushResult<()> {
+    if context.arguments.is_empty() {
+        return argument_error_legacy("Expected a value to match");
+    }
+    let location = context.arguments[0].location;
+    let mut cfg = Match::parse(context.remove_arguments(), context.global_state.printer())?;
+
+    // The value and the default command are unnamed, so that every named argument is a variant
+    let mut unnamed = cfg.unnamed.drain(..);
+    let (value, default) = match (unnamed.next(), unnamed.next(), unnamed.next()) {
+        (Some(value), None, _) => (value, None),
+        (Some(value), Some(Value::Command(default)), None) => (value, Some(default)),
+        (Some(_), Some(v), None) => return argument_error_legacy(format!(
+            "Expected the default to be a command, got a value of type {}",
+            v.value_type())),
+        _ => return argument_error_legacy("Expected a value to match and at most one default command"),
+    };
+
+    let value = match value {
+        Value::Enum(e) => e,
+        v => return argument_error_legacy(format!("Expected an enum value, got a value of type {}", v.value_type())),
+    };
+    let enum_type = value.enum_type();
+
+    for name in cfg.arms.keys() {
+        if enum_type.variant(name).is_none() {
+            return argument_error_legacy(format!("{} is not a variant of {}", name, enum_type.name()));
+        }
+    }
+    if default.is_none() {
+        let missing = enum_type
+            .variants()
+            .iter()
+            .filter(|v| !cfg.arms.contains_key(&v.name))
+            .map(|v| v.name.as_str())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return argument_error_legacy(format!("Match on {} does not handle {}", enum_type.name(), missing.join(", ")));
+        }
+    }
+
+    match (cfg.arms.remove(value.variant_name()), default) {
+        (Some(arm), _) => {
+            let arguments = value
+                .fields()
+                .into_iter()
+                .map(|(name, value)| Argument::named(&name, value, location))
+                .collect();
+            arm.eval(context.with_args(arguments, None))
+        }
+        (None, Some(default)) => default.eval(context.with_args(vec![], None)),
+        (None, None) => context.output.send(Value::Empty),
+    }
+}
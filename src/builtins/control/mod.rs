@@ -15,10 +15,14 @@ use os_pipe::PipeReader;
 use crate::lang::state::contexts::CommandContext;
 
 mod cmd;
+mod debug;
 mod help;
 mod r#for;
 mod r#if;
 mod r#loop;
+mod r#match;
+mod module;
+mod profile;
 mod timeit;
 mod timer;
 mod schedule;
@@ -123,9 +127,11 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             r#while::While::declare(env)?;
             r#loop::Loop::declare(env)?;
             r#for::For::declare(env)?;
+            r#match::Match::declare(env)?;
             cmd::Cmd::declare(env)?;
             Break::declare(env)?;
             timeit::TimeIt::declare(env)?;
+            profile::Profile::declare(env)?;
             timer::Timer::declare(env)?;
             schedule::Schedule::declare(env)?;
             Continue::declare(env)?;
@@ -133,6 +139,11 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             Bg::declare(env)?;
             Fg::declare(env)?;
             help::HelpSignature::declare(env)?;
+            debug::Debug::declare(env)?;
+            debug::Breakpoint::declare(env)?;
+            module::Source::declare(env)?;
+            module::Import::declare(env)?;
+            module::Export::declare(env)?;
             Ok(())
         }),
     )?;
@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+use crate::lang::command::OutputType::Known;
+use crate::lang::errors::{argument_error_legacy, error, to_crush_error, CrushResult};
+use crate::lang::execute;
+use crate::lang::pipe::black_hole;
+use crate::lang::pretty::create_pretty_printer;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{Value, ValueType};
+use signature::signature;
+
+#[signature(
+    control.source,
+    can_block = true,
+    output = Known(ValueType::Empty),
+    short = "Run a file in the current scope.",
+    long = "Unlike running a file as a command, all variables declared by the file are available afterwards.",
+    example = "source ./helpers.crush"
+)]
+pub struct Source {
+    #[description("the file to run.")]
+    file: PathBuf,
+}
+
+fn source(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Source = Source::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let output = create_pretty_printer(context.global_state.printer().clone(), &context.scope, &context.global_state);
+    execute::file(&context.scope, &cfg.file, &output, &context.global_state)?;
+    context.output.empty()
+}
+
+#[signature(
+    control.import,
+    can_block = true,
+    output = Known(ValueType::Scope),
+    short = "Import a module and return it as a scope.",
+    long = "The module is the file with the specified name and the extension .crush in the first directory of crush:path that contains it. The first time a module is imported, it is run in a new scope, and the members it exports are stored in a namespace. Later imports of the same file, even under a different name or through a different directory, return the same namespace without running it again.",
+    long = "A module exports the members passed to the export command. If it doesn't call export, all members with names not starting with an underscore are exported.",
+    example = "$util := $(import util)\n    util:greet \"world\""
+)]
+pub struct Import {
+    #[description("the name of the module.")]
+    name: String,
+}
+
+fn find_module(name: &str, scope: &Scope) -> CrushResult<PathBuf> {
+    let path = scope.get_absolute_path(vec!["global".to_string(), "crush".to_string(), "path".to_string()])?;
+    if let Value::List(path) = path {
+        for dir in path.iter() {
+            if let Value::File(dir) = dir {
+                let file = dir.join(format!("{}.crush", name));
+                if file.exists() {
+                    return Ok(file);
+                }
+            }
+        }
+    }
+    argument_error_legacy(format!("Could not find module {} in crush:path", name))
+}
+
+fn import(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Import = Import::parse(context.remove_arguments(), &context.global_state.printer())?;
+    // Modules are identified by their file, so that the same name can refer to different modules
+    // as crush:path changes, and a module reached through different paths is only run once
+    let file = to_crush_error(find_module(&cfg.name, &context.scope)?.canonicalize())?;
+    let key = file.to_string_lossy().to_string();
+
+    // A module that is still being imported is already in the cache, but using it would deadlock
+    let mut importing = context.scope.importing();
+    if importing.contains(&file) {
+        importing.reverse();
+        importing.push(file);
+        return error(format!(
+            "Circular import: {}",
+            importing.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join(" -> ")));
+    }
+
+    let modules = context.global_state.modules().clone();
+    if let Some(module) = modules.get_local(&key)? {
+        return context.output.send(module);
+    }
+
+    let root = match context.scope.get_absolute_path(vec!["global".to_string()])? {
+        Value::Scope(root) => root,
+        _ => return error("Missing root scope"),
+    };
+    let caller = context.scope.clone();
+    let state = context.global_state.clone();
+    let name = cfg.name.clone();
+    let module = modules.create_namespace(
+        &key,
+        format!("The module {}", file.display()),
+        Box::new(move |namespace| {
+            let env = root.create_module(&caller, &file);
+            execute::file(&env, &file, &black_hole(), &state)?;
+            let exports = env.exports();
+            if exports.is_empty() {
+                for (member, _) in env.dump_local()?.iter() {
+                    if !member.starts_with('_') {
+                        if let Some(value) = env.get_local(member)? {
+                            namespace.declare(member, value)?;
+                        }
+                    }
+                }
+            } else {
+                for member in exports {
+                    match env.get_local(&member)? {
+                        Some(value) => namespace.declare(&member, value)?,
+                        None => return error(format!("Module {} exports {}, which it doesn't declare", name, member)),
+                    }
+                }
+            }
+            Ok(())
+        }),
+    )?;
+
+    // Run the module right away, so that errors are reported here and not where it is first used
+    if let Err(e) = module.dump_local() {
+        modules.remove_str(&key)?;
+        return Err(e);
+    }
+    context.output.send(Value::Scope(module))
+}
+
+#[signature(
+    control.export,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Export members from the module being imported.",
+    long = "Only exported members are available to code importing the module. Call export once or more from the top level of the module.",
+    example = "export greet farewell"
+)]
+pub struct Export {
+    #[unnamed()]
+    #[description("the names of the members to export.")]
+    name: Vec<String>,
+}
+
+fn export(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Export = Export::parse(context.remove_arguments(), &context.global_state.printer())?;
+    context.scope.add_exports(&cfg.name)?;
+    context.output.empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+    use crate::lang::errors::CrushResult;
+    use crate::lang::printer::PrinterOutput;
+    use crate::lang::value::Value;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    /**
+    Write the modules to a new temporary directory, put it in crush:path and run the source.
+    Returns everything written to the printer, along with the result of the source.
+     */
+    fn run(modules: &[(&str, &str)], source: &str) -> (Vec<PrinterOutput>, CrushResult<Value>) {
+        let dir = std::env::temp_dir().join(format!("crush-modules-{:016x}", rand::random::<u64>()));
+        for (name, code) in modules {
+            let file = dir.join(name);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, code).unwrap();
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let printed = output.clone();
+        let interpreter = Interpreter::with_output(move |o| printed.lock().unwrap().push(o)).unwrap();
+        let source = format!(
+            "$_ignored := $(crush:path:clear)\n$_ignored = $(crush:path:push '{}')\n{}",
+            dir.display(),
+            source.replace("DIR", &dir.display().to_string()));
+        let res = interpreter.eval(&source);
+        drop(interpreter);
+        let _ = fs::remove_dir_all(&dir);
+        let output = output.lock().unwrap().clone();
+        (output, res)
+    }
+
+    #[test]
+    fn module_is_only_run_once() {
+        let (output, res) = run(&[("a.crush", "echo loading\n$x := 1")], "$a := $(import a)\n$b := $(import a)\necho $b:x");
+        assert!(res.is_ok());
+        assert_eq!(output, vec![PrinterOutput::Line("loading".to_string()), PrinterOutput::Line("1".to_string())]);
+    }
+
+    #[test]
+    fn modules_are_cached_by_file() {
+        let (output, res) = run(
+            &[("a.crush", "$x := \"first\""), ("other/a.crush", "$x := \"second\"")],
+            "$first := $(import a)\n$_ignored = $(crush:path:clear)\n$_ignored = $(crush:path:push 'DIR/other')\n$second := $(import a)\necho $first:x\necho $second:x");
+        assert!(res.is_ok());
+        assert_eq!(output, vec![PrinterOutput::Line("first".to_string()), PrinterOutput::Line("second".to_string())]);
+    }
+
+    #[test]
+    fn circular_import() {
+        let (_, res) = run(
+            &[("a.crush", "import b"), ("b.crush", "import a")],
+            "import a");
+        let message = res.err().unwrap().message();
+        assert!(message.contains("Circular import") && message.contains("a.crush -> ") && message.contains("b.crush -> "), "{}", message);
+    }
+
+    #[test]
+    fn exporting_undeclared_member() {
+        let (_, res) = run(&[("a.crush", "$x := 1\nexport x nosuch")], "import a");
+        let message = res.err().unwrap().message();
+        assert!(message.contains("Module a exports nosuch, which it doesn't declare"), "{}", message);
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::OnceLock;
+use chrono::Duration;
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::Known;
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::CrushResult;
+use crate::lang::pipe::pipe;
+use crate::lang::profile;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::value::{Value, ValueType};
+use signature::signature;
+
+fn profile_output_type() -> &'static Vec<ColumnType> {
+    static CELL: OnceLock<Vec<ColumnType>> = OnceLock::new();
+    CELL.get_or_init(|| vec![
+        ColumnType::new("stage", ValueType::String),
+        ColumnType::new("rows_in", ValueType::Integer),
+        ColumnType::new("rows_out", ValueType::Integer),
+        ColumnType::new("bytes_in", ValueType::Integer),
+        ColumnType::new("bytes_out", ValueType::Integer),
+        ColumnType::new("recv", ValueType::Duration),
+        ColumnType::new("send", ValueType::Duration),
+        ColumnType::new("cpu", ValueType::Duration),
+        ColumnType::new("peak_buffered", ValueType::Integer),
+    ])
+}
+
+#[signature(
+    control.profile,
+    can_block = true,
+    output = Known(ValueType::TableInputStream(profile_output_type().clone())),
+    short = "Run a closure and show statistics for every stage of its pipelines.",
+    long = "The output of the closure is discarded. For every command in the pipelines of the closure, the output lists how many rows it read and wrote, roughly how many bytes those rows used, how long it was blocked waiting for input (recv) and for the next stage to accept its output (send), how much CPU time it used, and the largest number of rows that were waiting in its output stream.",
+    long = "A stage that spends most of its time in recv is waiting for a slow upstream stage, while a stage that spends most of its time in send is waiting for a slow downstream stage.",
+    example = "profile {files --recurse | where {$size > 1000} | sort size}"
+)]
+pub struct Profile {
+    #[description("the closure to profile.")]
+    pipeline: Command,
+}
+
+fn profile(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Profile = Profile::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let stats = profile::Profile::new();
+
+    let (sender, receiver) = pipe();
+    let consumer = context.spawn("output consumer", move || {
+        if let Ok(Some(mut stream)) = receiver.recv()?.stream() {
+            while stream.read().is_ok() {}
+        }
+        Ok(())
+    })?;
+    cfg.pipeline.eval(
+        context.clone()
+            .with_args(vec![], None)
+            .with_output(sender)
+            .with_profile(stats.clone()))?;
+    context.global_state.threads().join_one(consumer, context.global_state.printer());
+
+    let output = context.output.initialize(profile_output_type())?;
+    for stage in stats.stages() {
+        output.send(Row::new(vec![
+            Value::from(stage.name()),
+            Value::Integer(stage.rows_in() as i128),
+            Value::Integer(stage.rows_out() as i128),
+            Value::Integer(stage.bytes_in() as i128),
+            Value::Integer(stage.bytes_out() as i128),
+            Value::Duration(Duration::nanoseconds(stage.recv_nanos() as i64)),
+            Value::Duration(Duration::nanoseconds(stage.send_nanos() as i64)),
+            Value::Duration(Duration::nanoseconds(stage.cpu_nanos() as i64)),
+            Value::Integer(stage.peak_buffered() as i128),
+        ]))?;
+    }
+    Ok(())
+}
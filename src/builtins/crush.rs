@@ -1,4 +1,6 @@
-use crate::lang::errors::{CrushResult};
+use crate::lang::errors::{error, CrushResult, to_crush_error};
+use std::fs::OpenOptions;
+use std::path::PathBuf;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::scope::Scope;
 use crate::lang::value::{Value, ValueType};
@@ -9,9 +11,10 @@ use nix::unistd::Pid;
 use crate::lang::data::dict::Dict;
 use std::env;
 use std::sync::OnceLock;
-use rustyline::history::{History, SearchDirection};
 use crate::data::list::List;
 use crate::lang::command::Command;
+use crate::lang::ast::format::format;
+use crate::lang::interactive::config_dir;
 
 fn make_env() -> CrushResult<Value> {
     let e = Dict::new(ValueType::String, ValueType::String)?;
@@ -25,6 +28,17 @@ fn make_arguments() -> Value {
     List::new(ValueType::String, env::args().map(|a| { Value::from(a) }).collect::<Vec<_>>()).into()
 }
 
+/**
+The directories that modules are imported from. By default, only the modules directory in the
+Crush configuration directory.
+ */
+fn make_path() -> Value {
+    let dirs = config_dir()
+        .map(|dir| vec![Value::from(dir.join("modules"))])
+        .unwrap_or_default();
+    List::new(ValueType::File, dirs).into()
+}
+
 fn threads_output_type() -> &'static Vec<ColumnType> {
     static CELL: OnceLock<Vec<ColumnType>> = OnceLock::new();
     CELL.get_or_init(|| vec![
@@ -78,6 +92,82 @@ fn prompt(context: CommandContext) -> CrushResult<()> {
     context.output.send(Value::Empty)
 }
 
+#[signature(
+    crush.suggest,
+    can_block = false,
+    short = "Turn suggestions in the interactive prompt on or off",
+    long = "While typing, the rest of the most recent matching command in the history is suggested, preferring commands run in the current directory. If no command matches, the only possible completion is suggested. Accept a suggestion with the right arrow key, or one word at a time with alt-f.",
+    long = "Without arguments, return whether suggestions are currently turned on.",
+    example = "crush:suggest false"
+)]
+struct Suggest {
+    #[description("whether to show suggestions.")]
+    enabled: Option<bool>,
+}
+
+fn suggest(context: CommandContext) -> CrushResult<()> {
+    let cfg: Suggest = Suggest::parse(context.arguments, &context.global_state.printer())?;
+    match cfg.enabled {
+        Some(enabled) => {
+            context.global_state.set_suggest(enabled);
+            context.output.send(Value::Empty)
+        }
+        None => context.output.send(Value::Bool(context.global_state.suggest())),
+    }
+}
+
+#[signature(
+    crush.trace,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Turn execution tracing on or off",
+    long = "While tracing is on, every command is logged right before it runs, with its evaluated arguments, how deeply it is nested in closures and where it is in the source. Once the command has finished, the time it took is logged as well.",
+    long = "By default, the trace is printed. If a file is given, the trace is instead appended to that file as JSON lines, one line when a command starts and one when it ends.",
+    example = "crush:trace file=./trace.jsonl command=cd command=git",
+)]
+struct Trace {
+    #[description("whether to trace.")]
+    #[default(true)]
+    enabled: bool,
+    #[description("append the trace to this file as JSON lines instead of printing it.")]
+    file: Option<PathBuf>,
+    #[description("only trace these commands. By default, all commands are traced.")]
+    command: Vec<String>,
+}
+
+fn trace(context: CommandContext) -> CrushResult<()> {
+    let cfg: Trace = Trace::parse(context.arguments, &context.global_state.printer())?;
+    let tracer = context.global_state.tracer();
+    if cfg.enabled {
+        let output = match cfg.file {
+            Some(file) => Some(to_crush_error(OpenOptions::new().create(true).append(true).open(file))?),
+            None => None,
+        };
+        tracer.start(output, cfg.command);
+    } else {
+        tracer.stop();
+    }
+    context.output.send(Value::Empty)
+}
+
+#[signature(
+    crush.fmt,
+    can_block = false,
+    output = Known(ValueType::String),
+    short = "Format Crush source code",
+    long = "Spacing is normalized, closures are indented and long pipelines are broken into multiple lines. Comments are kept.",
+    example = "crush:fmt \"ls|where {$size>1000}\""
+)]
+struct Fmt {
+    #[description("the source code to format.")]
+    source: String,
+}
+
+fn fmt(context: CommandContext) -> CrushResult<()> {
+    let cfg: Fmt = Fmt::parse(context.arguments, &context.global_state.printer())?;
+    context.output.send(Value::from(format(&cfg.source, context.global_state.parser())?))
+}
+
 fn job_output_type() -> &'static Vec<ColumnType> {
     static CELL: OnceLock<Vec<ColumnType>> = OnceLock::new();
     CELL.get_or_init(|| vec![
@@ -110,6 +200,12 @@ fn history_output_type() -> &'static Vec<ColumnType> {
     CELL.get_or_init(|| vec![
         ColumnType::new("idx", ValueType::Integer),
         ColumnType::new("command", ValueType::String),
+        // Entries written by older versions of Crush lack these
+        ColumnType::new("started", ValueType::optional(ValueType::Time)),
+        ColumnType::new("directory", ValueType::optional(ValueType::File)),
+        ColumnType::new("elapsed", ValueType::optional(ValueType::Duration)),
+        ColumnType::new("success", ValueType::optional(ValueType::Bool)),
+        ColumnType::new("session", ValueType::optional(ValueType::String)),
     ])
 }
 
@@ -118,25 +214,30 @@ fn history_output_type() -> &'static Vec<ColumnType> {
     can_block = true,
     short = "List previous commands",
     output = Known(ValueType::TableInputStream(history_output_type().clone())),
-    long = "All previous invocation")]
+    long = "All previous invocations, from all Crush sessions, along with when and in what directory they were run, how long they took, whether they succeeded and what session they were run in. Commands from older versions of Crush lack this information, and commands still running have no duration or success yet.",
+    example = "crush:history | where {$directory == $(pwd)} | where {$started > (time.now() - duration.of(days=7))}",
+)]
 struct HistoryCommand {}
 
 fn history(context: CommandContext) -> CrushResult<()> {
     let output = context.output.initialize(history_output_type())?;
-    let mut res = Vec::new();
-    context.global_state.editor().as_mut().map(|editor| {
-        let history = editor.history();
-        for i in 0..(history.len()) {
-            if let Ok(Some(c)) = history.get(i, SearchDirection::Reverse) {
-                res.push(c.entry.to_string());
-            }
+    let entries = match context.global_state.history() {
+        Some(history) => {
+            history.merge()?;
+            history.entries()
         }
-    });
-    let len = res.len();
-    for (idx, c) in res.into_iter().enumerate() {
+        None => vec![],
+    };
+    let len = entries.len();
+    for (idx, e) in entries.into_iter().enumerate() {
         output.send(Row::new(vec![
             Value::Integer((len - idx) as i128),
-            Value::from(c),
+            Value::from(e.command),
+            e.time.map(Value::Time).unwrap_or(Value::Empty),
+            e.directory.map(Value::from).unwrap_or(Value::Empty),
+            e.duration.map(Value::Duration).unwrap_or(Value::Empty),
+            e.success.map(Value::Bool).unwrap_or(Value::Empty),
+            e.session.map(Value::from).unwrap_or(Value::Empty),
         ]))?;
     }
     Ok(())
@@ -277,7 +378,201 @@ mod byte_unit {
     }
 }
 
+mod editor {
+    use super::*;
+    use crate::lang::interactive::editor::{
+        completion_name, mode_name, parse_completion, parse_key, parse_mode, KeyAction, KeyBinding, ACTIONS,
+    };
+    use crate::lang::errors::argument_error_legacy;
+
+    #[signature(
+        crush.editor.mode,
+        can_block = false,
+        short = "Set or get the editing mode of the interactive prompt",
+        long = "The mode is either \"emacs\" or \"vi\". Without arguments, return the current mode. The new mode is used from the next prompt on.",
+        example = "crush:editor:mode \"vi\""
+    )]
+    pub struct Mode {
+        #[description("the new editing mode.")]
+        mode: Option<String>,
+    }
+
+    fn mode(context: CommandContext) -> CrushResult<()> {
+        let cfg: Mode = Mode::parse(context.arguments, &context.global_state.printer())?;
+        let mut settings = context.global_state.editor_settings();
+        match cfg.mode {
+            Some(mode) => {
+                settings.mode = parse_mode(&mode)?;
+                context.global_state.set_editor_settings(settings);
+                context.output.send(Value::Empty)
+            }
+            None => context.output.send(Value::from(mode_name(settings.mode))),
+        }
+    }
+
+    #[signature(
+        crush.editor.completion,
+        can_block = false,
+        short = "Set or get how completions are shown in the interactive prompt",
+        long = "With \"list\", all possible completions are listed when there is more than one. With \"circular\", pressing tab repeatedly cycles through them. Without arguments, return the current style.",
+        example = "crush:editor:completion \"circular\""
+    )]
+    pub struct Completion {
+        #[description("the new completion style.")]
+        style: Option<String>,
+    }
+
+    fn completion(context: CommandContext) -> CrushResult<()> {
+        let cfg: Completion = Completion::parse(context.arguments, &context.global_state.printer())?;
+        let mut settings = context.global_state.editor_settings();
+        match cfg.style {
+            Some(style) => {
+                settings.completion = parse_completion(&style)?;
+                context.global_state.set_editor_settings(settings);
+                context.output.send(Value::Empty)
+            }
+            None => context.output.send(Value::from(completion_name(settings.completion))),
+        }
+    }
+
+    #[signature(
+        crush.editor.bind,
+        can_block = false,
+        output = Known(ValueType::Empty),
+        short = "Bind a key in the interactive prompt to an action or a closure",
+        long = "Keys are written like \"ctrl-x\", \"alt-shift-tab\", \"f5\" or \"q\". The action is either the name of an editor action, see crush:editor:actions, or a closure.",
+        long = "A closure is called with the current line and cursor position as the arguments `line` and `cursor`. Its output is inserted at the cursor. Lists and table streams are inserted as their elements, or the first column of their rows, separated by spaces.",
+        example = "crush:editor:bind \"ctrl-t\" {fzf}"
+    )]
+    pub struct Bind {
+        #[description("the key to bind.")]
+        key: String,
+        #[description("the name of an action, or a closure.")]
+        action: Value,
+    }
+
+    fn bind(context: CommandContext) -> CrushResult<()> {
+        let cfg: Bind = Bind::parse(context.arguments, &context.global_state.printer())?;
+        let event = parse_key(&cfg.key)?;
+        let action = KeyAction::new(cfg.action)?;
+        let mut settings = context.global_state.editor_settings();
+        settings.bindings.retain(|b| b.event != event);
+        settings.bindings.push(KeyBinding { key: cfg.key, event, action });
+        context.global_state.set_editor_settings(settings);
+        context.output.send(Value::Empty)
+    }
+
+    #[signature(
+        crush.editor.unbind,
+        can_block = false,
+        output = Known(ValueType::Empty),
+        short = "Remove a key binding created by crush:editor:bind",
+        long = "The key gets back its default meaning.",
+        example = "crush:editor:unbind \"ctrl-t\""
+    )]
+    pub struct Unbind {
+        #[description("the key to unbind.")]
+        key: String,
+    }
+
+    fn unbind(context: CommandContext) -> CrushResult<()> {
+        let cfg: Unbind = Unbind::parse(context.arguments, &context.global_state.printer())?;
+        let event = parse_key(&cfg.key)?;
+        let mut settings = context.global_state.editor_settings();
+        let len = settings.bindings.len();
+        settings.bindings.retain(|b| b.event != event);
+        if settings.bindings.len() == len {
+            return argument_error_legacy(format!("The key {} is not bound", cfg.key));
+        }
+        context.global_state.set_editor_settings(settings);
+        context.output.send(Value::Empty)
+    }
+
+    fn bindings_output_type() -> &'static Vec<ColumnType> {
+        static CELL: OnceLock<Vec<ColumnType>> = OnceLock::new();
+        CELL.get_or_init(|| vec![
+            ColumnType::new("key", ValueType::String),
+            ColumnType::new("action", ValueType::Any),
+        ])
+    }
+
+    #[signature(
+        crush.editor.bindings,
+        can_block = false,
+        output = Known(ValueType::TableInputStream(bindings_output_type().clone())),
+        short = "List the key bindings created by crush:editor:bind"
+    )]
+    pub struct Bindings {}
+
+    fn bindings(context: CommandContext) -> CrushResult<()> {
+        let output = context.output.initialize(bindings_output_type())?;
+        for binding in context.global_state.editor_settings().bindings {
+            output.send(Row::new(vec![
+                Value::from(binding.key),
+                binding.action.to_value(),
+            ]))?;
+        }
+        Ok(())
+    }
+
+    fn actions_output_type() -> &'static Vec<ColumnType> {
+        static CELL: OnceLock<Vec<ColumnType>> = OnceLock::new();
+        CELL.get_or_init(|| vec![
+            ColumnType::new("name", ValueType::String),
+            ColumnType::new("description", ValueType::String),
+        ])
+    }
+
+    #[signature(
+        crush.editor.actions,
+        can_block = false,
+        output = Known(ValueType::TableInputStream(actions_output_type().clone())),
+        short = "List the editor actions that keys can be bound to"
+    )]
+    pub struct Actions {}
+
+    fn actions(context: CommandContext) -> CrushResult<()> {
+        let output = context.output.initialize(actions_output_type())?;
+        for (name, description) in ACTIONS {
+            output.send(Row::new(vec![
+                Value::from(*name),
+                Value::from(*description),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+mod plugin {
+    use super::*;
+    use crate::lang::plugin;
+
+    #[signature(
+        crush.plugin.load,
+        can_block = true,
+        output = Known(ValueType::List(Box::from(ValueType::String))),
+        short = "Load a native plugin",
+        long = "A plugin is a shared library that registers namespaces and commands using the Crush plugin ABI. The namespaces are added to global:plugins, which is used by the global scope, and their names are returned.",
+        example = "crush:plugin:load ./libteam_commands.so"
+    )]
+    pub struct Load {
+        #[description("the shared library to load.")]
+        file: PathBuf,
+    }
+
+    fn load(context: CommandContext) -> CrushResult<()> {
+        let cfg: Load = Load::parse(context.arguments, &context.global_state.printer())?;
+        let plugins = match context.scope.get_absolute_path(vec!["global".to_string(), "plugins".to_string()])? {
+            Value::Scope(plugins) => plugins,
+            _ => return error("Missing plugins scope"),
+        };
+        let names = plugin::native::load(&cfg.file, &plugins)?;
+        context.output.send(List::new(ValueType::String, names.into_iter().map(Value::from).collect::<Vec<_>>()).into())
+    }
+}
+
 pub fn declare(root: &Scope) -> CrushResult<()> {
+    crate::lang::plugin::declare(root)?;
     root.create_namespace(
         "crush",
         "Metadata about this Crush shell instance",
@@ -291,15 +586,25 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             highlight.insert(Value::from("file_literal"), Value::from(""))?;
             highlight.insert(Value::from("label"), Value::from(""))?;
             highlight.insert(Value::from("numeric_literal"), Value::from(""))?;
+            highlight.insert(Value::from("command"), Value::from(""))?;
+            highlight.insert(Value::from("external_command"), Value::from("\x1b[3m"))?;
+            highlight.insert(Value::from("error"), Value::from("\x1b[31m"))?;
+            highlight.insert(Value::from("parse_error"), Value::from("\x1b[4m"))?;
+            highlight.insert(Value::from("matching_bracket"), Value::from("\x1b[1m"))?;
+            highlight.insert(Value::from("hint"), Value::from("\x1b[90m"))?;
             crush.declare("highlight", highlight.into())?;
 
             crush.declare("env", make_env()?)?;
             crush.declare("arguments", make_arguments())?;
+            crush.declare("path", make_path())?;
             Prompt::declare(crush)?;
+            Suggest::declare(crush)?;
             Threads::declare(crush)?;
             Exit::declare(crush)?;
             Jobs::declare(crush)?;
             HistoryCommand::declare(crush)?;
+            Fmt::declare(crush)?;
+            Trace::declare(crush)?;
 
             crush.create_namespace(
                 "locale",
@@ -321,6 +626,27 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
                     Ok(())
                 }),
             )?;
+            crush.create_namespace(
+                "editor",
+                "Settings for the line editor of the interactive prompt",
+                Box::new(move |env| {
+                    editor::Mode::declare(env)?;
+                    editor::Completion::declare(env)?;
+                    editor::Bind::declare(env)?;
+                    editor::Unbind::declare(env)?;
+                    editor::Bindings::declare(env)?;
+                    editor::Actions::declare(env)?;
+                    Ok(())
+                }),
+            )?;
+            crush.create_namespace(
+                "plugin",
+                "Native plugins implemented in shared libraries",
+                Box::new(move |env| {
+                    plugin::Load::declare(env)?;
+                    Ok(())
+                }),
+            )?;
             Ok(())
         }),
     )?;
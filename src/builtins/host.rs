@@ -16,6 +16,10 @@ use chrono::Duration;
 use crate::lang::data::table::ColumnFormat;
 use crate::lang::data::table::ColumnType;
 use crate::lang::data::table::Row;
+use crate::lang::data::table::Table;
+use crate::lang::data::edge::{Edge, Graph};
+use crate::lang::data::list::List;
+use std::collections::HashMap;
 
 extern crate uptime_lib;
 
@@ -228,9 +232,10 @@ mod macos {
     use chrono::Duration;
     use signature::signature;
     use crate::lang::data::table::{ColumnFormat};
+    use super::{graph_output_type, send_process_graph};
 
     lazy_static! {
-    static ref LIST_OUTPUT_TYPE: Vec<ColumnType> = vec![
+    static ref LIST_OUTPUT_TYPE: Vec<ColumnType> = graph_output_type(vec![
         ColumnType::new("pid", ValueType::Integer),
         ColumnType::new("ppid", ValueType::Integer),
         ColumnType::new("user", ValueType::String),
@@ -238,7 +243,7 @@ mod macos {
         ColumnType::new_with_format("vms", ColumnFormat::ByteUnit, ValueType::Integer),
         ColumnType::new("cpu", ValueType::Duration),
         ColumnType::new("name", ValueType::String),
-    ];
+    ]);
 }
 
     lazy_static! {
@@ -257,6 +262,8 @@ mod macos {
         can_block = true,
         short = "Return a table stream containing information on all running processes on this host",
         output = Known(ValueType::TableInputStream(LIST_OUTPUT_TYPE.clone())),
+        long = "The processes form a graph. The parent column is an edge to the parent of each process, and the children column is a list of edges to its children. Use the :: operator to access the members of the processes they point to, e.g. `host:procs | where {$parent::user != $user}`.",
+        long = "All processes are listed before the first one is sent, so that the edges can point to any of them.",
         long = "host:procs accepts no arguments.")]
     pub struct Procs {}
 
@@ -266,7 +273,11 @@ mod macos {
     use mach2::mach_time::mach_timebase_info;
 
     fn procs(context: CommandContext) -> CrushResult<()> {
-        let output = context.output.initialize(&LIST_OUTPUT_TYPE)?;
+        send_process_graph(&LIST_OUTPUT_TYPE, list_processes()?, context)
+    }
+
+    fn list_processes() -> CrushResult<Vec<Row>> {
+        let mut rows = Vec::new();
         let users = create_user_map()?;
         let mut info: mach_timebase_info = mach_timebase_info { numer: 0, denom: 0 };
         unsafe {
@@ -285,7 +296,7 @@ mod macos {
                                 .filter(|c| { *c > 0u8 })
                                 .collect()
                         ).unwrap_or_else(|_| { "<Invalid>".to_string() });
-                    output.send(Row::new(vec![
+                    rows.push(Row::new(vec![
                         Value::Integer(pid as i128),
                         Value::Integer(ppid),
                         users.get(&nix::unistd::Uid::from_raw(curr_task.pbsd.pbi_uid)).map(|s| Value::from(s)).unwrap_or_else(|| Value::from("?")),
@@ -296,11 +307,11 @@ mod macos {
                                 i64::from(info.numer) /
                                 i64::from(info.denom))),
                         Value::from(name),
-                    ]))?;
+                    ]));
                 }
             }
         }
-        Ok(())
+        Ok(rows)
     }
 
     #[signature(
@@ -384,9 +395,10 @@ mod linux {
     use signature::signature;
     use std::collections::HashMap;
     use crate::lang::data::table::{Row, ColumnFormat};
+    use super::{graph_output_type, send_process_graph};
 
     lazy_static! {
-    static ref LIST_OUTPUT_TYPE: Vec<ColumnType> = vec![
+    static ref LIST_OUTPUT_TYPE: Vec<ColumnType> = graph_output_type(vec![
         ColumnType::new("pid", ValueType::Integer),
         ColumnType::new("ppid", ValueType::Integer),
         ColumnType::new("status", ValueType::String),
@@ -395,7 +407,7 @@ mod linux {
         ColumnType::new_with_format("rss", ColumnFormat::ByteUnit, ValueType::Integer),
         ColumnType::new_with_format("vms", ColumnFormat::ByteUnit, ValueType::Integer),
         ColumnType::new("name", ValueType::String),
-    ];
+    ]);
     }
 
     fn state_name(s: Status) -> &'static str {
@@ -423,23 +435,26 @@ mod linux {
         can_block = true,
         short = "Return a table stream containing information on all running processes on this host",
         output = Known(ValueType::TableInputStream(LIST_OUTPUT_TYPE.clone())),
+        long = "The processes form a graph. The parent column is an edge to the parent of each process, and the children column is a list of edges to its children. Use the :: operator to access the members of the processes they point to, e.g. `host:procs | where {$parent::user != $user}`.",
+        long = "All processes are listed before the first one is sent, so that the edges can point to any of them.",
         long = "host:procs accepts no arguments.")]
     pub struct Procs {}
 
     fn procs(mut context: CommandContext) -> CrushResult<()> {
         Procs::parse(context.remove_arguments(), &context.global_state.printer())?;
-        let output = context.output.initialize(&LIST_OUTPUT_TYPE)?;
+        send_process_graph(&LIST_OUTPUT_TYPE, list_processes()?, context)
+    }
+
+    fn list_processes() -> CrushResult<Vec<Row>> {
         let users = create_user_map()?;
 
         match psutil::process::processes() {
-            Ok(procs) => {
-                for proc in procs {
-                    output.send(to_crush_error(handle_process(proc, &users))?)?;
-                }
-            }
-            Err(_) => return error("Failed to list processes"),
+            Ok(procs) => procs
+                .into_iter()
+                .map(|proc| to_crush_error(handle_process(proc, &users)))
+                .collect(),
+            Err(_) => error("Failed to list processes"),
         }
-        Ok(())
     }
 
     fn handle_process(proc: ProcessResult<Process>, users: &HashMap<Uid, String>) -> ProcessResult<Row> {
@@ -463,6 +478,68 @@ mod linux {
     }
 }
 
+/**
+The columns of the process list followed by the edges added by `process_graph`.
+ */
+fn graph_output_type(mut types: Vec<ColumnType>) -> Vec<ColumnType> {
+    types.push(ColumnType::new("parent", ValueType::optional(ValueType::Edge)));
+    types.push(ColumnType::new("children", ValueType::List(Box::from(ValueType::Edge))));
+    types
+}
+
+/**
+Turn the processes into a graph, and stream its rows to the output of the command.
+ */
+fn send_process_graph(types: &[ColumnType], rows: Vec<Row>, context: CommandContext) -> CrushResult<()> {
+    let output = context.output.initialize(types)?;
+    for row in process_graph(types, rows)?.iter() {
+        output.send(row)?;
+    }
+    Ok(())
+}
+
+/**
+Add a `parent` edge and a list of `children` edges to each process, turning the processes into a
+graph. The first two cells of each row must be the pid and the pid of the parent, and `types`
+must be the columns returned by `graph_output_type`.
+ */
+fn process_graph(types: &[ColumnType], mut rows: Vec<Row>) -> CrushResult<Table> {
+    let graph = Graph::new(types.to_vec());
+    let index = rows
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, row)| match row.cells()[0] {
+            Value::Integer(pid) => Some((pid, idx)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let parents = rows
+        .iter()
+        .enumerate()
+        .map(|(idx, row)| match row.cells()[1] {
+            Value::Integer(ppid) => index.get(&ppid).copied().filter(|parent| *parent != idx),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut children = vec![Vec::new(); rows.len()];
+    for (idx, parent) in parents.iter().enumerate() {
+        if let Some(parent) = parent {
+            children[*parent].push(Value::Edge(Edge::new(&graph, *parent, idx, true, Value::Empty)));
+        }
+    }
+
+    for ((idx, row), children) in rows.iter_mut().enumerate().zip(children) {
+        row.push(match parents[idx] {
+            Some(parent) => Value::Edge(Edge::new(&graph, idx, parent, true, Value::Empty)),
+            None => Value::Empty,
+        });
+        row.push(Value::List(List::new(ValueType::Edge, children)));
+    }
+    graph.set_rows(rows)?;
+    graph.table()
+}
+
 #[signature(
     host.signal,
     can_block = false,
@@ -13,6 +13,33 @@ use signature::signature;
 use std::collections::HashSet;
 use std::convert::{From, TryFrom};
 
+/**
+The column types of a table that can hold structs with any of the specified signatures, or None if
+they don't all have the same columns. Columns whose type differs between structs, e.g. because
+the value is sometimes null, get a union type like `integer|empty`.
+ */
+fn merge_signatures(signatures: &HashSet<Vec<ColumnType>>) -> Option<Vec<ColumnType>> {
+    let first = signatures.iter().next()?;
+    let same_columns = signatures.iter().all(|s| {
+        s.len() == first.len() && s.iter().zip(first.iter()).all(|(a, b)| a.name == b.name)
+    });
+    if !same_columns {
+        return None;
+    }
+    Some(
+        first
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| {
+                ColumnType::new(
+                    column.name.clone(),
+                    ValueType::either(signatures.iter().map(|s| s[idx].cell_type.clone()).collect()),
+                )
+            })
+            .collect(),
+    )
+}
+
 fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
     match json_value {
         serde_json::Value::Null => Ok(Value::Empty),
@@ -47,8 +74,8 @@ fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
                 0 => Ok(Value::Empty),
                 1 => {
                     let list_type = types.iter().next().unwrap();
-                    match (list_type, struct_types.len()) {
-                        (ValueType::Struct, 1) => {
+                    match (list_type, merge_signatures(&struct_types)) {
+                        (ValueType::Struct, Some(column_types)) => {
                             let row_list = lst
                                 .drain(..)
                                 .map(|v| match v {
@@ -56,15 +83,12 @@ fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
                                     _ => error("Impossible!"),
                                 })
                                 .collect::<CrushResult<Vec<Row>>>()?;
-                            Ok(Value::Table(Table::from((
-                                struct_types.iter().next().unwrap().clone(),
-                                row_list,
-                            ))))
+                            Ok(Value::Table(Table::from((column_types, row_list))))
                         }
                         _ => Ok(List::new(list_type.clone(), lst).into()),
                     }
                 }
-                _ => Ok(List::new(ValueType::Any, lst).into()),
+                _ => Ok(List::new(ValueType::either(types.into_iter().collect()), lst).into()),
             }
         }
         serde_json::Value::Object(o) => Ok(Value::Struct(Struct::new(
@@ -98,6 +122,13 @@ fn to_json(value: Value) -> CrushResult<serde_json::Value> {
                 .collect::<CrushResult<Vec<_>>>()?,
         )),
 
+        Value::Set(s) => Ok(serde_json::Value::Array(
+            s.elements()
+                .into_iter()
+                .map(to_json)
+                .collect::<CrushResult<Vec<_>>>()?,
+        )),
+
         Value::Table(t) => {
             let types = t.types().to_vec();
             let structs: CrushResult<Vec<serde_json::Value>> =
@@ -58,7 +58,7 @@ pub fn dir(context: CommandContext) -> CrushResult<()> {
     context.output.send(List::new(
         ValueType::String,
         cfg.value
-            .fields()
+            .fields_with_extensions(&context.scope)?
             .drain(..)
             .map(|n| Value::from(n))
             .collect::<Vec<_>>(),
@@ -81,7 +81,8 @@ fn echo(context: CommandContext) -> CrushResult<()> {
     let cfg: Echo = Echo::parse(context.arguments, &context.global_state.printer())?;
     let pretty = PrettyPrinter::new(
         context.global_state.printer().clone(),
-        context.global_state.format_data());
+        context.scope.clone(),
+        context.global_state.clone());
     for value in cfg.values {
         match (cfg.raw, &value) {
             (true, Value::String(s)) =>
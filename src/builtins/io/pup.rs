@@ -1,11 +1,11 @@
 use crate::lang::command::OutputType::Unknown;
-use crate::lang::errors::CrushResult;
+use crate::lang::errors::{to_crush_error, CrushResult};
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::signature::files::Files;
 use crate::lang::state::scope::ScopeLoader;
-use crate::lang::serialization::{deserialize_reader, serialize_writer};
+use crate::lang::serialization::{deserialize_reader, deserialize_stream, serialize_stream, serialize_writer, STREAM_MAGIC};
 use signature::signature;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 
 #[signature(
     io.pup.to,
@@ -14,17 +14,26 @@ use std::io::BufReader;
     short = "Serialize to pup format",
     long = "Pup is the native crush serialization format. All Crush types, including",
     long = "lambdas can be serialized to this format.",
+    long = "If stream is true, table streams are written a batch of rows at a time as they arrive,",
+    long = "instead of being read in full before anything is written.",
     example = "ls | pup:to")]
 struct To {
     #[unnamed()]
     file: Files,
+    #[description("write the streaming variant of the pup format.")]
+    #[default(false)]
+    stream: bool,
 }
 
 fn to(context: CommandContext) -> CrushResult<()> {
     let cfg: To = To::parse(context.arguments, &context.global_state.printer())?;
     let mut writer = cfg.file.writer(context.output)?;
     let value = context.input.recv()?;
-    serialize_writer(&value, &mut writer)
+    if cfg.stream {
+        serialize_stream(Ok(value), &mut writer)
+    } else {
+        serialize_writer(&value, &mut writer)
+    }
 }
 
 #[signature(
@@ -32,6 +41,7 @@ fn to(context: CommandContext) -> CrushResult<()> {
     can_block = true,
     output = Unknown,
     short = "Parse pup format",
+    long = "Both regular pup files and the streaming variant written by `pup:to --stream` are supported.",
     example = "pup:from serialized.pup")]
 struct From {
     #[unnamed()]
@@ -40,10 +50,17 @@ struct From {
 
 fn from(context: CommandContext) -> CrushResult<()> {
     let cfg: From = From::parse(context.arguments, &context.global_state.printer())?;
-    context.output.send(deserialize_reader(
-        &mut BufReader::new(&mut cfg.files.reader(context.input)?),
-        &context.scope,
-    )?)
+    let mut reader = BufReader::new(cfg.files.reader(context.input)?);
+    if to_crush_error(reader.fill_buf())?.starts_with(STREAM_MAGIC) {
+        context.output.send(deserialize_stream(
+            reader,
+            &context.scope,
+            &context.global_state,
+            |_| {},
+        )?)
+    } else {
+        context.output.send(deserialize_reader(&mut reader, &context.scope)?)
+    }
 }
 
 pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
@@ -27,6 +27,8 @@ pub mod types;
 mod user;
 mod var;
 
+pub use user::stop_workers;
+
 pub fn declare(root: &Scope) -> CrushResult<()> {
     comp::declare(root)?;
     cond::declare(root)?;
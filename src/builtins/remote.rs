@@ -5,7 +5,9 @@ use crate::lang::state::contexts::CommandContext;
 use crate::lang::signature::files::Files;
 use crate::lang::signature::patterns::Patterns;
 use crate::lang::state::scope::Scope;
-use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::serialization::{deserialize_stream, serialize};
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::data::dict::Dict;
 use crate::lang::data::table::{ColumnType, Row};
 use crate::lang::value::Value;
 use crate::lang::value::ValueType;
@@ -18,12 +20,13 @@ use ssh2::{CheckResult, HostKeyType, KnownHostKeyFormat, Session};
 use std::cmp::min;
 use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::util::user_map::get_current_username;
 use crate::lang::completion::Completion;
 use crate::lang::completion::parse::{PartialCommandResult, LastArgument};
 use std::convert::TryFrom;
-use crate::util::escape::{escape, escape_without_quotes};
+use crate::util::escape::{escape, escape_without_quotes, shell_quote};
+use crate::version_string;
 
 lazy_static! {
     static ref IDENTITY_OUTPUT_TYPE: Vec<ColumnType> = vec![
@@ -63,6 +66,165 @@ fn parse(
     Ok((host, username, port))
 }
 
+/**
+Settings used to bootstrap a crush binary on remote hosts that are missing one, or that have a
+crush binary of a different version than the local one.
+ */
+#[derive(Clone)]
+struct Deploy {
+    /// Directory on the remote host where uploaded binaries are cached. Relative paths are
+    /// relative to the home directory of the remote user.
+    cache_dir: String,
+    /// Mapping from platform name (e.g. `linux-x86_64`, see `remote_platform`) to the binary to
+    /// upload.
+    binaries: Option<Dict>,
+}
+
+impl Deploy {
+    fn new(cache_dir: String, env: &Scope) -> Deploy {
+        let binaries = match env.get_absolute_path(
+            vec!["global".to_string(), "remote".to_string(), "binaries".to_string()]) {
+            Ok(Value::Dict(d)) => Some(d),
+            _ => None,
+        };
+        Deploy { cache_dir, binaries }
+    }
+
+    fn remote_path(&self) -> String {
+        format!("{}/{}/crush", self.cache_dir, env!("CARGO_PKG_VERSION"))
+    }
+
+    /**
+    Find the local binary to upload to a remote host with the specified platform.
+     */
+    fn local_binary(&self, platform: &str) -> CrushResult<PathBuf> {
+        if let Some(binaries) = &self.binaries {
+            match binaries.get(&Value::from(platform)) {
+                Some(Value::File(f)) => return Ok(f.to_path_buf()),
+                Some(Value::String(f)) => return Ok(PathBuf::from(f.to_string())),
+                Some(v) => return error(format!(
+                    "Expected remote:binaries[\"{}\"] to be a file, found a value of type {}",
+                    platform, v.value_type())),
+                None => {}
+            }
+        }
+        if platform == local_platform() {
+            to_crush_error(std::env::current_exe())
+        } else {
+            error(format!(
+                "No crush binary available for platform {}. Add one to remote:binaries.",
+                platform))
+        }
+    }
+}
+
+/**
+The platform of this binary, in the format returned by `remote_platform`.
+ */
+fn local_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/**
+Convert the output of `uname -sm` on a remote host to a platform name using the operating system
+and architecture names of Rust, e.g. `macos-aarch64` for `Darwin arm64`.
+ */
+fn remote_platform(uname: &str) -> CrushResult<String> {
+    let mut parts = uname.split_whitespace();
+    let (os, arch) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(os), Some(arch), None) => (os, arch),
+        _ => return error(format!("Unexpected output from uname: {}", uname.trim())),
+    };
+    let os = match os {
+        "Darwin" => "macos".to_string(),
+        os => os.to_lowercase(),
+    };
+    let arch = match arch {
+        "arm64" => "aarch64",
+        "amd64" => "x86_64",
+        arch => arch,
+    };
+    Ok(format!("{}-{}", os, arch))
+}
+
+/**
+Run a command on the remote host and return its exit status and standard output.
+ */
+fn run_simple(sess: &Session, cmd: &str) -> CrushResult<(i32, String)> {
+    let mut channel = to_crush_error(sess.channel_session())?;
+    to_crush_error(channel.exec(cmd))?;
+    let mut out = String::new();
+    to_crush_error(channel.read_to_string(&mut out))?;
+    to_crush_error(channel.wait_close())?;
+    Ok((to_crush_error(channel.exit_status())?, out))
+}
+
+/**
+Returns true if the specified remote crush binary exists and has the same version as this binary.
+ */
+fn has_matching_version(sess: &Session, binary: &str) -> CrushResult<bool> {
+    let (status, out) = run_simple(sess, &format!("{} --version 2>/dev/null", binary))?;
+    Ok(status == 0 && out.trim() == version_string())
+}
+
+fn upload(sess: &Session, local: &Path, remote: &str) -> CrushResult<()> {
+    let data = to_crush_error(std::fs::read(local))?;
+    let remote_dir = mandate(Path::new(remote).parent(), "Invalid remote cache directory")?;
+    // Use a unique name, so that concurrent uploads to the same host don't overwrite each other
+    let tmp = format!("{}.{:016x}.tmp", remote, rand::random::<u64>());
+
+    let (status, _) = run_simple(
+        sess,
+        &format!("mkdir -p {}", shell_quote(&remote_dir.to_string_lossy())))?;
+    if status != 0 {
+        return error(format!("Failed to create remote directory {}", remote_dir.display()));
+    }
+
+    let mut channel = to_crush_error(sess.scp_send(Path::new(&tmp), 0o755, data.len() as u64, None))?;
+    to_crush_error(channel.write_all(&data))?;
+    to_crush_error(channel.send_eof())?;
+    to_crush_error(channel.wait_eof())?;
+    to_crush_error(channel.close())?;
+    to_crush_error(channel.wait_close())?;
+
+    let (status, _) = run_simple(sess, &format!("mv {} {}", shell_quote(&tmp), shell_quote(remote)))?;
+    if status != 0 {
+        return error(format!("Failed to install crush binary as {}", remote));
+    }
+    Ok(())
+}
+
+/**
+Return the command used to invoke crush on the remote host. If a deploy configuration is given,
+make sure that a crush binary of the same version as this one is available, uploading it if needed.
+ */
+fn remote_crush(sess: &Session, deploy: &Option<Deploy>) -> CrushResult<String> {
+    match deploy {
+        None => Ok("crush".to_string()),
+        Some(deploy) => {
+            if has_matching_version(sess, "crush")? {
+                return Ok("crush".to_string());
+            }
+            let remote = deploy.remote_path();
+            let quoted = shell_quote(&remote);
+            if !has_matching_version(sess, &quoted)? {
+                let (status, uname) = run_simple(sess, "uname -sm")?;
+                if status != 0 {
+                    return error("Failed to detect the platform of the remote host");
+                }
+                upload(sess, &deploy.local_binary(&remote_platform(&uname)?)?, &remote)?;
+                if !has_matching_version(sess, &quoted)? {
+                    return error(format!(
+                        "The uploaded crush binary {} does not report version {}",
+                        remote,
+                        version_string()));
+                }
+            }
+            Ok(quoted)
+        }
+    }
+}
+
 fn run_remote(
     cmd: &Vec<u8>,
     env: &Scope,
@@ -72,6 +234,8 @@ fn run_remote(
     host_file: &PathBuf,
     ignore_host_file: bool,
     allow_not_found: bool,
+    deploy: &Option<Deploy>,
+    global_state: &GlobalState,
 ) -> CrushResult<Value> {
     let (host, username, port) = parse(host, &default_username)?;
 
@@ -118,15 +282,16 @@ fn run_remote(
         to_crush_error(sess.userauth_agent(&username))?;
     }
 
+    let crush = remote_crush(&sess, deploy)?;
     let mut channel = to_crush_error(sess.channel_session())?;
-    to_crush_error(channel.exec("crush --pup"))?;
+    to_crush_error(channel.exec(&format!("{} --pup", crush)))?;
     to_crush_error(channel.write(cmd))?;
     to_crush_error(channel.send_eof())?;
-    let mut out_buf = Vec::new();
-    to_crush_error(channel.read_to_end(&mut out_buf))?;
-    let res = deserialize(&out_buf, env)?;
-    to_crush_error(channel.wait_close())?;
-    Ok(res)
+    deserialize_stream(
+        channel,
+        env,
+        global_state,
+        |mut channel| { let _ = channel.wait_close(); })
 }
 
 fn ssh_host_complete(
@@ -173,7 +338,9 @@ fn ssh_host_complete(
     remote.exec,
     can_block = true,
     short = "Execute a command on a remote host",
-    long = "    Execute the specified command on the soecified host"
+    long = "    Execute the specified command on the soecified host",
+    long = "If deploy is true, the version of the crush binary on the remote host is checked first. If it is missing or of a different version, the local binary is uploaded to the cache directory on the remote host and used instead. To deploy to hosts with a different operating system or architecture, add a binary for that platform to remote:binaries, e.g. `remote:binaries[\"linux-aarch64\"] = '/opt/crush/linux-aarch64/crush'`.",
+    example = "remote:exec {uptime} \"popplar.meadow\" --deploy",
 )]
 struct Exec {
     #[description("the command to execute.")]
@@ -195,6 +362,12 @@ struct Exec {
     )]
     #[default(false)]
     allow_not_found: bool,
+    #[description("if the remote host lacks a crush binary of the same version as this one, upload one.")]
+    #[default(false)]
+    deploy: bool,
+    #[description("directory on the remote host where uploaded crush binaries are stored.")]
+    #[default(".cache/crush")]
+    cache_dir: String,
 }
 
 fn exec(context: CommandContext) -> CrushResult<()> {
@@ -204,6 +377,7 @@ fn exec(context: CommandContext) -> CrushResult<()> {
     } else {
         home()?.join(".ssh/known_hosts")
     };
+    let deploy = cfg.deploy.then(|| Deploy::new(cfg.cache_dir, &context.scope));
     let mut in_buf = Vec::new();
     serialize(&Value::Command(cfg.command), &mut in_buf)?;
     context.output.send(run_remote(
@@ -215,6 +389,8 @@ fn exec(context: CommandContext) -> CrushResult<()> {
         &host_file,
         cfg.ignore_host_file,
         cfg.allow_not_found,
+        &deploy,
+        &context.global_state,
     )?)
 }
 
@@ -250,6 +426,12 @@ struct Pexec {
     )]
     #[default(false)]
     allow_not_found: bool,
+    #[description("if the remote host lacks a crush binary of the same version as this one, upload one.")]
+    #[default(false)]
+    deploy: bool,
+    #[description("directory on the remote host where uploaded crush binaries are stored.")]
+    #[default(".cache/crush")]
+    cache_dir: String,
 }
 
 lazy_static! {
@@ -267,6 +449,7 @@ fn pexec(mut context: CommandContext) -> CrushResult<()> {
         home()?.join(".ssh/known_hosts")
     };
 
+    let deploy = cfg.deploy.then(|| Deploy::new(cfg.cache_dir.clone(), &context.scope));
     let (host_send, host_recv) = unbounded::<String>();
     let (result_send, result_recv) = unbounded::<(String, Value)>();
 
@@ -291,6 +474,8 @@ fn pexec(mut context: CommandContext) -> CrushResult<()> {
         let my_host_file = host_file.clone();
         let my_ignore_host_file = cfg.ignore_host_file;
         let my_allow_not_found = cfg.allow_not_found;
+        let my_deploy = deploy.clone();
+        let my_state = context.global_state.clone();
 
         context.spawn(
             "remote:pexec",
@@ -305,7 +490,9 @@ fn pexec(mut context: CommandContext) -> CrushResult<()> {
                         &my_host_file,
                         my_ignore_host_file,
                         my_allow_not_found,
-                    )?;
+                        &my_deploy,
+                        &my_state,
+                    )?.materialize()?;
                     to_crush_error(my_send.send((host, res)))?;
                 }
                 Ok(())
@@ -436,6 +623,7 @@ pub fn declare(scope: &Scope) -> CrushResult<()> {
         "remote",
         "Remote code execution",
         Box::new(move |remote| {
+            remote.declare("binaries", Dict::new(ValueType::String, ValueType::File)?.into())?;
             Exec::declare(remote)?;
             Pexec::declare(remote)?;
             Identity::declare(remote)?;
@@ -455,3 +643,54 @@ pub fn declare(scope: &Scope) -> CrushResult<()> {
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deploy(binaries: &[(&str, Value)]) -> Deploy {
+        let dict = Dict::new(ValueType::String, ValueType::Any).unwrap();
+        for (platform, binary) in binaries {
+            dict.insert(Value::from(*platform), binary.clone()).unwrap();
+        }
+        Deploy { cache_dir: ".cache/crush".to_string(), binaries: Some(dict) }
+    }
+
+    #[test]
+    fn remote_path_includes_version() {
+        assert_eq!(
+            deploy(&[]).remote_path(),
+            format!(".cache/crush/{}/crush", env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn remote_platform_uses_rust_names() {
+        assert_eq!(remote_platform("Linux x86_64\n").unwrap(), "linux-x86_64");
+        assert_eq!(remote_platform("Darwin arm64\n").unwrap(), "macos-aarch64");
+        assert_eq!(remote_platform("FreeBSD amd64\n").unwrap(), "freebsd-x86_64");
+        assert!(remote_platform("Linux\n").is_err());
+    }
+
+    #[test]
+    fn local_binary_is_used_for_local_platform() {
+        assert_eq!(deploy(&[]).local_binary(&local_platform()).unwrap(), std::env::current_exe().unwrap());
+    }
+
+    #[test]
+    fn local_binary_is_not_used_for_other_os() {
+        let platform = format!("plan9-{}", std::env::consts::ARCH);
+        assert!(deploy(&[]).local_binary(&platform).is_err());
+    }
+
+    #[test]
+    fn configured_binaries_are_used() {
+        let deploy = deploy(&[
+            ("linux-aarch64", Value::from(Path::new("/opt/crush/linux-aarch64/crush"))),
+            ("macos-aarch64", Value::from("/opt/crush/macos-aarch64/crush")),
+            ("linux-riscv64", Value::Integer(1)),
+        ]);
+        assert_eq!(deploy.local_binary("linux-aarch64").unwrap(), PathBuf::from("/opt/crush/linux-aarch64/crush"));
+        assert_eq!(deploy.local_binary("macos-aarch64").unwrap(), PathBuf::from("/opt/crush/macos-aarch64/crush"));
+        assert!(deploy.local_binary("linux-riscv64").is_err());
+    }
+}
@@ -44,6 +44,7 @@ sum_function!(sum_duration, Duration, Duration::seconds(0), Duration);
     short = "Calculate the sum for the specific column across all rows.",
     example = "proc:list | sum cpu")]
 pub struct Sum {
+    #[column(ValueType::Integer, ValueType::Float, ValueType::Duration)]
     field: Option<String>,
 }
 
@@ -96,6 +97,7 @@ avg_function!(avg_duration, Duration, Duration::seconds(0), Duration, i32);
     short = "Calculate the average for the specific column across all rows.",
     example = "proc:list | avg cpu")]
 pub struct Avg {
+    #[column(ValueType::Integer, ValueType::Float, ValueType::Duration)]
     field: Option<String>,
 }
 
@@ -167,6 +169,9 @@ aggr_function!(max_file, File, "file", |a, b| std::cmp::max(a, b));
     long = "If the input only has one column, the column name is optional.\n\n    The column can be numeric, temporal, a string or a file.",
     example = "host:procs | min cpu")]
 pub struct Min {
+    #[column(
+        ValueType::Integer, ValueType::Float, ValueType::Duration,
+        ValueType::Time, ValueType::String, ValueType::File)]
     field: Option<String>,
 }
 
@@ -197,6 +202,9 @@ fn min(context: CommandContext) -> CrushResult<()> {
     long = "If the input only has one column, the column name is optional.\n\n    The column can be numeric, temporal, a string or a file.",
     example = "host:procs | max cpu")]
 pub struct Max {
+    #[column(
+        ValueType::Integer, ValueType::Float, ValueType::Duration,
+        ValueType::Time, ValueType::String, ValueType::File)]
     field: Option<String>,
 }
 
@@ -244,6 +252,7 @@ prod_function!(prod_float, f64, 1.0, Float);
     short = "Calculate the product for the specific column across all rows.",
     example = "seq 5 10 | prod")]
 pub struct Prod {
+    #[column(ValueType::Integer, ValueType::Float)]
     field: Option<String>,
 }
 
@@ -269,6 +278,7 @@ fn prod(context: CommandContext) -> CrushResult<()> {
     short = "Return the value of the specified column from the first row of the stream.",
 )]
 pub struct First {
+    #[column()]
     field: Option<String>,
 }
 
@@ -293,6 +303,7 @@ fn first(context: CommandContext) -> CrushResult<()> {
     short = "Return the value of the specified column from the last row of the stream.",
 )]
 pub struct Last {
+    #[column()]
     field: Option<String>,
 }
 
@@ -25,6 +25,7 @@ use crate::lang::state::global_state::GlobalState;
 )]
 pub struct Group {
     #[unnamed()]
+    #[column()]
     #[description("the column(s) to group by and copy into the output stream.")]
     group_by: Vec<String>,
     #[named()]
@@ -48,7 +48,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
                 "select copy_fields:field... [%] new_field=definition:command",
                 "Pass on some old fields and calculate new ones for each line of input",
                 example!(r#"files | select user path={"{}/{}":format (pwd) file}"#), Unknown,
-                vec![],
+                select::arguments(),
             )?;
             seq::Seq::declare(env)?;
             zip::Zip::declare(env)?;
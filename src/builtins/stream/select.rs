@@ -1,4 +1,4 @@
-use crate::lang::command::Command;
+use crate::lang::command::{ArgumentDescription, Command};
 use crate::lang::errors::error;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::pipe::{pipe, Stream};
@@ -7,7 +7,7 @@ use crate::{
     lang::errors::argument_error_legacy,
     lang::errors::CrushResult,
     data::table::ColumnType,
-    lang::{argument::Argument, data::table::Row, value::Value},
+    lang::{argument::Argument, data::table::Row, value::Value, value::ValueType},
     util::replace::Replace,
 };
 use crate::lang::ast::location::Location;
@@ -28,6 +28,34 @@ pub struct Config {
     location: Location,
 }
 
+/**
+Select parses its own arguments, these descriptions are used for completion and checking.
+ */
+pub fn arguments() -> Vec<ArgumentDescription> {
+    vec![
+        ArgumentDescription {
+            name: "copy_fields".to_string(),
+            value_type: ValueType::Any,
+            allowed: None,
+            description: None,
+            complete: None,
+            named: false,
+            unnamed: true,
+            column: Some(vec![]),
+        },
+        ArgumentDescription {
+            name: "new_field".to_string(),
+            value_type: ValueType::Any,
+            allowed: None,
+            description: None,
+            complete: None,
+            named: true,
+            unnamed: false,
+            column: None,
+        },
+    ]
+}
+
 pub fn run(config: Config, mut input: Stream, context: CommandContext) -> CrushResult<()> {
     let input_type = input.types().to_vec();
     let mut output_type = if config.copy {
@@ -15,6 +15,7 @@ use std::cmp::Ordering;
     output = Passthrough)]
 pub struct Sort {
     #[unnamed()]
+    #[column()]
     #[description("the columns to sort on. Optional if input only has one column.")]
     field: Vec<String>,
     #[description("reverse the sort order.")]
@@ -49,28 +50,27 @@ fn sort(context: CommandContext) -> CrushResult<()> {
                 res.push(row);
             }
 
-            if cfg.reverse {
-                res.sort_by(|a, b| {
-                    for idx in &indices {
-                        match b.cells()[*idx].partial_cmp(&a.cells()[*idx]) {
-                            None => panic!("Unexpcted sort failure"),
-                            Some(Ordering::Equal) => {}
-                            Some(ordering) => return ordering,
-                        }
+            // Instances of classes are compared by calling their __lt__ method, which may fail,
+            // and columns of type any may hold values that can't be compared at all
+            let mut failure = None;
+            res.sort_by(|a, b| {
+                let (a, b) = if cfg.reverse { (b, a) } else { (a, b) };
+                for idx in &indices {
+                    if failure.is_some() {
+                        return Ordering::Equal;
                     }
-                    Ordering::Equal
-                });
-            } else {
-                res.sort_by(|b, a| {
-                    for idx in &indices {
-                        match b.cells()[*idx].partial_cmp(&a.cells()[*idx]) {
-                            None => panic!("Unexpcted sort failure"),
-                            Some(Ordering::Equal) => {}
-                            Some(ordering) => return ordering,
-                        }
+                    match a.cells()[*idx].compare(&b.cells()[*idx], &context.scope, &context.global_state) {
+                        Ok(Some(Ordering::Equal)) => {}
+                        Ok(Some(ordering)) => return ordering,
+                        Ok(None) => failure = Some(argument_error_legacy("Values can't be compared with each other")),
+                        Err(e) => failure = Some(Err(e)),
                     }
-                    Ordering::Equal
-                });
+                }
+                Ordering::Equal
+            });
+
+            if let Some(failure) = failure {
+                return failure;
             }
 
             for row in res {
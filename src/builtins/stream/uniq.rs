@@ -1,9 +1,10 @@
 use crate::lang::errors::{error, CrushResult};
 use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
 use crate::lang::data::table::ColumnVec;
-use crate::lang::data::table::Row;
 use crate::lang::value::Value;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use signature::signature;
 use crate::lang::command::OutputType::Passthrough;
 
@@ -14,6 +15,7 @@ use crate::lang::command::OutputType::Passthrough;
     long = "If no column is given, the entire rows are compared.",
     example = "host:procs | uniq user")]
 pub struct Uniq {
+    #[column()]
     field: Option<String>,
 }
 
@@ -22,21 +24,18 @@ pub fn uniq(context: CommandContext) -> CrushResult<()> {
         Some(mut input) => {
             let cfg: Uniq = Uniq::parse(context.arguments, &context.global_state.printer())?;
             let output = context.output.initialize(input.types())?;
+            let mut seen = Seen::new(&context.scope, &context.global_state);
             match cfg.field.map(|f| input.types().find(&f)).transpose()? {
                 None => {
-                    let mut seen: HashSet<Row> = HashSet::new();
                     while let Ok(row) = input.read() {
-                        if !seen.contains(&row) {
-                            seen.insert(row.clone());
+                        if seen.insert(row.cells())? {
                             output.send(row)?;
                         }
                     }
                 }
                 Some(idx) => {
-                    let mut seen: HashSet<Value> = HashSet::new();
                     while let Ok(row) = input.read() {
-                        if !seen.contains(&row.cells()[idx]) {
-                            seen.insert(row.cells()[idx].clone());
+                        if seen.insert(&row.cells()[idx..idx + 1])? {
                             output.send(row)?;
                         }
                     }
@@ -47,3 +46,57 @@ pub fn uniq(context: CommandContext) -> CrushResult<()> {
         _ => error("Expected io to be a stream"),
     }
 }
+
+/**
+The values seen so far. Instances of classes are compared using their `__eq__` method, so values
+are grouped by a key where every instance is replaced by the output of its `__hash__` method, and
+only values in the same group are compared with each other.
+ */
+struct Seen<'a> {
+    scope: &'a Scope,
+    state: &'a GlobalState,
+    groups: HashMap<Vec<Value>, Vec<Vec<Value>>>,
+}
+
+impl<'a> Seen<'a> {
+    fn new(scope: &'a Scope, state: &'a GlobalState) -> Seen<'a> {
+        Seen { scope, state, groups: HashMap::new() }
+    }
+
+    /** Remember the values, and return true if they were not seen before. */
+    fn insert(&mut self, values: &[Value]) -> CrushResult<bool> {
+        let key = values.iter().map(|v| self.key(v)).collect::<CrushResult<Vec<_>>>()?;
+        let (scope, state) = (self.scope, self.state);
+        let group = self.groups.entry(key).or_default();
+        for other in group.iter() {
+            if equals(values, other, scope, state)? {
+                return Ok(false);
+            }
+        }
+        group.push(values.to_vec());
+        Ok(true)
+    }
+
+    fn key(&self, value: &Value) -> CrushResult<Value> {
+        match value {
+            Value::Struct(s) => match s.call_method("__hash__", vec![], self.scope, self.state) {
+                Some(hash) => {
+                    let hash = hash?;
+                    Ok(if hash.value_type().is_hashable() { hash } else { Value::Empty })
+                }
+                None => Ok(Value::Empty),
+            },
+            v if v.value_type().is_hashable() => Ok(v.clone()),
+            _ => Ok(Value::Empty),
+        }
+    }
+}
+
+fn equals(a: &[Value], b: &[Value], scope: &Scope, state: &GlobalState) -> CrushResult<bool> {
+    for (a, b) in a.iter().zip(b) {
+        if !a.equals(b, scope, state)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,168 @@
+use crate::lang::command::{Command, CommandBinder, CrushCommand};
+use crate::lang::command::OutputType::{Known, Unknown};
+use crate::lang::data::r#enum::{EnumType, Variant};
+use crate::lang::data::r#enum;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{argument_error_legacy, error, mandate, CrushResult};
+use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::this::This;
+use crate::lang::value::{Value, ValueType};
+use signature::signature;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+#[signature(
+    types.r#enum,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "Create a new enum type",
+    long = "An enum is a type whose values are one of a fixed set of variants. Each variant can carry a set of typed fields. The first argument is the name of the type, and the remaining arguments are the variants, in order. Variants without fields are given as unnamed arguments, variants with fields as named arguments, with a struct mapping the name of each field to its type as the value.",
+    long = "Variants without fields are values of the enum type, e.g. `$State:Pending`. Variants with fields are constructors, that accept the fields as named arguments, or as unnamed arguments in the order they were declared, e.g. `Result:Ok value=5`.",
+    long = "Use `match` to run different code depending on the variant of a value.",
+    example = "$Result := (enum Result Ok=(data value=$any) Err=(data reason=$string))",
+)]
+#[allow(unused)]
+pub struct Enum {
+    #[description("the name of the type.")]
+    name: String,
+    #[unnamed]
+    #[description("the variants without fields.")]
+    unnamed: Vec<String>,
+    #[named]
+    #[description("the variants with fields.")]
+    named: OrderedStringMap<Struct>,
+}
+
+/**
+The arguments are parsed by hand rather than by `Enum::parse`, which is only used for the help
+text. The order of the variants decides how values compare, and the signature collects named and
+unnamed arguments separately, so it would lose the order between variants with and without fields.
+ */
+fn r#enum(context: CommandContext) -> CrushResult<()> {
+    let mut arguments = context.arguments.into_iter();
+    let name = match arguments.next() {
+        Some(argument) => match (&argument.argument_type, &argument.value) {
+            (None, Value::String(name)) => name.to_string(),
+            _ => return argument.error("Expected the name of the enum as the first argument"),
+        },
+        None => return argument_error_legacy("Missing the name of the enum"),
+    };
+
+    let mut variants = Vec::new();
+    for argument in arguments {
+        match (&argument.argument_type, &argument.value) {
+            (None, Value::String(variant)) => variants.push(Variant {
+                name: variant.to_string(),
+                fields: vec![],
+            }),
+            (Some(variant), Value::Struct(fields)) => {
+                let mut res = Vec::new();
+                for (field, value) in fields.local_elements() {
+                    match value {
+                        Value::Type(t) => res.push((field, t)),
+                        v => return argument.error(format!(
+                            "Expected the field {} of {} to be a type, got a value of type {}",
+                            field, variant, v.value_type()
+                        )),
+                    }
+                }
+                variants.push(Variant {
+                    name: variant.clone(),
+                    fields: res,
+                });
+            }
+            (None, v) => return argument.error(format!(
+                "Expected the name of a variant, got a value of type {}",
+                v.value_type()
+            )),
+            (Some(variant), v) => return argument.error(format!(
+                "Expected the fields of {} to be a struct, got a value of type {}",
+                variant,
+                v.value_type()
+            )),
+        }
+    }
+
+    if variants.is_empty() {
+        return argument_error_legacy("An enum must have at least one variant");
+    }
+
+    context.output.send(Value::Type(ValueType::Enum(Arc::from(EnumType::new(name, variants)?))))
+}
+
+fn constructor() -> &'static Command {
+    static CELL: OnceLock<Command> = OnceLock::new();
+    CELL.get_or_init(|| {
+        <dyn CrushCommand>::command(
+            new_variant,
+            false,
+            ["global", "types", "enum", "new"],
+            "variant @unnamed @@named",
+            "Create a value of this variant of the enum",
+            None,
+            Unknown,
+            [],
+        )
+    })
+}
+
+/**
+Look up a variant of an enum type by name. Variants without fields are values of the enum type,
+variants with fields are returned as a command that constructs a value of the variant.
+ */
+pub fn variant(enum_type: &Arc<EnumType>, name: &str) -> CrushResult<Option<Value>> {
+    Ok(match enum_type.variant(name) {
+        None => None,
+        Some(idx) if enum_type.variants()[idx].fields.is_empty() => {
+            Some(Value::Enum(r#enum::Enum::new(enum_type.clone(), idx, vec![])?))
+        }
+        Some(_) => Some(Value::Command(constructor().bind(Value::Struct(Struct::new(
+            vec![
+                ("type", Value::Type(ValueType::Enum(enum_type.clone()))),
+                ("variant", Value::from(name)),
+            ],
+            None,
+        ))))),
+    })
+}
+
+fn new_variant(mut context: CommandContext) -> CrushResult<()> {
+    let this = context.this.r#struct()?;
+    let (enum_type, idx) = match (this.get("type"), this.get("variant")) {
+        (Some(Value::Type(ValueType::Enum(enum_type))), Some(Value::String(name))) => {
+            let idx = mandate(enum_type.variant(&name), "Unknown variant")?;
+            (enum_type, idx)
+        }
+        _ => return error("Invalid enum variant"),
+    };
+    let variant = &enum_type.variants()[idx];
+
+    let mut named = HashMap::new();
+    let mut unnamed = Vec::new();
+    for argument in context.arguments {
+        match argument.argument_type {
+            Some(name) => {
+                named.insert(name, argument.value);
+            }
+            None => unnamed.push(argument.value),
+        }
+    }
+
+    let mut unnamed = unnamed.into_iter();
+    let mut fields = Vec::new();
+    for (name, _) in &variant.fields {
+        match named.remove(name).or_else(|| unnamed.next()) {
+            Some(value) => fields.push(value),
+            None => return argument_error_legacy(format!("Missing field {}", name)),
+        }
+    }
+    if let Some(name) = named.keys().next() {
+        return argument_error_legacy(format!("Unknown field {}", name));
+    }
+    if unnamed.next().is_some() {
+        return argument_error_legacy("Too many arguments");
+    }
+
+    context.output.send(Value::Enum(r#enum::Enum::new(enum_type, idx, fields)?))
+}
@@ -226,7 +226,7 @@ fn push(mut context: CommandContext) -> CrushResult<()> {
     let mut cfg: Push = Push::parse(context.remove_arguments(), &context.global_state.printer())?;
 
     for el in &cfg.values {
-        if el.value_type() != l.element_type() && l.element_type() != ValueType::Any {
+        if !l.element_type().is(el) {
             return argument_error_legacy(format!("Invalid element type, got {} but expected {}", el.value_type().to_string(), l.element_type().to_string()));
         }
     }
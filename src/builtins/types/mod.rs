@@ -1,7 +1,7 @@
 use crate::lang::argument::column_names;
-use crate::lang::command::CrushCommand;
+use crate::lang::command::{Command, CrushCommand};
 use crate::lang::command::OutputType::{Known, Unknown};
-use crate::lang::errors::{CrushResult, mandate};
+use crate::lang::errors::{argument_error_legacy, CrushResult, mandate};
 use crate::lang::state::argument_vector::ArgumentVector;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::scope::Scope;
@@ -12,10 +12,12 @@ use crate::lang::{data::r#struct::Struct, value::Value};
 use crate::lang::ordered_string_map::OrderedStringMap;
 use signature::signature;
 use crate::lang::state::this::This;
+use crate::lang::data::list::List;
 
 pub mod binary;
 pub mod dict;
 pub mod duration;
+pub mod r#enum;
 pub mod file;
 pub mod float;
 pub mod glob;
@@ -23,11 +25,13 @@ pub mod integer;
 pub mod list;
 pub mod re;
 pub mod scope;
+pub mod set;
 pub mod string;
 pub mod table;
 pub mod table_input_stream;
 pub mod table_output_stream;
 pub mod time;
+pub mod r#type;
 
 #[signature(
     types.materialize,
@@ -92,10 +96,18 @@ fn data(context: CommandContext) -> CrushResult<()> {
     long = "Point:__init__ = {\n        |x:float y:float|\n        this:x = x\n        this:y = y\n    }",
     long = "Point:len = {\n        ||\n        math:sqrt this:x*this:x + this:y*this:y\n    }",
     long = "Point:__add__ = {\n        |other|\n        Point:new x=this:x+other:x y=this:y+other:y\n    }",
-    long = "p := (Point:new x=1.0 y=2.0)\n    p:len"
+    long = "p := (Point:new x=1.0 y=2.0)\n    p:len",
+    long = "A class can inherit from another class. Members are looked up in the instance, then in its class, then in the parent of the class and so on, up to types:root.",
+    long = "Point3 := (class parent=$Point)",
+    long = "Classes can customize their instances by defining these methods:",
+    long = "* __str__ and __repr__ return the string used to show an instance. __repr__ is preferred when the instance is part of another value, like a table.",
+    long = "* __getattr__ is called with the name of a member that doesn't exist.",
+    long = "* __eq__ and __lt__ compare an instance to another instance. They are used by comparison operators, sort and uniq.",
+    long = "* __hash__ returns a value that is the same for instances that are equal, which lets uniq avoid comparing every pair of instances.",
+    long = "Instances of classes are mutable, and can therefore not be used as dict keys or set members.",
 )]
 struct Class {
-    #[description("the type to convert the value to.")]
+    #[description("the class to inherit from.")]
     parent: Option<Struct>,
 }
 
@@ -107,6 +119,74 @@ fn class(context: CommandContext) -> CrushResult<()> {
     context.output.send(Value::Struct(res))
 }
 
+#[signature(
+    types.extend,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Add methods to a builtin type",
+    long = "The methods are available to code running in the current scope, and in scopes created inside of it, like closures. This means that a module can extend builtin types without affecting the code that imports it. Builtin methods take precedence over added ones, so added methods can't replace them.",
+    example = "extend $file mkdir={|| cmd mkdir -p $this}",
+)]
+struct Extend {
+    #[description("the type to add methods to.")]
+    target: ValueType,
+    #[named]
+    #[description("the methods to add.")]
+    methods: OrderedStringMap<Command>,
+}
+
+fn extend(context: CommandContext) -> CrushResult<()> {
+    let cfg: Extend = Extend::parse(context.arguments, &context.global_state.printer())?;
+    for (name, method) in cfg.methods {
+        if cfg.target.fields().contains_key(&name) {
+            return argument_error_legacy(format!("Type {} already has a method named {}", cfg.target, name));
+        }
+        context.scope.extend(cfg.target.clone(), &name, method)?;
+    }
+    context.output.empty()
+}
+
+#[signature(
+    types.member,
+    can_block = false,
+    output = Unknown,
+    short = "Return a member of a value, or of each element of a list",
+    long = "This is the command the `::` operator calls, i.e. `$parent::user` is short for `member $parent user`.",
+    long = "For an edge, the member is the column of the row the edge points to. For a list, the member is looked up in each element, and the results are returned as a new list. The empty value has no members, and gives the empty value.",
+    example = "host:procs | where {$parent::user != $global:user:me:name}",
+)]
+struct Member {
+    #[description("the value to return a member of.")]
+    value: Value,
+    #[description("the name of the member.")]
+    name: String,
+}
+
+fn member(context: CommandContext) -> CrushResult<()> {
+    let cfg: Member = Member::parse(context.arguments, context.global_state.printer())?;
+    context.output.send(member_of(cfg.value, &cfg.name, &context.scope)?)
+}
+
+fn member_of(value: Value, name: &str, scope: &Scope) -> CrushResult<Value> {
+    match value {
+        Value::List(list) => Ok(List::new_without_type(
+            list.iter()
+                .map(|v| member_of(v, name, scope))
+                .collect::<CrushResult<Vec<_>>>()?,
+        ).into()),
+        Value::Edge(edge) => edge.member(name),
+        Value::Empty => Ok(Value::Empty),
+        value => match value.field_with_extensions(name, scope)? {
+            Some(field) => Ok(field.bind(value)),
+            None => argument_error_legacy(format!(
+                "Missing field {} in value of type {}",
+                name,
+                value.value_type()
+            )),
+        },
+    }
+}
+
 pub fn column_types(columns: &OrderedStringMap<ValueType>) -> Vec<ColumnType> {
     columns.iter().map(|(key, value)| ColumnType::new(key, value.clone())).collect()
 }
@@ -209,6 +289,11 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             env.declare("root", Value::Struct(root))?;
             Data::declare(env)?;
             Class::declare(env)?;
+            Extend::declare(env)?;
+            Member::declare(env)?;
+            r#enum::Enum::declare(env)?;
+            r#type::Either::declare(env)?;
+            r#type::Optional::declare(env)?;
             Convert::declare(env)?;
             TypeOf::declare(env)?;
             Materialize::declare(env)?;
@@ -233,11 +318,13 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             env.declare("dict", Value::Type(ValueType::Dict(
                 Box::from(ValueType::Empty),
                 Box::from(ValueType::Empty))))?;
+            env.declare("set", Value::Type(ValueType::Set(Box::from(ValueType::Empty))))?;
 
             env.declare("table", Value::Type(ValueType::Table(vec![])))?;
             env.declare("table_input_stream", Value::Type(ValueType::TableInputStream(vec![])))?;
             env.declare("table_output_stream", Value::Type(ValueType::TableOutputStream(vec![])))?;
             env.declare("struct", Value::Type(ValueType::Struct))?;
+            env.declare("edge", Value::Type(ValueType::Edge))?;
             Ok(())
         }))?;
     root.r#use(&e);
@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::{Known, Unknown};
+use crate::lang::errors::{argument_error_legacy, data_error, mandate, CrushResult};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::value::Value;
+use crate::lang::{data::set::Set, value::ValueType};
+use ordered_map::OrderedMap;
+use signature::signature;
+use crate::data::table::ColumnVec;
+use crate::lang::state::argument_vector::ArgumentVector;
+use crate::lang::state::this::This;
+use crate::util::replace::Replace;
+
+pub fn methods() -> &'static OrderedMap<String, Command> {
+    static CELL: OnceLock<OrderedMap<String, Command>> = OnceLock::new();
+    CELL.get_or_init(|| {
+        let mut res: OrderedMap<String, Command> = OrderedMap::new();
+
+        Call::declare_method(&mut res);
+        New::declare_method(&mut res);
+        Of::declare_method(&mut res);
+        Collect::declare_method(&mut res);
+        Len::declare_method(&mut res);
+        Empty::declare_method(&mut res);
+        Clear::declare_method(&mut res);
+        CloneCmd::declare_method(&mut res);
+        ElementType::declare_method(&mut res);
+        Contains::declare_method(&mut res);
+        Insert::declare_method(&mut res);
+        Remove::declare_method(&mut res);
+        Union::declare_method(&mut res);
+        Intersection::declare_method(&mut res);
+        Difference::declare_method(&mut res);
+        IsSubset::declare_method(&mut res);
+        Add::declare_method(&mut res);
+        And::declare_method(&mut res);
+        Sub::declare_method(&mut res);
+
+        res
+    })
+}
+
+#[signature(
+    types.set.__call__,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "Returns a set type with the specified element type.",
+)]
+struct Call {
+    #[description("the type of the elements in the set.")]
+    element_type: ValueType,
+}
+
+fn __call__(mut context: CommandContext) -> CrushResult<()> {
+    match context.this.r#type()? {
+        ValueType::Set(t) => match *t {
+            ValueType::Empty => {
+                let cfg: Call = Call::parse(context.arguments, context.global_state.printer())?;
+                context.output.send(Value::Type(ValueType::Set(Box::new(cfg.element_type))))
+            }
+            t => {
+                if context.arguments.is_empty() {
+                    context.output.send(Value::Type(ValueType::Set(Box::from(t))))
+                } else {
+                    argument_error_legacy(format!(
+                        "Tried to set subtype on a set that already has the subtype {}",
+                        t
+                    ))
+                }
+            }
+        },
+        _ => argument_error_legacy("Invalid this, expected type set"),
+    }
+}
+
+#[signature(
+    types.set.new,
+    can_block = false,
+    output = Known(ValueType::Set(Box::from(ValueType::Any))),
+    short = "Create an empty set with the specified element type.",
+    example = "$s := $($(set $string):new)",
+)]
+struct New {}
+
+fn new(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    match context.this.r#type()? {
+        ValueType::Set(t) => context.output.send(Set::new(*t)?.into()),
+        _ => argument_error_legacy("Expected this to be a set type"),
+    }
+}
+
+#[signature(
+    types.set.of,
+    can_block = false,
+    output = Known(ValueType::Set(Box::from(ValueType::Any))),
+    short = "Create a new set containing the supplied elements.",
+    long = "If all elements are of the same type, that is the element type of the set, otherwise it is `any`.",
+    example = "set:of web1 web2 db1",
+)]
+struct Of {
+    #[description("the elements of the new set.")]
+    #[unnamed()]
+    values: Vec<Value>,
+}
+
+fn of(context: CommandContext) -> CrushResult<()> {
+    let cfg: Of = Of::parse(context.arguments, context.global_state.printer())?;
+    if cfg.values.is_empty() {
+        return argument_error_legacy("Expected at least one argument");
+    }
+    let types = cfg.values.iter().map(|v| v.value_type()).collect::<HashSet<_>>();
+    let element_type = if types.len() == 1 {
+        cfg.values[0].value_type()
+    } else {
+        ValueType::Any
+    };
+    let res = Set::new(element_type)?;
+    for value in cfg.values {
+        res.insert(value)?;
+    }
+    context.output.send(res.into())
+}
+
+#[signature(
+    types.set.collect,
+    can_block = true,
+    output = Known(ValueType::Set(Box::from(ValueType::Any))),
+    short = "Create a new set by reading a column from the input.",
+    long = "If no column is specified, input must be a stream with exactly one column.",
+    example = "host:procs | set:collect user",
+)]
+struct Collect {
+    #[description("the name of the column to read.")]
+    column: Option<String>,
+}
+
+fn collect(context: CommandContext) -> CrushResult<()> {
+    let cfg: Collect = Collect::parse(context.arguments, context.global_state.printer())?;
+    let mut input = mandate(context.input.recv()?.stream()?, "Expected a stream")?;
+    let input_type = input.types().to_vec();
+    let idx = match (input_type.len(), cfg.column) {
+        (_, Some(name)) => match input_type.as_slice().find(&name) {
+            Ok(idx) => idx,
+            _ => return data_error(format!("Column {} not found", name)),
+        },
+        (1, None) => 0,
+        _ => return data_error("Expected either input with exactly one column or an argument specifying which column to pick"),
+    };
+    let res = Set::new(input_type[idx].cell_type.clone())?;
+    while let Ok(row) = input.read() {
+        res.insert(Vec::from(row).replace(idx, Value::Empty))?;
+    }
+    context.output.send(res.into())
+}
+
+#[signature(
+    types.set.len,
+    can_block = false,
+    output = Known(ValueType::Integer),
+    short = "The number of elements in the set.",
+)]
+struct Len {}
+
+fn len(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    context
+        .output
+        .send(Value::Integer(context.this.set()?.len() as i128))
+}
+
+#[signature(
+    types.set.empty,
+    can_block = false,
+    output = Known(ValueType::Bool),
+    short = "True if there are no elements in the set.",
+)]
+struct Empty {}
+
+fn empty(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    context
+        .output
+        .send(Value::Bool(context.this.set()?.is_empty()))
+}
+
+#[signature(
+    types.set.clear,
+    can_block = false,
+    output = Unknown,
+    short = "Remove all elements from this set.",
+)]
+struct Clear {}
+
+fn clear(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let s = context.this.set()?;
+    s.clear();
+    context.output.send(s.into())
+}
+
+#[signature(
+    types.set.clone,
+    can_block = false,
+    output = Unknown,
+    short = "Create a new set with the same elements as this one.",
+)]
+struct CloneCmd {}
+
+fn clone(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let s = context.this.set()?;
+    context.output.send(s.copy().into())
+}
+
+#[signature(
+    types.set.element_type,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "The type of the elements in this set.",
+)]
+struct ElementType {}
+
+fn element_type(mut context: CommandContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    context
+        .output
+        .send(Value::Type(context.this.set()?.element_type()))
+}
+
+#[signature(
+    types.set.contains,
+    can_block = false,
+    output = Known(ValueType::Bool),
+    short = "True if the specified value is in the set.",
+)]
+struct Contains {
+    #[description("the value to check.")]
+    value: Value,
+}
+
+fn contains(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Contains = Contains::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(Value::Bool(s.contains(&cfg.value)))
+}
+
+#[signature(
+    types.set.insert,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Add the specified values to the set.",
+)]
+struct Insert {
+    #[description("the values to add.")]
+    #[unnamed()]
+    values: Vec<Value>,
+}
+
+fn insert(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Insert = Insert::parse(context.remove_arguments(), context.global_state.printer())?;
+    for value in cfg.values {
+        s.insert(value)?;
+    }
+    context.output.empty()
+}
+
+#[signature(
+    types.set.remove,
+    can_block = false,
+    output = Known(ValueType::Bool),
+    short = "Remove the specified value from the set, and return whether it was in the set.",
+)]
+struct Remove {
+    #[description("the value to remove.")]
+    value: Value,
+}
+
+fn remove(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Remove = Remove::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(Value::Bool(s.remove(&cfg.value)))
+}
+
+#[signature(
+    types.set.union,
+    can_block = false,
+    output = Unknown,
+    short = "A new set with the elements that are in either this set or the other set.",
+    example = "$(set:of a b):union $(set:of b c)",
+)]
+struct Union {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn union(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Union = Union::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.union(&cfg.other)?.into())
+}
+
+#[signature(
+    types.set.intersection,
+    can_block = false,
+    output = Unknown,
+    short = "A new set with the elements that are in both this set and the other set.",
+    example = "$(set:of a b):intersection $(set:of b c)",
+)]
+struct Intersection {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn intersection(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Intersection = Intersection::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.intersection(&cfg.other).into())
+}
+
+#[signature(
+    types.set.difference,
+    can_block = false,
+    output = Unknown,
+    short = "A new set with the elements of this set that are not in the other set.",
+    example = "$(set:of a b):difference $(set:of b c)",
+)]
+struct Difference {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn difference(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Difference = Difference::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.difference(&cfg.other).into())
+}
+
+#[signature(
+    types.set.is_subset,
+    can_block = false,
+    output = Known(ValueType::Bool),
+    short = "True if every element of this set is also in the other set.",
+)]
+struct IsSubset {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn is_subset(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: IsSubset = IsSubset::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(Value::Bool(s.is_subset(&cfg.other)))
+}
+
+#[signature(
+    types.set.__add__,
+    can_block = false,
+    output = Unknown,
+    short = "The union of this set and the other set.",
+)]
+struct Add {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn __add__(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Add = Add::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.union(&cfg.other)?.into())
+}
+
+#[signature(
+    types.set.__and__,
+    can_block = false,
+    output = Unknown,
+    short = "The intersection of this set and the other set.",
+)]
+struct And {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn __and__(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: And = And::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.intersection(&cfg.other).into())
+}
+
+#[signature(
+    types.set.__sub__,
+    can_block = false,
+    output = Unknown,
+    short = "The difference of this set and the other set.",
+)]
+struct Sub {
+    #[description("the other set.")]
+    other: Set,
+}
+
+fn __sub__(mut context: CommandContext) -> CrushResult<()> {
+    let s = context.this.set()?;
+    let cfg: Sub = Sub::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(s.difference(&cfg.other).into())
+}
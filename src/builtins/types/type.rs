@@ -0,0 +1,78 @@
+use std::sync::OnceLock;
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::Known;
+use crate::lang::errors::{argument_error_legacy, CrushResult};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::this::This;
+use crate::lang::value::{Value, ValueType};
+use ordered_map::OrderedMap;
+use signature::signature;
+
+pub fn methods() -> &'static OrderedMap<String, Command> {
+    static CELL: OnceLock<OrderedMap<String, Command>> = OnceLock::new();
+    CELL.get_or_init(|| {
+        let mut res: OrderedMap<String, Command> = OrderedMap::new();
+
+        Add::declare_method(&mut res);
+
+        res
+    })
+}
+
+#[signature(
+    types.r#type.__add__,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "A type that matches values of either this type or the other type.",
+    example = "$limit_type := (integer + empty)",
+)]
+struct Add {
+    #[description("the other type.")]
+    other: ValueType,
+}
+
+fn __add__(mut context: CommandContext) -> CrushResult<()> {
+    let this = context.this.r#type()?;
+    let cfg: Add = Add::parse(context.remove_arguments(), context.global_state.printer())?;
+    context.output.send(Value::Type(ValueType::either(vec![this, cfg.other])))
+}
+
+#[signature(
+    types.either,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "A type that matches values of any of the specified types.",
+    long = "Union types can be used anywhere a type is expected, e.g. as the type of a closure parameter, of a table column or of the elements of a list. The same type can also be written using the `+` operator, e.g. `(integer + float)`.",
+    example = "$number := $(either $integer $float)",
+)]
+pub struct Either {
+    #[unnamed()]
+    #[description("the types to match.")]
+    types: Vec<ValueType>,
+}
+
+fn either(context: CommandContext) -> CrushResult<()> {
+    let cfg: Either = Either::parse(context.arguments, context.global_state.printer())?;
+    if cfg.types.is_empty() {
+        return argument_error_legacy("Expected at least one type");
+    }
+    context.output.send(Value::Type(ValueType::either(cfg.types)))
+}
+
+#[signature(
+    types.optional,
+    can_block = false,
+    output = Known(ValueType::Type),
+    short = "A type that matches values of the specified type, or nothing.",
+    long = "This is the same type as `(the_type + empty)`.",
+    example = "$f := {|$limit: $(optional $integer)| ...}",
+)]
+pub struct Optional {
+    #[description("the type to match when there is a value.")]
+    value_type: ValueType,
+}
+
+fn optional(context: CommandContext) -> CrushResult<()> {
+    let cfg: Optional = Optional::parse(context.arguments, context.global_state.printer())?;
+    context.output.send(Value::Type(ValueType::optional(cfg.value_type)))
+}
@@ -1,9 +1,10 @@
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{BufReader, IsTerminal, Read, Write};
 use std::process;
-use std::process::Stdio;
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Stdio};
+use std::sync::{Arc, Mutex};
 use crate::lang::command::OutputType::Known;
-use crate::lang::command::OutputType::Unknown;
-use crate::lang::errors::{CrushResult, error, mandate};
+use crate::lang::errors::{error, CrushResult, mandate};
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::scope::Scope;
 use crate::lang::data::r#struct::Struct;
@@ -12,11 +13,15 @@ use signature::signature;
 use crate::lang::{data::table::ColumnType, data::table::Row};
 use lazy_static::lazy_static;
 use crate::util::user_map::{get_all_users, get_current_username, get_user};
-use crate::lang::command::{Command, CrushCommand};
-use crate::lang::serialization::{deserialize, serialize};
-use crate::lang::state::this::This;
+use crate::lang::command::Command;
+use crate::lang::interactive::read_password;
+use crate::lang::printer::Printer;
+use crate::lang::serialization::{deserialize_stream, serialize, write_frame};
 use crate::{argument_error_legacy, to_crush_error};
 use crate::util::logins;
+use crate::util::privilege::{Elevation, PASSWORD_PROMPT_MARKER};
+use crossbeam::channel::bounded;
+use ordered_map::OrderedMap;
 
 #[signature(
     user.me,
@@ -75,105 +80,209 @@ lazy_static! {
 
 lazy_static! {
     pub static ref USER: Struct = {
-        let do_cmd = <dyn CrushCommand>::command(
-                r#do,
-                true,
-                &["global", "user"],
-                "do command",
-                "Run specified closure or command as another user",
-                None,
-                Unknown,
-                [],
-            );
-            Struct::new(
-                vec![
-                    ("do", Value::Command(do_cmd)),
-                ],
-                None,
-            )
+        let mut methods = OrderedMap::new();
+        Do::declare_method(&mut methods);
+        Struct::new(
+            vec![
+                ("do", Value::Command(methods["do"].clone())),
+            ],
+            None,
+        )
     };
+
+    static ref WORKERS: Mutex<HashMap<(Elevation, String), Worker>> = Mutex::new(HashMap::new());
 }
 
 #[signature(
     user.r#do,
     can_block = true,
     short = "Execute a lambda as another user.",
+    long = "The lambda is serialized and sent to a crush process running as the other user. This process is kept running, so that subsequent calls do not have to start a new process or ask for a password again.",
+    long = "With the sudo method, crush asks for the password itself, using the line editor. doas and su ask for it on the terminal, and pkexec uses the polkit authentication agent.",
+    example = "user:do user=\"postgres\" method=\"su\" {files /var/lib/postgresql}",
 )]
 pub struct Do {
     #[description("the command to run as another user.")]
     command: Command,
+    #[description("the user to run the command as. Defaults to the user this method is called on, or to root.")]
+    user: Option<String>,
+    #[description("the tool used to switch to the other user.")]
+    #[values("sudo", "doas", "su", "pkexec")]
+    #[default("sudo")]
+    method: String,
 }
 
 /**
-Current implementation is crude and grossly inefficient.
+A crush process running as another user, reading pup-serialized closures from its standard input
+and writing the results to its standard output as pup streams.
+ */
+struct Worker {
+    child: process::Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    stdout: BufReader<ChildStdout>,
+}
 
-Firstly, it just shells out to the sudo command - which leads to potential visual problems with
-the terminal.
+/**
+Forward everything the worker writes to standard error to the printer. If the worker is waiting
+for a password, ask the user for one and pass it on.
+ */
+fn forward_stderr(
+    mut stderr: ChildStderr,
+    stdin: Arc<Mutex<ChildStdin>>,
+    method: Elevation,
+    printer: Printer,
+) {
+    let mut pending = String::new();
+    let mut buf = [0u8; 1024];
+    while let Ok(len) = stderr.read(&mut buf) {
+        if len == 0 {
+            break;
+        }
+        pending.push_str(&String::from_utf8_lossy(&buf[0..len]));
+        let (lines, prompt) = parse_stderr(&mut pending, method);
+        for line in lines {
+            printer.error(&line);
+        }
+        if let Some(prompt) = prompt {
+            let password = match read_password(&prompt) {
+                Ok(p) => p,
+                Err(e) => {
+                    printer.crush_error(e);
+                    String::new()
+                }
+            };
+            let mut stdin = stdin.lock().unwrap();
+            let _ = stdin.write_all(password.as_bytes());
+            let _ = stdin.write_all(b"\n");
+            let _ = stdin.flush();
+        }
+    }
+}
 
-Secondly, it creates 3 separate subthreads just to deal with stdin, stdout and stderr without
-blocking while the main thread waits for the command to exit. It is easy to do this much more
-efficiently, but this was the most straight forward implementation and the sudo command should
-never be run in a loop regardless.
+/**
+Remove all complete, non-empty lines from the pending standard error output of a worker and return
+them. If the method reads the password from standard input and the rest of the output is a password
+prompt, it is removed as well, and the prompt to show the user is returned.
  */
-fn r#do(mut context: CommandContext) -> CrushResult<()> {
-    let cfg: Do = Do::parse(context.remove_arguments(), &context.global_state.printer())?;
-    let this = context.this.r#struct()?;
-    if let Some(Value::String(username)) = this.get("username") {
-        let mut cmd = process::Command::new("sudo");
-        let printer = context.global_state.printer().clone();
+fn parse_stderr(pending: &mut String, method: Elevation) -> (Vec<String>, Option<String>) {
+    let mut lines = Vec::new();
+    while let Some(idx) = pending.find('\n') {
+        let line = pending[0..idx].trim().to_string();
+        pending.drain(0..(idx + 1));
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    let mut prompt = None;
+    if method.reads_password_from_stdin() {
+        if let Some(idx) = pending.find(PASSWORD_PROMPT_MARKER) {
+            prompt = Some(format!(
+                "[{}] password for {}",
+                method,
+                pending[(idx + PASSWORD_PROMPT_MARKER.len())..].trim_start()));
+            pending.clear();
+        }
+    }
+    (lines, prompt)
+}
 
-        cmd.arg("--user").arg(&username.to_string());
-        cmd.arg("--").arg("crush").arg("--pup");
+impl Worker {
+    fn start(method: Elevation, username: &str, context: &CommandContext) -> CrushResult<Worker> {
+        if method.needs_terminal() && !std::io::stdin().is_terminal() {
+            return error(format!(
+                "The {} method needs a terminal to ask for a password, but standard input is not a terminal. Use sudo, doas or pkexec instead.",
+                method));
+        }
+        let binary = to_crush_error(std::env::current_exe())?;
+        let mut cmd = method.command(username, &binary, &["--pup-worker"]);
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
         let mut child = to_crush_error(cmd.spawn())?;
-        let mut stdin = mandate(child.stdin.take(), "Expected stdin stream")?;
-        let mut serialized = Vec::new();
-        serialize(&Value::Command(cfg.command), &mut serialized)?;
+        let stdin = Arc::new(Mutex::new(mandate(child.stdin.take(), "Expected stdin stream")?));
+        let stdout = BufReader::new(mandate(child.stdout.take(), "Expected output stream")?);
+        let stderr = mandate(child.stderr.take(), "Expected error stream")?;
 
-        context.spawn("sudo:stdin", move || {
-            stdin.write(&serialized)?;
-            Ok(())
-        })?;
-
-        let mut stdout = mandate(child.stdout.take(), "Expected output stream")?;
-        let env = context.scope.clone();
-        let my_context = context.clone();
-        context.spawn("sudo:stdout", move || {
-            let mut buff = Vec::new();
-            to_crush_error(stdout.read_to_end(&mut buff))?;
-            if buff.len() == 0 {
-                error("No value returned")
-            } else {
-                my_context
-                    .output
-                    .send(deserialize(&buff, &env)?)
-            }
-        })?;
-
-        let mut stderr = mandate(child.stderr.take(), "Expected error stream")?;
-        context.spawn("sudo:stderr", move || {
-            let mut buff = Vec::new();
-            to_crush_error(stderr.read_to_end(&mut buff))?;
-            let errors = to_crush_error(String::from_utf8(buff))?;
-            for e in errors.split('\n') {
-                let err = e.trim();
-                if !err.is_empty() {
-                    printer.error(err);
-                }
-            }
-            Ok(())
-        })?;
+        // This thread lives as long as the worker does, so it does not belong to the current job.
+        let my_stdin = stdin.clone();
+        let my_printer = context.global_state.printer().clone();
+        context.global_state.threads().spawn(
+            &format!("{}:stderr", method),
+            None,
+            move || {
+                forward_stderr(stderr, my_stdin, method, my_printer);
+                Ok(())
+            })?;
+
+        // The worker starts by sending an empty value. Once that has arrived, any password prompts
+        // are done and the versions of the two binaries are known to match.
+        let (worker_send, worker_recv) = bounded(1);
+        deserialize_stream(
+            Worker { child, stdin, stdout },
+            &context.scope,
+            &context.global_state,
+            move |worker| { let _ = worker_send.send(worker); })?;
+        to_crush_error(worker_recv.recv())
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+/**
+Stop all cached workers. The threads forwarding their standard error exit once the workers are
+gone, so this must be called before joining all threads.
+ */
+pub fn stop_workers() {
+    WORKERS.lock().unwrap().clear();
+}
+
+impl Read for Worker {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
 
-        child.wait()?;
-        Ok(())
-    } else {
-        argument_error_legacy("Invalid user")
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
     }
 }
 
+fn r#do(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Do = Do::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let username = match (cfg.user, &context.this) {
+        (Some(user), _) => user,
+        (None, Some(Value::Struct(this))) => match this.get("username") {
+            Some(Value::String(username)) => username.to_string(),
+            _ => return argument_error_legacy("Invalid user"),
+        },
+        (None, _) => "root".to_string(),
+    };
+    let method = to_crush_error(Elevation::try_from(cfg.method.as_str()))?;
+
+    let mut serialized = Vec::new();
+    serialize(&Value::Command(cfg.command), &mut serialized)?;
+
+    // A worker is removed from the cache while it is in use, and put back once its output has
+    // been read in full. Overlapping calls therefore get separate workers.
+    let key = (method, username);
+    let cached = WORKERS.lock().unwrap().remove(&key);
+    let worker = match cached.and_then(|mut worker| if worker.is_alive() { Some(worker) } else { None }) {
+        Some(worker) => worker,
+        None => Worker::start(method, &key.1, &context)?,
+    };
+    write_frame(&mut *worker.stdin.lock().unwrap(), &serialized)?;
+    context.output.send(deserialize_stream(
+        worker,
+        &context.scope,
+        &context.global_state,
+        move |worker| { WORKERS.lock().unwrap().entry(key).or_insert(worker); })?)
+}
+
 
 #[signature(
     user.list,
@@ -242,8 +351,41 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             Current::declare(user)?;
             List::declare(user)?;
             GetItem::declare(user)?;
+            Do::declare(user)?;
             Ok(())
         }),
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_lines_are_forwarded() {
+        let mut pending = "first\n\n  second  \npart".to_string();
+        let (lines, prompt) = parse_stderr(&mut pending, Elevation::Sudo);
+        assert_eq!(lines, vec!["first", "second"]);
+        assert_eq!(prompt, None);
+        assert_eq!(pending, "part");
+    }
+
+    #[test]
+    fn password_prompt_is_detected() {
+        let mut pending = format!("warning\n{} root: ", PASSWORD_PROMPT_MARKER);
+        let (lines, prompt) = parse_stderr(&mut pending, Elevation::Sudo);
+        assert_eq!(lines, vec!["warning"]);
+        assert_eq!(prompt, Some("[sudo] password for root: ".to_string()));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn password_prompt_is_ignored_for_methods_using_the_terminal() {
+        let mut pending = format!("{} root: ", PASSWORD_PROMPT_MARKER);
+        let (lines, prompt) = parse_stderr(&mut pending, Elevation::Doas);
+        assert!(lines.is_empty());
+        assert_eq!(prompt, None);
+        assert_eq!(pending, format!("{} root: ", PASSWORD_PROMPT_MARKER));
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+use crossbeam::channel::unbounded;
+use crate::builtins::{declare, stop_workers};
+use crate::lang::errors::{error, to_crush_error, CrushErrorType, CrushResult};
+use crate::lang::pipe::{empty_channel, pipe};
+use crate::lang::printer;
+use crate::lang::printer::{Printer, PrinterOutput};
+use crate::lang::state::contexts::JobContext;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::{Scope, ScopeLoader};
+use crate::lang::value::Value;
+
+/**
+A Crush interpreter that can be embedded in another program.
+
+Code is run with all builtins available, and the output of the code is returned as a value
+instead of being printed. Anything else the code prints, e.g. using `echo` or errors in commands
+that are not the last command of a job, goes to the printer of the interpreter.
+
+Commands implemented in Rust can be added using `create_namespace`. Since the code generated by
+the `signature` macro refers to `crate::lang` and `crate::ordered_map`, programs that use the
+macro need `use crush::{lang, ordered_map};` in the root of the crate.
+ */
+pub struct Interpreter {
+    root: Scope,
+    env: Scope,
+    global_state: GlobalState,
+}
+
+impl Interpreter {
+    /**
+    Create an interpreter that prints to standard output and standard error.
+     */
+    pub fn new() -> CrushResult<Interpreter> {
+        let (printer, _) = printer::init();
+        Interpreter::with_printer(printer)
+    }
+
+    /**
+    Create an interpreter that passes everything it prints to the callback.
+     */
+    pub fn with_output(callback: impl FnMut(PrinterOutput) + Send + 'static) -> CrushResult<Interpreter> {
+        let (printer, _) = printer::with_callback(callback);
+        Interpreter::with_printer(printer)
+    }
+
+    fn with_printer(printer: Printer) -> CrushResult<Interpreter> {
+        let root = Scope::create_root();
+        let env = root.create_child(&root, false);
+        let global_state = GlobalState::new(printer)?;
+        declare(&root)?;
+        Ok(Interpreter {
+            root,
+            env,
+            global_state,
+        })
+    }
+
+    /**
+    The scope code is run in. Variables declared by the code end up here, and the host program
+    can declare its own variables here.
+     */
+    pub fn scope(&self) -> &Scope {
+        &self.env
+    }
+
+    pub fn global_state(&self) -> &GlobalState {
+        &self.global_state
+    }
+
+    /**
+    Add a namespace to `global:plugins`, which is used by the root scope. The loader is called to declare the members of
+    the namespace the first time it is used, e.g. `MyCommand::declare(env)` for a command defined
+    using the `signature` macro. Builtin namespaces take precedence over namespaces added this
+    way, so the name should not be used by a builtin namespace.
+     */
+    pub fn create_namespace(
+        &self,
+        name: &str,
+        description: impl Into<String>,
+        loader: impl FnOnce(&mut ScopeLoader) -> CrushResult<()> + Send + 'static,
+    ) -> CrushResult<Scope> {
+        match self.root.get_local("plugins")? {
+            Some(Value::Scope(plugins)) => plugins.create_namespace(name, description, Box::new(loader)),
+            _ => error("Missing plugins scope"),
+        }
+    }
+
+    /**
+    Run the code, and return the output of its last job. Table streams are materialized into
+    tables. If a job fails, its errors are returned and the remaining jobs are not run.
+     */
+    pub fn eval(&self, source: &str) -> CrushResult<Value> {
+        // Errors are collected instead of printed, so that they can be returned to the caller
+        let (error_snd, error_recv) = unbounded();
+        let printer = self.global_state.printer().clone();
+        let (eval_printer, _) = printer::with_callback(move |output| match output {
+            PrinterOutput::Line(line) => printer.line(&line),
+            PrinterOutput::Error(e) => { let _ = error_snd.send(e); }
+        });
+        let eval_state = self.global_state.with_printer(eval_printer);
+
+        let jobs = eval_state.parser().parse(source, &self.env)?;
+        let mut res = Value::Empty;
+        for job in jobs {
+            let (sender, receiver) = pipe();
+            let handle = job.eval(JobContext::new(
+                empty_channel(),
+                sender,
+                self.env.clone(),
+                eval_state.clone(),
+            ))?;
+            // The output must be read before waiting for the job, which may block until it is
+            let output = receiver.recv().and_then(|value| value.materialize());
+            if let Some(id) = handle {
+                match eval_state.threads().wait_for(id) {
+                    Err(e) if !e.is(CrushErrorType::SendError) => return Err(e),
+                    _ => {}
+                }
+            }
+            // Commands that do not block are run directly, and report their errors to the printer
+            eval_state.printer().ping();
+            let errors = error_recv.try_iter().collect::<Vec<_>>();
+            if !errors.is_empty() {
+                return error(errors.join("\n"));
+            }
+            res = output.unwrap_or(Value::Empty);
+        }
+        // Make sure everything printed while running the code has reached the printer
+        self.global_state.printer().ping();
+        Ok(res)
+    }
+
+    /**
+    Run the code in the file, like `eval`.
+     */
+    pub fn eval_file(&self, file: &Path) -> CrushResult<Value> {
+        self.eval(&to_crush_error(std::fs::read_to_string(file))?)
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        stop_workers();
+        self.global_state.threads().join(self.global_state.printer());
+        self.global_state.printer().ping();
+        let _ = self.root.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use signature::signature;
+    use crate::lang::state::contexts::CommandContext;
+
+    #[signature(ops.double, can_block = false, short = "Double a number.")]
+    pub struct Double {
+        number: i128,
+    }
+
+    fn double(context: CommandContext) -> CrushResult<()> {
+        let cfg: Double = Double::parse(context.arguments, &context.global_state.printer())?;
+        context.output.send(Value::Integer(cfg.number * 2))
+    }
+
+    #[signature(ops.fail, can_block = false, short = "Always fail.")]
+    pub struct Fail {}
+
+    fn fail(_context: CommandContext) -> CrushResult<()> {
+        error("Failed on purpose")
+    }
+
+    #[test]
+    fn eval_returns_output_of_last_job() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        assert!(matches!(interpreter.eval("$a := 4\n(a + 3)").unwrap(), Value::Integer(7)));
+        assert!(matches!(interpreter.eval("(a * 2)").unwrap(), Value::Integer(8)));
+    }
+
+    #[test]
+    fn eval_returns_errors() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        assert!(interpreter.eval("no_such_command").is_err());
+    }
+
+    #[test]
+    fn eval_returns_errors_of_non_blocking_commands() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let captured = lines.clone();
+        let interpreter = Interpreter::with_output(move |output| captured.lock().unwrap().push(output)).unwrap();
+        interpreter.create_namespace("ops", "Commands of the host program.", |env| {
+            Fail::declare(env)?;
+            Ok(())
+        }).unwrap();
+        assert!(interpreter.eval("ops:fail").is_err());
+        assert!(interpreter.eval("ops:fail\necho \"not run\"").is_err());
+        assert!(lines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn host_commands_can_be_called() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        interpreter.create_namespace("ops", "Commands of the host program.", |env| {
+            Double::declare(env)?;
+            Ok(())
+        }).unwrap();
+        assert!(matches!(interpreter.eval("ops:double 21").unwrap(), Value::Integer(42)));
+    }
+
+    #[test]
+    fn printed_output_is_captured() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let captured = lines.clone();
+        let interpreter = Interpreter::with_output(move |output| captured.lock().unwrap().push(output)).unwrap();
+        interpreter.eval("echo \"hello\"").unwrap();
+        assert_eq!(*lines.lock().unwrap(), vec![PrinterOutput::Line("hello".to_string())]);
+    }
+}
@@ -0,0 +1,634 @@
+/**
+A formatter that turns Crush source code into a canonical form.
+
+The source is parsed into an abstract syntax tree, which is then printed back out. The parser
+desugars operators into method calls, e.g. `(a + b)` becomes `a:__add__ b`, so the formatter uses
+the source locations of the nodes to tell such calls apart from ones actually written by the user.
+Comments are not part of the syntax tree, so they are read separately using the lexer and put
+back in before the job they precede, or at the end of the line they end.
+ */
+use crate::lang::argument::SwitchStyle;
+use crate::lang::ast::lexer::Lexer;
+use crate::lang::ast::node::Node;
+use crate::lang::ast::parameter_node::ParameterNode;
+use crate::lang::ast::token::Token;
+use crate::lang::ast::tracked_string::TrackedString;
+use crate::lang::ast::{CommandNode, JobListNode, JobNode};
+use crate::lang::errors::{error, to_crush_error, CrushResult};
+use crate::lang::parser::Parser;
+use crate::lang::state::global_state::GlobalState;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Pipelines and closures longer than this are broken into multiple lines.
+pub const MAX_WIDTH: usize = 80;
+const INDENT: &str = "    ";
+
+/**
+Format the specified Crush source code.
+ */
+pub fn format(source: &str, parser: &Parser) -> CrushResult<String> {
+    let ast = parser.ast(source)?;
+    let mut formatter = Formatter::new(source);
+    Ok(formatter.job_list(&ast, 0, source.len()))
+}
+
+/**
+What `crush --fmt` does with the formatted source.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FormatAction {
+    /// Print the formatted source to standard output.
+    Print,
+    /// Print the names of all files that are not already formatted.
+    List,
+    /// Rewrite the files in place.
+    Write,
+}
+
+/**
+Format the specified files, or standard input if no files are given. Returns false if any file
+could not be parsed, or in list mode, if any file is not already formatted.
+ */
+pub fn format_files(files: &[PathBuf], action: FormatAction, global_state: &GlobalState) -> CrushResult<bool> {
+    let printer = global_state.printer();
+    if files.is_empty() {
+        if action == FormatAction::Write {
+            return error("Can't rewrite standard input in place");
+        }
+        let mut source = String::new();
+        to_crush_error(std::io::stdin().read_to_string(&mut source))?;
+        let formatted = format(&source, global_state.parser())?;
+        return Ok(match action {
+            FormatAction::List => formatted == source,
+            _ => {
+                printer.line(formatted.trim_end_matches('\n'));
+                true
+            }
+        });
+    }
+
+    let mut ok = true;
+    for file in files {
+        let source = to_crush_error(std::fs::read_to_string(file))?;
+        let formatted = match format(&source, global_state.parser()) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                printer.error(&format!("{}: {}", file.display(), e.message()));
+                ok = false;
+                continue;
+            }
+        };
+        match action {
+            FormatAction::Print => printer.line(formatted.trim_end_matches('\n')),
+            FormatAction::List => if formatted != source {
+                printer.line(&file.display().to_string());
+                ok = false;
+            },
+            FormatAction::Write => if formatted != source {
+                to_crush_error(std::fs::write(file, formatted))?;
+            },
+        }
+    }
+    Ok(ok)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Command,
+    Expression,
+}
+
+/**
+What a command in the syntax tree was written as.
+ */
+enum Shape<'n> {
+    /// An operator, e.g. `a + b`, that the parser turned into a method or function call.
+    Binary(&'n str, &'n Node, &'n Node),
+    /// A unary operator, e.g. `-a`.
+    Unary(&'n str, &'n Node),
+    /// A plain value in expression mode, e.g. `(a)`, that the parser turned into a call to `val`.
+    Value(&'n Node),
+    /// An actual command invocation.
+    Call,
+}
+
+fn precedence(op: &str) -> u8 {
+    match op {
+        "and" | "or" | "==" => 2,
+        "&" => 4,
+        "+" | "-" => 5,
+        "*" | "/" => 6,
+        "::" => ITEM_PRECEDENCE,
+        _ => 3,
+    }
+}
+
+const ITEM_PRECEDENCE: u8 = 8;
+const UNARY_PRECEDENCE: u8 = 7;
+
+fn indent(level: usize) -> String {
+    INDENT.repeat(level)
+}
+
+fn fits(text: &str, level: usize) -> bool {
+    text.lines()
+        .enumerate()
+        .all(|(idx, line)| line.chars().count() + if idx == 0 { level * INDENT.len() } else { 0 } <= MAX_WIDTH)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<TrackedString>,
+    /// The index of the first comment that has not yet been printed.
+    next_comment: usize,
+    job_starts: Vec<usize>,
+    job_ends: Vec<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Formatter<'a> {
+        let mut comments = Vec::new();
+        let mut job_starts = Vec::new();
+        let mut job_ends = Vec::new();
+        for token in Lexer::with_comments(source).flatten() {
+            match token.1 {
+                Token::Comment(s, l) => comments.push(TrackedString::new(s.trim_end(), l)),
+                Token::JobStart(l) => job_starts.push(l.start),
+                Token::JobEnd(l) => job_ends.push(l.start),
+                _ => {}
+            }
+        }
+        Formatter {
+            source,
+            comments,
+            next_comment: 0,
+            job_starts,
+            job_ends,
+        }
+    }
+
+    /**
+    True if the string does not appear in the source at its location, i.e. it was inserted by
+    the parser.
+     */
+    fn synthetic(&self, s: &TrackedString) -> bool {
+        self.source.get(s.location.start..s.location.end) != Some(s.string.as_str())
+    }
+
+    fn has_blank_line(&self, from: usize, to: usize) -> bool {
+        let between: Vec<&str> = self.source[from..to].split('\n').collect();
+        between.len() > 2 && between[1..between.len() - 1].iter().any(|line| line.trim().is_empty())
+    }
+
+    fn has_comment(&self, from: usize, to: usize) -> bool {
+        self.comments[self.next_comment..].iter()
+            .any(|c| c.location.start >= from && c.location.start < to)
+    }
+
+    /**
+    Print all remaining comments that start before the specified offset on lines of their own,
+    keeping a single blank line wherever the source had one or more.
+     */
+    fn comments(&mut self, before: usize, level: usize, previous: &mut Option<usize>, out: &mut String) {
+        while self.next_comment < self.comments.len() && self.comments[self.next_comment].location.start < before {
+            let comment = &self.comments[self.next_comment];
+            if let Some(previous) = previous {
+                if self.has_blank_line(*previous, comment.location.start) {
+                    out.push('\n');
+                }
+            }
+            out.push_str(&indent(level));
+            out.push_str(&comment.string);
+            out.push('\n');
+            *previous = Some(comment.location.end);
+            self.next_comment += 1;
+        }
+    }
+
+    /**
+    If the next comment is on the same line as the specified offset, return it.
+     */
+    fn trailing_comment(&mut self, after: usize) -> Option<String> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.location.start >= after && !self.source[after..comment.location.start].contains('\n') {
+            self.next_comment += 1;
+            Some(comment.string.clone())
+        } else {
+            None
+        }
+    }
+
+    fn job_list(&mut self, jobs: &JobListNode, level: usize, end: usize) -> String {
+        let mut res = String::new();
+        let mut previous = None;
+        for job in &jobs.jobs {
+            self.comments(job.location.start, level, &mut previous, &mut res);
+            if let Some(previous) = previous {
+                if self.has_blank_line(previous, job.location.start) {
+                    res.push('\n');
+                }
+            }
+            res.push_str(&indent(level));
+            res.push_str(&self.job(job, level));
+            if let Some(comment) = self.trailing_comment(job.location.end) {
+                res.push(' ');
+                res.push_str(&comment);
+            }
+            res.push('\n');
+            previous = Some(job.location.end);
+        }
+        // Comments after the last job, and any comments inside of jobs that were printed on a
+        // single line
+        self.comments(end, level, &mut previous, &mut res);
+        res
+    }
+
+    /**
+    Format a job, first trying to fit it on a single line, then breaking the pipeline into one
+    command per line, and finally also expanding any closures into multiple lines.
+     */
+    fn job(&mut self, job: &JobNode, level: usize) -> String {
+        let commented = job.commands.windows(2)
+            .any(|pair| self.has_comment(pair[0].location.end, pair[1].location.start));
+        let start = self.next_comment;
+        if !commented {
+            let res = self.pipeline(job, level, false, false);
+            if fits(&res, level) {
+                return res;
+            }
+            self.next_comment = start;
+        }
+        if job.commands.len() > 1 {
+            let res = self.pipeline(job, level, true, false);
+            if fits(&res, level) {
+                return res;
+            }
+            self.next_comment = start;
+        }
+        self.pipeline(job, level, job.commands.len() > 1, true)
+    }
+
+    fn pipeline(&mut self, job: &JobNode, level: usize, broken: bool, expand: bool) -> String {
+        let mut res = String::new();
+        for (idx, command) in job.commands.iter().enumerate() {
+            let command_level = if broken && idx > 0 { level + 1 } else { level };
+            if idx > 0 {
+                if broken {
+                    res.push_str(" |");
+                    if let Some(comment) = self.trailing_comment(job.commands[idx - 1].location.end) {
+                        res.push(' ');
+                        res.push_str(&comment);
+                    }
+                    res.push('\n');
+                    self.comments(command.location.start, command_level, &mut None, &mut res);
+                    res.push_str(&indent(command_level));
+                } else {
+                    res.push_str(" | ");
+                }
+            }
+            res.push_str(&self.command(command, command_level, expand));
+        }
+        res
+    }
+
+    fn command(&mut self, command: &CommandNode, level: usize, expand: bool) -> String {
+        match command.expressions.as_slice() {
+            [Node::Assignment(target, style, op, value)] if !self.is_switch(style, value) => format!(
+                "{} {} {}",
+                self.node(target, Mode::Command, level, expand),
+                op,
+                self.node(value, Mode::Command, level, expand)),
+            expressions => expressions.iter()
+                .map(|e| self.node(e, Mode::Command, level, expand))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    /**
+    True for the `true` value the parser inserts for switches like `--force`.
+     */
+    fn is_switch(&self, style: &SwitchStyle, value: &Node) -> bool {
+        match (style, value) {
+            (SwitchStyle::None, _) => false,
+            (_, Node::Identifier(s)) => s.string == "true" && self.synthetic(s),
+            _ => false,
+        }
+    }
+
+    fn shape<'n>(&self, command: &'n CommandNode) -> Shape<'n> where 'a: 'n {
+        match command.expressions.as_slice() {
+            [Node::GetAttr(parent, name), rest @ ..] if self.synthetic(name) => {
+                let op = self.source.get(name.location.start..name.location.end).unwrap_or("");
+                match rest {
+                    [value] if name.string == "val" => Shape::Value(value),
+                    [] => Shape::Unary(op, parent),
+                    [right] => Shape::Binary(op, parent, right),
+                    [left, right] => Shape::Binary(op, left, right),
+                    _ => Shape::Call,
+                }
+            }
+            _ => Shape::Call,
+        }
+    }
+
+    fn is_command_substitution(&self, job: &JobNode) -> bool {
+        self.source[..job.location.start].trim_end().ends_with("$(")
+    }
+
+    /**
+    The precedence of the operator a node was written as, or that of an item if it wasn't an
+    operator. In command mode, arithmetic operators are always wrapped in parenthesis.
+     */
+    fn precedence(&self, node: &Node, mode: Mode) -> u8 {
+        match node {
+            Node::Assignment(_, _, _, _) => 1,
+            Node::Unary(_, _) => UNARY_PRECEDENCE,
+            Node::Substitution(job) if job.commands.len() == 1 => {
+                let p = match self.shape(&job.commands[0]) {
+                    Shape::Binary(op, _, _) => precedence(op),
+                    Shape::Unary(_, _) => UNARY_PRECEDENCE,
+                    Shape::Value(_) | Shape::Call => ITEM_PRECEDENCE,
+                };
+                if mode == Mode::Command && p > 3 { ITEM_PRECEDENCE } else { p }
+            }
+            _ => ITEM_PRECEDENCE,
+        }
+    }
+
+    /**
+    Format the operand of an operator, adding parenthesis if needed.
+     */
+    fn operand(&mut self, node: &Node, mode: Mode, min: u8, level: usize, expand: bool) -> String {
+        if self.precedence(node, mode) < min {
+            format!("({})", self.node(node, Mode::Expression, level, expand))
+        } else {
+            self.node(node, mode, level, expand)
+        }
+    }
+
+    fn node(&mut self, node: &Node, mode: Mode, level: usize, expand: bool) -> String {
+        match node {
+            Node::Assignment(target, style, op, value) => {
+                if self.is_switch(style, value) {
+                    let dashes = if let SwitchStyle::Double = style { "--" } else { "-" };
+                    format!("{}{}", dashes, self.node(target, mode, level, expand))
+                } else if mode == Mode::Expression {
+                    format!(
+                        "{} {} {}",
+                        self.node(target, mode, level, expand),
+                        op,
+                        self.node(value, mode, level, expand))
+                } else {
+                    format!(
+                        "{}{}{}",
+                        self.node(target, mode, level, expand),
+                        op,
+                        self.node(value, mode, level, expand))
+                }
+            }
+            Node::Unary(op, value) =>
+                format!("{}{}", op.string, self.operand(value, mode, UNARY_PRECEDENCE, level, expand)),
+            Node::Identifier(s) => match mode {
+                Mode::Command => format!("${}", s.string),
+                Mode::Expression => s.string.clone(),
+            },
+            Node::String(s, false) if mode == Mode::Expression => format!("\"{}\"", s.string),
+            Node::File(s, false) if mode == Mode::Expression => format!("'{}'", s.string),
+            Node::Glob(s) | Node::String(s, _) | Node::File(s, _) | Node::Integer(s) | Node::Float(s) =>
+                s.string.clone(),
+            Node::Regex(s) => format!("re\"{}\"", s.string),
+            Node::GetItem(container, key) => format!(
+                "{}[{}]",
+                self.operand(container, mode, ITEM_PRECEDENCE, level, expand),
+                self.node(key, Mode::Command, level, expand)),
+            Node::GetAttr(parent, name) => format!(
+                "{}{}{}",
+                self.operand(parent, mode, ITEM_PRECEDENCE, level, expand),
+                if mode == Mode::Command { ":" } else { "." },
+                name.string),
+            Node::Substitution(job) => self.substitution(job, mode, level, expand),
+            Node::Closure(signature, body) => self.closure(signature, body, level, expand),
+        }
+    }
+
+    fn substitution(&mut self, job: &JobNode, mode: Mode, level: usize, expand: bool) -> String {
+        if job.commands.len() == 1 {
+            let res = match self.shape(&job.commands[0]) {
+                Shape::Binary("::", parent, Node::String(name, _)) => {
+                    return format!(
+                        "{}::{}",
+                        self.operand(parent, mode, ITEM_PRECEDENCE, level, expand),
+                        name.string);
+                }
+                Shape::Binary(op, left, right) => {
+                    let p = precedence(op);
+                    if mode == Mode::Command && p <= 3 {
+                        // Comparisons and logical operators don't need parenthesis in command mode
+                        let right_min = if p == 3 { UNARY_PRECEDENCE } else { p + 1 };
+                        return format!(
+                            "{} {} {}",
+                            self.operand(left, mode, p, level, expand),
+                            op,
+                            self.operand(right, mode, right_min, level, expand));
+                    }
+                    format!(
+                        "{} {} {}",
+                        self.operand(left, Mode::Expression, p, level, expand),
+                        op,
+                        self.operand(right, Mode::Expression, p + 1, level, expand))
+                }
+                Shape::Unary(op, value) =>
+                    format!("{}{}", op, self.operand(value, Mode::Expression, UNARY_PRECEDENCE, level, expand)),
+                Shape::Value(value) => self.node(value, Mode::Expression, level, expand),
+                Shape::Call => {
+                    if self.is_command_substitution(job) {
+                        return format!("$({})", self.job(job, level));
+                    }
+                    self.call(&job.commands[0], level, expand)
+                }
+            };
+            match mode {
+                Mode::Command => format!("({})", res),
+                Mode::Expression => res,
+            }
+        } else if self.is_command_substitution(job) {
+            format!("$({})", self.job(job, level))
+        } else {
+            let res = job.commands.iter()
+                .map(|c| match self.shape(c) {
+                    Shape::Call => self.call(c, level, expand),
+                    _ => self.substitution(
+                        &JobNode { commands: vec![c.clone()], location: c.location },
+                        Mode::Expression,
+                        level,
+                        expand),
+                })
+                .collect::<Vec<_>>()
+                .join(" | ");
+            match mode {
+                Mode::Command => format!("({})", res),
+                Mode::Expression => res,
+            }
+        }
+    }
+
+    /**
+    Format a command invocation in expression mode, e.g. `dict:new(key=$string, value=$any)`.
+     */
+    fn call(&mut self, command: &CommandNode, level: usize, expand: bool) -> String {
+        let arguments = command.expressions[1..].iter()
+            .map(|e| match e {
+                Node::Assignment(target, _, op, value) => format!(
+                    "{}{}{}",
+                    self.node(target, Mode::Expression, level, expand),
+                    op,
+                    self.node(value, Mode::Expression, level, expand)),
+                _ => self.node(e, Mode::Expression, level, expand),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{}({})",
+            self.operand(&command.expressions[0], Mode::Expression, ITEM_PRECEDENCE, level, expand),
+            arguments)
+    }
+
+    fn parameter(&mut self, parameter: &ParameterNode, level: usize) -> String {
+        match parameter {
+            ParameterNode::Parameter(name, parameter_type, default) => {
+                let mut res = format!("${}", name.string);
+                if let Some(t) = parameter_type {
+                    res.push(':');
+                    res.push_str(&self.node(t, Mode::Command, level, false));
+                }
+                if let Some(d) = default {
+                    res.push('=');
+                    res.push_str(&self.node(d, Mode::Command, level, false));
+                }
+                res
+            }
+            ParameterNode::Named(name) => format!("@@{}", name.string),
+            ParameterNode::Unnamed(name) => format!("@{}", name.string),
+        }
+    }
+
+    /**
+    Format a closure. Closures with a single job and no comments are kept on a single line,
+    unless the job they are part of is too long. Otherwise the signature and each job go on lines
+    of their own.
+     */
+    fn closure(
+        &mut self,
+        signature: &Option<Vec<ParameterNode>>,
+        body: &JobListNode,
+        level: usize,
+        expand: bool,
+    ) -> String {
+        let first = signature.iter().flatten()
+            .map(|p| match p {
+                ParameterNode::Parameter(n, _, _) | ParameterNode::Named(n) | ParameterNode::Unnamed(n) =>
+                    n.location.start,
+            })
+            .next()
+            .unwrap_or(body.location.start);
+        let start = self.job_starts.iter().rev().find(|s| **s < first).copied().unwrap_or(0);
+        let end = self.job_ends.iter().find(|e| **e >= body.location.end).copied().unwrap_or(self.source.len());
+
+        let signature = signature.as_ref().map(|parameters| {
+            format!("|{}|", parameters.iter()
+                .map(|p| self.parameter(p, level))
+                .collect::<Vec<_>>()
+                .join(" "))
+        });
+
+        if !expand && body.jobs.len() <= 1 && !self.has_comment(start, end) {
+            let start_comment = self.next_comment;
+            let job = body.jobs.first().map(|j| self.job(j, level)).unwrap_or_default();
+            if !job.contains('\n') {
+                return match (signature, job.is_empty()) {
+                    (None, _) => format!("{{{}}}", job),
+                    (Some(s), true) => format!("{{{}}}", s),
+                    (Some(s), false) => format!("{{{} {}}}", s, job),
+                };
+            }
+            self.next_comment = start_comment;
+        }
+
+        let mut res = "{\n".to_string();
+        if let Some(s) = signature {
+            res.push_str(&indent(level + 1));
+            res.push_str(&s);
+            res.push('\n');
+        }
+        res.push_str(&self.job_list(body, level + 1, end));
+        res.push_str(&indent(level));
+        res.push('}');
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(s: &str) -> String {
+        format(s, &Parser::new()).unwrap()
+    }
+
+    #[test]
+    fn normalizes_spacing() {
+        assert_eq!(fmt("echo   1    2;echo 3\n"), "echo 1 2\necho 3\n");
+        assert_eq!(fmt("$a:=1\n$a   =   2\n"), "$a := 1\n$a = 2\n");
+        assert_eq!(fmt("ls|where {$size>1000}\n"), "ls | where {$size > 1000}\n");
+    }
+
+    #[test]
+    fn keeps_operators() {
+        assert_eq!(fmt("$a := (1+2*3)\n"), "$a := (1 + 2 * 3)\n");
+        assert_eq!(fmt("$a := ((1+2)*3)\n"), "$a := ((1 + 2) * 3)\n");
+        assert_eq!(fmt("$a := (-x.len())\n"), "$a := (-x.len())\n");
+        assert_eq!(fmt("$b := $(list:of 1 2)\n"), "$b := $(list:of 1 2)\n");
+        assert_eq!(fmt("$p := (Point.new(x=1.0,y=2))\n"), "$p := (Point.new(x=1.0, y=2))\n");
+        assert_eq!(fmt("find . --recursive -v\n"), "find . --recursive -v\n");
+        assert_eq!(fmt("where {$parent::user != $user}\n"), "where {$parent::user != $user}\n");
+        assert_eq!(fmt("$a := (children::pid.len())\n"), "$a := (children::pid.len())\n");
+        assert_eq!(fmt("$t := (ls|materialize)\n"), "$t := (ls | materialize)\n");
+        assert_eq!(fmt("$c := ((a&b)&c)\n"), "$c := (a & b & c)\n");
+        assert_eq!(fmt("$c := (a&(b - c))\n"), "$c := (a & b - c)\n");
+        assert_eq!(fmt("$c := ((a&b) - c)\n"), "$c := ((a & b) - c)\n");
+    }
+
+    #[test]
+    fn keeps_comments() {
+        assert_eq!(
+            fmt("# First\necho 1 # One\n\n\n# Second\necho 2\n"),
+            "# First\necho 1 # One\n\n# Second\necho 2\n");
+        assert_eq!(
+            fmt("ls | # Files\n where {$size > 1000}\n"),
+            "ls | # Files\n    where {$size > 1000}\n");
+    }
+
+    #[test]
+    fn indents_closures() {
+        assert_eq!(
+            fmt("$f := {|$a $b|\necho $a\n  echo $b}\n"),
+            "$f := {\n    |$a $b|\n    echo $a\n    echo $b\n}\n");
+        assert_eq!(fmt("$f := {  |$a|   echo $a  }\n"), "$f := {|$a| echo $a}\n");
+    }
+
+    #[test]
+    fn breaks_long_pipelines() {
+        assert_eq!(
+            fmt("files --recurse | where {$type == \"file\"} | sort size | head 10 | select name size\n"),
+            "files --recurse |\n    where {$type == \"file\"} |\n    sort size |\n    head 10 |\n    select name size\n");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let source = "# Test\n$Point := $(class)\n$Point:len = {\n    ||\n    (math.sqrt(this.x*this.x + this.y*this.y))\n}\n";
+        let formatted = fmt(source);
+        assert_eq!(fmt(&formatted), formatted);
+    }
+}
@@ -11,6 +11,7 @@ enum LexerMode {
 
 pub struct Lexer<'input> {
     mode: Vec<LexerMode>,
+    comments: bool,
     full_str: &'input str,
     chars: Peekable<CharIndices<'input>>,
 }
@@ -21,11 +22,43 @@ impl<'input> Lexer<'input> {
     pub fn new(input: &'input str) -> Self {
         Lexer {
             mode: vec![LexerMode::Command],
+            comments: false,
             full_str: input,
             chars: input.char_indices().peekable(),
         }
     }
 
+    /**
+    Create a lexer that also emits comments as tokens. The parser does not accept comment tokens,
+    but tools like the formatter need to know where they are.
+     */
+    pub fn with_comments(input: &'input str) -> Self {
+        Lexer {
+            comments: true,
+            ..Lexer::new(input)
+        }
+    }
+
+    /**
+    Skip the remainder of a comment starting at the specified offset. The newline ending the
+    comment is left alone, since it also ends the current job.
+     */
+    fn comment(&mut self, start: usize) -> Option<Spanned<'input>> {
+        let mut end_idx = start + 1;
+        while let Some((i, ch)) = self.chars.peek() {
+            if *ch == '\n' {
+                break;
+            }
+            end_idx = i + ch.len_utf8();
+            self.chars.next();
+        }
+        if self.comments {
+            Some(Token::Comment(&self.full_str[start..end_idx], Location::new(start, end_idx)).into())
+        } else {
+            None
+        }
+    }
+
     fn next_command(&mut self) -> Option<Spanned<'input>> {
         loop {
             let cc = self.chars.next();
@@ -39,6 +72,10 @@ impl<'input> Lexer<'input> {
                             self.chars.next();
                             return Some(Token::Declare(Location::new(i, i + 2)).into());
                         }
+                        Some((_, ':')) => {
+                            self.chars.next();
+                            return Some(Token::MemberBroadcastOperator(Location::new(i, i + 2)).into());
+                        }
                         _ => return Some(Token::MemberOperator(Location::from(i)).into()),
                     }
                 }
@@ -120,13 +157,9 @@ impl<'input> Lexer<'input> {
                     }
                 }
 
-                Some((_, '#')) => {
-                    loop {
-                        let cc2 = self.chars.next();
-                        match cc2 {
-                            Some((_, '\n')) | None => break,
-                            Some((_, _)) => {}
-                        }
+                Some((i, '#')) => {
+                    if let Some(comment) = self.comment(i) {
+                        return Some(comment);
                     }
                 }
 
@@ -304,6 +337,10 @@ impl<'input> Lexer<'input> {
                             self.chars.next();
                             return Some(Token::Declare(Location::new(i, i + 2)).into());
                         }
+                        Some((_, ':')) => {
+                            self.chars.next();
+                            return Some(Token::MemberBroadcastOperator(Location::new(i, i + 2)).into());
+                        }
                         _ => return Some(Token::MemberOperator(Location::from(i)).into()),
                     }
                 }
@@ -389,6 +426,7 @@ impl<'input> Lexer<'input> {
                 Some((i, '-')) => return Some(Token::Minus(Location::from(i)).into()),
                 Some((i, '*')) => return Some(Token::Star(Location::from(i)).into()),
                 Some((i, '/')) => return Some(Token::Slash(Location::from(i)).into()),
+                Some((i, '&')) => return Some(Token::Ampersand(Location::from(i)).into()),
 
                 Some((i, '=')) => {
                     let cc2 = self.chars.peek();
@@ -401,13 +439,9 @@ impl<'input> Lexer<'input> {
                     }
                 }
 
-                Some((_, '#')) => {
-                    loop {
-                        let cc2 = self.chars.next();
-                        match cc2 {
-                            Some((_, '\n')) | None => break,
-                            Some((_, _)) => {}
-                        }
+                Some((i, '#')) => {
+                    if let Some(comment) = self.comment(i) {
+                        return Some(comment);
                     }
                 }
 
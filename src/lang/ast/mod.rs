@@ -18,6 +18,7 @@ pub mod parameter_node;
 pub mod lexer;
 pub mod token;
 pub mod node;
+pub mod format;
 
 #[derive(Clone, Debug)]
 pub struct JobListNode {
@@ -135,6 +136,8 @@ pub fn operator(iop: impl Into<TrackedString>, l: Box<Node>, r: Box<Node>) -> Bo
         "=~" => operator_method("match", op.location, r, l),
         "!~" => operator_method("not_match", op.location, r, l),
 
+        "::" => operator_function(&["global", "types", "member"], op.location, l, r),
+
         _ => panic!("Unknown operator {}", &op.string),
     }
 }
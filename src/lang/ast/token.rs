@@ -13,6 +13,7 @@ pub enum Token<'input> {
     Minus(Location),
     Star(Location),
     Slash(Location),
+    Ampersand(Location),
     QuotedString(&'input str, Location),
     Identifier(&'input str, Location),
     Flag(&'input str, Location),
@@ -24,6 +25,7 @@ pub enum Token<'input> {
     Integer(&'input str, Location),
     Float(&'input str, Location),
     MemberOperator(Location),
+    MemberBroadcastOperator(Location),
     Equals(Location),
     Declare(Location),
     Separator(&'input str, Location),
@@ -37,6 +39,7 @@ pub enum Token<'input> {
     Unnamed(Location),
     Named(Location),
     ExprModeStart(Location),
+    Comment(&'input str, Location),
 }
 
 impl Token<'_> {
@@ -55,7 +58,9 @@ impl Token<'_> {
             Token::Regex(_, l) |
             Token::Integer(_, l) |
             Token::Float(_, l) |
+            Token::Comment(_, l) |
             Token::MemberOperator(l) |
+            Token::MemberBroadcastOperator(l) |
             Token::Equals(l) |
             Token::Declare(l) |
             Token::Separator(_, l) |
@@ -73,10 +78,29 @@ impl Token<'_> {
             Token::Minus(l) |
             Token::Star(l) |
             Token::Slash(l) |
+            Token::Ampersand(l) |
             Token::ExprModeStart(l) => *l,
         }
     }
 
+    /**
+    The key in `crush:highlight` used to color this token, if any.
+     */
+    pub fn highlight_class(&self) -> Option<&'static str> {
+        use Token::*;
+        match self {
+            Flag(_, _) | String(_, _) | QuotedString(_, _) | Regex(_, _) => Some("string_literal"),
+            File(_, _) | Glob(_, _) | QuotedFile(_, _) => Some("file_literal"),
+            Float(_, _) | Integer(_, _) => Some("numeric_literal"),
+            Unnamed(_) | Named(_) | Pipe(_) | LogicalOperator(_, _) | UnaryOperator(_, _) |
+            ComparisonOperator(_, _) | Equals(_) | Declare(_) | GetItemEnd(_) | GetItemStart(_) | SubEnd(_) |
+            Bang(_) | Plus(_) | Minus(_) | Star(_) | Slash(_) | Ampersand(_) | MemberOperator(_) | MemberBroadcastOperator(_) |
+            ExprModeStart(_) |
+            SubStart(_) | JobEnd(_) | JobStart(_) => Some("operator"),
+            Identifier(_, _) | Separator(_, _) | Comment(_, _) => None,
+        }
+    }
+
     pub fn as_string(&self) -> &str {
         match self {
             Token::LogicalOperator(s, _) |
@@ -92,8 +116,10 @@ impl Token<'_> {
             Token::Regex(s, _) |
             Token::Integer(s, _) |
             Token::Separator(s, _) |
+            Token::Comment(s, _) |
             Token::Float(s, _) => s,
             Token::MemberOperator(_) => ":",
+            Token::MemberBroadcastOperator(_) => "::",
             Token::Equals(_) => "=",
             Token::Declare(_) => ":=",
             Token::SubStart(_) => "(",
@@ -111,6 +137,7 @@ impl Token<'_> {
             Token::Minus(_) => "-",
             Token::Star(_) => "*",
             Token::Slash(_) => "/",
+            Token::Ampersand(_) => "&",
         }
     }
 }
@@ -141,7 +168,9 @@ impl<'a> Into<Spanned<'a>> for Token<'a> {
             Token::Integer(_, l) |
             Token::ComparisonOperator(_, l) |
             Token::Float(_, l) |
+            Token::Comment(_, l) |
             Token::MemberOperator(l) |
+            Token::MemberBroadcastOperator(l) |
             Token::Equals(l) |
             Token::Declare(l) |
             Token::Separator(_, l) |
@@ -159,6 +188,7 @@ impl<'a> Into<Spanned<'a>> for Token<'a> {
             Token::Minus(l) |
             Token::Star(l) |
             Token::Slash(l) |
+            Token::Ampersand(l) |
             Token::ExprModeStart(l) => { l }
         };
         Ok((loc.start, self, loc.end))
@@ -97,7 +97,9 @@ impl From<Token<'_>> for TrackedString {
             Token::Regex(_, l) |
             Token::Integer(_, l) |
             Token::Float(_, l) |
+            Token::Comment(_, l) |
             Token::MemberOperator(l) |
+            Token::MemberBroadcastOperator(l) |
             Token::Equals( l) |
             Token::Declare(l) |
             Token::Separator(_, l) |
@@ -114,6 +116,7 @@ impl From<Token<'_>> for TrackedString {
             Token::Minus(l) |
             Token::Star(l) |
             Token::Slash(l) |
+            Token::Ampersand(l) |
             Token::Bang(l) |
             Token::ExprModeStart(l) => { TrackedString::new(value.as_string(), l) }
         }
@@ -0,0 +1,496 @@
+/**
+A static checker that finds mistakes in a script without running it. It resolves the commands and
+variables a script uses against the global scope, validates the arguments passed to builtins and
+propagates the output types of commands through pipelines to validate the columns that
+aggregating commands operate on.
+ */
+use crate::lang::ast::lexer::Lexer;
+use crate::lang::ast::location::Location;
+use crate::lang::ast::node::Node;
+use crate::lang::ast::tracked_string::TrackedString;
+use crate::lang::ast::{CommandNode, JobListNode, JobNode};
+use crate::lang::command::{ArgumentDescription, Command, OutputType};
+use crate::lang::command_invocation::resolve_external_command;
+use crate::lang::data::table::ColumnType;
+use crate::lang::errors::{to_crush_error, CrushResult};
+use crate::lang::lsp::analysis::{path, Analysis, Reference};
+use crate::lang::parser::Parser;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{Value, ValueType};
+use crate::util::escape::unescape;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/**
+A mistake found by the checker.
+ */
+pub struct Finding {
+    pub location: Location,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/**
+Check the specified source code. Parse errors are reported as a single finding, since nothing else
+can be checked without a syntax tree.
+ */
+pub fn check(source: &str, env: &Scope, parser: &Parser) -> Vec<Finding> {
+    match parser.ast(source) {
+        Err(e) => vec![Finding {
+            location: e.location().unwrap_or_else(|| lexer_error_location(source)),
+            severity: Severity::Error,
+            message: e.message(),
+        }],
+        Ok(ast) => {
+            let mut checker = Checker {
+                env,
+                analysis: Analysis::new(&ast, source.len()),
+                findings: Vec::new(),
+            };
+            for reference in &checker.analysis.references {
+                if let Some((location, message)) = check_reference(env, &checker.analysis, reference) {
+                    checker.findings.push(Finding { location, severity: Severity::Warning, message });
+                }
+            }
+            checker.job_list(&ast, false);
+            checker.findings.sort_by_key(|f| f.location.start);
+            checker.findings
+        }
+    }
+}
+
+/**
+Check the specified files, or standard input if no file is given, and print all findings. Returns
+false if any errors were found.
+ */
+pub fn check_files(files: &[PathBuf], env: &Scope, global_state: &GlobalState) -> CrushResult<bool> {
+    let sources = if files.is_empty() {
+        let mut source = String::new();
+        to_crush_error(std::io::stdin().read_to_string(&mut source))?;
+        vec![("<stdin>".to_string(), source)]
+    } else {
+        files.iter()
+            .map(|file| Ok((file.display().to_string(), to_crush_error(std::fs::read_to_string(file))?)))
+            .collect::<CrushResult<Vec<_>>>()?
+    };
+
+    let printer = global_state.printer();
+    let mut ok = true;
+    for (name, source) in sources {
+        for finding in check(&source, env, global_state.parser()) {
+            let (line, column) = line_and_column(&source, finding.location.start);
+            printer.line(&format!("{}:{}:{}: {}: {}", name, line, column, finding.severity, finding.message));
+            ok &= finding.severity != Severity::Error;
+        }
+    }
+    Ok(ok)
+}
+
+pub fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/**
+The location of a lexer error, which is the first character after the last token that could be
+read that isn't whitespace.
+ */
+pub fn lexer_error_location(text: &str) -> Location {
+    let mut end = 0;
+    for token in Lexer::new(text) {
+        match token {
+            Ok((_, _, token_end)) => end = token_end,
+            Err(_) => break,
+        }
+    }
+    let rest = &text[end.min(text.len())..];
+    Location::from(end + rest.len() - rest.trim_start().len())
+}
+
+/**
+Look up the value a path refers to in the specified scope. Returns the value, or the index in the
+path of the first element that could not be found.
+ */
+pub fn resolve(env: &Scope, path: &[TrackedString]) -> Result<Value, usize> {
+    let mut value = match env.get(&path[0].string) {
+        Ok(Some(value)) => value,
+        _ => return Err(0),
+    };
+    for (idx, member) in path.iter().enumerate().skip(1) {
+        value = match value.field_with_extensions(&member.string, env) {
+            Ok(Some(value)) => value,
+            _ => return Err(idx),
+        };
+    }
+    Ok(value)
+}
+
+/**
+Check that a reference refers to something that exists. Members are only checked on namespaces,
+since the members of other values can't be known without running the script. Inside of closures,
+the value a method is called on may be a column of the input, e.g. `value:len`, so it is only
+reported if it is unknown in the global scope.
+ */
+pub fn check_reference(env: &Scope, analysis: &Analysis, reference: &Reference) -> Option<(Location, String)> {
+    let root = &reference.path[0];
+    if analysis.declaration(&root.string, root.location.start).is_some() {
+        return None;
+    }
+    if reference.in_closure && (!reference.is_command || root.string == "this") {
+        return None;
+    }
+    match resolve(env, &reference.path) {
+        Ok(_) => None,
+        Err(0) if reference.in_closure && reference.path.len() > 1 => None,
+        Err(0) => {
+            if reference.is_command {
+                if reference.path.len() == 1 {
+                    if let Ok(Some(_)) = resolve_external_command(&root.string, env) {
+                        return None;
+                    }
+                }
+                Some((root.location, format!("Unknown command {}", root.string)))
+            } else {
+                Some((root.location, format!("Unknown variable {}", root.string)))
+            }
+        }
+        Err(idx) => match resolve(env, &reference.path[..idx]) {
+            Ok(Value::Scope(_)) => Some((
+                reference.path[idx].location,
+                format!("Unknown member {}", reference.path[idx].string))),
+            _ => None,
+        },
+    }
+}
+
+/**
+Whether an argument of the expected type accepts a value of the actual type. Arguments that are
+lists also accept a single element, arguments that accept anything use the type `any`, and union
+types accept a value of any of their member types.
+ */
+fn accepts(expected: &ValueType, actual: &ValueType) -> bool {
+    match (expected, actual) {
+        (ValueType::Any, _) | (_, ValueType::Any) => true,
+        (ValueType::List(_), ValueType::List(_)) => true,
+        (ValueType::List(element), actual) => accepts(element, actual),
+        (ValueType::Union(options), actual) => options.iter().any(|option| accepts(option, actual)),
+        (expected, actual) => expected.is_compatible_with(actual),
+    }
+}
+
+fn type_list(types: &[ValueType]) -> String {
+    let names = types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => names.join(""),
+    }
+}
+
+struct Checker<'a> {
+    env: &'a Scope,
+    analysis: Analysis,
+    findings: Vec<Finding>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, location: Location, message: String) {
+        self.findings.push(Finding { location, severity: Severity::Error, message });
+    }
+
+    fn warning(&mut self, location: Location, message: String) {
+        self.findings.push(Finding { location, severity: Severity::Warning, message });
+    }
+
+    fn job_list(&mut self, jobs: &JobListNode, in_closure: bool) {
+        for job in &jobs.jobs {
+            self.job(job, in_closure);
+        }
+    }
+
+    /**
+    Check each command of a pipeline, using the output type of the previous command as the input
+    type.
+     */
+    fn job(&mut self, job: &JobNode, in_closure: bool) {
+        let mut input = OutputType::Unknown;
+        for command in &job.commands {
+            for expression in &command.expressions {
+                self.nested(expression, in_closure);
+            }
+            input = match self.command(command, in_closure) {
+                Some(cmd) => self.arguments(&cmd, command, &input, in_closure)
+                    .map(OutputType::Known)
+                    .unwrap_or(OutputType::Unknown),
+                None => OutputType::Unknown,
+            };
+        }
+    }
+
+    /**
+    Check the jobs inside of closures and substitutions.
+     */
+    fn nested(&mut self, node: &Node, in_closure: bool) {
+        match node {
+            Node::Assignment(target, _, _, value) => {
+                self.nested(target, in_closure);
+                self.nested(value, in_closure);
+            }
+            Node::Unary(_, value) => self.nested(value, in_closure),
+            Node::GetItem(container, key) => {
+                self.nested(container, in_closure);
+                self.nested(key, in_closure);
+            }
+            Node::GetAttr(parent, _) => self.nested(parent, in_closure),
+            Node::Substitution(job) => self.job(job, in_closure),
+            Node::Closure(_, body) => self.job_list(body, true),
+            Node::Identifier(_) | Node::Glob(_) | Node::Regex(_) | Node::String(_, _) |
+            Node::File(_, _) | Node::Integer(_) | Node::Float(_) => {}
+        }
+    }
+
+    /**
+    The builtin invoked by a command, if it can be known without running the script.
+     */
+    fn command(&self, command: &CommandNode, in_closure: bool) -> Option<Command> {
+        if let (1, Node::Assignment(..)) = (command.expressions.len(), &command.expressions[0]) {
+            return None;
+        }
+        let path = path(&command.expressions[0], true)?;
+        let root = &path[0];
+        if self.analysis.declaration(&root.string, root.location.start).is_some()
+            || (in_closure && root.string == "this") {
+            return None;
+        }
+        match resolve(self.env, &path) {
+            Ok(Value::Command(cmd)) => Some(cmd),
+            _ => None,
+        }
+    }
+
+    /**
+    The type of a value passed as an argument, if it can be known without running the script.
+    Inside of closures, variables may refer to columns of the input, so their types are unknown.
+     */
+    fn value_type(&self, node: &Node, in_closure: bool) -> Option<ValueType> {
+        match node {
+            Node::Integer(_) => Some(ValueType::Integer),
+            Node::Float(_) => Some(ValueType::Float),
+            Node::String(_, _) => Some(ValueType::String),
+            Node::File(_, _) => Some(ValueType::File),
+            Node::Glob(_) => Some(ValueType::Glob),
+            Node::Regex(_) => Some(ValueType::Regex),
+            Node::Closure(_, _) => Some(ValueType::Command),
+            Node::Identifier(name) if !in_closure => {
+                if self.analysis.declaration(&name.string, name.location.start).is_some() {
+                    return None;
+                }
+                resolve(self.env, std::slice::from_ref(name)).ok().map(|v| v.value_type())
+            }
+            _ => None,
+        }
+    }
+
+    /**
+    Validate the arguments of a command invocation against the argument descriptions of the
+    builtin, and return the output type of the command.
+     */
+    fn arguments(
+        &mut self,
+        cmd: &Command,
+        command: &CommandNode,
+        input: &OutputType,
+        in_closure: bool,
+    ) -> Option<ValueType> {
+        let output = cmd.output_type(input).cloned();
+        let descriptions = cmd.arguments();
+        if descriptions.is_empty() {
+            return output;
+        }
+        let name = path(&command.expressions[0], true)
+            .map(|p| p.iter().map(|s| s.string.clone()).collect::<Vec<_>>().join(":"))
+            .unwrap_or_default();
+        let has_named_fallback = descriptions.iter().any(|d| d.named);
+
+        let mut named = Vec::new();
+        let mut unnamed = Vec::new();
+        let mut spread = false;
+        let mut assigned: Vec<(&ArgumentDescription, &Node)> = Vec::new();
+        for expression in &command.expressions[1..] {
+            match expression {
+                Node::Assignment(target, _, op, value) if op == "=" => match target.as_ref() {
+                    Node::String(argument, false) | Node::Identifier(argument) => {
+                        match descriptions.iter().find(|d| d.name == argument.string) {
+                            Some(description) => {
+                                named.push(argument.string.clone());
+                                assigned.push((description, value));
+                            }
+                            None => if !has_named_fallback {
+                                self.error(
+                                    argument.location,
+                                    format!("Unknown argument {} for command {}", argument.string, name));
+                            },
+                        }
+                    }
+                    _ => {}
+                },
+                Node::Unary(op, _) if op.string == "@" || op.string == "@@" => spread = true,
+                _ => unnamed.push(expression),
+            }
+        }
+
+        // Unnamed arguments are assigned in order to the arguments that were not given by name,
+        // until an argument that accepts all remaining unnamed arguments is reached.
+        if !spread {
+            let mut remaining = unnamed.into_iter();
+            for description in descriptions {
+                if description.named || named.contains(&description.name) {
+                    continue;
+                }
+                if description.unnamed {
+                    assigned.extend(remaining.by_ref().map(|node| (description, node)));
+                    break;
+                }
+                if let ValueType::List(_) = description.value_type {
+                    continue;
+                }
+                match remaining.next() {
+                    Some(node) => assigned.push((description, node)),
+                    None => break,
+                }
+            }
+            // Commands that accept any named argument often handle unnamed arguments themselves
+            for node in remaining.filter(|_| !has_named_fallback) {
+                self.warning(node.location(), format!("Unused argument to command {}", name));
+            }
+        }
+
+        for (description, node) in &assigned {
+            self.argument(&name, description, node, input, in_closure);
+        }
+
+        // An omitted column argument means the only column of the input
+        if let Some([column]) = input.columns() {
+            for description in descriptions {
+                if !assigned.iter().any(|(d, _)| d.name == description.name) {
+                    self.column_type(&name, description, column, command.expressions[0].location());
+                }
+            }
+        }
+        output
+    }
+
+    fn argument(
+        &mut self,
+        name: &str,
+        description: &ArgumentDescription,
+        node: &Node,
+        input: &OutputType,
+        in_closure: bool,
+    ) {
+        if let Some(actual) = self.value_type(node, in_closure) {
+            if !accepts(&description.value_type, &actual) {
+                self.error(node.location(), format!(
+                    "Expected argument {} to be of type {}, was of type {}",
+                    description.name, description.value_type, actual));
+                return;
+            }
+        }
+
+        let literal = match node {
+            Node::String(s, true) => unescape(&s.string).ok(),
+            Node::String(s, false) => Some(s.string.clone()),
+            _ => None,
+        };
+
+        if let (Some(allowed), Some(literal)) = (&description.allowed, &literal) {
+            let value = Value::from(literal.as_str());
+            if !allowed.contains(&value) {
+                self.error(node.location(), format!(
+                    "Only the following values are allowed for argument {}: {}",
+                    description.name,
+                    allowed.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")));
+                return;
+            }
+        }
+
+        if let (Some(columns), Some(literal), Some(_)) = (input.columns(), &literal, &description.column) {
+            match columns.iter().find(|c| &c.name == literal) {
+                Some(column) => self.column_type(name, description, column, node.location()),
+                None => self.error(node.location(), format!(
+                    "Unknown column {}, available columns are {}",
+                    literal,
+                    columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>().join(", "))),
+            }
+        }
+    }
+
+    fn column_type(&mut self, name: &str, description: &ArgumentDescription, column: &ColumnType, location: Location) {
+        if let Some(types) = &description.column {
+            if !types.is_empty() && !types.contains(&column.cell_type) {
+                self.error(location, format!(
+                    "Can't use column {} of type {} with {}, expected a column of type {}",
+                    column.name, column.cell_type, name, type_list(types)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::declare;
+
+    fn messages(source: &str) -> Vec<(Severity, String)> {
+        let root = Scope::create_root();
+        declare(&root).unwrap();
+        check(source, &root, &Parser::new())
+            .into_iter()
+            .map(|f| (f.severity, source[f.location.start..f.location.end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn valid_script() {
+        assert!(messages("$a := 4\nhost:procs | where {$cpu > $a} | sort cpu --reverse | sum rss\n").is_empty());
+    }
+
+    #[test]
+    fn column_of_wrong_type() {
+        assert_eq!(messages("host:procs | sum user"), vec![(Severity::Error, "user".to_string())]);
+    }
+
+    #[test]
+    fn unknown_column_through_passthrough() {
+        assert_eq!(messages("host:procs | sort cpu | avg nosuch"), vec![(Severity::Error, "nosuch".to_string())]);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(
+            messages("files recurse=5 colour=true"),
+            vec![(Severity::Error, "5".to_string()), (Severity::Error, "colour".to_string())]);
+    }
+
+    #[test]
+    fn undeclared_variable() {
+        assert_eq!(messages("echo $x\n$y := 1"), vec![(Severity::Warning, "x".to_string())]);
+    }
+}
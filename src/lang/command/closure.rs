@@ -37,6 +37,7 @@ impl CrushCommand for Closure {
         let env = parent_env.create_child(&context.scope, false);
 
         let mut cc = CompileContext::from(&context).with_scope(&env);
+        let profile = context.profile().cloned();
         if let Some(this) = context.this {
             env.redeclare("this", this)?;
         }
@@ -65,7 +66,7 @@ impl CrushCommand for Closure {
                 output,
                 env.clone(),
                 context.global_state.clone(),
-            ))?;
+            ).with_profile(profile.clone()))?;
             let local_printer = context.global_state.printer().clone();
             let local_threads = context.global_state.threads().clone();
             job.map(|id| local_threads.join_one(id, &local_printer));
@@ -585,6 +586,8 @@ impl Closure {
                         } else if let Some(default) = default {
                             let env = context.env.clone();
                             env.redeclare(&name.string, default.eval_and_bind(context)?)?;
+                        } else if matches!(value_type, ValueType::Union(_)) && value_type.is(&Value::Empty) {
+                            context.env.redeclare(&name.string, Value::Empty)?;
                         } else {
                             return argument_error(
                                 format!(
@@ -17,6 +17,7 @@ use std::sync::Arc;
 use crate::lang::ast::tracked_string::TrackedString;
 use crate::lang::completion::Completion;
 use crate::lang::completion::parse::PartialCommandResult;
+use crate::lang::data::table::ColumnType;
 
 pub type Command = Arc<dyn CrushCommand + Send + Sync>;
 
@@ -38,6 +39,18 @@ impl CommandBinder for Command {
 }
 
 impl OutputType {
+    /**
+    The columns of the output, if it is known to be a table or a table stream.
+     */
+    pub fn columns(&self) -> Option<&[ColumnType]> {
+        match self {
+            OutputType::Known(ValueType::TableInputStream(c))
+            | OutputType::Known(ValueType::TableOutputStream(c))
+            | OutputType::Known(ValueType::Table(c)) => Some(c),
+            _ => None,
+        }
+    }
+
     fn calculate<'a>(&'a self, input: &'a OutputType) -> Option<&'a ValueType> {
         match self {
             OutputType::Unknown => None,
@@ -70,6 +83,9 @@ pub struct ArgumentDescription {
         res: &mut Vec<Completion>) -> CrushResult<()>>,
     pub named: bool,
     pub unnamed: bool,
+    /// If this argument names a column of the input, the types that column may have. An empty
+    /// list means any type is accepted.
+    pub column: Option<Vec<ValueType>>,
 }
 
 pub trait CrushCommand: Help {
@@ -203,6 +219,13 @@ impl dyn CrushCommand {
         })
     }
 
+    pub fn bound(command: &Command, this: Value) -> Command {
+        Arc::from(BoundCommand {
+            command: command.clone(),
+            this,
+        })
+    }
+
     pub fn deserialize(
         id: usize,
         elements: &[Element],
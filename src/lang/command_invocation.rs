@@ -52,6 +52,11 @@ impl CommandInvocation {
         &self.command
     }
 
+    pub fn location(&self) -> Location {
+        self.arguments.iter()
+            .fold(self.command.location(), |location, argument| location.union(argument.location))
+    }
+
     /**
     Evaluates all the arguments into values, and puts them into a CommandContext,
     ready to be exacuted by the main command.
@@ -91,7 +96,7 @@ pub fn eval_non_blocking(command: &ValueDefinition, arguments: &Vec<ArgumentDefi
     {
         // Try to find the command in this thread. This may fail if the command is found via a subshell, in which case we need to spawn a thread
         Ok((this, value)) => {
-            eval_internal(this, value, arguments.clone(), context, command.location())
+            eval_internal(this, value, arguments.clone(), context, command)
         }
         Err(err) =>
             if let ValueDefinition::Identifier(str) = command {
@@ -112,15 +117,12 @@ pub fn eval(command: &ValueDefinition, arguments: &Vec<ArgumentDefinition>, cont
         Ok(Some(context.spawn(
             &command.to_string(),
             move || {
-                match eval_non_blocking(&command, &arguments, my_context.clone()) {
-                    Ok(Some(id)) => my_context.global_state.threads().join_one(
-                        id,
-                        &my_context.global_state.printer(),
-                    ),
-                    Err(e) => my_context.global_state.printer().crush_error(e),
-                    _ => {}
+                // Errors are returned rather than printed, so that whoever waits for this thread
+                // can report them with the right context
+                match eval_non_blocking(&command, &arguments, my_context.clone())? {
+                    Some(id) => my_context.global_state.threads().wait_for(id),
+                    None => Ok(()),
                 }
-                Ok(())
             },
         )?))
     }
@@ -131,13 +133,13 @@ fn eval_internal(
     value: Value,
     local_arguments: Vec<ArgumentDefinition>,
     context: JobContext,
-    location: Location,
+    source: &ValueDefinition,
 ) -> CrushResult<Option<ThreadId>> {
     match value {
-        Value::Command(command) => eval_command(command, this, local_arguments, context),
-        Value::Type(t) => eval_type(t, local_arguments, context, location),
-        Value::Struct(s) => eval_struct(s, local_arguments, context, location),
-        v => eval_other(v, local_arguments, context, location),
+        Value::Command(command) => eval_command(command, this, local_arguments, context, source),
+        Value::Type(t) => eval_type(t, local_arguments, context, source),
+        Value::Struct(s) => eval_struct(s, local_arguments, context, source),
+        v => eval_other(v, local_arguments, context, source),
     }
 }
 
@@ -145,14 +147,15 @@ fn eval_other(
     value: Value,
     local_arguments: Vec<ArgumentDefinition>,
     context: JobContext,
-    location: Location,
+    source: &ValueDefinition,
 ) -> CrushResult<Option<ThreadId>> {
     if local_arguments.len() == 0 {
         eval_command(
             context.scope.global_static_cmd(vec!["global", "io", "val"])?,
             None,
-            vec![ArgumentDefinition::unnamed(ValueDefinition::Value(value, location))],
+            vec![ArgumentDefinition::unnamed(ValueDefinition::Value(value, source.location()))],
             context,
+            source,
         )
     } else {
         error(&format!("{} is not a command.", value))
@@ -163,7 +166,7 @@ fn eval_type(
     value_type: ValueType,
     local_arguments: Vec<ArgumentDefinition>,
     context: JobContext,
-    location: Location,
+    source: &ValueDefinition,
 ) -> CrushResult<Option<ThreadId>> {
     match value_type.fields().get("__call__") {
         None => eval_command(
@@ -171,15 +174,17 @@ fn eval_type(
             None,
             vec![ArgumentDefinition::unnamed(ValueDefinition::Value(
                 Value::Type(value_type),
-                location,
+                source.location(),
             ))],
             context,
+            source,
         ),
         Some(call) => eval_command(
             call.clone(),
             Some(Value::Type(value_type)),
             local_arguments,
             context,
+            source,
         ),
     }
 }
@@ -188,11 +193,11 @@ fn eval_struct(
     struct_value: Struct,
     local_arguments: Vec<ArgumentDefinition>,
     context: JobContext,
-    location: Location,
+    source: &ValueDefinition,
 ) -> CrushResult<Option<ThreadId>> {
     match struct_value.get("__call__") {
         Some(Value::Command(call)) =>
-            eval_command(call, Some(Value::Struct(struct_value)), local_arguments, context),
+            eval_command(call, Some(Value::Struct(struct_value)), local_arguments, context, source),
 
         Some(v) => error(
             format!(
@@ -208,9 +213,10 @@ fn eval_struct(
                     None,
                     vec![ArgumentDefinition::unnamed(ValueDefinition::Value(
                         Value::Struct(struct_value),
-                        location,
+                        source.location(),
                     ))],
                     context,
+                    source,
                 )
             } else {
                 error(
@@ -229,27 +235,40 @@ fn eval_command(
     this: Option<Value>,
     local_arguments: Vec<ArgumentDefinition>,
     context: JobContext,
+    source: &ValueDefinition,
 ) -> CrushResult<Option<ThreadId>> {
     if !command.might_block(&local_arguments, &mut CompileContext::from(&context))
         && !arg_can_block(&local_arguments, &mut CompileContext::from(&context))
     {
         let new_context =
             CommandInvocation::execution_context(local_arguments, this, context.clone())?;
-        context.global_state.printer().handle_error(command.eval(new_context));
+        context.global_state.printer().handle_error(run(source, &command, new_context));
         Ok(None)
     } else {
         let name = command.name().to_string();
         let my_context = context.clone();
+        let source = source.clone();
         Ok(Some(context.spawn(
             &name,
             move || {
                 let res = CommandInvocation::execution_context(local_arguments, this, my_context.clone())?;
-                command.eval(res)
+                run(&source, &command, res)
             },
         )?))
     }
 }
 
+/**
+Run a command, measuring its CPU time if it is a profiled stage of a job.
+ */
+fn run(source: &ValueDefinition, command: &Command, context: CommandContext) -> CrushResult<()> {
+    let tracer = context.global_state.tracer().clone();
+    match context.output.profile().cloned() {
+        Some(stats) => stats.measure_cpu(|| tracer.eval(source, command, context)),
+        None => tracer.eval(source, command, context),
+    }
+}
+
 pub fn resolve_external_command(name: &str, env: &Scope) -> CrushResult<Option<PathBuf>> {
     if let Some(Value::List(path)) = env.get("cmd_path")? {
         let path_vec: Vec<_> = path.iter().collect();
@@ -280,18 +299,14 @@ fn try_external_command(
                 0,
                 ArgumentDefinition::unnamed(ValueDefinition::Value(Value::from(path), cmd.location)),
             );
-            let call = CommandInvocation {
-                command: ValueDefinition::Value(
-                    Value::Command(
-                        context
-                            .scope
-                            .global_static_cmd(vec!["global", "control", "cmd"])?,
-                    ),
-                    cmd.location,
-                ),
+            // Pass on the name of the external command as the source, so that it shows up in traces
+            eval_command(
+                context.scope.global_static_cmd(vec!["global", "control", "cmd"])?,
+                None,
                 arguments,
-            };
-            call.eval(context)
+                context,
+                &ValueDefinition::Identifier(cmd.clone()),
+            )
         }
     }
 }
@@ -7,9 +7,9 @@ use crate::lang::errors::CrushResult;
 use crate::lang::value::{ValueType, Value};
 use crate::util::directory_lister::DirectoryLister;
 use std::path::PathBuf;
-use crate::lang::completion::parse::{ParseResult, CompletionCommand, LastArgument, parse, PartialCommandResult};
+use crate::lang::completion::parse::{ParseResult, CompletionCommand, LastArgument, parse, PartialCommandResult, InputTypes};
 use nix::NixPath;
-use crate::lang::command::ArgumentDescription;
+use crate::lang::command::{ArgumentDescription, OutputType};
 use crate::util::escape::escape_without_quotes;
 use crate::lang::parser::Parser;
 
@@ -113,15 +113,16 @@ fn prefix_match(prefix: &str, whole: &str) -> bool {
 
 fn complete_label(
     value: Value,
+    scope: &Scope,
     prefix: &str,
     t: &ValueType,
     cursor: usize,
     out: &mut Vec<Completion>,
 ) -> CrushResult<()> {
-    out.append(&mut value.fields()
+    out.append(&mut value.fields_with_extensions(scope)?
         .iter()
         .filter(|k| prefix_match(prefix, k))
-        .filter(|k| value.field(*k)
+        .filter(|k| value.field_with_extensions(k, scope)
             .map(|opt| opt.map(
                 |val| is_or_has_type(&val, t, 4))
                 .unwrap_or(false))
@@ -130,7 +131,7 @@ fn complete_label(
             completion: format!(
                 "{}{}",
                 &k[prefix.len()..],
-                completion_suffix(value.field(k), t)),
+                completion_suffix(value.field_with_extensions(k, scope), t)),
             display: k.clone(),
             position: cursor,
         })
@@ -138,6 +139,31 @@ fn complete_label(
     Ok(())
 }
 
+/**
+Complete the names of the columns of the input, showing the type of each column.
+ */
+fn complete_column(
+    input: &OutputType,
+    prefix: &str,
+    t: &ValueType,
+    cursor: usize,
+    out: &mut Vec<Completion>,
+) -> CrushResult<()> {
+    if let Some(columns) = input.columns() {
+        out.append(&mut columns
+            .iter()
+            .filter(|c| prefix_match(prefix, &c.name))
+            .filter(|c| t.is_compatible_with(&c.cell_type))
+            .map(|c| Completion {
+                completion: format!("{} ", &c.name[prefix.len()..]),
+                display: format!("{} ({})", c.name, c.cell_type),
+                position: cursor,
+            })
+            .collect());
+    }
+    Ok(())
+}
+
 fn complete_file(
     lister: &impl DirectoryLister,
     prefix: impl Into<PathBuf>,
@@ -249,6 +275,16 @@ fn complete_argument_description(
     if let Some(cmd) = &argument_description.complete {
         cmd(&parse_result, cursor, scope, res)?;
     }
+    if argument_description.column.is_some() {
+        let prefix = match &parse_result.last_argument {
+            LastArgument::Unknown => Some(""),
+            LastArgument::Field(prefix) => Some(prefix.as_str()),
+            _ => None,
+        };
+        if let Some(prefix) = prefix {
+            complete_column(&parse_result.input.command, prefix, &ValueType::Any, cursor, res)?;
+        }
+    }
     Ok(())
 }
 
@@ -278,7 +314,7 @@ fn complete_partial_argument(
         }
 
         LastArgument::Unknown => {
-            complete_label(Value::Scope(scope.clone()), "", &argument_type, cursor, res)?;
+            complete_label(Value::Scope(scope.clone()), scope, "", &argument_type, cursor, res)?;
             complete_file(lister, "", false, &argument_type, cursor, res)?;
             if parse_result.last_argument_name.is_none() {
                 if let CompletionCommand::Known(cmd) = parse_result.command {
@@ -288,7 +324,8 @@ fn complete_partial_argument(
         }
 
         LastArgument::Label(label) => {
-            complete_label(Value::Scope(scope.clone()), &label, &argument_type, cursor, res)?;
+            complete_column(&parse_result.input.closure, &label, &argument_type, cursor, res)?;
+            complete_label(Value::Scope(scope.clone()), scope, &label, &argument_type, cursor, res)?;
         }
 
         LastArgument::Field(label) => {
@@ -300,7 +337,7 @@ fn complete_partial_argument(
         }
 
         LastArgument::Member(parent, field) => {
-            complete_label(parent, &field, &argument_type, cursor, res)?;
+            complete_label(parent, scope, &field, &argument_type, cursor, res)?;
         }
 
         LastArgument::File(l, quoted) => {
@@ -323,20 +360,21 @@ pub fn complete(
     let mut res = Vec::new();
     match parse_result {
         ParseResult::Nothing => {
-            complete_label(Value::Scope(scope.clone()), "", &ValueType::Any, cursor, &mut res)?;
+            complete_label(Value::Scope(scope.clone()), scope, "", &ValueType::Any, cursor, &mut res)?;
             complete_file(lister, "", false, &ValueType::Any, cursor, &mut res)?;
         }
 
-        ParseResult::PartialLabel(label) => {
-            complete_label(Value::Scope(scope.clone()), &label, &ValueType::Any, cursor, &mut res)?;
+        ParseResult::PartialLabel(label, InputTypes { closure, .. }) => {
+            complete_column(&closure, &label, &ValueType::Any, cursor, &mut res)?;
+            complete_label(Value::Scope(scope.clone()), scope, &label, &ValueType::Any, cursor, &mut res)?;
         }
 
         ParseResult::PartialField(label) => {
-            complete_label(Value::Scope(scope.clone()), &label, &ValueType::Any, cursor, &mut res)?;
+            complete_label(Value::Scope(scope.clone()), scope, &label, &ValueType::Any, cursor, &mut res)?;
         }
 
         ParseResult::PartialMember(parent, label) => {
-            complete_label(parent, &label, &ValueType::Any, cursor, &mut res)?;
+            complete_label(parent, scope, &label, &ValueType::Any, cursor, &mut res)?;
         }
 
         ParseResult::PartialFile(cmd, quoted) =>
@@ -358,6 +396,8 @@ mod tests {
     use crate::util::directory_lister::tests::FakeDirectoryLister;
     use signature::signature;
     use crate::lang::state::contexts::CommandContext;
+    use crate::lang::command::{Command, OutputType::Known, OutputType::Passthrough};
+    use crate::lang::data::table::ColumnType;
 
     fn parser() -> Parser {
         Parser::new()
@@ -409,6 +449,51 @@ mod tests {
         argument3: String,
     }
 
+    fn procs_cmd(_context: CommandContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    fn filter_cmd(_context: CommandContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    fn sort_cmd(_context: CommandContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    fn procs_output_type() -> Vec<ColumnType> {
+        vec![
+            ColumnType::new("pid", ValueType::Integer),
+            ColumnType::new("name", ValueType::String),
+        ]
+    }
+
+    #[signature(procs_cmd, output = Known(ValueType::TableInputStream(procs_output_type())))]
+    struct ProcsCmdSignature {}
+
+    #[signature(filter_cmd, output = Passthrough)]
+    struct FilterCmdSignature {
+        condition: Command,
+    }
+
+    #[signature(sort_cmd, output = Passthrough)]
+    struct SortCmdSignature {
+        #[unnamed()]
+        #[column()]
+        field: Vec<String>,
+    }
+
+    fn scope_with_pipeline_commands() -> Scope {
+        let root = Scope::create_root();
+        root.create_namespace("namespace", "bla", Box::new(|env| {
+            ProcsCmdSignature::declare(env)?;
+            FilterCmdSignature::declare(env)?;
+            SortCmdSignature::declare(env)?;
+            Ok(())
+        })).unwrap();
+        root
+    }
+
     fn scope_with_function() -> Scope {
         let root = Scope::create_root();
         let chld = root.create_namespace("namespace", "bla", Box::new(|env| {
@@ -820,4 +905,38 @@ mod tests {
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "\"\":format ");
     }
+
+    #[test]
+    fn check_column_completion() {
+        let line = "namespace:procs_cmd | namespace:sort_cmd ";
+        let cursor = line.len();
+
+        let s = scope_with_pipeline_commands();
+        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let displays = completions.iter().map(|c| c.display()).collect::<Vec<_>>();
+        assert!(displays.contains(&"pid (integer)"));
+        assert!(displays.contains(&"name (string)"));
+    }
+
+    #[test]
+    fn check_column_completion_through_passthrough() {
+        let line = "namespace:procs_cmd | namespace:filter_cmd {true} | namespace:sort_cmd p";
+        let cursor = line.len();
+
+        let s = scope_with_pipeline_commands();
+        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(&completions[0].complete(line), "namespace:procs_cmd | namespace:filter_cmd {true} | namespace:sort_cmd pid ");
+    }
+
+    #[test]
+    fn check_column_variable_completion_in_closure() {
+        let line = "namespace:procs_cmd | namespace:filter_cmd {$pi";
+        let cursor = line.len();
+
+        let s = scope_with_pipeline_commands();
+        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(&completions[0].complete(line), "namespace:procs_cmd | namespace:filter_cmd {$pid ");
+    }
 }
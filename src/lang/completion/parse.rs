@@ -2,7 +2,7 @@ use std::cmp::min;
 use crate::lang::ast::{node::Node, CommandNode, JobListNode, JobNode};
 use crate::lang::errors::{error, CrushResult, mandate, argument_error_legacy, to_crush_error};
 use crate::lang::value::{ValueType, Value};
-use crate::lang::command::{Command, ArgumentDescription};
+use crate::lang::command::{Command, ArgumentDescription, OutputType};
 use crate::lang::state::scope::Scope;
 use std::ops::Deref;
 use regex::Regex;
@@ -55,6 +55,20 @@ pub struct PartialCommandResult {
     pub previous_arguments: Vec<PreviousArgument>,
     pub last_argument_name: Option<String>,
     pub last_argument: LastArgument,
+    pub input: InputTypes,
+}
+
+/**
+The types flowing into the code being completed, calculated from the output types of the upstream
+commands in the pipeline.
+ */
+#[derive(Clone)]
+pub struct InputTypes {
+    /// The input of the command being completed.
+    pub command: OutputType,
+    /// The input of the command that the innermost closure around the cursor is passed to. The
+    /// columns of this input are available as variables inside the closure.
+    pub closure: OutputType,
 }
 
 impl PartialCommandResult {
@@ -117,7 +131,7 @@ impl PartialCommandResult {
 #[derive(Clone)]
 pub enum ParseResult {
     Nothing,
-    PartialLabel(String),
+    PartialLabel(String, InputTypes),
     PartialField(String),
     PartialMember(Value, String),
     PartialFile(String, bool),
@@ -129,7 +143,7 @@ impl Display for ParseResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             ParseResult::Nothing => f.write_str("nothing"),
-            ParseResult::PartialLabel(l) => {
+            ParseResult::PartialLabel(l, _) => {
                 f.write_str("label ")?;
                 f.write_str(l)
             }
@@ -214,6 +228,63 @@ fn find_command_in_job_list(ast: JobListNode, cursor: usize) -> CrushResult<Comm
                 .map(|c| c.clone())), "Nothing to complete")
 }
 
+fn output_type(cmd: &CommandNode, input: &OutputType, scope: &Scope) -> OutputType {
+    match parse_command_node(&cmd.expressions[0], scope) {
+        Ok(CompletionCommand::Known(command)) =>
+            command.output_type(input)
+                .map(|t| OutputType::Known(t.clone()))
+                .unwrap_or(OutputType::Unknown),
+        _ => OutputType::Unknown,
+    }
+}
+
+fn find_input_in_expression(exp: &Node, cursor: usize, scope: &Scope, input: &OutputType, closure: &OutputType) -> Option<InputTypes> {
+    match exp {
+        Node::Assignment(_, _, _, b) => find_input_in_expression(b, cursor, scope, input, closure),
+
+        Node::Substitution(j) if j.location.contains(cursor) =>
+            Some(find_input_in_job(j, cursor, scope, closure)),
+
+        Node::Closure(_, joblist) if joblist.location.contains(cursor) =>
+            Some(find_input_in_job_list(joblist, cursor, scope, input)),
+
+        _ => None,
+    }
+}
+
+/**
+Calculate the input types of the command the cursor is in, following the same path through the
+job as `find_command_in_job`.
+ */
+fn find_input_in_job(job: &JobNode, cursor: usize, scope: &Scope, closure: &OutputType) -> InputTypes {
+    let mut input = OutputType::Unknown;
+    for (idx, cmd) in job.commands.iter().enumerate() {
+        if cmd.location.contains(cursor) || idx == job.commands.len() - 1 {
+            for exp in &cmd.expressions {
+                if let Some(res) = find_input_in_expression(exp, cursor, scope, &input, closure) {
+                    return res;
+                }
+            }
+            break;
+        }
+        input = output_type(cmd, &input, scope);
+    }
+    InputTypes {
+        command: input,
+        closure: closure.clone(),
+    }
+}
+
+fn find_input_in_job_list(ast: &JobListNode, cursor: usize, scope: &Scope, closure: &OutputType) -> InputTypes {
+    match ast.jobs.iter().find(|job| job.location.contains(cursor)).or(ast.jobs.last()) {
+        Some(job) => find_input_in_job(job, cursor, scope, closure),
+        None => InputTypes {
+            command: OutputType::Unknown,
+            closure: closure.clone(),
+        },
+    }
+}
+
 fn fetch_value(node: &Node, scope: &Scope, is_command: bool) -> CrushResult<Option<Value>> {
     match node {
         Node::Identifier(l) => scope.get(&l.string),
@@ -229,7 +300,7 @@ fn fetch_value(node: &Node, scope: &Scope, is_command: bool) -> CrushResult<Opti
 
         Node::GetAttr(n, l) =>
             match fetch_value(n, scope, is_command)? {
-                Some(parent) => parent.field(&l.string),
+                Some(parent) => parent.field_with_extensions(&l.string, scope),
                 None => Ok(None),
             },
 
@@ -295,6 +366,7 @@ pub fn parse(
         return Ok(ParseResult::Nothing);
     }
 
+    let input = find_input_in_job_list(&ast, cursor, scope, &OutputType::Unknown);
     let cmd = find_command_in_job_list(ast, cursor)?;
 
     match cmd.expressions.len() {
@@ -305,7 +377,8 @@ pub fn parse(
                 match cmd {
                     Node::Identifier(label) =>
                         Ok(ParseResult::PartialLabel(
-                            label.prefix(cursor).string)),
+                            label.prefix(cursor).string,
+                            input)),
 
                     Node::String(string, true) =>
                         Ok(ParseResult::PartialQuotedString(string.prefix(cursor).string)),
@@ -335,6 +408,7 @@ pub fn parse(
                         previous_arguments: vec![],
                         last_argument: LastArgument::Unknown,
                         last_argument_name: None,
+                        input,
                     }
                 ))
             }
@@ -371,6 +445,7 @@ pub fn parse(
                                 previous_arguments,
                                 last_argument: LastArgument::Switch(substring.to_string()),
                                 last_argument_name,
+                                input,
                             }
                         ))
                     }
@@ -387,6 +462,7 @@ pub fn parse(
                                     previous_arguments,
                                     last_argument: LastArgument::Label(l.string.clone()),
                                     last_argument_name,
+                                    input,
                                 }
                             )),
 
@@ -397,6 +473,7 @@ pub fn parse(
                                     previous_arguments,
                                     last_argument: LastArgument::Field(l.string.clone()),
                                     last_argument_name,
+                                    input,
                                 }
                             )),
 
@@ -409,6 +486,7 @@ pub fn parse(
                                         mandate(fetch_value(parent, scope, false)?, "unknown value")?,
                                         field.prefix(cursor).string),
                                     last_argument_name,
+                                    input,
                                 })),
 
                         Node::File(path, quoted) =>
@@ -420,6 +498,7 @@ pub fn parse(
                                         if *quoted { unescape(&path.string)? } else { path.string.clone() },
                                         *quoted),
                                     last_argument_name,
+                                    input,
                                 }
                             )),
 
@@ -430,6 +509,7 @@ pub fn parse(
                                     previous_arguments,
                                     last_argument: LastArgument::QuotedString(unescape(&s.string)?),
                                     last_argument_name,
+                                    input,
                                 }
                             )),
 
@@ -442,6 +522,7 @@ pub fn parse(
                             previous_arguments,
                             last_argument: LastArgument::Unknown,
                             last_argument_name,
+                            input,
                         }
                     ))
                 }
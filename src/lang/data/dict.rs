@@ -68,21 +68,33 @@ impl Dict {
     }
 
     pub fn get(&self, key: &Value) -> Option<Value> {
+        if !key.value_type().is_hashable() {
+            return None;
+        }
         let entries = self.entries.lock().unwrap();
         entries.get(key).map(|c| c.clone())
     }
 
     pub fn contains(&self, key: &Value) -> bool {
+        if !key.value_type().is_hashable() {
+            return false;
+        }
         let entries = self.entries.lock().unwrap();
         entries.contains_key(key)
     }
 
     pub fn remove(&self, key: &Value) -> Option<Value> {
+        if !key.value_type().is_hashable() {
+            return None;
+        }
         let mut entries = self.entries.lock().unwrap();
         entries.remove(key)
     }
 
     pub fn insert(&self, key: Value, value: Value) -> CrushResult<()> {
+        if !key.value_type().is_hashable() {
+            return argument_error_legacy(format!("Values of type {} can't be used as dict keys.", key.value_type()));
+        }
         let mut entries = self.entries.lock().unwrap();
         if !self.key_type.is(&key) {
             return argument_error_legacy(format!("Invalid key type, expected {}, got {}.", self.key_type.to_string(), key.value_type().to_string()));
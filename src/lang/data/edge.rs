@@ -0,0 +1,471 @@
+/**
+Code related to graphs. The nodes of a graph are the rows of a table, and the edges are values
+stored in the cells of those rows that refer to other rows of the same table.
+ */
+use crate::lang::data::list::List;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::data::table::{ColumnType, ColumnVec, Row, Table};
+use crate::lang::errors::{error, mandate, CrushResult};
+use crate::lang::value::{Value, ValueType};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock, Weak};
+
+/**
+The rows of a graph. The rows are set once all edges have been created, since the edges must
+refer to the graph before it is complete.
+ */
+pub struct Graph {
+    types: Vec<ColumnType>,
+    rows: OnceLock<Vec<Row>>,
+}
+
+impl Graph {
+    /**
+    Create a graph without any rows. Create the edges of the graph using `Edge::new`, and then
+    add the rows containing them using `set_rows`.
+     */
+    pub fn new(types: Vec<ColumnType>) -> Arc<Graph> {
+        Arc::new(Graph {
+            types,
+            rows: OnceLock::new(),
+        })
+    }
+
+    pub fn types(&self) -> &[ColumnType] {
+        &self.types
+    }
+
+    /**
+    The rows of the graph. Edges to this graph in the returned rows only hold weak references to
+    it.
+     */
+    pub fn rows(&self) -> CrushResult<&Vec<Row>> {
+        mandate(self.rows.get(), "Graph is incomplete")
+    }
+
+    pub fn set_rows(self: &Arc<Graph>, rows: Vec<Row>) -> CrushResult<()> {
+        let rows = rows
+            .into_iter()
+            .map(|row| Row::new(row.into_cells().into_iter().map(|cell| weaken(cell, self)).collect()))
+            .collect();
+        match self.rows.set(rows) {
+            Ok(()) => Ok(()),
+            Err(_) => error("The rows of the graph have already been set"),
+        }
+    }
+
+    /**
+    A table containing all rows of the graph.
+     */
+    pub fn table(self: &Arc<Graph>) -> CrushResult<Table> {
+        Ok(Table::from((
+            self.types.clone(),
+            (0..self.rows()?.len()).map(|idx| self.row(idx)).collect::<CrushResult<Vec<_>>>()?,
+        )))
+    }
+
+    /**
+    Return the row with the specified index. Edges stored inside the graph only hold weak
+    references to it, so that the graph doesn't keep itself alive. The edges of the returned row
+    hold strong references instead.
+     */
+    fn row(self: &Arc<Graph>, idx: usize) -> CrushResult<Row> {
+        let row = mandate(self.rows()?.get(idx), "Index out of bounds")?;
+        Ok(Row::new(
+            row.cells()
+                .iter()
+                .map(|cell| strengthen(cell.clone(), self))
+                .collect(),
+        ))
+    }
+}
+
+fn strengthen(value: Value, graph: &Arc<Graph>) -> Value {
+    match value {
+        Value::Edge(edge) if edge.is_in(graph) => Value::Edge(Edge {
+            graph: GraphRef::Strong(graph.clone()),
+            ..edge
+        }),
+        Value::List(list) if list.element_type() == ValueType::Edge => Value::List(List::new(
+            ValueType::Edge,
+            list.iter().map(|v| strengthen(v, graph)).collect::<Vec<_>>(),
+        )),
+        v => v,
+    }
+}
+
+fn weaken(value: Value, graph: &Arc<Graph>) -> Value {
+    match value {
+        Value::Edge(edge) if edge.is_in(graph) => Value::Edge(Edge {
+            graph: GraphRef::Weak(Arc::downgrade(graph)),
+            ..edge
+        }),
+        Value::List(list) if list.element_type() == ValueType::Edge => Value::List(List::new(
+            ValueType::Edge,
+            list.iter().map(|v| weaken(v, graph)).collect::<Vec<_>>(),
+        )),
+        v => v,
+    }
+}
+
+#[derive(Clone)]
+enum GraphRef {
+    Weak(Weak<Graph>),
+    Strong(Arc<Graph>),
+}
+
+impl GraphRef {
+    fn upgrade(&self) -> Option<Arc<Graph>> {
+        match self {
+            GraphRef::Weak(g) => g.upgrade(),
+            GraphRef::Strong(g) => Some(g.clone()),
+        }
+    }
+
+    fn as_ptr(&self) -> *const Graph {
+        match self {
+            GraphRef::Weak(g) => g.as_ptr(),
+            GraphRef::Strong(g) => Arc::as_ptr(g),
+        }
+    }
+}
+
+/**
+A reference from one row of a graph to another, along with an arbitrary value.
+ */
+#[derive(Clone)]
+pub struct Edge {
+    graph: GraphRef,
+    from: usize,
+    to: usize,
+    directed: bool,
+    value: Box<Value>,
+}
+
+impl Edge {
+    /**
+    Create an edge from the row with index `from` to the row with index `to` of a graph.
+     */
+    pub fn new(graph: &Arc<Graph>, from: usize, to: usize, directed: bool, value: Value) -> Edge {
+        Edge {
+            graph: GraphRef::Strong(graph.clone()),
+            from,
+            to,
+            directed,
+            value: Box::from(value),
+        }
+    }
+
+    fn is_in(&self, graph: &Arc<Graph>) -> bool {
+        std::ptr::eq(self.graph.as_ptr(), Arc::as_ptr(graph))
+    }
+
+    fn is_same(&self, other: &Edge) -> bool {
+        std::ptr::eq(self.graph.as_ptr(), other.graph.as_ptr())
+            && self.from == other.from
+            && self.to == other.to
+            && self.directed == other.directed
+    }
+
+    pub fn graph(&self) -> CrushResult<Arc<Graph>> {
+        mandate(self.graph.upgrade(), "The graph of this edge no longer exists")
+    }
+
+    pub fn from(&self) -> usize {
+        self.from
+    }
+
+    pub fn to(&self) -> usize {
+        self.to
+    }
+
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /**
+    The row this edge points to.
+     */
+    pub fn node(&self) -> CrushResult<Struct> {
+        let graph = self.graph()?;
+        Ok(graph.row(self.to)?.into_struct(graph.types()))
+    }
+
+    /**
+    The value of the specified column in the row this edge points to.
+     */
+    pub fn member(&self, name: &str) -> CrushResult<Value> {
+        let graph = self.graph()?;
+        let idx = graph.types().find(name)?;
+        Ok(graph.row(self.to)?.into_cells().swap_remove(idx))
+    }
+
+    pub fn field(&self, name: &str) -> CrushResult<Option<Value>> {
+        Ok(match name {
+            "node" => Some(Value::Struct(self.node()?)),
+            "value" => Some(self.value().clone()),
+            "directed" => Some(Value::Bool(self.directed)),
+            _ => None,
+        })
+    }
+
+    pub fn fields() -> Vec<String> {
+        vec!["directed".to_string(), "node".to_string(), "value".to_string()]
+    }
+
+    /**
+    Create a table of the tree below the row this edge points to. The tree is made up of the
+    edges in the same column as this edge. If that column holds lists of edges, they point to the
+    children of a row, e.g. `children`. If it holds single edges, they point to the parent of a
+    row, e.g. `parent`. Each row is visited once, and the first column is indented by the depth
+    of the row in the tree. Edge columns are left out.
+     */
+    pub fn tree(&self) -> CrushResult<Table> {
+        let graph = self.graph()?;
+        let column = mandate(graph.rows()?.get(self.from), "Index out of bounds")?
+            .cells()
+            .iter()
+            .position(|cell| match cell {
+                Value::Edge(edge) => edge.is_same(self),
+                Value::List(list) => list.iter().any(|v| match v {
+                    Value::Edge(edge) => edge.is_same(self),
+                    _ => false,
+                }),
+                _ => false,
+            });
+        let columns = graph
+            .types()
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !is_edge_type(&t.cell_type))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        let types = columns
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| {
+                let t = &graph.types()[*column];
+                if idx == 0 {
+                    ColumnType::new(t.name.clone(), ValueType::String)
+                } else {
+                    t.clone()
+                }
+            })
+            .collect();
+
+        let rows = graph.rows()?;
+        let mut children = vec![Vec::new(); rows.len()];
+        if let Some(column) = column {
+            for (idx, row) in rows.iter().enumerate() {
+                match row.cells().get(column) {
+                    Some(Value::Edge(edge)) if edge.to < rows.len() => children[edge.to].push(idx),
+                    Some(Value::List(list)) => children[idx].extend(list.iter().filter_map(|v| match v {
+                        Value::Edge(edge) => Some(edge.to),
+                        _ => None,
+                    })),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut res = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(self.to, 0usize)];
+        while let Some((idx, depth)) = stack.pop() {
+            if !visited.insert(idx) {
+                continue;
+            }
+            let cells = graph.row(idx)?.into_cells();
+            res.push(Row::new(
+                columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, column)| {
+                        if i == 0 {
+                            Value::from(format!("{}{}", "  ".repeat(depth), cells[*column]))
+                        } else {
+                            cells[*column].clone()
+                        }
+                    })
+                    .collect(),
+            ));
+            if let Some(children) = children.get(idx) {
+                stack.extend(children.iter().rev().map(|child| (*child, depth + 1)));
+            }
+        }
+        Ok(Table::from((types, res)))
+    }
+
+    pub fn materialize(self) -> CrushResult<Edge> {
+        Ok(Edge {
+            value: Box::from(self.value.materialize()?),
+            ..self
+        })
+    }
+}
+
+fn is_edge_type(t: &ValueType) -> bool {
+    match t {
+        ValueType::Edge => true,
+        ValueType::List(t) => **t == ValueType::Edge,
+        _ => false,
+    }
+}
+
+/**
+Edges are shown as an arrow followed by the first column of the row they point to, or the index
+of that row if the first column is itself an edge.
+ */
+impl Display for Edge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.directed { "->" } else { "--" })?;
+        let label = self
+            .graph
+            .upgrade()
+            .and_then(|graph| graph.rows.get().and_then(|rows| rows.get(self.to)).and_then(|row| row.cells().first().cloned()));
+        match label {
+            Some(Value::Edge(_)) | Some(Value::List(_)) | None => {
+                f.write_str("#")?;
+                self.to.fmt(f)
+            }
+            Some(v) => v.fmt(f),
+        }
+    }
+}
+
+impl Hash for Edge {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.from.hash(state);
+        self.to.hash(state);
+        self.directed.hash(state);
+    }
+}
+
+impl PartialEq for Edge {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_same(other) && self.value == other.value
+    }
+}
+
+/**
+Edges in the same graph are ordered by the rows they connect.
+ */
+impl PartialOrd for Edge {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if !std::ptr::eq(self.graph.as_ptr(), other.graph.as_ptr()) {
+            return None;
+        }
+        Some(
+            self.from
+                .cmp(&other.from)
+                .then(self.to.cmp(&other.to))
+                .then(self.directed.cmp(&other.directed)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use crate::lang::serialization::{deserialize, serialize};
+    use crate::lang::state::scope::Scope;
+
+    /**
+    A graph of four rows, where root is the parent of a and b, and a is the parent of c. Each row
+    has a name, an edge to its parent and a list of edges to its children.
+     */
+    fn family() -> Table {
+        let names = ["root", "a", "b", "c"];
+        let parents = [None, Some(0), Some(0), Some(1)];
+        let graph = Graph::new(vec![
+            ColumnType::new("name", ValueType::String),
+            ColumnType::new("parent", ValueType::Edge),
+            ColumnType::new("children", ValueType::List(Box::from(ValueType::Edge))),
+        ]);
+        let rows = (0..names.len())
+            .map(|idx| {
+                let children = (0..names.len())
+                    .filter(|child| parents[*child] == Some(idx))
+                    .map(|child| Value::Edge(Edge::new(&graph, idx, child, true, Value::Empty)))
+                    .collect::<Vec<_>>();
+                Row::new(vec![
+                    Value::from(names[idx]),
+                    match parents[idx] {
+                        Some(parent) => Value::Edge(Edge::new(&graph, idx, parent, true, Value::Empty)),
+                        None => Value::Empty,
+                    },
+                    Value::List(List::new(ValueType::Edge, children)),
+                ])
+            })
+            .collect();
+        graph.set_rows(rows).unwrap();
+        graph.table().unwrap()
+    }
+
+    fn edge(value: Value) -> Edge {
+        match value {
+            Value::Edge(edge) => edge,
+            _ => panic!("Expected an edge"),
+        }
+    }
+
+    fn children(table: &Table, idx: usize) -> Vec<Edge> {
+        match &table.row(idx).unwrap().cells()[2] {
+            Value::List(list) => list.iter().map(edge).collect(),
+            _ => panic!("Expected a list"),
+        }
+    }
+
+    #[test]
+    fn member_of_the_row_an_edge_points_to() {
+        let family = family();
+        let parent = edge(family.row(3).unwrap().cells()[1].clone());
+        assert!(parent.member("name").unwrap() == Value::from("a"));
+        assert!(parent.node().unwrap().get("name") == Some(Value::from("a")));
+        assert!(children(&family, 0).iter().map(|e| e.to()).collect::<Vec<_>>() == vec![1, 2]);
+    }
+
+    #[test]
+    fn graph_outlives_the_table() {
+        let parent = edge(family().row(3).unwrap().cells()[1].clone());
+        assert!(parent.member("name").unwrap() == Value::from("a"));
+    }
+
+    #[test]
+    fn tree_below_a_child() {
+        let tree = children(&family(), 0)[0].tree().unwrap();
+        assert_eq!(tree.types().len(), 1);
+        let names = tree.iter().map(|row| row.cells()[0].to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a".to_string(), "  c".to_string()]);
+    }
+
+    #[test]
+    fn edges_survive_serialization() {
+        let mut buf = Vec::new();
+        serialize(&Value::Table(family()), &mut buf).unwrap();
+        let copy = match deserialize(&buf, &Scope::create_root()).unwrap() {
+            Value::Table(table) => table,
+            _ => panic!("Expected a table"),
+        };
+        let parent = edge(copy.row(3).unwrap().cells()[1].clone());
+        assert!(parent.member("name").unwrap() == Value::from("a"));
+        assert!(children(&copy, 0).iter().map(|e| e.member("name").unwrap()).collect::<Vec<_>>()
+            == vec![Value::from("a"), Value::from("b")]);
+    }
+
+    #[test]
+    fn member_broadcast_operator() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        interpreter.scope().declare("family", Value::Table(family())).unwrap();
+        assert!(interpreter.eval("val $family[0]:children::name").unwrap()
+            == Value::List(List::new(ValueType::String, vec![Value::from("a"), Value::from("b")])));
+    }
+}
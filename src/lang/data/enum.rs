@@ -0,0 +1,185 @@
+use crate::lang::errors::{argument_error_legacy, CrushResult};
+use crate::lang::value::{Value, ValueType};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/**
+A single variant of an enum, with the names and types of the fields it carries.
+ */
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<(String, ValueType)>,
+}
+
+/**
+A sum type, as created by `types:enum`. Two enum types are the same type if they have the same
+name and the same variants.
+ */
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct EnumType {
+    name: String,
+    variants: Vec<Variant>,
+}
+
+impl EnumType {
+    pub fn new(name: String, variants: Vec<Variant>) -> CrushResult<EnumType> {
+        for (idx, variant) in variants.iter().enumerate() {
+            if variants[..idx].iter().any(|v| v.name == variant.name) {
+                return argument_error_legacy(format!("Duplicate variant {}", variant.name));
+            }
+        }
+        Ok(EnumType { name, variants })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|v| v.name == name)
+    }
+}
+
+/**
+A value of an enum type, i.e. one of the variants of the enum along with the values of its
+fields.
+ */
+#[derive(Clone)]
+pub struct Enum {
+    enum_type: Arc<EnumType>,
+    variant: usize,
+    fields: Vec<Value>,
+}
+
+impl Enum {
+    /**
+    Create a new value of the specified variant. The fields are checked against the types declared
+    for the variant.
+     */
+    pub fn new(enum_type: Arc<EnumType>, variant: usize, fields: Vec<Value>) -> CrushResult<Enum> {
+        let declared = &enum_type.variants[variant];
+        if declared.fields.len() != fields.len() {
+            return argument_error_legacy(format!(
+                "{}:{} expects {} fields, got {}",
+                enum_type.name,
+                declared.name,
+                declared.fields.len(),
+                fields.len()
+            ));
+        }
+        for ((name, field_type), value) in declared.fields.iter().zip(fields.iter()) {
+            if !field_type.is(value) {
+                return argument_error_legacy(format!(
+                    "Field {} of {}:{} is of type {}, got a value of type {}",
+                    name,
+                    enum_type.name,
+                    declared.name,
+                    field_type,
+                    value.value_type()
+                ));
+            }
+        }
+        Ok(Enum {
+            enum_type,
+            variant,
+            fields,
+        })
+    }
+
+    pub fn enum_type(&self) -> &Arc<EnumType> {
+        &self.enum_type
+    }
+
+    pub fn variant(&self) -> usize {
+        self.variant
+    }
+
+    pub fn variant_name(&self) -> &str {
+        &self.enum_type.variants[self.variant].name
+    }
+
+    pub fn fields(&self) -> Vec<(String, Value)> {
+        self.enum_type.variants[self.variant]
+            .fields
+            .iter()
+            .map(|(name, _)| name.clone())
+            .zip(self.fields.iter().cloned())
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.enum_type.variants[self.variant]
+            .fields
+            .iter()
+            .position(|(n, _)| n == name)
+            .map(|idx| self.fields[idx].clone())
+    }
+
+    pub fn materialize(self) -> CrushResult<Enum> {
+        Ok(Enum {
+            enum_type: self.enum_type,
+            variant: self.variant,
+            fields: self
+                .fields
+                .into_iter()
+                .map(|v| v.materialize())
+                .collect::<CrushResult<Vec<_>>>()?,
+        })
+    }
+}
+
+impl Display for Enum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.variant_name())?;
+        for (name, value) in self.fields() {
+            f.write_str(" ")?;
+            name.fmt(f)?;
+            f.write_str("=(")?;
+            value.fmt(f)?;
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+impl Hash for Enum {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.variant.hash(state);
+        self.fields.iter().for_each(|value| {
+            if value.value_type().is_hashable() {
+                value.hash(state);
+            }
+        });
+    }
+}
+
+impl PartialEq for Enum {
+    fn eq(&self, other: &Self) -> bool {
+        self.enum_type == other.enum_type
+            && self.variant == other.variant
+            && self.fields == other.fields
+    }
+}
+
+/**
+Values of the same enum are ordered by the order in which the variants were declared, and then by
+the values of their fields.
+ */
+impl PartialOrd for Enum {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.enum_type != other.enum_type {
+            return None;
+        }
+        match self.variant.cmp(&other.variant) {
+            Ordering::Equal => self.fields.partial_cmp(&other.fields),
+            o => Some(o),
+        }
+    }
+}
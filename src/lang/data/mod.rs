@@ -1,5 +1,8 @@
 pub mod dict;
 pub mod r#struct;
+pub mod edge;
+pub mod r#enum;
 pub mod list;
+pub mod set;
 pub mod binary;
 pub mod table;
@@ -0,0 +1,210 @@
+use crate::lang::errors::{argument_error_legacy, error, CrushResult};
+use crate::lang::pipe::Stream;
+use crate::lang::value::vec_reader::VecReader;
+use crate::lang::{value::Value, value::ValueType};
+use crate::util::identity_arc::Identity;
+use ordered_map::OrderedMap;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/**
+A mutable, unordered collection of unique values. Elements are kept in insertion order.
+ */
+#[derive(Clone)]
+pub struct Set {
+    element_type: ValueType,
+    elements: Arc<Mutex<OrderedMap<Value, ()>>>,
+}
+
+impl Identity for Set {
+    fn id(&self) -> u64 {
+        self.elements.id()
+    }
+}
+
+impl Set {
+    pub fn new(element_type: ValueType) -> CrushResult<Set> {
+        if !element_type.is_hashable() {
+            error("Tried to create set with unhashable element type")
+        } else {
+            Ok(Set {
+                element_type,
+                elements: Arc::new(Mutex::new(OrderedMap::new())),
+            })
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let elements = self.elements.lock().unwrap();
+        elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut elements = self.elements.lock().unwrap();
+        elements.clear()
+    }
+
+    pub fn copy(&self) -> Set {
+        let elements = self.elements.lock().unwrap();
+        Set {
+            element_type: self.element_type.clone(),
+            elements: Arc::new(Mutex::new(elements.clone())),
+        }
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        if !value.value_type().is_hashable() {
+            return false;
+        }
+        let elements = self.elements.lock().unwrap();
+        elements.contains_key(value)
+    }
+
+    pub fn remove(&self, value: &Value) -> bool {
+        if !value.value_type().is_hashable() {
+            return false;
+        }
+        let mut elements = self.elements.lock().unwrap();
+        elements.remove(value).is_some()
+    }
+
+    pub fn insert(&self, value: Value) -> CrushResult<()> {
+        if !value.value_type().is_hashable() {
+            return argument_error_legacy(format!(
+                "Values of type {} can't be stored in a set.",
+                value.value_type()
+            ));
+        }
+        if !self.element_type.is(&value) {
+            return argument_error_legacy(format!(
+                "Invalid element type, expected {}, got {}.",
+                self.element_type,
+                value.value_type()
+            ));
+        }
+        let mut elements = self.elements.lock().unwrap();
+        elements.insert(value, ());
+        Ok(())
+    }
+
+    pub fn element_type(&self) -> ValueType {
+        self.element_type.clone()
+    }
+
+    pub fn set_type(&self) -> ValueType {
+        ValueType::Set(Box::from(self.element_type.clone()))
+    }
+
+    pub fn elements(&self) -> Vec<Value> {
+        let elements = self.elements.lock().unwrap();
+        elements.keys().cloned().collect()
+    }
+
+    pub fn stream(&self) -> Stream {
+        Box::new(VecReader::new(self.elements(), self.element_type.clone()))
+    }
+
+    /**
+    A new set containing all elements of this set followed by all elements of the other set.
+     */
+    pub fn union(&self, other: &Set) -> CrushResult<Set> {
+        let res = self.copy();
+        for value in other.elements() {
+            res.insert(value)?;
+        }
+        Ok(res)
+    }
+
+    /**
+    A new set containing the elements of this set that are also in the other set.
+     */
+    pub fn intersection(&self, other: &Set) -> Set {
+        self.filter(|value| other.contains(value))
+    }
+
+    /**
+    A new set containing the elements of this set that are not in the other set.
+     */
+    pub fn difference(&self, other: &Set) -> Set {
+        self.filter(|value| !other.contains(value))
+    }
+
+    pub fn is_subset(&self, other: &Set) -> bool {
+        self.elements().iter().all(|value| other.contains(value))
+    }
+
+    fn filter(&self, predicate: impl Fn(&Value) -> bool) -> Set {
+        let mut res = OrderedMap::new();
+        for value in self.elements() {
+            if predicate(&value) {
+                res.insert(value, ());
+            }
+        }
+        Set {
+            element_type: self.element_type.clone(),
+            elements: Arc::new(Mutex::new(res)),
+        }
+    }
+
+    pub fn materialize(self) -> CrushResult<Set> {
+        let elements = self.elements();
+        let mut map = OrderedMap::with_capacity(elements.len());
+        for value in elements {
+            map.insert(value.materialize()?, ());
+        }
+        Ok(Set {
+            element_type: self.element_type.materialize()?,
+            elements: Arc::new(Mutex::new(map)),
+        })
+    }
+}
+
+/**
+Two sets are equal if they contain the same elements, regardless of order.
+ */
+impl PartialEq for Set {
+    fn eq(&self, other: &Set) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+/**
+Sets are partially ordered by inclusion.
+ */
+impl PartialOrd for Set {
+    fn partial_cmp(&self, other: &Set) -> Option<Ordering> {
+        match (self.is_subset(other), other.is_subset(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+impl Display for Set {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str("set{")?;
+        let mut first = true;
+        for value in self.elements() {
+            if first {
+                first = false;
+            } else {
+                f.write_str(" ")?;
+            }
+            f.write_str(&value.to_string())?;
+        }
+        f.write_str("}")
+    }
+}
+
+impl From<Set> for Value {
+    fn from(set: Set) -> Value {
+        Value::Set(set)
+    }
+}
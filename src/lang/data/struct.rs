@@ -1,5 +1,11 @@
+use crate::lang::argument::Argument;
+use crate::lang::command::Command;
+use crate::lang::ast::location::Location;
 use crate::lang::errors::{error, CrushError, CrushResult};
-use crate::lang::pipe::CrushStream;
+use crate::lang::pipe::{pipe, CrushStream};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
 use crate::lang::data::table::ColumnType;
 use crate::lang::data::table::Row;
 use crate::lang::value::Value;
@@ -241,6 +247,64 @@ impl Struct {
     pub fn set_parent(&self, parent: Option<Struct>) {
         self.data.lock().unwrap().parent = parent;
     }
+
+    /**
+    The method with the specified name in the class of this struct, i.e. in its parents. Methods
+    that customize instances are looked up this way, so that they don't apply to the class itself.
+     */
+    fn class_method(&self, name: &str) -> Option<Command> {
+        let parent = self.data.lock().unwrap().parent.clone();
+        match parent?.get(name) {
+            Some(Value::Command(command)) => Some(command),
+            _ => None,
+        }
+    }
+
+    /**
+    Call the method with the specified name in the class of this struct, if it has one, and
+    return its output.
+     */
+    pub fn call_method(
+        &self,
+        name: &str,
+        arguments: Vec<Value>,
+        scope: &Scope,
+        state: &GlobalState,
+    ) -> Option<CrushResult<Value>> {
+        let command = self.class_method(name)?;
+        let (sender, receiver) = pipe();
+        let arguments = arguments.into_iter()
+            .map(|value| Argument::unnamed(value, Location::new(0, 0)))
+            .collect();
+        let context = CommandContext::new(scope, state)
+            .with_args(arguments, Some(Value::Struct(self.clone())))
+            .with_output(sender);
+        Some(command.eval(context).and_then(|_| receiver.recv()))
+    }
+
+    /**
+    The output of the `__str__` method of this struct, or else of its `__repr__` method, if its
+    class has either.
+     */
+    pub fn str(&self, scope: &Scope, state: &GlobalState) -> CrushResult<Option<String>> {
+        match self.call_method("__str__", vec![], scope, state)
+            .or_else(|| self.call_method("__repr__", vec![], scope, state)) {
+            Some(value) => Ok(Some(value?.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /**
+    The output of the `__repr__` method of this struct, or else of its `__str__` method, if its
+    class has either. Used when a struct is shown as part of another value, like a table.
+     */
+    pub fn repr(&self, scope: &Scope, state: &GlobalState) -> CrushResult<Option<String>> {
+        match self.call_method("__repr__", vec![], scope, state)
+            .or_else(|| self.call_method("__str__", vec![], scope, state)) {
+            Some(value) => Ok(Some(value?.to_string())),
+            None => Ok(None),
+        }
+    }
 }
 
 impl Display for Struct {
@@ -217,8 +217,22 @@ impl ColumnType {
 impl Display for ColumnType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.name.fmt(f)?;
-        f.write_str("=($")?;
-        self.cell_type.fmt(f)?;
+        f.write_str("=(")?;
+        match &self.cell_type {
+            ValueType::Union(types) => {
+                for (idx, t) in types.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_str(" + ")?;
+                    }
+                    f.write_str("$")?;
+                    t.fmt(f)?;
+                }
+            }
+            t => {
+                f.write_str("$")?;
+                t.fmt(f)?;
+            }
+        }
         f.write_str(")")
     }
 }
@@ -0,0 +1,314 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use crate::lang::check::line_and_column;
+use crate::lang::command_invocation::CommandInvocation;
+use crate::lang::errors::to_crush_error;
+use crate::lang::execute;
+use crate::lang::pretty::create_pretty_printer;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+
+/**
+When to pause next.
+ */
+#[derive(Clone, Copy)]
+enum StepMode {
+    /** Only pause at breakpoints. */
+    Run,
+    /** Pause before the next command, including commands inside of called closures. */
+    Step,
+    /** Pause before the next command that is not inside of a closure called at this depth. */
+    Next(usize),
+    /** Pause before the next command after the closure at this depth has returned. */
+    Finish(usize),
+}
+
+struct DebuggerData {
+    mode: StepMode,
+    source: Option<String>,
+    last_command: String,
+}
+
+/**
+An interactive debugger. When stepping, execution is paused before every command that is about
+to be run, and a prompt is shown where the user can inspect and modify the scope the command runs
+in, look at the call stack, and decide how to continue.
+ */
+#[derive(Clone)]
+pub struct Debugger {
+    data: Arc<Mutex<DebuggerData>>,
+    /** True unless the mode is Run, so that checking if we should pause is cheap. */
+    stepping: Arc<AtomicBool>,
+    /** True while running code typed at the debugger prompt, which should never be paused. */
+    evaluating: Arc<AtomicBool>,
+    /** Held while the prompt is shown, so that only one thread at a time is paused at the prompt. */
+    editor: Arc<Mutex<Option<DefaultEditor>>>,
+}
+
+const HELP: &[&str] = &[
+    "step (s)       Run until the next command, stepping into closures",
+    "next (n)       Run until the next command, stepping over closures",
+    "finish (f)     Run until the current closure returns",
+    "continue (c)   Run until the next breakpoint",
+    "backtrace (bt) Show the call stack",
+    "locals         Show the variables of the current scope",
+    "help           Show this message",
+    "",
+    "Anything else is run as Crush code in the current scope. An empty line repeats the last",
+    "command, and end of file stops debugging.",
+];
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            data: Arc::new(Mutex::new(DebuggerData {
+                mode: StepMode::Run,
+                source: None,
+                last_command: String::new(),
+            })),
+            stepping: Arc::new(AtomicBool::new(false)),
+            evaluating: Arc::new(AtomicBool::new(false)),
+            editor: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn set_mode(&self, mode: StepMode) {
+        self.data.lock().unwrap().mode = mode;
+        self.stepping.store(!matches!(mode, StepMode::Run), Ordering::Relaxed);
+    }
+
+    /**
+    Start stepping, i.e. pause before the next command.
+     */
+    pub fn start(&self) {
+        self.set_mode(StepMode::Step);
+    }
+
+    /**
+    Stop stepping. Breakpoints still pause execution.
+     */
+    pub fn stop(&self) {
+        self.set_mode(StepMode::Run);
+    }
+
+    /**
+    Set the source code that locations refer to, so that they can be shown as line numbers.
+     */
+    pub fn set_source(&self, source: String) {
+        self.data.lock().unwrap().source = Some(source);
+    }
+
+    /**
+    Called before every command is run. The command must already be set as the position of
+    the scope.
+     */
+    pub fn before(&self, scope: &Scope, state: &GlobalState) {
+        if !self.stepping.load(Ordering::Relaxed) || self.evaluating.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut editor = self.editor.lock().unwrap();
+        if self.should_pause(scope.call_stack().len()) {
+            self.interact(&mut editor, scope, state);
+        }
+    }
+
+    /**
+    Whether to pause before a command at the specified depth of the call stack.
+     */
+    fn should_pause(&self, depth: usize) -> bool {
+        match self.data.lock().unwrap().mode {
+            StepMode::Run => false,
+            StepMode::Step => true,
+            StepMode::Next(d) => depth <= d,
+            StepMode::Finish(d) => depth < d,
+        }
+    }
+
+    /**
+    The mode that a debugger command typed while paused at the specified depth switches to, or
+    None if the command doesn't resume execution.
+     */
+    fn resume_mode(command: &str, depth: usize) -> Option<StepMode> {
+        match command {
+            "step" | "s" => Some(StepMode::Step),
+            "next" | "n" => Some(StepMode::Next(depth)),
+            "finish" | "f" => Some(StepMode::Finish(depth)),
+            "continue" | "c" => Some(StepMode::Run),
+            _ => None,
+        }
+    }
+
+    /**
+    Pause execution unconditionally, unless running code typed at the debugger prompt.
+     */
+    pub fn breakpoint(&self, scope: &Scope, state: &GlobalState) {
+        if self.evaluating.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut editor = self.editor.lock().unwrap();
+        self.interact(&mut editor, scope, state);
+    }
+
+    /**
+    Describe a position. If the source is known, the command is shown as it was written,
+    otherwise it is reconstructed from the parsed command.
+     */
+    fn format_position(&self, position: &CommandInvocation) -> String {
+        let location = position.location();
+        match &self.data.lock().unwrap().source {
+            Some(source) => {
+                let (line, column) = line_and_column(source, location.start);
+                let text = source.get(location.start..location.end)
+                    .and_then(|text| text.lines().next())
+                    .unwrap_or("");
+                format!("{} (line {}, column {})", text.trim(), line, column)
+            }
+            None => format!("{} (characters {}-{})", position, location.start, location.end),
+        }
+    }
+
+    fn interact(&self, editor: &mut Option<DefaultEditor>, scope: &Scope, state: &GlobalState) {
+        let stack = scope.call_stack();
+        match stack.first() {
+            Some((_, position)) => state.printer().line(&format!("Paused at {}", self.format_position(position))),
+            None => state.printer().line("Paused"),
+        }
+
+        if editor.is_none() {
+            match to_crush_error(DefaultEditor::new()) {
+                Ok(e) => *editor = Some(e),
+                Err(e) => {
+                    state.printer().crush_error(e);
+                    return;
+                }
+            }
+        }
+        let editor = editor.as_mut().unwrap();
+
+        loop {
+            state.printer().ping();
+            let line = match editor.readline("debug# ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => {
+                    self.stop();
+                    return;
+                }
+            };
+            let line = if line.trim().is_empty() {
+                self.data.lock().unwrap().last_command.clone()
+            } else {
+                let _ = editor.add_history_entry(&line);
+                self.data.lock().unwrap().last_command = line.clone();
+                line
+            };
+
+            match Debugger::resume_mode(line.trim(), stack.len()) {
+                Some(mode) => {
+                    self.set_mode(mode);
+                    return;
+                }
+                None => self.command(line.trim(), &stack, scope, state),
+            }
+        }
+    }
+
+    fn command(&self, command: &str, stack: &[(Scope, Arc<CommandInvocation>)], scope: &Scope, state: &GlobalState) {
+        match command {
+            "" => {}
+            "backtrace" | "bt" =>
+                for (idx, (_, position)) in stack.iter().enumerate() {
+                    state.printer().line(&format!("#{} {}", idx, self.format_position(position)));
+                },
+            "locals" => self.locals(scope, state),
+            "help" =>
+                for line in HELP {
+                    state.printer().line(line);
+                },
+            code => {
+                self.evaluating.store(true, Ordering::Relaxed);
+                let output = create_pretty_printer(state.printer().clone(), scope, state);
+                state.printer().handle_error(execute::string(scope, code, &output, state));
+                self.evaluating.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn locals(&self, scope: &Scope, state: &GlobalState) {
+        let names = match scope.dump_local() {
+            Ok(names) => names,
+            Err(e) => return state.printer().crush_error(e),
+        };
+        for (name, _) in names.iter() {
+            if let Ok(Some(value)) = scope.get_local(name) {
+                state.printer().line(&format!("{}: {}", name, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resume(debugger: &Debugger, command: &str, depth: usize) {
+        debugger.set_mode(Debugger::resume_mode(command, depth).unwrap());
+    }
+
+    #[test]
+    fn only_pauses_when_started() {
+        let debugger = Debugger::new();
+        assert!(!debugger.should_pause(1));
+        debugger.start();
+        assert!(debugger.stepping.load(Ordering::Relaxed));
+        assert!(debugger.should_pause(1));
+        debugger.stop();
+        assert!(!debugger.stepping.load(Ordering::Relaxed));
+        assert!(!debugger.should_pause(1));
+    }
+
+    #[test]
+    fn step_pauses_at_any_depth() {
+        let debugger = Debugger::new();
+        resume(&debugger, "step", 2);
+        assert!(debugger.should_pause(1));
+        assert!(debugger.should_pause(2));
+        assert!(debugger.should_pause(3));
+    }
+
+    #[test]
+    fn next_steps_over_closures() {
+        let debugger = Debugger::new();
+        resume(&debugger, "n", 2);
+        assert!(debugger.stepping.load(Ordering::Relaxed));
+        assert!(debugger.should_pause(1));
+        assert!(debugger.should_pause(2));
+        assert!(!debugger.should_pause(3));
+    }
+
+    #[test]
+    fn finish_runs_until_the_closure_returns() {
+        let debugger = Debugger::new();
+        resume(&debugger, "finish", 2);
+        assert!(debugger.should_pause(1));
+        assert!(!debugger.should_pause(2));
+        assert!(!debugger.should_pause(3));
+    }
+
+    #[test]
+    fn continue_stops_stepping() {
+        let debugger = Debugger::new();
+        debugger.start();
+        resume(&debugger, "c", 2);
+        assert!(!debugger.stepping.load(Ordering::Relaxed));
+        assert!(!debugger.should_pause(1));
+    }
+
+    #[test]
+    fn other_commands_do_not_resume() {
+        assert!(Debugger::resume_mode("bt", 1).is_none());
+        assert!(Debugger::resume_mode("echo 1", 1).is_none());
+    }
+}
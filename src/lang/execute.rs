@@ -1,13 +1,18 @@
-use crate::lang::errors::{argument_error_legacy, to_crush_error, CrushResult};
+use crate::lang::errors::{argument_error_legacy, error, to_crush_error, CrushResult};
 use crate::lang::state::contexts::{CommandContext, JobContext};
 use crate::lang::state::scope::Scope;
-use crate::lang::serialization::{deserialize, serialize};
-use crate::lang::pipe::{pipe, empty_channel, ValueSender};
+use crate::lang::serialization::{deserialize, deserialize_stream, read_frame, serialize, serialize_stream, serialize_stream_checked, write_frame};
+use crate::lang::pipe::{black_hole, pipe, empty_channel, ValueSender};
 use crate::lang::value::Value;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::{fs};
 use crate::lang::state::global_state::GlobalState;
+use crate::lang::printer;
+use crate::lang::printer::PrinterOutput;
+use crossbeam::channel::{bounded, unbounded};
 
 pub fn file(
     global_env: &Scope,
@@ -16,48 +21,204 @@ pub fn file(
     global_state: &GlobalState,
 ) -> CrushResult<()> {
     let cmd = to_crush_error(fs::read_to_string(filename))?;
-    string(global_env, &cmd.as_str(), output, global_state)
+    string(global_env, &cmd.as_str(), output, global_state)?;
+    Ok(())
 }
 
-pub fn pup(
-    env: Scope,
+/**
+Run a script in the specified scope, and send the output of its last job to the output.
+ */
+fn script(
+    env: &Scope,
+    source: &str,
+    output: ValueSender,
+    global_state: &GlobalState,
+) -> CrushResult<()> {
+    let jobs = global_state.parser().parse(source, env)?;
+    for (idx, job_definition) in jobs.iter().enumerate() {
+        let job_output = if idx == jobs.len() - 1 { output.clone() } else { black_hole() };
+        let handle = job_definition.eval(JobContext::new(
+            empty_channel(),
+            job_output,
+            env.clone(),
+            global_state.clone(),
+        ))?;
+        handle.map(|id| global_state.threads().join_one(id, global_state.printer()));
+    }
+    Ok(())
+}
+
+/**
+Deserialize a pup-serialized closure, run it and write its output to the destination in the
+streaming pup format. Instead of a closure, the request may also be a string containing a script,
+which is run directly in the specified scope, so that e.g. its declarations are kept.
+ */
+fn eval_pup(
+    env: &Scope,
     buf: &Vec<u8>,
+    destination: &mut dyn Write,
     global_state: &GlobalState,
 ) -> CrushResult<()> {
-    let cmd = deserialize(buf, &env)?;
-    match cmd {
-        Value::Command(cmd) => {
-            let (snd, recv) = pipe();
-
-            global_state.threads().spawn(
-                "serializer",
-                None,
-                move || {
-                    let val = recv.recv()?;
-                    let mut buf = Vec::new();
-                    serialize(&val.materialize()?, &mut buf)?;
-                    to_crush_error(std::io::stdout().write(&buf))?;
-                    Ok(())
-                },
-            )?;
-
-            cmd.eval(CommandContext::new(&env, global_state).with_output(snd))?;
-            global_state.threads().join(global_state.printer());
+    let run: Box<dyn FnOnce(&Scope, &GlobalState, ValueSender) -> CrushResult<()> + Send> =
+        match deserialize(buf, env) {
+            Ok(Value::Command(cmd)) => Box::new(
+                move |env, state, output| cmd.eval(CommandContext::new(env, state).with_output(output))),
+            Ok(Value::String(source)) => Box::new(
+                move |env, state, output| script(env, &source, output, state)),
+            Ok(_) => return serialize_stream(
+                argument_error_legacy("Expected a command or a script, but found other value"),
+                destination),
+            Err(e) => return serialize_stream(Err(e), destination),
+        };
+    // Errors are collected instead of printed, so that they can be sent to the reader,
+    // even when they happen after the command has started writing its output
+    let (error_snd, error_recv) = unbounded();
+    let printer = global_state.printer().clone();
+    let (request_printer, _) = printer::with_callback(move |output| match output {
+        PrinterOutput::Line(line) => printer.line(&line),
+        PrinterOutput::Error(e) => { let _ = error_snd.send(e); }
+    });
+    let request_state = global_state.with_printer(request_printer);
 
+    let (snd, recv) = pipe();
+    let my_env = env.clone();
+    let my_state = request_state.clone();
+    let id = global_state.threads().spawn(
+        "pup",
+        None,
+        move || run(&my_env, &my_state, snd),
+    )?;
+
+    let errors = || {
+        // Only wait for our own thread, other requests may be running in parallel when serving
+        request_state.threads().join_one(id, request_state.printer());
+        request_state.printer().ping();
+        let errors = error_recv.try_iter().collect::<Vec<_>>();
+        if errors.is_empty() {
             Ok(())
+        } else {
+            error(errors.join("\n"))
         }
+    };
+    match recv.recv() {
+        Ok(val) => serialize_stream_checked(Ok(val), destination, errors),
+        Err(_) => serialize_stream(
+            errors().and_then(|_| error("Command did not return a value")),
+            destination),
+    }
+}
 
-        _ => argument_error_legacy("Expected a command, but found other value"),
+pub fn pup(
+    env: Scope,
+    buf: &Vec<u8>,
+    global_state: &GlobalState,
+) -> CrushResult<()> {
+    eval_pup(&env, buf, &mut std::io::stdout().lock(), global_state)
+}
+
+/**
+Run as a long lived worker process. Reads a sequence of frames containing pup-serialized closures
+from the input, runs each one in turn and writes its output to the output as a pup stream. Before
+reading the first request, an empty value is written, so that the caller knows the worker has
+started.
+ */
+pub fn pup_worker(
+    env: Scope,
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    global_state: &GlobalState,
+) -> CrushResult<()> {
+    if serialize_stream(Ok(Value::Empty), output).is_err() {
+        // The caller went away before sending anything, e.g. because it only checked that
+        // someone is listening
+        return Ok(());
+    }
+    while let Some(request) = read_frame(input)? {
+        eval_pup(&env, &request, output, global_state)?;
+    }
+    Ok(())
+}
+
+/**
+Listen for connections on a Unix socket, and serve each connection like `pup_worker` does with
+its standard input and output. Each connection runs in a child scope of the specified scope, so
+that variables declared by concurrent clients do not clash. Loaded modules, cached connections and
+the variables of the specified scope itself are shared by all connections.
+ */
+pub fn serve(env: Scope, socket: &Path, global_state: &GlobalState) -> CrushResult<()> {
+    if let Ok(metadata) = fs::symlink_metadata(socket) {
+        if !metadata.file_type().is_socket() {
+            return error(format!("{} exists and is not a socket", socket.display()));
+        }
+        // Only remove sockets left behind by servers that are no longer running
+        if UnixStream::connect(socket).is_ok() {
+            return error(format!("Another process is already listening on {}", socket.display()));
+        }
+        to_crush_error(fs::remove_file(socket))?;
     }
+    let listener = to_crush_error(UnixListener::bind(socket))?;
+    for connection in listener.incoming() {
+        // Report the errors of finished connections, so that their threads do not pile up
+        global_state.threads().reap(global_state.printer());
+        let mut input = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                global_state.printer().error(&e.to_string());
+                continue;
+            }
+        };
+        let mut output = to_crush_error(input.try_clone())?;
+        let my_env = env.create_child(&env, false);
+        let my_state = global_state.clone();
+        global_state.threads().spawn(
+            "serve",
+            None,
+            move || pup_worker(my_env, &mut input, &mut output, &my_state))?;
+    }
+    Ok(())
 }
 
+/**
+Run a script in a crush process started with `--serve`, and send its output to the specified
+sender. The script is run in a child scope of the global scope of the server, so that it has
+access to everything that has been loaded into the server, but its own declarations are private.
+ */
+pub fn client(
+    global_env: &Scope,
+    socket: &Path,
+    source: &str,
+    output: &ValueSender,
+    global_state: &GlobalState,
+) -> CrushResult<()> {
+    let mut request = Vec::new();
+    serialize(&Value::from(source), &mut request)?;
+
+    let connection = to_crush_error(UnixStream::connect(socket))?;
+    // The server starts by sending an empty value, which is used to verify the version.
+    let (connection_send, connection_recv) = bounded(1);
+    deserialize_stream(
+        connection,
+        global_env,
+        global_state,
+        move |connection| { let _ = connection_send.send(connection); })?;
+    let mut connection = to_crush_error(connection_recv.recv())?;
+
+    write_frame(&mut connection, &request)?;
+    output.send(deserialize_stream(connection, global_env, global_state, |_| {})?)
+}
+
+/**
+Run the jobs in the specified string, waiting for each one to finish. Returns whether the last
+command of every job succeeded, much like the exit status of a pipeline in other shells.
+ */
 pub fn string(
     global_env: &Scope,
     command: &str,
     output: &ValueSender,
     global_state: &GlobalState,
-) -> CrushResult<()> {
+) -> CrushResult<bool> {
     let jobs = global_state.parser().parse(command, &global_env)?;
+    let mut success = true;
     for job_definition in jobs {
         let handle = job_definition.eval(JobContext::new(
             empty_channel(),
@@ -66,11 +227,105 @@ pub fn string(
             global_state.clone(),
         ))?;
 
-        handle.map(|id| global_state.threads()
-            .join_one(
-                id,
-                &global_state.printer().with_source(command, job_definition.location()),
-            ));
+        if let Some(id) = handle {
+            success &= global_state.threads()
+                .join_one(
+                    id,
+                    &global_state.printer().with_source(command, job_definition.location()),
+                );
+        }
+    }
+    Ok(success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use crate::lang::serialization::model::{stream_frame, StreamFrame};
+    use crate::lang::serialization::STREAM_MAGIC;
+    use prost::Message;
+    use std::io::Cursor;
+
+    /**
+    Run the closure in the source through eval_pup, and return the frames of the resulting stream.
+     */
+    fn frames(source: &str) -> Vec<stream_frame::Frame> {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        let mut request = Vec::new();
+        serialize(&interpreter.eval(source).unwrap(), &mut request).unwrap();
+        response_frames(interpreter.scope(), &request, interpreter.global_state())
+    }
+
+    /**
+    Run the serialized request through eval_pup, and return the frames of the resulting stream.
+     */
+    fn response_frames(root: &Scope, request: &Vec<u8>, global_state: &GlobalState) -> Vec<stream_frame::Frame> {
+        let mut response = Vec::new();
+        eval_pup(root, request, &mut response, global_state).unwrap();
+        assert!(response.starts_with(STREAM_MAGIC));
+        let mut cursor = Cursor::new(response.split_off(STREAM_MAGIC.len()));
+        let mut res = Vec::new();
+        while let Some(frame) = read_frame(&mut cursor).unwrap() {
+            res.push(StreamFrame::decode(&frame[..]).unwrap().frame.unwrap());
+        }
+        res
+    }
+
+    #[test]
+    fn successful_stream_ends_with_end_frame() {
+        let frames = frames("val {seq 3}");
+        assert!(matches!(frames.first(), Some(stream_frame::Frame::Header(_))));
+        assert!(matches!(frames.last(), Some(stream_frame::Frame::End(_))));
+    }
+
+    #[test]
+    fn errors_after_output_end_stream_with_error_frame() {
+        let frames = frames("val {seq 10 | where {if (value > 5) {$nosuch} {$true}}}");
+        assert!(frames.iter().any(|f| matches!(f, stream_frame::Frame::Rows(_))));
+        match frames.last() {
+            Some(stream_frame::Frame::Error(e)) => assert!(e.contains("nosuch")),
+            _ => panic!("Expected the stream to end with an error"),
+        }
+    }
+
+    #[test]
+    fn malformed_requests_are_answered_with_error_frame() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        let frames = response_frames(interpreter.scope(), &vec![0x0f, 0, 0, 0, 0, 0], interpreter.global_state());
+        assert!(matches!(frames.last(), Some(stream_frame::Frame::Error(_))));
+    }
+
+    #[test]
+    fn pup_worker_answers_each_request() {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        let mut input = Vec::new();
+        for source in ["val {(20 + 22)}", "val {seq 3}", "val {\"done\"}"] {
+            let mut request = Vec::new();
+            serialize(&interpreter.eval(source).unwrap(), &mut request).unwrap();
+            write_frame(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        pup_worker(interpreter.scope().clone(), &mut Cursor::new(input), &mut output, interpreter.global_state()).unwrap();
+
+        // Read the responses the way user:do does, passing the source on from one to the next
+        let mut source = Cursor::new(output);
+        let mut values = Vec::new();
+        for _ in 0..4 {
+            let (snd, recv) = bounded(1);
+            let value = deserialize_stream(source, interpreter.scope(), interpreter.global_state(), move |s| {
+                let _ = snd.send(s);
+            }).unwrap();
+            values.push(value.materialize().unwrap());
+            source = recv.recv().unwrap();
+        }
+        assert_eq!(source.position() as usize, source.get_ref().len());
+        assert!(matches!(values[0], Value::Empty));
+        assert!(values[1] == Value::Integer(42));
+        match &values[2] {
+            Value::Table(t) => assert_eq!(t.len(), 3),
+            v => panic!("Expected a table, got a value of type {}", v.value_type()),
+        }
+        assert!(values[3] == Value::from("done"));
     }
-    Ok(())
 }
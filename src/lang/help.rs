@@ -3,3 +3,18 @@ pub trait Help {
     fn short_help(&self) -> String;
     fn long_help(&self) -> Option<String>;
 }
+
+/**
+The full help text of something, as shown by the `help` command.
+ */
+pub fn help_text(o: &dyn Help) -> String {
+    match o.long_help() {
+        None => format!("{}\n\n    {}", o.signature(), o.short_help()),
+        Some(long_help) => format!(
+            "{}\n\n    {}\n\n{}",
+            o.signature(),
+            o.short_help(),
+            long_help
+        ),
+    }
+}
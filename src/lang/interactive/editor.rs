@@ -0,0 +1,388 @@
+use rustyline::config::Configurer;
+use rustyline::{At, Anchor, Cmd, CompletionType, ConditionalEventHandler, EditMode, Editor, Event, EventContext, EventHandler, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount, Word};
+use crate::lang::argument::Argument;
+use crate::lang::ast::location::Location;
+use crate::lang::command::Command;
+use crate::lang::errors::{argument_error_legacy, mandate, CrushResult};
+use crate::lang::interactive::history::{CrushHistory, HistorySearch};
+use crate::lang::interactive::rustyline_helper::{AcceptHintWord, RustylineHelper};
+use crate::lang::pipe::pipe;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::Value;
+
+/**
+The actions that a key can be bound to, along with a description of each.
+
+The names are the same as the ones used by readline.
+ */
+pub const ACTIONS: &[(&str, &str)] = &[
+    ("accept-line", "Run the current line"),
+    ("accept-hint-word", "Accept the next word of the suggestion"),
+    ("backward-char", "Move back one character"),
+    ("backward-delete-char", "Delete the character before the cursor"),
+    ("backward-kill-line", "Kill from the start of the line to the cursor"),
+    ("backward-kill-word", "Kill the word before the cursor"),
+    ("backward-word", "Move back to the start of the current or previous word"),
+    ("beginning-of-history", "Move to the first line in the history"),
+    ("beginning-of-line", "Move to the start of the line"),
+    ("capitalize-word", "Capitalize the current or following word"),
+    ("clear-screen", "Clear the screen"),
+    ("complete", "Complete the word before the cursor"),
+    ("complete-backward", "Complete the word before the cursor, cycling backwards"),
+    ("complete-hint", "Accept the whole suggestion"),
+    ("delete-char", "Delete the character at the cursor"),
+    ("downcase-word", "Lowercase the current or following word"),
+    ("end-of-history", "Move to the end of the history"),
+    ("end-of-line", "Move to the end of the line"),
+    ("forward-char", "Move forward one character"),
+    ("forward-search-history", "Search forward through the history"),
+    ("forward-word", "Move forward to the end of the current or next word"),
+    ("history-search-backward", "Search backward through the history for lines starting with the text before the cursor"),
+    ("history-search-forward", "Search forward through the history for lines starting with the text before the cursor"),
+    ("insert-newline", "Insert a newline without running the line"),
+    ("kill-line", "Kill from the cursor to the end of the line"),
+    ("kill-whole-line", "Kill the whole line"),
+    ("kill-word", "Kill from the cursor to the end of the current word"),
+    ("next-history", "Move to the next line in the history"),
+    ("noop", "Do nothing"),
+    ("previous-history", "Move to the previous line in the history"),
+    ("quoted-insert", "Insert the next key literally"),
+    ("reverse-search-history", "Search backward through the whole history"),
+    ("reverse-search-history-local", "Search backward through the commands run in the current directory"),
+    ("transpose-chars", "Swap the character before the cursor with the one at the cursor"),
+    ("transpose-words", "Swap the word before the cursor with the one after it"),
+    ("undo", "Undo the last edit"),
+    ("upcase-word", "Uppercase the current or following word"),
+    ("yank", "Insert the most recently killed text"),
+    ("yank-pop", "Replace the just yanked text with the text killed before it"),
+];
+
+fn action_command(name: &str) -> Option<Cmd> {
+    Some(match name {
+        "accept-line" => Cmd::AcceptLine,
+        "backward-char" => Cmd::Move(Movement::BackwardChar(1)),
+        "backward-delete-char" => Cmd::Kill(Movement::BackwardChar(1)),
+        "backward-kill-line" => Cmd::Kill(Movement::BeginningOfLine),
+        "backward-kill-word" => Cmd::Kill(Movement::BackwardWord(1, Word::Emacs)),
+        "backward-word" => Cmd::Move(Movement::BackwardWord(1, Word::Emacs)),
+        "beginning-of-history" => Cmd::BeginningOfHistory,
+        "beginning-of-line" => Cmd::Move(Movement::BeginningOfLine),
+        "capitalize-word" => Cmd::CapitalizeWord,
+        "clear-screen" => Cmd::ClearScreen,
+        "complete" => Cmd::Complete,
+        "complete-backward" => Cmd::CompleteBackward,
+        "complete-hint" => Cmd::CompleteHint,
+        "delete-char" => Cmd::Kill(Movement::ForwardChar(1)),
+        "downcase-word" => Cmd::DowncaseWord,
+        "end-of-history" => Cmd::EndOfHistory,
+        "end-of-line" => Cmd::Move(Movement::EndOfLine),
+        "forward-char" => Cmd::Move(Movement::ForwardChar(1)),
+        "forward-search-history" => Cmd::ForwardSearchHistory,
+        "forward-word" => Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "history-search-backward" => Cmd::HistorySearchBackward,
+        "history-search-forward" => Cmd::HistorySearchForward,
+        "insert-newline" => Cmd::Newline,
+        "kill-line" => Cmd::Kill(Movement::EndOfLine),
+        "kill-whole-line" => Cmd::Kill(Movement::WholeLine),
+        "kill-word" => Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "next-history" => Cmd::NextHistory,
+        "noop" => Cmd::Noop,
+        "previous-history" => Cmd::PreviousHistory,
+        "quoted-insert" => Cmd::QuotedInsert,
+        "transpose-chars" => Cmd::TransposeChars,
+        "transpose-words" => Cmd::TransposeWords(1),
+        "undo" => Cmd::Undo(1),
+        "upcase-word" => Cmd::UpcaseWord,
+        "yank" => Cmd::Yank(1, Anchor::Before),
+        "yank-pop" => Cmd::YankPop,
+        _ => return None,
+    })
+}
+
+#[derive(Clone)]
+pub enum KeyAction {
+    Builtin(String),
+    Closure(Command),
+}
+
+impl KeyAction {
+    pub fn new(value: Value) -> CrushResult<KeyAction> {
+        match value {
+            Value::String(name) =>
+                if ACTIONS.iter().any(|(n, _)| *n == name.as_ref()) {
+                    Ok(KeyAction::Builtin(name.to_string()))
+                } else {
+                    argument_error_legacy(format!("Unknown editor action {}", name))
+                },
+            Value::Command(cmd) => Ok(KeyAction::Closure(cmd)),
+            v => argument_error_legacy(format!(
+                "Expected an action name or a closure, got a value of type {}", v.value_type())),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            KeyAction::Builtin(name) => Value::from(name.as_str()),
+            KeyAction::Closure(cmd) => Value::Command(cmd.clone()),
+        }
+    }
+
+    fn handler(&self, history: &CrushHistory, env: &Scope, state: &GlobalState) -> EventHandler {
+        match self {
+            KeyAction::Builtin(name) => match name.as_str() {
+                "accept-hint-word" => EventHandler::Conditional(Box::new(AcceptHintWord {})),
+                "reverse-search-history" =>
+                    EventHandler::Conditional(Box::new(HistorySearch { history: history.clone(), local: false })),
+                "reverse-search-history-local" =>
+                    EventHandler::Conditional(Box::new(HistorySearch { history: history.clone(), local: true })),
+                _ => EventHandler::Simple(action_command(name).unwrap_or(Cmd::Noop)),
+            },
+            KeyAction::Closure(cmd) => EventHandler::Conditional(Box::new(ClosureHandler {
+                command: cmd.clone(),
+                env: env.clone(),
+                state: state.clone(),
+            })),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct KeyBinding {
+    pub key: String,
+    pub event: KeyEvent,
+    pub action: KeyAction,
+}
+
+/**
+The user configurable settings of the line editor. Every change bumps the version,
+so that the interactive loop knows to apply the settings before reading the next line.
+ */
+#[derive(Clone)]
+pub struct EditorSettings {
+    pub mode: EditMode,
+    pub completion: CompletionType,
+    pub bindings: Vec<KeyBinding>,
+    pub version: u64,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        EditorSettings {
+            mode: EditMode::Emacs,
+            completion: CompletionType::List,
+            bindings: vec![],
+            version: 0,
+        }
+    }
+}
+
+pub fn mode_name(mode: EditMode) -> &'static str {
+    match mode {
+        EditMode::Vi => "vi",
+        _ => "emacs",
+    }
+}
+
+pub fn parse_mode(name: &str) -> CrushResult<EditMode> {
+    match name {
+        "emacs" => Ok(EditMode::Emacs),
+        "vi" => Ok(EditMode::Vi),
+        _ => argument_error_legacy(format!("Unknown edit mode {}, expected emacs or vi", name)),
+    }
+}
+
+pub fn completion_name(completion: CompletionType) -> &'static str {
+    match completion {
+        CompletionType::Circular => "circular",
+        _ => "list",
+    }
+}
+
+pub fn parse_completion(name: &str) -> CrushResult<CompletionType> {
+    match name {
+        "list" => Ok(CompletionType::List),
+        "circular" => Ok(CompletionType::Circular),
+        _ => argument_error_legacy(format!("Unknown completion type {}, expected list or circular", name)),
+    }
+}
+
+/**
+Parse a key description like `ctrl-x`, `alt-shift-tab`, `f5` or `q`.
+ */
+pub fn parse_key(key: &str) -> CrushResult<KeyEvent> {
+    let mut modifiers = Modifiers::NONE;
+    let mut rest = key;
+    loop {
+        let lower = rest.to_lowercase();
+        let (prefix, modifier) = if lower.starts_with("ctrl-") {
+            ("ctrl-", Modifiers::CTRL)
+        } else if lower.starts_with("alt-") {
+            ("alt-", Modifiers::ALT)
+        } else if lower.starts_with("shift-") {
+            ("shift-", Modifiers::SHIFT)
+        } else {
+            break;
+        };
+        modifiers |= modifier;
+        rest = &rest[prefix.len()..];
+    }
+    let mut chars = rest.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyEvent::normalize(KeyEvent::new(c, modifiers)));
+    }
+    let code = match rest.to_lowercase().as_str() {
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "down" => KeyCode::Down,
+        "end" => KeyCode::End,
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "home" => KeyCode::Home,
+        "insert" => KeyCode::Insert,
+        "left" => KeyCode::Left,
+        "pagedown" => KeyCode::PageDown,
+        "pageup" => KeyCode::PageUp,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "up" => KeyCode::Up,
+        name => {
+            let number = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok());
+            match number {
+                Some(n) if (1..=24).contains(&n) => KeyCode::F(n),
+                _ => return argument_error_legacy(format!("Unknown key {}", key)),
+            }
+        }
+    };
+    Ok(KeyEvent::normalize(KeyEvent(code, modifiers)))
+}
+
+/**
+Convert the output of a key binding closure into the text to insert into the line.
+Lists and streams are inserted as their elements separated by spaces.
+ */
+fn insertion(value: Value) -> CrushResult<String> {
+    Ok(match value {
+        Value::Empty => String::new(),
+        Value::String(s) => s.to_string(),
+        Value::BinaryInputStream(_) | Value::Binary(_) => match value.materialize()? {
+            Value::Binary(b) => String::from_utf8_lossy(&b).trim_end().to_string(),
+            _ => String::new(),
+        },
+        value => match value.stream()? {
+            Some(mut stream) => {
+                let mut words = Vec::new();
+                while let Ok(row) = stream.read() {
+                    words.push(mandate(row.cells().first(), "Empty row")?.to_string());
+                }
+                words.join(" ")
+            }
+            None => value.to_string(),
+        },
+    })
+}
+
+/**
+Runs a closure bound to a key. The closure receives the current line and cursor position
+as the arguments `line` and `cursor`, and its output is inserted at the cursor.
+ */
+struct ClosureHandler {
+    command: Command,
+    env: Scope,
+    state: GlobalState,
+}
+
+impl ClosureHandler {
+    fn run(&self, line: &str, cursor: usize) -> CrushResult<String> {
+        let location = Location::new(0, 0);
+        let (sender, receiver) = pipe();
+        self.command.eval(
+            CommandContext::new(&self.env, &self.state)
+                .with_args(
+                    vec![
+                        Argument::named("line", Value::from(line), location),
+                        Argument::named("cursor", Value::Integer(cursor as i128), location),
+                    ],
+                    None)
+                .with_output(sender))?;
+        insertion(receiver.recv().unwrap_or(Value::Empty))
+    }
+}
+
+impl ConditionalEventHandler for ClosureHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        match self.run(ctx.line(), ctx.pos()) {
+            Ok(text) if !text.is_empty() => Some(Cmd::Insert(1, text)),
+            Ok(_) => Some(Cmd::Repaint),
+            Err(e) => {
+                self.state.printer().crush_error(e);
+                Some(Cmd::Repaint)
+            }
+        }
+    }
+}
+
+fn bind_defaults(rl: &mut Editor<RustylineHelper, CrushHistory>, history: &CrushHistory) {
+    rl.bind_sequence(
+        KeyEvent::alt('f'),
+        EventHandler::Conditional(Box::new(AcceptHintWord {})));
+    rl.bind_sequence(
+        KeyEvent::ctrl('r'),
+        EventHandler::Conditional(Box::new(HistorySearch { history: history.clone(), local: false })));
+    rl.bind_sequence(
+        KeyEvent::alt('r'),
+        EventHandler::Conditional(Box::new(HistorySearch { history: history.clone(), local: true })));
+}
+
+/**
+Apply the editor settings to the editor. The keys bound by the previous call are unbound
+first, and the keys bound by this call are returned.
+ */
+pub fn apply(
+    rl: &mut Editor<RustylineHelper, CrushHistory>,
+    settings: &EditorSettings,
+    previous: &[KeyEvent],
+    env: &Scope,
+    state: &GlobalState,
+) -> Vec<KeyEvent> {
+    rl.set_edit_mode(settings.mode);
+    rl.set_completion_type(settings.completion);
+    for event in previous {
+        rl.unbind_sequence(*event);
+    }
+    let history = rl.history().clone();
+    bind_defaults(rl, &history);
+    for binding in &settings.bindings {
+        rl.bind_sequence(binding.event, binding.action.handler(&history, env, state));
+    }
+    settings.bindings.iter().map(|b| b.event).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_parsed() {
+        assert_eq!(parse_key("ctrl-x").unwrap(), KeyEvent::normalize(KeyEvent::ctrl('x')));
+        assert_eq!(parse_key("Ctrl-X").unwrap(), parse_key("ctrl-x").unwrap());
+        assert_eq!(parse_key("alt-f").unwrap(), KeyEvent::alt('f'));
+        assert_eq!(parse_key("f5").unwrap(), KeyEvent(KeyCode::F(5), Modifiers::NONE));
+        assert_eq!(parse_key("shift-tab").unwrap(), KeyEvent(KeyCode::BackTab, Modifiers::NONE));
+        assert_eq!(parse_key("ctrl-alt-up").unwrap(), KeyEvent(KeyCode::Up, Modifiers::CTRL_ALT));
+        assert_eq!(parse_key("q").unwrap(), KeyEvent::from('q'));
+        assert!(parse_key("ctrl-").is_err());
+        assert!(parse_key("hyper-x").is_err());
+        assert!(parse_key("f99").is_err());
+    }
+
+    #[test]
+    fn all_actions_are_known() {
+        for (name, _) in ACTIONS {
+            let special = ["accept-hint-word", "reverse-search-history", "reverse-search-history-local"];
+            assert!(special.contains(name) || action_command(name).is_some(), "{}", name);
+        }
+    }
+}
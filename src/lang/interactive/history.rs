@@ -0,0 +1,634 @@
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use chrono::{DateTime, Duration, Local};
+use rustyline::config::HistoryDuplicates;
+use rustyline::history::{History, SearchDirection, SearchResult};
+use rustyline::{Cmd, ConditionalEventHandler, Config, Event, EventContext, RepeatCount};
+use serde_json::json;
+use crate::lang::errors::{CrushResult, to_crush_error};
+use crate::util::file::cwd;
+
+/**
+A single command in the history, along with when, where and how it was run. Entries imported
+from the old plain text history file have no metadata.
+ */
+#[derive(Clone)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub time: Option<DateTime<Local>>,
+    pub directory: Option<PathBuf>,
+    pub duration: Option<Duration>,
+    pub success: Option<bool>,
+    pub session: Option<String>,
+}
+
+impl HistoryEntry {
+    fn to_json(&self) -> String {
+        json!({
+            "command": self.command,
+            "time": self.time.map(|t| t.to_rfc3339()),
+            "directory": self.directory.as_ref().map(|d| d.to_string_lossy().to_string()),
+            "duration": self.duration.map(|d| d.num_milliseconds()),
+            "success": self.success,
+            "session": self.session,
+        }).to_string()
+    }
+
+    fn from_json(line: &str) -> Option<HistoryEntry> {
+        let value: serde_json::Value = serde_json::from_str(line).ok()?;
+        Some(HistoryEntry {
+            command: value.get("command")?.as_str()?.to_string(),
+            time: value.get("time")
+                .and_then(|t| t.as_str())
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Local)),
+            directory: value.get("directory").and_then(|d| d.as_str()).map(PathBuf::from),
+            duration: value.get("duration").and_then(|d| d.as_i64()).map(Duration::milliseconds),
+            success: value.get("success").and_then(|s| s.as_bool()),
+            session: value.get("session").and_then(|s| s.as_str()).map(|s| s.to_string()),
+        })
+    }
+}
+
+/**
+A lock file next to the history file. It is held while appending to the history file and while
+compacting it, so that no entries are lost when a session appends to the file while another one
+rewrites it. The lock file is removed when this is dropped.
+ */
+struct HistoryLock {
+    path: PathBuf,
+}
+
+/** Lock files older than this were left behind by a session that died while holding the lock. */
+const STALE_LOCK: std::time::Duration = std::time::Duration::from_secs(5);
+
+impl HistoryLock {
+    fn acquire(file: &Path) -> std::io::Result<HistoryLock> {
+        let path = file.with_extension("lock");
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(HistoryLock { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK);
+                    if stale {
+                        let _ = std::fs::remove_file(&path);
+                    } else {
+                        sleep(std::time::Duration::from_millis(10));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for HistoryLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct HistoryData {
+    entries: Vec<HistoryEntry>,
+    max_len: usize,
+    ignore_space: bool,
+    ignore_dups: bool,
+    session: String,
+    file: Option<PathBuf>,
+    /** The inode of the history file, used to notice when another session has compacted it. */
+    inode: Option<u64>,
+    /** How many bytes of the history file have been read. */
+    offset: u64,
+    /** How many entries have been read from the history file. */
+    lines: usize,
+    /** The index of the entry of the command that is currently running, if any. */
+    pending: Option<usize>,
+    /** Whether searches only match commands that were run in the current working directory. */
+    local_search: bool,
+}
+
+impl HistoryData {
+    fn ignore(&self, line: &str) -> bool {
+        self.max_len == 0
+            || line.is_empty()
+            || (self.ignore_space && line.starts_with(char::is_whitespace))
+    }
+
+    fn truncate(&mut self) {
+        if self.entries.len() > self.max_len {
+            let excess = self.entries.len() - self.max_len;
+            self.entries.drain(..excess);
+            self.pending = self.pending.and_then(|idx| idx.checked_sub(excess));
+        }
+    }
+
+    /**
+    Read all complete entries that have been appended to the history file since it was last read.
+     */
+    fn read_new(&mut self) -> std::io::Result<Vec<HistoryEntry>> {
+        let mut file = match &self.file {
+            Some(path) if path.exists() => File::open(path)?,
+            _ => return Ok(vec![]),
+        };
+        let inode = file.metadata()?.ino();
+        let compacted = self.inode.is_some_and(|i| i != inode);
+        if self.inode != Some(inode) {
+            self.inode = Some(inode);
+            self.offset = 0;
+            self.lines = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        // Another session might be in the middle of writing the last line
+        let complete = buf.iter().rposition(|b| *b == b'\n').map(|idx| idx + 1).unwrap_or(0);
+        self.offset += complete as u64;
+        let lines = String::from_utf8_lossy(&buf[..complete]).lines().map(|l| l.to_string()).collect::<Vec<_>>();
+        self.lines += lines.len();
+        let mut entries = lines.iter()
+            .filter_map(|l| HistoryEntry::from_json(l))
+            .collect::<Vec<_>>();
+        if compacted {
+            // The compacted file was read from the start, so skip the entries we already have
+            entries.retain(|e| !self.entries.iter().any(|known|
+                known.command == e.command && known.time == e.time && known.session == e.session));
+        }
+        Ok(entries)
+    }
+
+    /**
+    Rewrite the history file to only contain the last `max_len` entries, once it contains more
+    than twice as many. Compaction is skipped if another session has appended to the file since
+    it was last read; it will happen on a later call instead.
+     */
+    fn compact(&mut self) -> std::io::Result<()> {
+        let path = match &self.file {
+            Some(path) if self.lines > 2 * self.max_len => path.clone(),
+            _ => return Ok(()),
+        };
+        let _lock = HistoryLock::acquire(&path)?;
+        let mut content = Vec::new();
+        let mut file = File::open(&path)?;
+        if Some(file.metadata()?.ino()) != self.inode {
+            return Ok(());
+        }
+        file.read_to_end(&mut content)?;
+        if content.len() as u64 != self.offset {
+            return Ok(());
+        }
+        let content = String::from_utf8_lossy(&content);
+        let lines = content.lines().collect::<Vec<_>>();
+        let keep = &lines[lines.len().saturating_sub(self.max_len)..];
+        let mut buf = String::new();
+        for line in keep {
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        // Write to a new file and rename it, so that a crash never leaves a truncated history
+        let tmp = path.with_extension("tmp");
+        File::create(&tmp)?.write_all(buf.as_bytes())?;
+        std::fs::rename(&tmp, &path)?;
+        self.inode = Some(std::fs::metadata(&path)?.ino());
+        self.offset = buf.len() as u64;
+        self.lines = keep.len();
+        Ok(())
+    }
+
+    /**
+    Append entries to the history file. Every write is a single append under the lock, so that
+    concurrent sessions never overwrite each other's entries.
+     */
+    fn write(&self, entries: &[HistoryEntry]) -> std::io::Result<()> {
+        if let Some(path) = &self.file {
+            let mut buf = String::new();
+            for entry in entries {
+                buf.push_str(&entry.to_json());
+                buf.push('\n');
+            }
+            let _lock = HistoryLock::acquire(path)?;
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(buf.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn search_match(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+        test: impl Fn(&str) -> Option<usize>,
+    ) -> Option<SearchResult<'static>> {
+        if term.is_empty() || start >= self.entries.len() {
+            return None;
+        }
+        let directory = if self.local_search { cwd().ok() } else { None };
+        let matches = |idx: usize| {
+            let entry = &self.entries[idx];
+            if directory.is_some() && entry.directory != directory {
+                return None;
+            }
+            test(&entry.command).map(|pos| SearchResult {
+                entry: Cow::Owned(entry.command.clone()),
+                idx,
+                pos,
+            })
+        };
+        match dir {
+            SearchDirection::Reverse => (0..=start).rev().find_map(matches),
+            SearchDirection::Forward => (start..self.entries.len()).find_map(matches),
+        }
+    }
+}
+
+/**
+Undo the escaping of backslashes and newlines in the plain text history format.
+ */
+fn unescape_legacy(line: &str) -> String {
+    let mut res = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                res.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                res.push('\\');
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/**
+The history of the interactive prompt. Each command is stored together with when and where it was
+run, how long it took and whether it succeeded.
+
+The history file contains one JSON object per line. Entries are appended to it, and entries
+written by other sessions are merged in when the file is read. Once the file contains more than
+twice the maximum number of history entries, it is rewritten to only contain the most recent ones.
+ */
+#[derive(Clone)]
+pub struct CrushHistory {
+    data: Arc<Mutex<HistoryData>>,
+}
+
+impl CrushHistory {
+    pub fn new(config: &Config) -> CrushHistory {
+        CrushHistory {
+            data: Arc::from(Mutex::new(HistoryData {
+                entries: Vec::new(),
+                max_len: config.max_history_size(),
+                ignore_space: config.history_ignore_space(),
+                ignore_dups: config.history_duplicates() == HistoryDuplicates::IgnoreConsecutive,
+                session: format!("{:016x}", rand::random::<u64>()),
+                file: None,
+                inode: None,
+                offset: 0,
+                lines: 0,
+                pending: None,
+                local_search: false,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HistoryData> {
+        self.data.lock().unwrap()
+    }
+
+    /**
+    Import a history file in the plain text format used by older versions of Crush into the
+    current history file.
+     */
+    pub fn import(&self, path: &Path) -> CrushResult<()> {
+        let mut content = String::new();
+        to_crush_error(File::open(path).and_then(|mut f| f.read_to_string(&mut content)))?;
+        let entries = content.lines()
+            .filter(|line| *line != "#V2" && !line.is_empty())
+            .map(|line| HistoryEntry {
+                command: unescape_legacy(line),
+                time: None,
+                directory: None,
+                duration: None,
+                success: None,
+                session: None,
+            })
+            .collect::<Vec<_>>();
+        let mut data = self.lock();
+        to_crush_error(data.write(&entries))?;
+        data.entries.extend(entries);
+        data.truncate();
+        Ok(())
+    }
+
+    /**
+    Merge in the entries that other sessions have written to the history file since it was last
+    read, and compact the file if it has grown too large.
+     */
+    pub fn merge(&self) -> CrushResult<()> {
+        let mut data = self.lock();
+        let session = data.session.clone();
+        let new = to_crush_error(data.read_new())?
+            .into_iter()
+            .filter(|e| e.session.as_ref() != Some(&session))
+            .collect::<Vec<_>>();
+        // Keep the currently running command last, so that it can be completed later
+        let idx = data.pending.unwrap_or(data.entries.len());
+        data.pending = data.pending.map(|idx| idx + new.len());
+        data.entries.splice(idx..idx, new);
+        data.truncate();
+        to_crush_error(data.compact())
+    }
+
+    /**
+    Record the duration and outcome of the most recently added command, and append it to the
+    history file.
+     */
+    pub fn finish(&self, duration: Duration, success: bool) -> CrushResult<()> {
+        let mut data = self.lock();
+        match data.pending.take() {
+            Some(idx) => {
+                data.entries[idx].duration = Some(duration);
+                data.entries[idx].success = Some(success);
+                let entry = data.entries[idx].clone();
+                to_crush_error(data.write(&[entry]))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.lock().entries.clone()
+    }
+
+    pub fn last(&self) -> Option<String> {
+        self.lock().entries.last().map(|e| e.command.clone())
+    }
+
+    /**
+    The most recent command that starts with the specified prefix and is longer than it. Commands
+    that were run in the current working directory are preferred over more recent ones that
+    weren't.
+     */
+    pub fn suggest(&self, prefix: &str) -> Option<String> {
+        let data = self.lock();
+        let directory = cwd().ok();
+        let mut candidates = data.entries.iter()
+            .rev()
+            .filter(|e| e.command.len() > prefix.len() && e.command.starts_with(prefix));
+        let first = candidates.next()?;
+        if directory.is_none() || first.directory == directory {
+            return Some(first.command.clone());
+        }
+        Some(candidates
+            .find(|e| e.directory == directory)
+            .unwrap_or(first)
+            .command.clone())
+    }
+
+    pub fn set_local_search(&self, local: bool) {
+        self.lock().local_search = local;
+    }
+}
+
+impl History for CrushHistory {
+    fn get(&self, index: usize, _dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.lock().entries.get(index).map(|e| SearchResult {
+            entry: Cow::Owned(e.command.clone()),
+            idx: index,
+            pos: 0,
+        }))
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        self.add_owned(line.to_string())
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        let mut data = self.lock();
+        data.pending = None;
+        if data.ignore(&line) {
+            return Ok(false);
+        }
+        // Consecutive duplicates are collapsed in memory, but both are kept in the file
+        if data.ignore_dups && data.entries.last().is_some_and(|e| e.command == line) {
+            data.entries.pop();
+        }
+        let session = data.session.clone();
+        data.entries.push(HistoryEntry {
+            command: line,
+            time: Some(Local::now()),
+            directory: cwd().ok(),
+            duration: None,
+            success: None,
+            session: Some(session),
+        });
+        data.pending = Some(data.entries.len() - 1);
+        data.truncate();
+        Ok(true)
+    }
+
+    fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        let mut data = self.lock();
+        data.max_len = len;
+        data.truncate();
+        Ok(())
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        self.lock().ignore_dups = yes;
+        Ok(())
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        self.lock().ignore_space = yes;
+    }
+
+    /** Entries are appended to the history file as each command finishes, so this does nothing. */
+    fn save(&mut self, _path: &Path) -> rustyline::Result<()> {
+        Ok(())
+    }
+
+    /** Entries are appended to the history file as each command finishes, so this does nothing. */
+    fn append(&mut self, _path: &Path) -> rustyline::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        let mut data = self.lock();
+        data.file = Some(path.to_path_buf());
+        data.inode = None;
+        data.offset = 0;
+        data.pending = None;
+        data.entries = data.read_new()?;
+        data.truncate();
+        Ok(data.compact()?)
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        let mut data = self.lock();
+        data.entries.clear();
+        data.pending = None;
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.lock().search_match(term, start, dir, |entry| entry.find(term)))
+    }
+
+    fn starts_with(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.lock().search_match(
+            term,
+            start,
+            dir,
+            |entry| if entry.starts_with(term) { Some(term.len()) } else { None }))
+    }
+}
+
+/**
+Start or continue a reverse history search, either through the whole history, or only through
+the commands that were run in the current working directory.
+ */
+pub struct HistorySearch {
+    pub history: CrushHistory,
+    pub local: bool,
+}
+
+impl ConditionalEventHandler for HistorySearch {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, _ctx: &EventContext) -> Option<Cmd> {
+        self.history.set_local_search(self.local);
+        Some(Cmd::ReverseSearchHistory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, directory: &str) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            time: Some(Local::now()),
+            directory: Some(PathBuf::from(directory)),
+            duration: Some(Duration::milliseconds(12)),
+            success: Some(false),
+            session: Some("abc".to_string()),
+        }
+    }
+
+    #[test]
+    fn legacy_unescape() {
+        assert_eq!(unescape_legacy("echo a"), "echo a");
+        assert_eq!(unescape_legacy(r"echo a\nb"), "echo a\nb");
+        assert_eq!(unescape_legacy(r"echo a\\nb"), r"echo a\nb");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let e = entry("echo \"a\"\nb", "/tmp");
+        let parsed = HistoryEntry::from_json(&e.to_json()).unwrap();
+        assert_eq!(parsed.command, e.command);
+        assert_eq!(parsed.directory, e.directory);
+        assert_eq!(parsed.duration, e.duration);
+        assert_eq!(parsed.success, e.success);
+        assert_eq!(parsed.session, e.session);
+        assert!(HistoryEntry::from_json("{\"command\": \"ls\"").is_none());
+    }
+
+    #[test]
+    fn suggestion_prefers_current_directory() {
+        let history = CrushHistory::new(&Config::default());
+        let here = cwd().unwrap().to_string_lossy().to_string();
+        history.lock().entries.extend(vec![
+            entry("echo here", &here),
+            entry("echo elsewhere", "/nonexistent"),
+        ]);
+        assert_eq!(history.suggest("echo"), Some("echo here".to_string()));
+        assert_eq!(history.suggest("echo e"), Some("echo elsewhere".to_string()));
+        assert_eq!(history.suggest("echo here"), None);
+    }
+
+    #[test]
+    fn sessions_merge_appended_entries() {
+        let file = std::env::temp_dir().join(format!("crush-history-{:016x}.jsonl", rand::random::<u64>()));
+        let mut a = CrushHistory::new(&Config::default());
+        let mut b = CrushHistory::new(&Config::default());
+        a.load(&file).unwrap();
+        b.load(&file).unwrap();
+
+        a.add("echo a").unwrap();
+        a.finish(Duration::milliseconds(1), true).unwrap();
+        b.add("echo b").unwrap();
+        b.finish(Duration::milliseconds(1), false).unwrap();
+        a.merge().unwrap();
+
+        let commands = a.entries().into_iter().map(|e| e.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["echo a", "echo b"]);
+        let mut c = CrushHistory::new(&Config::default());
+        c.load(&file).unwrap();
+        assert_eq!(c.len(), 2);
+        assert_eq!(c.entries()[1].success, Some(false));
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn large_files_are_compacted() {
+        let file = std::env::temp_dir().join(format!("crush-history-{:016x}.jsonl", rand::random::<u64>()));
+        let mut a = CrushHistory::new(&Config::default());
+        let mut b = CrushHistory::new(&Config::default());
+        a.set_max_len(2).unwrap();
+        a.load(&file).unwrap();
+        b.load(&file).unwrap();
+
+        for i in 0..5 {
+            a.add(&format!("echo {}", i)).unwrap();
+            a.finish(Duration::milliseconds(1), true).unwrap();
+        }
+        b.merge().unwrap();
+        a.merge().unwrap();
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(!file.with_extension("lock").exists());
+
+        // The entries of the compacted file that b has already read are not merged in again
+        a.add("echo 5").unwrap();
+        a.finish(Duration::milliseconds(1), true).unwrap();
+        b.merge().unwrap();
+        let commands = b.entries().into_iter().map(|e| e.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["echo 0", "echo 1", "echo 2", "echo 3", "echo 4", "echo 5"]);
+        let _ = std::fs::remove_file(&file);
+    }
+}
@@ -1,10 +1,14 @@
+pub mod password_helper;
 pub mod rustyline_helper;
+pub mod history;
+pub mod editor;
 
 use std::fs;
 use rustyline;
 
 use rustyline::error::ReadlineError;
-use rustyline::{Editor, Config, CompletionType, EditMode};
+use rustyline::{Editor, Config};
+use rustyline::history::{DefaultHistory, History};
 use crate::util::file::home;
 use std::path::PathBuf;
 use crate::lang::state::scope::Scope;
@@ -18,6 +22,8 @@ use crate::lang::command_invocation::CommandInvocation;
 use crate::lang::value::{ValueDefinition, Value};
 use crate::lang::ast::location::Location;
 use crate::lang::state::contexts::JobContext;
+use chrono::Local;
+use history::CrushHistory;
 
 const DEFAULT_PROMPT: &'static str = "crush# ";
 
@@ -31,9 +37,28 @@ pub fn config_dir() -> CrushResult<PathBuf> {
 }
 
 fn crush_history_file() -> CrushResult<PathBuf> {
+    Ok(config_dir()?.join("history.jsonl"))
+}
+
+/**
+The plain text history file used by older versions of Crush.
+ */
+fn legacy_history_file() -> CrushResult<PathBuf> {
     Ok(config_dir()?.join("history"))
 }
 
+fn load_history(history: &mut CrushHistory) -> CrushResult<()> {
+    let file = crush_history_file()?;
+    ensure_parent_exists(&file)?;
+    let new = !file.exists();
+    to_crush_error(history.load(&file))?;
+    let legacy = legacy_history_file()?;
+    if new && legacy.exists() {
+        history.import(&legacy)?;
+    }
+    Ok(())
+}
+
 pub fn execute_prompt(
     prompt: Option<Command>,
     env: &Scope,
@@ -61,6 +86,18 @@ pub fn execute_prompt(
     }
 }
 
+/**
+Read a password from the user. The input is masked, and is not added to any history.
+ */
+pub fn read_password(prompt: &str) -> CrushResult<String> {
+    let config = Config::builder()
+        .auto_add_history(false)
+        .build();
+    let mut rl: Editor<password_helper::PasswordHelper, DefaultHistory> = to_crush_error(Editor::with_config(config))?;
+    rl.set_helper(Some(password_helper::PasswordHelper {}));
+    to_crush_error(rl.readline(prompt))
+}
+
 pub fn load_init(
     env: &Scope,
     global_state: &GlobalState,
@@ -86,23 +123,34 @@ pub fn run(
 
     let config = Config::builder()
         .history_ignore_space(true)
-        .completion_type(CompletionType::List)
-        .edit_mode(EditMode::Emacs)
+        .max_history_size(10000)?
         .build();
 
+    let mut history = CrushHistory::new(&config);
+    printer.handle_error(load_history(&mut history));
+
     let h = rustyline_helper::RustylineHelper::new(
         global_state.clone(),
         global_env.clone(),
+        history.clone(),
     );
 
-    let mut rl = Editor::with_config(config)?;
+    let mut rl = Editor::with_history(config, history.clone())?;
     rl.set_helper(Some(h));
     global_state.set_editor(Some(rl));
+    global_state.set_history(Some(history.clone()));
+
+    let mut applied_version = None;
+    let mut bound_keys = Vec::new();
 
-    if let Ok(file) = crush_history_file() {
-        let _ = global_state.editor().as_mut().map(|rl| { rl.load_history(&file) });
-    }
     loop {
+        let settings = global_state.editor_settings();
+        if applied_version != Some(settings.version) {
+            if let Some(rl) = global_state.editor().as_mut() {
+                bound_keys = editor::apply(rl, &settings, &bound_keys, &global_env, global_state);
+            }
+            applied_version = Some(settings.version);
+        }
         let prompt = match execute_prompt(global_state.prompt(), &global_env, global_state) {
             Ok(s) => s,
             Err(e) => {
@@ -119,23 +167,25 @@ pub fn run(
                 } else {
 
                     if cmd.trim() == "!!" {
-                        cmd = global_state
-                            .editor().as_mut()
-                            .map(|rl| { rl.history().into_iter().last().map(|s| {s.to_string()})})
-                                .unwrap_or(None).unwrap_or(cmd);
+                        cmd = history.last().unwrap_or(cmd);
                     }
                     global_state.editor().as_mut().map(|rl| { rl.add_history_entry(&cmd) });
                     global_state.threads().reap(global_state.printer());
-                    global_state
-                        .printer()
-                        .handle_error(
-                            execute::string(
-                                &global_env,
-                                &cmd,
-                                pretty_printer,
-                                global_state,
-                            ));
+                    let start = Local::now();
+                    let success = match execute::string(
+                        &global_env,
+                        &cmd,
+                        pretty_printer,
+                        global_state,
+                    ) {
+                        Ok(success) => success,
+                        Err(e) => {
+                            global_state.printer().crush_error(e);
+                            false
+                        }
+                    };
                     global_state.threads().reap(global_state.printer());
+                    global_state.printer().handle_error(history.finish(Local::now() - start, success));
                     if global_state.exit_status().is_some() {
                         break;
                     }
@@ -157,31 +207,11 @@ pub fn run(
                 break;
             }
         }
-
-        if let Ok(file) = crush_history_file() {
-            if ensure_parent_exists(&file).is_ok() {
-                match global_state.editor().as_mut().map(|rl| { rl.save_history(&file) }) {
-                    Some(Err(err)) =>
-                        global_state.printer().line(&format!(
-                            "Error: Failed to save history to {}: {}",
-                            file.as_os_str().to_str().unwrap_or("???"),
-                            err)),
-                    None => {
-                        global_state.printer().line("no editor!");
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-        }
+        history.set_local_search(false);
     }
 
-    if let Ok(file) = crush_history_file() {
-        if let Some(Err(err)) = global_state.editor().as_mut().map(|rl| { rl.save_history(&file) }) {
-            global_state.printer().line(&format!("Error: Failed to save history: {}", err))
-        }
-    }
     global_state.set_editor(None);
+    global_state.set_history(None);
     Ok(())
 }
 
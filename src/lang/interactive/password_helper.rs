@@ -0,0 +1,34 @@
+use std::borrow::Cow;
+use std::borrow::Cow::Owned;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline_derive::Helper;
+
+/**
+A rustyline helper used when reading passwords. It replaces every character of the input with an
+asterisk, and does no completion, hinting or validation.
+ */
+#[derive(Helper)]
+pub struct PasswordHelper {}
+
+impl Completer for PasswordHelper {
+    type Candidate = String;
+}
+
+impl Hinter for PasswordHelper {
+    type Hint = String;
+}
+
+impl Validator for PasswordHelper {}
+
+impl Highlighter for PasswordHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Owned("*".repeat(line.chars().count()))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+}
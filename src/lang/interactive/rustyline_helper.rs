@@ -2,34 +2,53 @@ use rustyline::validate::{ValidationResult, Validator};
 use rustyline::{validate, Context};
 use std::borrow::Cow::{Owned, Borrowed};
 use std::borrow::Cow;
-use rustyline::hint::{Hinter, HistoryHinter};
-use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+use rustyline::highlight::Highlighter;
 use rustyline::error::ReadlineError;
 use rustyline::completion::{Pair, Completer};
 use crate::lang::errors::CrushResult;
 use std::cmp::min;
+use crate::lang::ast::lexer::Lexer;
+use crate::lang::ast::location::Location;
 use crate::lang::ast::token::Token;
+use crate::lang::check::{check_reference, lexer_error_location, resolve};
+use crate::lang::data::dict::Dict;
+use crate::lang::lsp::analysis::Analysis;
 use crate::lang::value::Value;
 use crate::util::directory_lister::directory_lister;
 use crate::lang::state::scope::Scope;
 use rustyline_derive::Helper;
 use crate::lang::state::global_state::GlobalState;
+use crate::lang::interactive::history::CrushHistory;
 
 #[derive(Helper)]
 pub struct RustylineHelper {
     state: GlobalState,
     scope: Scope,
-    highlighter: MatchingBracketHighlighter,
-    hinter: HistoryHinter,
+    history: CrushHistory,
 }
 
 impl RustylineHelper {
-    pub fn new(state: GlobalState, scope: Scope) -> RustylineHelper {
+    pub fn new(state: GlobalState, scope: Scope, history: CrushHistory) -> RustylineHelper {
         RustylineHelper {
             state,
             scope,
-            highlighter: MatchingBracketHighlighter::new(),
-            hinter: HistoryHinter {},
+            history,
+        }
+    }
+
+    /**
+    The only valid continuation of the line, if there is exactly one.
+     */
+    fn completion_hint(&self, line: &str, ctx: &Context<'_>) -> Option<String> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        match self.complete_internal(line, line.len(), ctx) {
+            Ok((_, completions)) if completions.len() == 1 && !completions[0].replacement.is_empty() =>
+                Some(completions[0].replacement.clone()),
+            _ => None,
         }
     }
 
@@ -49,61 +68,150 @@ impl RustylineHelper {
         Ok((pos, crunched))
     }
 
-    fn get_color(&self, token_type: Token) -> Option<String> {
-        if let Ok(Value::Dict(highlight)) = self.scope.get_absolute_path(
+    fn highlight_dict(&self) -> Option<Dict> {
+        match self.scope.get_absolute_path(
             vec!["global".to_string(), "crush".to_string(), "highlight".to_string()]) {
-            use Token::*;
-            let res = match token_type {
-                Flag(_, _) | String(_, _) | QuotedString(_, _) => highlight.get(&Value::from("string_literal")),
-                Regex(_, _) => highlight.get(&Value::from("string_literal")),
-                File(_, _) | Glob(_, _) | QuotedFile(_, _) => highlight.get(&Value::from("file_literal")),
-                Float(_, _) | Integer(_, _) => highlight.get(&Value::from("numeric_literal")),
-                Unnamed(_) | Named( _) | Pipe( _) | LogicalOperator(_, _) | UnaryOperator(_, _) |
-                ComparisonOperator(_, _) | Equals( _) | Declare( _) | GetItemEnd( _) | GetItemStart( _) | SubEnd( _) |
-                Bang(_) | Plus(_) | Minus(_) | Star(_) | Slash(_) | MemberOperator(_) | ExprModeStart(_) |
-                SubStart( _) | JobEnd( _) | JobStart( _) =>
-                    highlight.get(&Value::from("operator")),
-                Identifier(_, _) => None,
-                Separator(_, _) => None,
-            };
-            match res {
-                Some(Value::String(s)) => Some(s.to_string()),
-                _ => None,
-            }
-        } else {
-            None
+            Ok(Value::Dict(highlight)) => Some(highlight),
+            _ => None,
         }
     }
 
-    fn highlight_internal(&self, line: &str, _cursor: usize) -> CrushResult<String> {
-        let mut res = String::new();
-        let mut pos = 0;
-        for tok in self.state.parser().tokenize(
-            &self.state.parser().close_token(line))? {
-            if pos >= line.len() {
-                break;
+    /**
+    Semantic classes of parts of the line, i.e. unknown commands and variables, builtin and
+    external commands, and the offending span of a parse error. Incomplete lines are closed
+    before parsing, so that only actual errors are reported while typing.
+     */
+    fn semantic_spans(&self, line: &str) -> Vec<(Location, &'static str)> {
+        let parser = self.state.parser();
+        let ast = match parser.ast(line) {
+            Ok(ast) => ast,
+            Err(_) => match parser.close_command(line).and_then(|closed| parser.ast(&closed)) {
+                Ok(ast) => ast,
+                Err(e) => return vec![(
+                    e.location().unwrap_or_else(|| lexer_error_location(line)),
+                    "parse_error")],
+            },
+        };
+        let analysis = Analysis::new(&ast, line.len());
+        let mut res = Vec::new();
+        for reference in &analysis.references {
+            if let Some((location, _)) = check_reference(&self.scope, &analysis, reference) {
+                res.push((location, "error"));
+            } else if reference.is_command {
+                let root = &reference.path[0];
+                if analysis.declaration(&root.string, root.location.start).is_some() {
+                    continue;
+                }
+                match resolve(&self.scope, &reference.path) {
+                    Ok(Value::Command(_)) => res.push((reference.location(), "command")),
+                    Err(0) if reference.path.len() == 1 => res.push((root.location, "external_command")),
+                    _ => {}
+                }
             }
-            if tok.location().start >= line.len() {
-                break;
+        }
+        res
+    }
+
+    /**
+    The locations of the bracket at or right before the cursor and the bracket matching it.
+     */
+    fn matching_brackets(tokens: &[Token], cursor: usize) -> Vec<Location> {
+        let is_open = |t: &Token| matches!(
+            t, Token::SubStart(_) | Token::ExprModeStart(_) | Token::JobStart(_) | Token::GetItemStart(_));
+        let is_close = |t: &Token| matches!(t, Token::SubEnd(_) | Token::JobEnd(_) | Token::GetItemEnd(_));
+        let brackets = tokens.iter().filter(|t| is_open(t) || is_close(t)).collect::<Vec<_>>();
+        let idx = match brackets.iter().position(|t| t.location().start == cursor)
+            .or_else(|| brackets.iter().position(|t| t.location().end == cursor)) {
+            Some(idx) => idx,
+            None => return vec![],
+        };
+        let mut depth = 0;
+        let candidates: Box<dyn Iterator<Item=&&Token>> = if is_open(brackets[idx]) {
+            Box::new(brackets[idx..].iter())
+        } else {
+            Box::new(brackets[..=idx].iter().rev())
+        };
+        for t in candidates {
+            if is_open(t) == is_open(brackets[idx]) {
+                depth += 1;
+            } else {
+                depth -= 1;
+                if depth == 0 {
+                    return vec![brackets[idx].location(), t.location()];
+                }
             }
-            res.push_str(&line[pos..min(tok.location().start, line.len())]);
-            let mut do_reset = false;
-            match self.get_color(tok) {
-                Some(color) => {
-                    if !color.is_empty() {
-                        do_reset = true;
-                        res.push_str(&color);
-                    }
+        }
+        vec![]
+    }
+
+    fn highlight_internal(&self, line: &str, cursor: usize) -> CrushResult<String> {
+        let highlight = match self.highlight_dict() {
+            Some(highlight) => highlight,
+            None => return Ok(line.to_string()),
+        };
+        let color = |class: &str| match highlight.get(&Value::from(class)) {
+            Some(Value::String(s)) => s.to_string(),
+            _ => String::new(),
+        };
+
+        // The escape codes to use for each byte of the line. Token colors are replaced by
+        // semantic colors, while parse errors and matching brackets are added on top.
+        let mut styles = vec![String::new(); line.len()];
+        let mut set = |location: Location, style: &str, replace: bool| {
+            for s in &mut styles[min(location.start, line.len())..min(location.end, line.len())] {
+                if replace {
+                    *s = style.to_string();
+                } else {
+                    s.push_str(style);
                 }
-                None => {}
             }
+        };
 
-            res.push_str(&line[tok.location().start..min(tok.location().end, line.len())]);
+        // Color the tokens up to the first lexical error, which is reported as a parse error below
+        let closed = self.state.parser().close_token(line);
+        let tokens = Lexer::new(&closed)
+            .map_while(|t| t.ok().map(|(_, token, _)| token))
+            .collect::<Vec<_>>();
+        for tok in &tokens {
+            if let Some(class) = tok.highlight_class() {
+                set(tok.location(), &color(class), true);
+            }
+        }
+        let semantic = self.semantic_spans(line);
+        for (location, class) in &semantic {
+            if *class != "parse_error" {
+                set(*location, &color(class), true);
+            }
+        }
+        for (location, class) in &semantic {
+            if *class == "parse_error" {
+                // Errors at the end of the line are shown on the last character
+                let location = if location.start >= line.len() {
+                    Location::new(line.len().saturating_sub(1), line.len())
+                } else {
+                    *location
+                };
+                set(location, &color(class), false);
+            }
+        }
+        for location in Self::matching_brackets(&tokens, cursor) {
+            set(location, &color("matching_bracket"), false);
+        }
 
-            if do_reset {
-                res.push_str("\x1b[0m");
+        let mut res = String::new();
+        let mut current = "";
+        for (idx, ch) in line.char_indices() {
+            if styles[idx] != current {
+                if !current.is_empty() {
+                    res.push_str("\x1b[0m");
+                }
+                current = &styles[idx];
+                res.push_str(current);
             }
-            pos = tok.location().end;
+            res.push(ch);
+        }
+        if !current.is_empty() {
+            res.push_str("\x1b[0m");
         }
         Ok(res)
     }
@@ -131,7 +239,34 @@ impl Completer for RustylineHelper {
 impl Hinter for RustylineHelper {
     type Hint = String;
     fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
-        self.hinter.hint(line, pos, ctx)
+        if !self.state.suggest() || line.is_empty() || pos < line.len() {
+            return None;
+        }
+        self.history.suggest(line)
+            .map(|command| command[line.len()..].to_string())
+            .or_else(|| self.completion_hint(line, ctx))
+    }
+}
+
+/**
+Accept the next word of the current suggestion. When there is no suggestion, or the cursor isn't
+at the end of the line, the key does what it would otherwise do.
+ */
+pub struct AcceptHintWord {}
+
+impl ConditionalEventHandler for AcceptHintWord {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.pos() < ctx.line().len() {
+            return None;
+        }
+        let hint = ctx.hint_text()?;
+        let start = hint.len() - hint.trim_start().len();
+        let end = hint[start..].find(char::is_whitespace).map(|idx| start + idx).unwrap_or(hint.len());
+        if end == 0 {
+            None
+        } else {
+            Some(Cmd::Insert(1, hint[..end].to_string()))
+        }
     }
 }
 
@@ -152,7 +287,11 @@ impl Highlighter for RustylineHelper {
     }
 
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
-        Owned("\x1b[1m".to_owned() + hint + "\x1b[m")
+        match self.highlight_dict().and_then(|highlight| highlight.get(&Value::from("hint"))) {
+            Some(Value::String(style)) if !style.is_empty() =>
+                Owned(style.to_string() + hint + "\x1b[0m"),
+            _ => Borrowed(hint),
+        }
     }
 
     fn highlight_char(&self, _line: &str, _pos: usize, forced: bool) -> bool {
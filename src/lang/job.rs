@@ -2,13 +2,24 @@ use crate::lang::command_invocation::CommandInvocation;
 use crate::lang::errors::CrushResult;
 use crate::lang::state::contexts::{CompileContext, JobContext};
 use crate::lang::pipe::pipe;
+use std::sync::Arc;
 use std::thread::ThreadId;
 use std::fmt::{Display, Formatter};
 use crate::lang::ast::location::Location;
 
+/**
+Record the command as the current position of the scope, and give the debugger a chance to pause
+before it is run.
+ */
+fn before(call_def: &Arc<CommandInvocation>, context: &JobContext) {
+    context.scope.set_position(call_def.clone());
+    context.global_state.debugger().before(&context.scope, &context.global_state);
+}
+
 #[derive(Clone)]
 pub struct Job {
-    commands: Vec<CommandInvocation>,
+    /// Shared, so that recording the position of a scope before each command is cheap.
+    commands: Vec<Arc<CommandInvocation>>,
     location: Location,
 }
 
@@ -18,7 +29,10 @@ impl Job {
     }
 
     pub fn new(commands: Vec<CommandInvocation>, location: Location) -> Job {
-        Job { commands, location }
+        Job {
+            commands: commands.into_iter().map(Arc::new).collect(),
+            location,
+        }
     }
 
     pub fn can_block(&self, context: &mut CompileContext) -> bool {
@@ -29,16 +43,23 @@ impl Job {
         }
     }
 
-    pub fn commands(&self) -> &[CommandInvocation] {
+    pub fn commands(&self) -> &[Arc<CommandInvocation>] {
         &self.commands
     }
 
     pub fn eval(&self, context: JobContext) -> CrushResult<Option<ThreadId>> {
         let context = context.running(self.to_string());
+        let stages = context.profile.as_ref()
+            .map(|profile| self.commands.iter().map(|c| profile.stage(c.to_string())).collect::<Vec<_>>());
         let mut input = context.input.clone();
         let last_job_idx = self.commands.len() - 1;
-        for call_def in &self.commands[..last_job_idx] {
-            let (output, next_input) = pipe();
+        for (idx, call_def) in self.commands[..last_job_idx].iter().enumerate() {
+            before(call_def, &context);
+            let (mut output, next_input) = pipe();
+            if let Some(stages) = &stages {
+                input = input.profiled(stages[idx].clone());
+                output = output.profiled(stages[idx].clone());
+            }
             call_def.eval(context.with_io(input, output))?;
             input = next_input;
 
@@ -52,7 +73,15 @@ impl Job {
         }
 
         let last_call_def = &self.commands[last_job_idx];
-        last_call_def.eval(context.with_io(input, context.output.clone())).map_err(|e| e.with_location(self.location))
+        before(last_call_def, &context);
+        let output = match &stages {
+            Some(stages) => {
+                input = input.profiled(stages[last_job_idx].clone());
+                context.output.profiled(stages[last_job_idx].clone())
+            }
+            None => context.output.clone(),
+        };
+        last_call_def.eval(context.with_io(input, output)).map_err(|e| e.with_location(self.location))
     }
 
     /** Extracts the help message from a closure definition */
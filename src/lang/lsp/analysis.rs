@@ -0,0 +1,193 @@
+use crate::lang::ast::location::Location;
+use crate::lang::ast::node::Node;
+use crate::lang::ast::parameter_node::ParameterNode;
+use crate::lang::ast::tracked_string::TrackedString;
+use crate::lang::ast::{CommandNode, JobListNode, JobNode};
+
+/**
+A variable declared in a document, either using `:=` or as a closure parameter.
+ */
+pub struct Declaration {
+    pub name: String,
+    pub location: Location,
+    /// The part of the document where the declaration is visible, i.e. the enclosing closure or
+    /// the whole document.
+    pub region: Location,
+}
+
+/**
+A use of a variable or command in a document, e.g. `$foo` or `files:cat`.
+ */
+pub struct Reference {
+    /// The identifier followed by any members accessed using `:`.
+    pub path: Vec<TrackedString>,
+    /// True if the reference is the command of a command invocation.
+    pub is_command: bool,
+    /// True if the reference is inside a closure. Closures passed to commands like `where` have
+    /// the columns of the input available as variables, so these can't be checked statically.
+    pub in_closure: bool,
+}
+
+impl Reference {
+    pub fn location(&self) -> Location {
+        self.path.iter()
+            .map(|s| s.location)
+            .reduce(|a, b| a.union(b))
+            .unwrap()
+    }
+}
+
+/**
+The declarations and references found in the abstract syntax tree of a document.
+ */
+pub struct Analysis {
+    pub declarations: Vec<Declaration>,
+    pub references: Vec<Reference>,
+}
+
+impl Analysis {
+    pub fn new(ast: &JobListNode, len: usize) -> Analysis {
+        let mut res = Analysis {
+            declarations: Vec::new(),
+            references: Vec::new(),
+        };
+        res.job_list(ast, Location::new(0, len), false);
+        res
+    }
+
+    /**
+    Find the declaration a name at the specified position refers to. Declarations in inner
+    closures take precedence, and within the same closure, the closest preceding declaration is
+    used. If there is no preceding one, a later declaration in the same closure is used, since a
+    closure may be called after a variable it uses has been declared.
+     */
+    pub fn declaration(&self, name: &str, offset: usize) -> Option<&Declaration> {
+        self.declarations.iter()
+            .filter(|d| d.name == name && d.region.contains(offset))
+            .max_by_key(|d| (d.region.start, d.location.start <= offset, d.location.start))
+    }
+
+    /**
+    Find the reference at the specified position, and the number of elements of its path up to
+    and including the one under the cursor.
+     */
+    pub fn reference(&self, offset: usize) -> Option<(&Reference, usize)> {
+        self.references.iter()
+            .filter_map(|r| r.path.iter()
+                .position(|s| s.location.contains(offset))
+                .map(|idx| (r, idx + 1)))
+            .min_by_key(|(r, _)| r.location().len())
+    }
+
+    fn declare(&mut self, name: &TrackedString, region: Location) {
+        self.declarations.push(Declaration {
+            // Named and unnamed argument parameters keep their leading dollar sign
+            name: name.string.trim_start_matches('$').to_string(),
+            location: name.location,
+            region,
+        });
+    }
+
+    fn job_list(&mut self, jobs: &JobListNode, region: Location, in_closure: bool) {
+        for job in &jobs.jobs {
+            self.job(job, region, in_closure);
+        }
+    }
+
+    fn job(&mut self, job: &JobNode, region: Location, in_closure: bool) {
+        for command in &job.commands {
+            self.command(command, region, in_closure);
+        }
+    }
+
+    fn command(&mut self, command: &CommandNode, region: Location, in_closure: bool) {
+        match (command.expressions.len(), &command.expressions[0]) {
+            (1, Node::Assignment(target, _, op, value)) => {
+                match (op.as_str(), target.as_ref()) {
+                    (":=", Node::Identifier(name)) => self.declare(name, region),
+                    _ => self.node(target, false, region, in_closure),
+                }
+                self.node(value, false, region, in_closure);
+            }
+            _ => {
+                self.node(&command.expressions[0], true, region, in_closure);
+                for argument in &command.expressions[1..] {
+                    self.node(argument, false, region, in_closure);
+                }
+            }
+        }
+    }
+
+    fn node(&mut self, node: &Node, is_command: bool, region: Location, in_closure: bool) {
+        match node {
+            // Outside of standalone assignments, the left hand side is the name of an argument
+            Node::Assignment(_, _, _, value) => self.node(value, false, region, in_closure),
+            Node::Unary(_, value) => self.node(value, false, region, in_closure),
+            Node::Identifier(_) | Node::String(_, false) | Node::GetAttr(_, _) => {
+                match path(node, is_command) {
+                    Some(path) => self.references.push(Reference {
+                        path,
+                        // A variable in command position, like `$foo` or `$foo:bar`, is a
+                        // variable lookup and not a command lookup
+                        is_command: is_command && !is_variable(node),
+                        in_closure,
+                    }),
+                    None => if let Node::GetAttr(parent, _) = node {
+                        self.node(parent, false, region, in_closure)
+                    },
+                }
+            }
+            Node::GetItem(container, key) => {
+                self.node(container, false, region, in_closure);
+                self.node(key, false, region, in_closure);
+            }
+            Node::Substitution(job) => self.job(job, region, in_closure),
+            Node::Closure(parameters, body) => {
+                let inner = body.location;
+                for parameter in parameters.iter().flatten() {
+                    match parameter {
+                        ParameterNode::Parameter(name, parameter_type, default) => {
+                            self.declare(name, inner);
+                            if let Some(t) = parameter_type {
+                                self.node(t, false, region, in_closure);
+                            }
+                            if let Some(d) = default {
+                                self.node(d, false, region, in_closure);
+                            }
+                        }
+                        ParameterNode::Named(name) | ParameterNode::Unnamed(name) =>
+                            self.declare(name, inner),
+                    }
+                }
+                self.job_list(body, inner, true);
+            }
+            Node::Glob(_) | Node::Regex(_) | Node::String(_, true) | Node::File(_, _) |
+            Node::Integer(_) | Node::Float(_) => {}
+        }
+    }
+}
+
+/**
+The path of identifiers that a node refers to, if the node is an identifier, possibly followed by
+member accesses. In command position, unquoted strings are identifiers too.
+ */
+pub fn path(node: &Node, is_command: bool) -> Option<Vec<TrackedString>> {
+    match node {
+        Node::Identifier(name) => Some(vec![name.clone()]),
+        Node::String(name, false) if is_command => Some(vec![name.clone()]),
+        Node::GetAttr(parent, name) => {
+            let mut res = path(parent, is_command)?;
+            res.push(name.clone());
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+fn is_variable(node: &Node) -> bool {
+    match node {
+        Node::Identifier(_) => true,
+        Node::GetAttr(parent, _) => is_variable(parent),
+        _ => false,
+    }
+}
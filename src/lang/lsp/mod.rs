@@ -0,0 +1,570 @@
+/**
+A language server for Crush scripts, speaking the Language Server Protocol over standard input
+and output. Started using `crush --lsp`.
+ */
+use crate::lang::ast::lexer::Lexer;
+use crate::lang::ast::location::Location;
+use crate::lang::ast::token::Token;
+use crate::lang::check::{check, resolve, Severity};
+use crate::lang::completion::complete;
+use crate::lang::errors::{error, to_crush_error, CrushResult};
+use crate::lang::help::help_text;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+use crate::util::directory_lister::directory_lister;
+use analysis::Analysis;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+pub mod analysis;
+
+/**
+The semantic token types reported to the client. The lexer tokens are mapped to these using the
+same classes as are used for syntax highlighting in interactive mode.
+ */
+const TOKEN_TYPES: [&str; 4] = ["string", "number", "operator", "variable"];
+
+/**
+The index into TOKEN_TYPES of the semantic token type of a token, if it has one.
+ */
+fn token_type(token: &Token) -> Option<usize> {
+    match token {
+        // Identifiers are references to variables, i.e. `$foo`, or `foo` in expression mode
+        Token::Identifier(_, _) => Some(3),
+        _ => match token.highlight_class() {
+            Some("string_literal") | Some("file_literal") => Some(0),
+            Some("numeric_literal") => Some(1),
+            Some("operator") => Some(2),
+            _ => None,
+        },
+    }
+}
+
+/**
+Read the body of the next message, or return None if the client closed the connection.
+ */
+fn read_message(input: &mut dyn BufRead) -> CrushResult<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if to_crush_error(input.read_line(&mut line))? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(to_crush_error(value.trim().parse::<usize>())?);
+        }
+    }
+    match length {
+        Some(length) => {
+            let mut buf = vec![0u8; length];
+            to_crush_error(input.read_exact(&mut buf))?;
+            Ok(Some(buf))
+        }
+        None => error("Missing Content-Length header in message"),
+    }
+}
+
+fn write_message(output: &mut dyn Write, message: &serde_json::Value) -> CrushResult<()> {
+    let body = message.to_string();
+    to_crush_error(write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body))?;
+    to_crush_error(output.flush())
+}
+
+/**
+Converts byte offsets into positions, which use zero based line numbers and UTF-16 based
+character offsets. Offsets must be passed in increasing order, so that converting all the tokens
+of a document only needs a single pass over the text.
+ */
+struct PositionTracker<'a> {
+    text: &'a str,
+    offset: usize,
+    line: u64,
+    character: u64,
+}
+
+impl PositionTracker<'_> {
+    fn new(text: &str) -> PositionTracker<'_> {
+        PositionTracker {
+            text,
+            offset: 0,
+            line: 0,
+            character: 0,
+        }
+    }
+
+    /**
+    Return the line and character of the specified offset. Offsets before the previous one are
+    treated as the previous one.
+     */
+    fn advance(&mut self, offset: usize) -> (u64, u64) {
+        let mut offset = offset.min(self.text.len()).max(self.offset);
+        while !self.text.is_char_boundary(offset) {
+            offset += 1;
+        }
+        for ch in self.text[self.offset..offset].chars() {
+            if ch == '\n' {
+                self.line += 1;
+                self.character = 0;
+            } else {
+                self.character += ch.len_utf16() as u64;
+            }
+        }
+        self.offset = offset;
+        (self.line, self.character)
+    }
+}
+
+fn position(text: &str, offset: usize) -> serde_json::Value {
+    let (line, character) = PositionTracker::new(text).advance(offset);
+    json!({
+        "line": line,
+        "character": character,
+    })
+}
+
+fn offset(text: &str, position: &serde_json::Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((idx, _)) => idx + 1,
+            None => return text.len(),
+        }
+    };
+    let mut units = 0;
+    for (idx, ch) in text[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return line_start + idx;
+        }
+        units += ch.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, location: Location) -> serde_json::Value {
+    json!({
+        "start": position(text, location.start),
+        "end": position(text, location.end.max(location.start)),
+    })
+}
+
+fn diagnostic(text: &str, location: Location, severity: u8, message: String) -> serde_json::Value {
+    json!({
+        "range": range(text, location),
+        "severity": severity,
+        "source": "crush",
+        "message": message,
+    })
+}
+
+struct Server<'a> {
+    env: Scope,
+    global_state: &'a GlobalState,
+    documents: HashMap<String, String>,
+}
+
+impl Server<'_> {
+    fn analysis(&self, text: &str) -> Option<Analysis> {
+        self.global_state.parser().ast(text)
+            .ok()
+            .map(|ast| Analysis::new(&ast, text.len()))
+    }
+
+    fn diagnostics(&self, text: &str) -> Vec<serde_json::Value> {
+        check(text, &self.env, self.global_state.parser())
+            .into_iter()
+            .map(|finding| diagnostic(
+                text,
+                finding.location,
+                match finding.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                },
+                finding.message))
+            .collect()
+    }
+
+    fn publish_diagnostics(&self, uri: &str, output: &mut dyn Write) -> CrushResult<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(text) => self.diagnostics(text),
+            None => vec![],
+        };
+        write_message(output, &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {
+                "uri": uri,
+                "diagnostics": diagnostics,
+            },
+        }))
+    }
+
+    fn document(&self, params: &serde_json::Value) -> CrushResult<(&str, usize)> {
+        match self.documents.get(params["textDocument"]["uri"].as_str().unwrap_or("")) {
+            Some(text) => Ok((text, offset(text, &params["position"]))),
+            None => error("Unknown document"),
+        }
+    }
+
+    fn completion(&self, params: &serde_json::Value) -> CrushResult<serde_json::Value> {
+        let (text, cursor) = self.document(params)?;
+        let line_start = text[..cursor].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = text[cursor..].find('\n').map(|idx| cursor + idx).unwrap_or(text.len());
+        let completions = complete(
+            &text[line_start..line_end],
+            cursor - line_start,
+            &self.env,
+            self.global_state.parser(),
+            &directory_lister())?;
+        let cursor_range = range(text, Location::new(cursor, cursor));
+        Ok(json!(completions.iter().map(|c| json!({
+            "label": c.display(),
+            "textEdit": {
+                "range": cursor_range,
+                "newText": c.replacement(),
+            },
+        })).collect::<Vec<_>>()))
+    }
+
+    fn hover(&self, params: &serde_json::Value) -> CrushResult<serde_json::Value> {
+        let (text, cursor) = self.document(params)?;
+        let analysis = match self.analysis(text) {
+            Some(analysis) => analysis,
+            None => return Ok(serde_json::Value::Null),
+        };
+        let (reference, len) = match analysis.reference(cursor) {
+            Some(r) => r,
+            None => return Ok(serde_json::Value::Null),
+        };
+        let root = &reference.path[0];
+        let contents = match analysis.declaration(&root.string, root.location.start) {
+            Some(declaration) if len == 1 => format!(
+                "Variable {}, declared on line {}",
+                declaration.name,
+                position(text, declaration.location.start)["line"].as_u64().unwrap_or(0) + 1),
+            Some(_) => return Ok(serde_json::Value::Null),
+            None => match resolve(&self.env, &reference.path[..len]) {
+                Ok(value) => help_text(&value),
+                Err(_) => return Ok(serde_json::Value::Null),
+            },
+        };
+        Ok(json!({
+            "contents": {
+                "kind": "plaintext",
+                "value": contents,
+            },
+            "range": range(text, reference.path[len - 1].location),
+        }))
+    }
+
+    fn definition(&self, params: &serde_json::Value) -> CrushResult<serde_json::Value> {
+        let (text, cursor) = self.document(params)?;
+        let definition = self.analysis(text).and_then(|analysis| {
+            let (reference, len) = analysis.reference(cursor)?;
+            if len != 1 {
+                return None;
+            }
+            let root = &reference.path[0];
+            analysis.declaration(&root.string, root.location.start)
+                .map(|declaration| json!({
+                    "uri": params["textDocument"]["uri"],
+                    "range": range(text, declaration.location),
+                }))
+        });
+        Ok(definition.unwrap_or(serde_json::Value::Null))
+    }
+
+    fn semantic_tokens(&self, params: &serde_json::Value) -> CrushResult<serde_json::Value> {
+        let text = match self.documents.get(params["textDocument"]["uri"].as_str().unwrap_or("")) {
+            Some(text) => text,
+            None => return error("Unknown document"),
+        };
+        let mut data = Vec::new();
+        let mut positions = PositionTracker::new(text);
+        let mut previous_line = 0;
+        let mut previous_start = 0;
+        for token in Lexer::new(text) {
+            let (start, token, end) = match token {
+                Ok(token) => token,
+                Err(_) => break,
+            };
+            let token_type = match token_type(&token) {
+                Some(t) => t,
+                None => continue,
+            };
+            // Tokens may not span multiple lines, so multi line strings are split up
+            let mut line_offset = start;
+            for line in text[start..end].split('\n') {
+                let (line_number, character) = positions.advance(line_offset);
+                let length = line.encode_utf16().count();
+                if length > 0 {
+                    let delta_start = if line_number == previous_line { character - previous_start } else { character };
+                    data.extend_from_slice(&[line_number - previous_line, delta_start, length as u64, token_type as u64, 0]);
+                    previous_line = line_number;
+                    previous_start = character;
+                }
+                line_offset += line.len() + 1;
+            }
+        }
+        Ok(json!({ "data": data }))
+    }
+
+    fn capabilities() -> serde_json::Value {
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "completionProvider": { "triggerCharacters": [":", "$", "/"] },
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "semanticTokensProvider": {
+                    "legend": {
+                        "tokenTypes": TOKEN_TYPES,
+                        "tokenModifiers": [],
+                    },
+                    "full": true,
+                },
+            },
+            "serverInfo": {
+                "name": "crush",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn request(&self, method: &str, params: &serde_json::Value) -> Option<CrushResult<serde_json::Value>> {
+        Some(match method {
+            "initialize" => Ok(Server::capabilities()),
+            "shutdown" => Ok(serde_json::Value::Null),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => return None,
+        })
+    }
+
+    fn notification(&mut self, method: &str, params: &serde_json::Value, output: &mut dyn Write) -> CrushResult<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri, output)
+            }
+            "textDocument/didChange" => {
+                // We only support full document sync, so the last change contains the whole document
+                if let Some(change) = params["contentChanges"].as_array().and_then(|c| c.last()) {
+                    self.documents.insert(uri.clone(), change["text"].as_str().unwrap_or("").to_string());
+                }
+                self.publish_diagnostics(&uri, output)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri, output)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/**
+Run the language server until the client sends the exit notification or closes the connection.
+ */
+pub fn run(
+    env: Scope,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    global_state: &GlobalState,
+) -> CrushResult<()> {
+    let mut server = Server {
+        env,
+        global_state,
+        documents: HashMap::new(),
+    };
+    while let Some(body) = read_message(input)? {
+        let message: serde_json::Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                // The id of a request we can't parse is unknown, so the error is sent with a null id
+                write_message(output, &json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": format!("Parse error: {}", e) },
+                }))?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or("");
+        if method == "exit" {
+            break;
+        }
+        let params = &message["params"];
+        match &message.get("id") {
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Some(Ok(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Some(Err(e)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32603, "message": e.message() },
+                    }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("Unknown method {}", method) },
+                    }),
+                };
+                write_message(output, &response)?;
+            }
+            None => server.notification(method, params, output)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use std::io::Cursor;
+
+    fn message(value: serde_json::Value) -> String {
+        let body = value.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /**
+    Run the server on the specified input, and return all messages sent by the server.
+     */
+    fn serve(input: String) -> Vec<serde_json::Value> {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        let mut output = Vec::new();
+        run(interpreter.scope().clone(), &mut Cursor::new(input.into_bytes()), &mut output, interpreter.global_state()).unwrap();
+        let mut output = Cursor::new(output);
+        let mut res = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            res.push(serde_json::from_slice(&body).unwrap());
+        }
+        res
+    }
+
+    /**
+    Open a document with the specified text, send the requests, and return all messages sent by
+    the server.
+     */
+    fn session(text: &str, requests: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+        let mut input = message(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///test.crush", "text": text } },
+        }));
+        for request in requests {
+            input.push_str(&message(request));
+        }
+        input.push_str(&message(json!({ "jsonrpc": "2.0", "method": "exit" })));
+        serve(input)
+    }
+
+    #[test]
+    fn diagnostics_are_published_on_open() {
+        let messages = session("echo $x\n", vec![]);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = messages[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["severity"], 2);
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 0, "character": 6 }));
+        assert_eq!(diagnostics[0]["range"]["end"], json!({ "line": 0, "character": 7 }));
+    }
+
+    #[test]
+    fn completion_of_namespace_member() {
+        let messages = session("$a := 1\nhost:pro", vec![json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": "file:///test.crush" },
+                "position": { "line": 1, "character": 8 },
+            },
+        })]);
+        assert_eq!(messages.len(), 2);
+        let response = &messages[1];
+        assert_eq!(response["id"], 7);
+        let items = response["result"].as_array().unwrap();
+        let procs = items.iter()
+            .find(|item| item["label"] == "procs")
+            .expect("Expected procs to be suggested");
+        assert_eq!(procs["textEdit"]["newText"], "cs ");
+        assert_eq!(procs["textEdit"]["range"]["start"], json!({ "line": 1, "character": 8 }));
+    }
+
+    #[test]
+    fn completion_of_unknown_document_is_an_error() {
+        let messages = session("", vec![json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": "file:///other.crush" },
+                "position": { "line": 0, "character": 0 },
+            },
+        })]);
+        assert_eq!(messages[1]["error"]["message"], "Unknown document");
+    }
+
+    #[test]
+    fn variables_are_semantic_tokens() {
+        let messages = session("echo $x", vec![json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "textDocument/semanticTokens/full",
+            "params": { "textDocument": { "uri": "file:///test.crush" } },
+        })]);
+        let data = messages[1]["result"]["data"].as_array().unwrap();
+        // Each token is five numbers: line delta, start delta, length, type and modifiers
+        let variable = data.chunks(5)
+            .find(|token| token[3] == 3)
+            .expect("Expected a variable token");
+        assert_eq!(variable[1], 5);
+        assert_eq!(variable[2], 2);
+    }
+
+    #[test]
+    fn multi_line_tokens_are_split() {
+        let messages = session("echo \"ä\nb\" $x\n$y", vec![json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "textDocument/semanticTokens/full",
+            "params": { "textDocument": { "uri": "file:///test.crush" } },
+        })]);
+        let data = messages[1]["result"]["data"].as_array().unwrap();
+        assert_eq!(data, &vec![
+            json!(0), json!(0), json!(4), json!(0), json!(0),
+            json!(0), json!(5), json!(2), json!(0), json!(0),
+            json!(1), json!(0), json!(2), json!(0), json!(0),
+            json!(0), json!(3), json!(2), json!(3), json!(0),
+            json!(1), json!(0), json!(2), json!(3), json!(0),
+        ]);
+    }
+
+    #[test]
+    fn invalid_json_is_a_parse_error() {
+        let mut input = "Content-Length: 5\r\n\r\n{nope".to_string();
+        input.push_str(&message(json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" })));
+        input.push_str(&message(json!({ "jsonrpc": "2.0", "method": "exit" })));
+        let messages = serve(input);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["id"], serde_json::Value::Null);
+        assert_eq!(messages[0]["error"]["code"], -32700);
+        assert_eq!(messages[1]["id"], 1);
+        assert_eq!(messages[1]["result"], serde_json::Value::Null);
+    }
+}
@@ -5,19 +5,25 @@ pub mod argument;
 pub mod ast;
 pub mod command;
 pub mod command_invocation;
+pub mod check;
 pub mod completion;
+pub mod debug;
 pub mod errors;
 pub mod execute;
 pub mod help;
 pub mod job;
+pub mod lsp;
 pub mod parser;
 pub mod pretty;
 pub mod printer;
+pub mod profile;
 pub mod serialization;
 pub mod pipe;
+pub mod plugin;
 pub mod signature;
 pub mod state;
 pub mod threads;
+pub mod trace;
 pub mod value;
 pub mod data;
 pub mod interactive;
@@ -1,7 +1,8 @@
-use crate::lang::errors::{to_crush_error, CrushResult, CrushError};
+use crate::lang::errors::{CrushResult, CrushError};
 use crate::lang::job::Job;
 use crate::lang::state::scope::Scope;
-use crate::lang::ast::{token::Token, JobListNode, lexer::Lexer};
+use crate::lang::ast::{token::Token, JobListNode, lexer::{Lexer, LexicalError}, location::Location};
+use lalrpop_util::ParseError;
 use std::sync::{Arc, Mutex};
 
 lalrpop_mod!(pub lalrparser, "/lang/lalrparser.rs");
@@ -40,6 +41,25 @@ fn close_switch(input: &str) -> String {
     }
 }
 
+/**
+Convert a parse error into a CrushError, keeping track of where in the input the error happened.
+Errors from the lexer have no location.
+ */
+fn parse_error(err: ParseError<usize, Token, LexicalError>) -> CrushError {
+    let location = match &err {
+        ParseError::InvalidToken { location } |
+        ParseError::UnrecognizedEof { location, .. } => Some(Location::from(*location)),
+        ParseError::UnrecognizedToken { token: (start, _, end), .. } |
+        ParseError::ExtraToken { token: (start, _, end) } => Some(Location::new(*start, *end)),
+        ParseError::User { .. } => None,
+    };
+    let res = CrushError::from(err);
+    match location {
+        Some(location) => res.with_location(location),
+        None => res,
+    }
+}
+
 #[derive(Clone)]
 pub struct Parser {
     parser: Arc<Mutex<lalrparser::JobListParser>>,
@@ -58,7 +78,7 @@ impl Parser {
 
     pub fn ast(&self, s: &str) -> CrushResult<JobListNode> {
         let lex = Lexer::new(s);
-        to_crush_error(self.parser.lock().unwrap().parse(s, lex))
+        self.parser.lock().unwrap().parse(s, lex).map_err(parse_error)
     }
     pub fn tokenize<'a>(&self, s: &'a str) -> CrushResult<Vec<Token<'a>>> {
         let l = Lexer::new(s);
@@ -95,11 +115,12 @@ impl Parser {
                 Token::Minus(_) |
                 Token::Star(_) |
                 Token::Slash(_) |
+                Token::Ampersand(_) |
                 Token::Bang(_) |
                 Token::Equals( _) | Token::Declare( _) |
                 Token::ComparisonOperator(_, _) | Token::UnaryOperator(_, _) |
                 Token::LogicalOperator(_, _) | Token::Named( _) | Token::Unnamed( _) |
-                Token::Pipe( _) | Token::MemberOperator(_) => { needs_trailing_arg = true }
+                Token::Pipe( _) | Token::MemberOperator(_) | Token::MemberBroadcastOperator(_) => { needs_trailing_arg = true }
                 Token::SubStart( _) => { stack.push(")"); }
                 Token::ExprModeStart(_) => {stack.push(")");}
                 Token::JobStart( _) => { stack.push("}"); }
@@ -116,6 +137,7 @@ impl Parser {
                 Token::Integer(_, _) => {}
                 Token::Float(_, _) => {}
                 Token::Separator(_, _) => {}
+                Token::Comment(_, _) => {}
             }
         }
         stack.reverse();
@@ -131,6 +153,7 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use crate::lang::ast::location::Location;
+    use crate::lang::ast::node::Node;
     use super::*;
 
     fn p() -> Parser {
@@ -164,6 +187,18 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn check_comment_ends_job() {
+        let tok = p().tokenize("echo 1 # note\necho 2").unwrap();
+        assert_eq!(tok, vec![
+            Token::String("echo", Location::new(0, 4)),
+            Token::Integer("1", Location::from(5)),
+            Token::Separator("\n", Location::from(13)),
+            Token::String("echo", Location::new(14, 18)),
+            Token::Integer("2", Location::from(19)),
+        ]);
+    }
+
     #[test]
     fn check_token_offsets() {
         let tok = p().tokenize("123:123.4 foo=\"bar\"").unwrap();
@@ -185,6 +220,15 @@ mod tests {
         assert_eq!(tok[2].location(), Location::new(13usize, 16usize));
     }
 
+    #[test]
+    fn pipeline_in_expression_mode() {
+        let ast = p().ast("(ls | materialize)\n").unwrap();
+        match &ast.jobs[0].commands[0].expressions[0] {
+            Node::Substitution(job) => assert_eq!(job.commands.len(), 2),
+            n => panic!("Expected a substitution, got {:?}", n),
+        }
+    }
+
     #[test]
     fn close_command_test() {
         let p = Parser::new();
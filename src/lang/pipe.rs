@@ -11,19 +11,33 @@ use crate::lang::value::Value;
 use chrono::Duration;
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
 use lazy_static::lazy_static;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::lang::profile::{row_size, StageStats};
 
 pub type RecvTimeoutError = crossbeam::channel::RecvTimeoutError;
+pub type TryRecvError = crossbeam::channel::TryRecvError;
 
 
 #[derive(Clone)]
 pub struct ValueSender {
     sender: Sender<Value>,
     is_pipeline: bool,
+    profile: Option<Arc<StageStats>>,
 }
 
 impl ValueSender {
     pub fn send(&self, cell: Value) -> CrushResult<()> {
-        match self.sender.send(cell) {
+        let res = match &self.profile {
+            None => self.sender.send(cell),
+            Some(profile) => {
+                let start = Instant::now();
+                let res = self.sender.send(cell);
+                profile.waited(start, true);
+                res
+            }
+        };
+        match res {
             Ok(_) => Ok(()),
             Err(_) => send_error(),
         }
@@ -34,7 +48,8 @@ impl ValueSender {
     }
 
     pub fn initialize(&self, signature: &[ColumnType]) -> CrushResult<OutputStream> {
-        let (output, input) = streams(signature.to_vec());
+        let (mut output, input) = streams(signature.to_vec());
+        output.profile = self.profile.clone();
         self.send(Value::TableInputStream(input))?;
         Ok(output)
     }
@@ -42,22 +57,55 @@ impl ValueSender {
     pub fn is_pipeline(&self) -> bool {
         self.is_pipeline
     }
+
+    /**
+    Return a sender that records what the stage sends into the specified statistics.
+     */
+    pub fn profiled(&self, profile: Arc<StageStats>) -> ValueSender {
+        ValueSender { sender: self.sender.clone(), is_pipeline: self.is_pipeline, profile: Some(profile) }
+    }
+
+    pub fn profile(&self) -> Option<&Arc<StageStats>> {
+        self.profile.as_ref()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ValueReceiver {
     receiver: Receiver<Value>,
     is_pipeline: bool,
+    profile: Option<Arc<StageStats>>,
 }
 
 impl ValueReceiver {
     pub fn recv(&self) -> CrushResult<Value> {
-        to_crush_error(self.receiver.recv())
+        match &self.profile {
+            None => to_crush_error(self.receiver.recv()),
+            Some(profile) => {
+                let start = Instant::now();
+                let res = to_crush_error(self.receiver.recv());
+                profile.waited(start, false);
+                match res? {
+                    Value::TableInputStream(mut stream) => {
+                        stream.profile = Some(profile.clone());
+                        Ok(Value::TableInputStream(stream))
+                    }
+                    value => Ok(value),
+                }
+            }
+        }
     }
 
     pub fn is_pipeline(&self) -> bool {
         self.is_pipeline
     }
+
+    /**
+    Return a receiver that records what the stage receives into the specified statistics.
+     */
+    pub fn profiled(&self, profile: Arc<StageStats>) -> ValueReceiver {
+        ValueReceiver { receiver: self.receiver.clone(), is_pipeline: self.is_pipeline, profile: Some(profile) }
+    }
 }
 
 lazy_static! {
@@ -88,11 +136,22 @@ pub fn empty_channel() -> ValueReceiver {
 pub struct OutputStream {
     sender: Sender<Row>,
     types: Vec<ColumnType>,
+    profile: Option<Arc<StageStats>>,
 }
 
 impl OutputStream {
     pub fn send(&self, row: Row) -> CrushResult<()> {
-        match self.sender.send(row) {
+        let res = match &self.profile {
+            None => self.sender.send(row),
+            Some(profile) => {
+                let size = row_size(&row);
+                let start = Instant::now();
+                let res = self.sender.send(row);
+                profile.sent(size, start, self.sender.len());
+                res
+            }
+        };
+        match res {
             Ok(_) => Ok(()),
             Err(_) => send_error(),
         }
@@ -107,6 +166,7 @@ impl OutputStream {
 pub struct InputStream {
     receiver: Receiver<Row>,
     types: Vec<ColumnType>,
+    profile: Option<Arc<StageStats>>,
 }
 
 impl InputStream {
@@ -126,11 +186,29 @@ impl InputStream {
     }
 
     pub fn recv(&self) -> CrushResult<Row> {
-        self.validate(to_crush_error(self.receiver.recv()))
+        self.validate(self.profiled(|| to_crush_error(self.receiver.recv())))
     }
 
     pub fn recv_timeout(&self, timeout: Duration) -> Result<Row, RecvTimeoutError> {
-        self.receiver.recv_timeout(timeout.to_std().unwrap())
+        self.profiled(|| self.receiver.recv_timeout(timeout.to_std().unwrap()))
+    }
+
+    pub fn try_recv(&self) -> Result<Row, TryRecvError> {
+        self.profiled(|| self.receiver.try_recv())
+    }
+
+    fn profiled<E>(&self, recv: impl FnOnce() -> Result<Row, E>) -> Result<Row, E> {
+        match &self.profile {
+            None => recv(),
+            Some(profile) => {
+                let start = Instant::now();
+                let res = recv();
+                if let Ok(row) = &res {
+                    profile.received(row, start);
+                }
+                res
+            }
+        }
     }
 
     pub fn types(&self) -> &[ColumnType] {
@@ -147,10 +225,10 @@ impl InputStream {
                     if !ct.cell_type.is(c) {
                         return error(
                             format!(
-                                "Wrong cell type in io column {:?}, expected {:?}, got {:?}",
+                                "Wrong cell type in io column {}, expected {}, got {}",
                                 ct.name,
-                                c.value_type(),
-                                ct.cell_type
+                                ct.cell_type,
+                                c.value_type()
                             )
                                 .as_str(),
                         );
@@ -169,8 +247,8 @@ A Sender/Receiver pair that is bounded to only one Value on the wire before bloc
 pub fn pipe() -> (ValueSender, ValueReceiver) {
     let (send, recv) = bounded(1);
     (
-        ValueSender { sender: send, is_pipeline: true },
-        ValueReceiver { receiver: recv, is_pipeline: true },
+        ValueSender { sender: send, is_pipeline: true, profile: None },
+        ValueReceiver { receiver: recv, is_pipeline: true, profile: None },
     )
 }
 
@@ -180,8 +258,8 @@ A Sender/Receiver pair that is bounded to only one Value on the wire before bloc
 pub fn printer_pipe() -> (ValueSender, ValueReceiver) {
     let (send, recv) = bounded(1);
     (
-        ValueSender { sender: send, is_pipeline: false },
-        ValueReceiver { receiver: recv, is_pipeline: false },
+        ValueSender { sender: send, is_pipeline: false, profile: None },
+        ValueReceiver { receiver: recv, is_pipeline: false, profile: None },
     )
 }
 
@@ -191,10 +269,12 @@ pub fn streams(signature: Vec<ColumnType>) -> (OutputStream, InputStream) {
         OutputStream {
             sender: output,
             types: signature.clone(),
+            profile: None,
         },
         InputStream {
             receiver: input,
             types: signature,
+            profile: None,
         },
     )
 }
@@ -205,10 +285,12 @@ pub fn unlimited_streams(signature: Vec<ColumnType>) -> (OutputStream, InputStre
         OutputStream {
             sender: output,
             types: signature.clone(),
+            profile: None,
         },
         InputStream {
             receiver: input,
             types: signature,
+            profile: None,
         },
     )
 }
@@ -0,0 +1,220 @@
+/**
+Plugins add namespaces with commands that are implemented outside of Crush. A plugin is either a
+shared library that is loaded into the Crush process (see `native`), or an executable that Crush
+talks to over its standard input and output (see `process`). Either way, arguments and results
+are passed as values in the pup format.
+ */
+use std::fmt::Formatter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use libloading::Library;
+use crate::lang::argument::ArgumentDefinition;
+use crate::lang::command::{ArgumentDescription, Command, CrushCommand, OutputType};
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{argument_error_legacy, error, CrushResult};
+use crate::lang::help::Help;
+use crate::lang::serialization::model::{element, Element};
+use crate::lang::serialization::{Serializable, SerializationState};
+use crate::lang::state::contexts::{CommandContext, CompileContext};
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{Value, ValueType};
+
+pub mod native;
+pub mod process;
+
+/**
+Create the scope that the namespaces of all plugins are declared in, and declare the plugin
+processes found in the plugins directory. The root scope is read only, so plugin namespaces can't
+be declared there, but the root uses this scope, so they can be used just like builtin namespaces.
+ */
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let plugins = Scope::create(
+        Some("plugins".to_string()),
+        Some("Namespaces registered by plugins and by programs embedding Crush.".to_string()),
+        false,
+        false,
+        false,
+    );
+    root.declare("plugins", Value::Scope(plugins.clone()))?;
+    root.r#use(&plugins);
+    process::declare(&plugins)
+}
+
+struct PluginArgument {
+    name: String,
+    value_type: ValueType,
+    description: String,
+}
+
+enum Implementation {
+    Native {
+        call: native::CrushCommandFn,
+        /** Keeps the library loaded for as long as the command exists. */
+        _library: Arc<Library>,
+    },
+    Process {
+        executable: PathBuf,
+    },
+}
+
+struct PluginCommand {
+    full_name: Vec<String>,
+    signature: String,
+    short_help: String,
+    long_help: Option<String>,
+    plugin_arguments: Vec<PluginArgument>,
+    arguments: Vec<ArgumentDescription>,
+    implementation: Implementation,
+}
+
+impl PluginCommand {
+    fn new(
+        namespace: &str,
+        name: &str,
+        short_help: String,
+        long_help: Option<String>,
+        plugin_arguments: Vec<PluginArgument>,
+        implementation: Implementation,
+    ) -> PluginCommand {
+        PluginCommand {
+            full_name: vec!["global".to_string(), "plugins".to_string(), namespace.to_string(), name.to_string()],
+            signature: plugin_arguments.iter()
+                .fold(name.to_string(), |s, a| format!("{} {}=({})", s, a.name, a.value_type)),
+            short_help,
+            long_help,
+            arguments: plugin_arguments.iter().map(|a| ArgumentDescription {
+                name: a.name.clone(),
+                value_type: a.value_type.clone(),
+                allowed: None,
+                description: Some(a.description.clone()),
+                complete: None,
+                named: false,
+                unnamed: false,
+                column: None,
+            }).collect(),
+            plugin_arguments,
+            implementation,
+        }
+    }
+
+    /** Match the arguments of a call to the arguments of the command, by name or position. */
+    fn arguments_struct(&self, context: &mut CommandContext) -> CrushResult<Struct> {
+        let mut unnamed = Vec::new();
+        let mut named = Vec::new();
+        for argument in context.remove_arguments() {
+            match argument.argument_type {
+                None => unnamed.push(argument.value),
+                Some(name) => named.push((name, argument.value)),
+            }
+        }
+        if let Some((name, _)) = named.iter().find(|(n, _)| !self.plugin_arguments.iter().any(|a| a.name == *n)) {
+            return argument_error_legacy(format!("{} has no argument named {}", self.name(), name));
+        }
+        let mut unnamed = unnamed.drain(..);
+        let mut fields = Vec::new();
+        for argument in &self.plugin_arguments {
+            let value = match named.iter().position(|(n, _)| *n == argument.name) {
+                Some(idx) => named.remove(idx).1,
+                None => match unnamed.next() {
+                    Some(value) => value,
+                    None => return argument_error_legacy(format!("Missing argument {}", argument.name)),
+                },
+            };
+            if !argument.value_type.is(&value) {
+                return argument_error_legacy(format!(
+                    "Argument {} should be of type {}, was {}", argument.name, argument.value_type, value.value_type()));
+            }
+            fields.push((argument.name.clone(), value));
+        }
+        if unnamed.next().is_some() || !named.is_empty() {
+            return argument_error_legacy(format!("Too many arguments to {}", self.name()));
+        }
+        Ok(Struct::new(fields, None))
+    }
+}
+
+/** Plugins name the types of arguments, since they have no access to the types themselves. */
+fn parse_type(name: &str) -> CrushResult<ValueType> {
+    Ok(match name {
+        "string" => ValueType::String,
+        "integer" => ValueType::Integer,
+        "float" => ValueType::Float,
+        "bool" => ValueType::Bool,
+        "file" => ValueType::File,
+        "time" => ValueType::Time,
+        "duration" => ValueType::Duration,
+        "glob" => ValueType::Glob,
+        "regex" => ValueType::Regex,
+        "binary" => ValueType::Binary,
+        "struct" => ValueType::Struct,
+        "any" => ValueType::Any,
+        _ => return error(format!("Unsupported argument type {} in plugin", name)),
+    })
+}
+
+impl CrushCommand for PluginCommand {
+    fn eval(&self, mut context: CommandContext) -> CrushResult<()> {
+        let arguments = self.arguments_struct(&mut context)?;
+        match &self.implementation {
+            Implementation::Native { call, .. } => native::eval(*call, arguments, context),
+            Implementation::Process { executable } => process::eval(executable, self.name(), arguments, context),
+        }
+    }
+
+    fn might_block(&self, _arg: &[ArgumentDefinition], _context: &mut CompileContext) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        &self.full_name[self.full_name.len() - 1]
+    }
+
+    fn help(&self) -> &dyn Help {
+        self
+    }
+
+    fn serialize(
+        &self,
+        elements: &mut Vec<Element>,
+        state: &mut SerializationState,
+    ) -> CrushResult<usize> {
+        let strings_idx = self.full_name.serialize(elements, state)?;
+        let idx = elements.len();
+        elements.push(Element {
+            element: Some(element::Element::Command(strings_idx as u64)),
+        });
+        Ok(idx)
+    }
+
+    fn bind_helper(&self, wrapped: &Command, this: Value) -> Command {
+        <dyn CrushCommand>::bound(wrapped, this)
+    }
+
+    fn output_type<'a>(&'a self, _input: &'a OutputType) -> Option<&'a ValueType> {
+        None
+    }
+
+    fn arguments(&self) -> &Vec<ArgumentDescription> {
+        &self.arguments
+    }
+}
+
+impl Help for PluginCommand {
+    fn signature(&self) -> String {
+        self.signature.clone()
+    }
+
+    fn short_help(&self) -> String {
+        self.short_help.clone()
+    }
+
+    fn long_help(&self) -> Option<String> {
+        self.long_help.clone()
+    }
+}
+
+impl std::fmt::Debug for PluginCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Command")
+    }
+}
@@ -0,0 +1,373 @@
+/**
+Native plugins. A plugin is a shared library that exposes a small, versioned C ABI. When loaded,
+the plugin registers namespaces and commands, and when one of its commands is called, the
+arguments and the result are passed as values in the pup format.
+
+A plugin exports two functions:
+
+* `uint32_t crush_plugin_abi_version()`, which must return `PLUGIN_ABI_VERSION`, and
+* `int32_t crush_plugin_init(const CrushPluginApi *api)`, which registers the namespaces and
+  commands of the plugin by calling the functions in `api`, and returns 0 on success.
+
+The structs passed between Crush and the plugin are defined below. Commands may be called from
+any thread, and concurrently.
+ */
+use std::ffi::{c_char, c_void, CStr};
+use std::path::Path;
+use std::sync::Arc;
+use libloading::Library;
+use crate::lang::command::{Command, CrushCommand};
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{argument_error_legacy, error, to_crush_error, CrushResult};
+use crate::lang::plugin::{parse_type, Implementation, PluginArgument, PluginCommand};
+use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::Value;
+
+/** The version of the plugin ABI. It is increased whenever the structs below change. */
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/** Called by a command to write its result, which is a pup encoded value, or an error message. */
+pub type CrushWriteFn = unsafe extern "C" fn(result: *mut c_void, data: *const u8, len: usize);
+
+/**
+A command implemented by a plugin. The arguments are a pup encoded struct with one field per
+argument of the command. On success, the command writes its pup encoded result and returns 0,
+otherwise it writes a UTF-8 error message and returns any other value.
+ */
+pub type CrushCommandFn = unsafe extern "C" fn(
+    arguments: *const u8,
+    arguments_len: usize,
+    result: *mut c_void,
+    write_result: CrushWriteFn,
+) -> i32;
+
+#[repr(C)]
+pub struct CrushArgumentDefinition {
+    pub name: *const c_char,
+    /** The name of the type of the argument, e.g. "string" or "integer", or "any". */
+    pub value_type: *const c_char,
+    pub description: *const c_char,
+}
+
+#[repr(C)]
+pub struct CrushCommandDefinition {
+    /** A namespace previously registered by the same plugin. */
+    pub namespace: *const c_char,
+    pub name: *const c_char,
+    pub short_help: *const c_char,
+    /** May be null. */
+    pub long_help: *const c_char,
+    pub arguments: *const CrushArgumentDefinition,
+    pub argument_count: usize,
+    pub call: CrushCommandFn,
+}
+
+#[repr(C)]
+pub struct CrushPluginApi {
+    pub version: u32,
+    /** Passed back as the first argument of the register functions. */
+    pub registrar: *mut c_void,
+    pub register_namespace: unsafe extern "C" fn(
+        registrar: *mut c_void,
+        name: *const c_char,
+        description: *const c_char,
+    ) -> i32,
+    pub register_command: unsafe extern "C" fn(
+        registrar: *mut c_void,
+        command: *const CrushCommandDefinition,
+    ) -> i32,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type InitFn = unsafe extern "C" fn(api: *const CrushPluginApi) -> i32;
+
+/**
+Everything a plugin registers is collected here while its init function runs, and only added to
+the namespace afterwards, so that no plugin code runs while a scope is locked.
+ */
+struct Registrar {
+    library: Arc<Library>,
+    namespaces: Vec<(String, String, Vec<PluginCommand>)>,
+    error: Option<String>,
+}
+
+impl Registrar {
+    fn namespace(&mut self, name: *const c_char, description: *const c_char) -> CrushResult<()> {
+        let name = string(name, "namespace name")?;
+        if self.namespaces.iter().any(|(n, _, _)| *n == name) {
+            return error(format!("Namespace {} registered twice", name));
+        }
+        self.namespaces.push((name, string(description, "namespace description")?, Vec::new()));
+        Ok(())
+    }
+
+    fn command(&mut self, definition: &CrushCommandDefinition) -> CrushResult<()> {
+        let namespace = string(definition.namespace, "command namespace")?;
+        let name = string(definition.name, "command name")?;
+        let mut plugin_arguments = Vec::new();
+        if definition.argument_count > 0 {
+            // Safe as long as the plugin passes a valid array, which the ABI requires
+            let arguments = unsafe { std::slice::from_raw_parts(definition.arguments, definition.argument_count) };
+            for argument in arguments {
+                plugin_arguments.push(PluginArgument {
+                    name: string(argument.name, "argument name")?,
+                    value_type: parse_type(&string(argument.value_type, "argument type")?)?,
+                    description: string(argument.description, "argument description")?,
+                });
+            }
+        }
+        let long_help = if definition.long_help.is_null() {
+            None
+        } else {
+            Some(string(definition.long_help, "long help")?)
+        };
+        let command = PluginCommand::new(
+            &namespace,
+            &name,
+            string(definition.short_help, "short help")?,
+            long_help,
+            plugin_arguments,
+            Implementation::Native {
+                call: definition.call,
+                _library: self.library.clone(),
+            },
+        );
+        match self.namespaces.iter_mut().find(|(n, _, _)| *n == namespace) {
+            Some((_, _, commands)) => {
+                if commands.iter().any(|c| c.name() == name) {
+                    return error(format!("Command {}:{} registered twice", namespace, name));
+                }
+                commands.push(command);
+                Ok(())
+            }
+            None => error(format!("Command {} registered in unknown namespace {}", name, namespace)),
+        }
+    }
+
+    /** Report the first error to Crush, and a failure to the plugin. */
+    fn report(&mut self, res: CrushResult<()>) -> i32 {
+        match res {
+            Ok(()) => 0,
+            Err(e) => {
+                self.error.get_or_insert(e.message());
+                1
+            }
+        }
+    }
+}
+
+fn string(s: *const c_char, what: &str) -> CrushResult<String> {
+    if s.is_null() {
+        return error(format!("Plugin passed a null {}", what));
+    }
+    // Safe as long as the plugin passes a nul terminated string, which the ABI requires
+    match unsafe { CStr::from_ptr(s) }.to_str() {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => error(format!("Plugin passed a {} that is not valid UTF-8", what)),
+    }
+}
+
+unsafe extern "C" fn register_namespace(registrar: *mut c_void, name: *const c_char, description: *const c_char) -> i32 {
+    let registrar = &mut *(registrar as *mut Registrar);
+    let res = registrar.namespace(name, description);
+    registrar.report(res)
+}
+
+unsafe extern "C" fn register_command(registrar: *mut c_void, command: *const CrushCommandDefinition) -> i32 {
+    let registrar = &mut *(registrar as *mut Registrar);
+    let res = match command.as_ref() {
+        Some(command) => registrar.command(command),
+        None => error("Plugin passed a null command"),
+    };
+    registrar.report(res)
+}
+
+unsafe extern "C" fn write_result(result: *mut c_void, data: *const u8, len: usize) {
+    let result = &mut *(result as *mut Vec<u8>);
+    if len > 0 {
+        result.extend_from_slice(std::slice::from_raw_parts(data, len));
+    }
+}
+
+/**
+Load the plugin in the specified file and declare the namespaces it registers in the plugins
+scope. Returns the names of the namespaces.
+ */
+pub fn load(file: &Path, plugins: &Scope) -> CrushResult<Vec<String>> {
+    // Safe as long as the library is a well-behaved plugin. Running its initialisers is the point.
+    let library = Arc::new(to_crush_error(unsafe { Library::new(file) })?);
+    let version = unsafe {
+        let abi_version: libloading::Symbol<AbiVersionFn> = match library.get(b"crush_plugin_abi_version\0") {
+            Ok(f) => f,
+            Err(_) => return argument_error_legacy(format!("{} is not a Crush plugin", file.display())),
+        };
+        abi_version()
+    };
+    if version != PLUGIN_ABI_VERSION {
+        return argument_error_legacy(format!(
+            "{} uses plugin ABI version {}, but this version of Crush supports version {}",
+            file.display(), version, PLUGIN_ABI_VERSION));
+    }
+
+    let mut registrar = Registrar {
+        library: library.clone(),
+        namespaces: Vec::new(),
+        error: None,
+    };
+    let api = CrushPluginApi {
+        version: PLUGIN_ABI_VERSION,
+        registrar: &mut registrar as *mut Registrar as *mut c_void,
+        register_namespace,
+        register_command,
+    };
+    let status = unsafe {
+        let init: libloading::Symbol<InitFn> = to_crush_error(library.get(b"crush_plugin_init\0"))?;
+        init(&api)
+    };
+    if let Some(message) = registrar.error {
+        return error(format!("Failed to load plugin {}: {}", file.display(), message));
+    }
+    if status != 0 {
+        return error(format!("Failed to load plugin {}: initialisation returned {}", file.display(), status));
+    }
+
+    // Check everything before declaring anything, so that a failed load leaves nothing behind
+    for (name, _, _) in &registrar.namespaces {
+        if plugins.get_local(name)?.is_some() {
+            return error(format!("Failed to load plugin {}: namespace {} already exists", file.display(), name));
+        }
+    }
+    let mut names = Vec::new();
+    for (name, description, commands) in registrar.namespaces {
+        plugins.create_namespace(
+            &name,
+            description,
+            Box::new(move |env| {
+                for command in commands {
+                    let short_name = command.name().to_string();
+                    env.declare(&short_name, Value::Command(Arc::from(command) as Command))?;
+                }
+                Ok(())
+            }),
+        )?;
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/** Call a command implemented by a native plugin. */
+pub(super) fn eval(call: CrushCommandFn, arguments: Struct, context: CommandContext) -> CrushResult<()> {
+    let mut arguments_buf = Vec::new();
+    serialize(&Value::Struct(arguments), &mut arguments_buf)?;
+    let mut result: Vec<u8> = Vec::new();
+    // Safe as long as the plugin follows the ABI
+    let status = unsafe {
+        call(
+            arguments_buf.as_ptr(),
+            arguments_buf.len(),
+            &mut result as *mut Vec<u8> as *mut c_void,
+            write_result,
+        )
+    };
+    if status != 0 {
+        return error(String::from_utf8_lossy(&result).to_string());
+    }
+    if result.is_empty() {
+        return context.output.empty();
+    }
+    context.output.send(deserialize(&result, &context.scope)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use std::path::PathBuf;
+    use std::process::Command as Process;
+    use std::sync::OnceLock;
+
+    /** Build the plugin in the test_plugin crate, and return the path of the library. */
+    fn fixture() -> &'static Path {
+        static LIBRARY: OnceLock<PathBuf> = OnceLock::new();
+        LIBRARY.get_or_init(|| {
+            let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+            let target = root.join("target").join("test_plugin");
+            let status = Process::new(env!("CARGO"))
+                .args(["build", "--quiet", "--package", "test_plugin", "--target-dir"])
+                .arg(&target)
+                .current_dir(root)
+                .status()
+                .expect("failed to run cargo");
+            assert!(status.success(), "failed to build the test plugin");
+            target.join("debug").join(format!(
+                "{}test_plugin{}",
+                std::env::consts::DLL_PREFIX,
+                std::env::consts::DLL_SUFFIX))
+        })
+    }
+
+    /** Create an interpreter, and return it along with its plugins scope. */
+    fn plugins() -> (Interpreter, Scope) {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        match interpreter.scope().get_absolute_path(vec!["global".to_string(), "plugins".to_string()]) {
+            Ok(Value::Scope(plugins)) => (interpreter, plugins),
+            _ => panic!("Missing plugins scope"),
+        }
+    }
+
+    #[test]
+    fn load_declares_namespaces() {
+        let (_interpreter, plugins) = plugins();
+        assert_eq!(load(fixture(), &plugins).unwrap(), vec!["fixture", "fixture_extra"]);
+        match plugins.get_local("fixture").unwrap() {
+            Some(Value::Scope(fixture)) => {
+                let commands = fixture.dump_local().unwrap().into_iter().map(|(n, _)| n).collect::<Vec<_>>();
+                assert_eq!(commands, vec!["identity", "fail", "garbage"]);
+            }
+            _ => panic!("Expected the fixture namespace to be declared"),
+        }
+    }
+
+    #[test]
+    fn namespace_collision_declares_nothing() {
+        let (_interpreter, plugins) = plugins();
+        plugins.declare("fixture_extra", Value::Empty).unwrap();
+        let err = load(fixture(), &plugins).err().unwrap();
+        assert!(err.message().contains("namespace fixture_extra already exists"));
+        assert!(plugins.get_local("fixture").unwrap().is_none());
+    }
+
+    /** Load the fixture into a new interpreter and run the source. */
+    fn run(source: &str) -> CrushResult<Value> {
+        let (interpreter, plugins) = plugins();
+        load(fixture(), &plugins).unwrap();
+        interpreter.eval(source)
+    }
+
+    #[test]
+    fn call_passes_arguments_and_result() {
+        match run("fixture:identity text=\"hello\"") {
+            Ok(Value::Struct(result)) => assert!(result.get("text") == Some(Value::from("hello"))),
+            _ => panic!("Expected the arguments to be returned as a struct"),
+        }
+    }
+
+    #[test]
+    fn call_reports_errors() {
+        let message = run("fixture:fail").err().unwrap().message();
+        assert!(message.contains("Failed on purpose"), "{}", message);
+    }
+
+    #[test]
+    fn call_reports_malformed_results() {
+        let message = run("fixture:garbage").err().unwrap().message();
+        assert!(message.contains("invalid wire type"), "{}", message);
+    }
+
+    #[test]
+    fn arguments_are_checked_before_calling() {
+        assert!(run("fixture:identity text=5").is_err());
+    }
+}
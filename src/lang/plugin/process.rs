@@ -0,0 +1,193 @@
+/**
+Plugin processes. A plugin process is an executable in the plugins directory of the Crush
+configuration directory, and can be written in any language. Each executable becomes a namespace
+named after the file. The namespace is lazily loaded, and when it is, the plugin is started to
+declare its commands.
+
+Every time one of the commands is run, the plugin is started again, and Crush sends it the
+request and the input of the command, after which the plugin sends back the output. All messages
+are protobuf messages from crush.proto, see `PluginDeclaration` for the details.
+ */
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, ChildStdout, Stdio};
+use std::sync::Arc;
+use prost::Message;
+use crate::lang::command::Command;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{error, to_crush_error, CrushResult};
+use crate::lang::interactive::config_dir;
+use crate::lang::plugin::{parse_type, Implementation, PluginArgument, PluginCommand};
+use crate::lang::serialization::model::{PluginDeclaration, PluginRequest};
+use crate::lang::serialization::{deserialize_stream, read_frame, serialize_stream, serialized_value, write_frame};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::Value;
+
+/** Shared libraries in the plugins directory are native plugins, which are loaded explicitly. */
+fn is_plugin(path: &Path) -> bool {
+    let executable = fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+    executable && !matches!(path.extension().and_then(|e| e.to_str()), Some("so") | Some("dylib"))
+}
+
+/**
+Declare a lazily loaded namespace for every plugin in the plugins directory.
+ */
+pub fn declare(plugins: &Scope) -> CrushResult<()> {
+    match config_dir() {
+        Ok(dir) => declare_directory(plugins, &dir.join("plugins")),
+        Err(_) => Ok(()),
+    }
+}
+
+fn declare_directory(plugins: &Scope, directory: &Path) -> CrushResult<()> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        _ => return Ok(()),
+    };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| is_plugin(path))
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if plugins.get_local(&name)?.is_some() {
+            continue;
+        }
+        let executable = path.clone();
+        let namespace = name.clone();
+        plugins.create_namespace(
+            &name,
+            format!("The plugin {}", path.display()),
+            Box::new(move |env| {
+                for command in declaration(&executable)?.commands {
+                    let mut arguments = Vec::new();
+                    for argument in command.arguments {
+                        arguments.push(PluginArgument {
+                            name: argument.name,
+                            value_type: parse_type(&argument.value_type)?,
+                            description: argument.description,
+                        });
+                    }
+                    let plugin_command = PluginCommand::new(
+                        &namespace,
+                        &command.name,
+                        command.short_help,
+                        if command.long_help.is_empty() { None } else { Some(command.long_help) },
+                        arguments,
+                        Implementation::Process { executable: executable.clone() },
+                    );
+                    env.declare(&command.name, Value::Command(Arc::from(plugin_command) as Command))?;
+                }
+                Ok(())
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+/**
+Start the plugin and read the declaration it writes on startup.
+ */
+fn start(executable: &PathBuf) -> CrushResult<(ChildStdin, ChildStdout, PluginDeclaration)> {
+    let mut child = to_crush_error(
+        std::process::Command::new(executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn())?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    // Nothing waits for the plugin to finish, so reap it once it does to avoid leaving a zombie
+    to_crush_error(std::thread::Builder::new()
+        .name("plugin reaper".to_string())
+        .spawn(move || { let _ = child.wait(); }))?;
+    match (stdin, stdout) {
+        (Some(stdin), Some(mut stdout)) => match read_frame(&mut stdout)? {
+            Some(buf) => Ok((stdin, stdout, to_crush_error(PluginDeclaration::decode(&buf[..]))?)),
+            None => error(format!("Plugin {} exited without declaring its commands", executable.display())),
+        },
+        _ => error("Failed to connect to plugin"),
+    }
+}
+
+/**
+Start the plugin only to read its declaration. Closing its input tells the plugin to exit.
+ */
+fn declaration(executable: &PathBuf) -> CrushResult<PluginDeclaration> {
+    let (_, _, declaration) = start(executable)?;
+    Ok(declaration)
+}
+
+/** Run a command in a new instance of the plugin. */
+pub(super) fn eval(executable: &PathBuf, command: &str, arguments: Struct, context: CommandContext) -> CrushResult<()> {
+    let (mut stdin, stdout, _) = start(executable)?;
+    let request = PluginRequest {
+        command: command.to_string(),
+        arguments: Some(serialized_value(&Value::Struct(arguments))?),
+    };
+    let mut buf = Vec::with_capacity(request.encoded_len());
+    to_crush_error(request.encode(&mut buf))?;
+    write_frame(&mut stdin, &buf)?;
+
+    // The input is written in a separate thread, so that plugins that stream their output while
+    // reading their input don't deadlock
+    let input = context.input.recv()?;
+    context.spawn("plugin input", move || {
+        let _ = serialize_stream(Ok(input), &mut stdin);
+        Ok(())
+    })?;
+    context.output.send(deserialize_stream(stdout, &context.scope, &context.global_state, |_| {})?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    /**
+    Create an interpreter with the plugins in the test_plugin directory declared. The only
+    executable there is the stub plugin process_plugin.py.
+     */
+    fn interpreter() -> Interpreter {
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        match interpreter.scope().get_absolute_path(vec!["global".to_string(), "plugins".to_string()]) {
+            Ok(Value::Scope(plugins)) =>
+                declare_directory(&plugins, &Path::new(env!("CARGO_MANIFEST_DIR")).join("test_plugin")).unwrap(),
+            _ => panic!("Missing plugins scope"),
+        }
+        interpreter
+    }
+
+    #[test]
+    fn plugin_declares_commands() {
+        let interpreter = interpreter();
+        match interpreter.scope().get_absolute_path(vec!["global".to_string(), "plugins".to_string(), "process_plugin".to_string()]) {
+            Ok(Value::Scope(stub)) => {
+                let commands = stub.dump_local().unwrap().into_iter().map(|(n, _)| n).collect::<Vec<_>>();
+                assert_eq!(commands, vec!["echo", "fail"]);
+            }
+            _ => panic!("Expected the process_plugin namespace to be declared"),
+        }
+    }
+
+    #[test]
+    fn call_passes_arguments_and_result() {
+        match interpreter().eval("process_plugin:echo text=\"hello\"") {
+            Ok(Value::Struct(result)) => assert!(result.get("text") == Some(Value::from("hello"))),
+            res => panic!("Expected the arguments to be returned as a struct, got {:?}", res.err()),
+        }
+    }
+
+    #[test]
+    fn call_reports_errors() {
+        let message = interpreter().eval("process_plugin:fail").err().unwrap().message();
+        assert!(message.contains("Failed on purpose"), "{}", message);
+    }
+}
@@ -3,7 +3,6 @@ A receiver of values that prints any values sent to it in a human readable forma
  */
 
 use crate::lang::data::binary::BinaryReader;
-use crate::lang::errors::to_crush_error;
 use crate::lang::printer::Printer;
 use crate::lang::pipe::{CrushStream, InputStream, ValueSender, printer_pipe};
 use crate::lang::data::table::ColumnType;
@@ -16,30 +15,36 @@ use crate::lang::value::ValueType;
 use crate::lang::data::r#struct::Struct;
 use std::cmp::max;
 use std::io::{BufReader, Read};
-use std::thread;
 use chrono::Duration;
 use crate::util::hex::to_hex;
 use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
 use crate::data::table::ColumnFormat;
 use crate::state::global_state::FormatData;
 
 pub fn create_pretty_printer(
     printer: Printer,
+    scope: &Scope,
     global_state: &GlobalState,
 ) -> ValueSender {
+    let scope = scope.clone();
     let global_state = global_state.clone();
     let (o, i) = printer_pipe();
     let printer_clone = global_state.printer().clone();
-    printer_clone.handle_error(to_crush_error(
-        thread::Builder::new()
-            .name("output-formater".to_string())
-            .spawn(move || {
-                let mut pp = PrettyPrinter { printer, format_data: global_state.format_data() };
-                while let Ok(val) = i.recv() {
-                    pp.format_data = global_state.format_data();
-                    pp.print_value(val, &ColumnFormat::None);
-                }
-            }),
+    // The thread is tracked, so that joining all threads waits for everything to be printed. Printing
+    // may call methods of classes, which need the scope to still be around.
+    let threads = global_state.threads().clone();
+    printer_clone.handle_error(threads.spawn(
+        "output-formater",
+        None,
+        move || {
+            let mut pp = PrettyPrinter::new(printer, scope, global_state.clone());
+            while let Ok(val) = i.recv() {
+                pp.format_data = global_state.format_data();
+                pp.print_value(val, &ColumnFormat::None);
+            }
+            Ok(())
+        },
     ));
     o
 }
@@ -64,6 +69,9 @@ impl Width for &str {
 pub struct PrettyPrinter {
     printer: Printer,
     format_data: FormatData,
+    /** Used to call the methods of classes that customize how their instances are printed. */
+    scope: Scope,
+    global_state: GlobalState,
 }
 
 fn is_printable(v: u8) -> bool {
@@ -120,8 +128,23 @@ fn is_text(buff: &[u8]) -> bool {
 }
 
 impl PrettyPrinter {
-    pub fn new(printer: Printer, format_data: FormatData) -> PrettyPrinter {
-        PrettyPrinter { printer, format_data }
+    pub fn new(printer: Printer, scope: Scope, global_state: GlobalState) -> PrettyPrinter {
+        PrettyPrinter { printer, format_data: global_state.format_data(), scope, global_state }
+    }
+
+    /**
+    Format a value for printing, using the `__repr__` or `__str__` method of instances of classes
+    that have one.
+     */
+    fn format(&self, value: &Value, format: &ColumnFormat, table: bool) -> String {
+        if let Value::Struct(s) = value {
+            match s.repr(&self.scope, &self.global_state) {
+                Ok(Some(s)) => return s,
+                Ok(None) => {}
+                Err(e) => self.printer.crush_error(e),
+            }
+        }
+        value.to_pretty_string(&self.format_data, format, table)
     }
 
     pub fn print_value(&self, cell: Value, format: &ColumnFormat) {
@@ -129,15 +152,23 @@ impl PrettyPrinter {
             Value::TableInputStream(mut output) => self.print_stream(&mut output, 0),
             Value::Table(rows) => self.print_stream(&mut TableReader::new(rows), 0),
             Value::BinaryInputStream(mut b) => self.print_binary(b.as_mut(), 0),
+            Value::Edge(edge) => match edge.tree() {
+                Ok(tree) => self.print_stream(&mut TableReader::new(tree), 0),
+                Err(e) => self.printer.crush_error(e),
+            },
             Value::Empty => {}
-            Value::Struct(data) => self.print_struct(data, 0),
+            Value::Struct(data) => match data.str(&self.scope, &self.global_state) {
+                Ok(Some(s)) => self.printer.line(&s),
+                Ok(None) => self.print_struct(data, 0),
+                Err(e) => self.printer.crush_error(e),
+            },
             Value::List(list) =>
                 if list.len() < 8 {
                     self.printer.line(list.to_string().as_str())
                 } else {
                     self.print_stream(list.stream().as_mut(), 0)
                 }
-            _ => self.printer.line(cell.to_pretty_string(&self.format_data, format, false).as_str()),
+            _ => self.printer.line(self.format(&cell, format, false).as_str()),
         };
     }
 
@@ -184,18 +215,28 @@ impl PrettyPrinter {
         }
     }
 
-    fn calculate_body_width(&self, w: &mut [usize], data: &[Row], col_count: usize, columns: &[ColumnType]) {
-        for r in data {
-            for (idx, c) in r.cells().iter().enumerate() {
-                if idx == col_count {
-                    break;
-                }
-                let l = c.to_pretty_string(&self.format_data, &columns[idx].format, true).width();
-                w[idx] = max(w[idx], l);
+    fn calculate_body_width(&self, w: &mut [usize], formatted: &[Vec<String>]) {
+        for r in formatted {
+            for (idx, c) in r.iter().enumerate() {
+                w[idx] = max(w[idx], c.width());
             }
         }
     }
 
+    /**
+    Format the cells of each row that are shown as columns. Formatting a class instance calls its
+    __str__ or __repr__ method, so each cell is only formatted once, and the result is used both
+    to calculate the column widths and to print the row.
+     */
+    fn format_body(&self, data: &[Row], columns: &[ColumnType]) -> Vec<Vec<String>> {
+        data.iter()
+            .map(|r| r.cells().iter()
+                .zip(columns)
+                .map(|(c, column)| self.format(c, &column.format, true))
+                .collect())
+            .collect()
+    }
+
     fn print_header(&self, w: &[usize], types: &[ColumnType], indent: usize) {
         if types.is_empty() {
             return;
@@ -216,11 +257,11 @@ impl PrettyPrinter {
         &self,
         w: &[usize],
         mut r: Vec<Value>,
+        formatted: Vec<String>,
         indent: usize,
         rows: &mut Vec<Table>,
         outputs: &mut Vec<InputStream>,
         binaries: &mut Vec<Box<dyn BinaryReader>>,
-        columns: &[ColumnType],
         col_count: usize,
     ) {
         if col_count == 0 {
@@ -229,11 +270,7 @@ impl PrettyPrinter {
         let cell_len = r.len();
         let mut row = " ".repeat(indent * 4);
         let last_idx = col_count - 1;
-        for (idx, c) in r.drain(..).enumerate() {
-            if idx == col_count {
-                break;
-            }
-            let formated_cell = c.to_pretty_string(&self.format_data, &columns[idx].format, true);
+        for (idx, (c, formated_cell)) in r.drain(..).zip(formatted).enumerate() {
             let spaces = if idx == cell_len - 1 {
                 "".to_string()
             } else {
@@ -267,9 +304,9 @@ impl PrettyPrinter {
         self.printer.line(&row);
     }
 
-    fn print_body(&self, w: &[usize], columns: &[ColumnType], data: Vec<Row>, indent: usize, last_separate: bool) {
+    fn print_body(&self, w: &[usize], data: Vec<Row>, formatted: Vec<Vec<String>>, indent: usize, last_separate: bool) {
         let col_count = w.len();
-        for r in data.into_iter() {
+        for (r, formatted) in data.into_iter().zip(formatted) {
             let mut rows = Vec::new();
             let mut outputs = Vec::new();
             let mut binaries = Vec::new();
@@ -278,13 +315,13 @@ impl PrettyPrinter {
 
             if last_separate {
                 let last = r_vec.remove(r_vec.len() - 1);
-                self.print_row(w, r_vec, indent, &mut rows, &mut outputs, &mut binaries, columns, col_count);
+                self.print_row(w, r_vec, formatted, indent, &mut rows, &mut outputs, &mut binaries, col_count);
                 match last {
                     Value::Struct(s) => self.print_struct(s, indent + 1),
                     _ => panic!("Invalid data"),
                 }
             } else {
-                self.print_row(w, r_vec, indent, &mut rows, &mut outputs, &mut binaries, columns, col_count);
+                self.print_row(w, r_vec, formatted, indent, &mut rows, &mut outputs, &mut binaries, col_count);
             }
 
             for r in rows {
@@ -346,11 +383,13 @@ impl PrettyPrinter {
 
             let mut w = vec![0; types.len()];
 
+            let formatted = self.format_body(&data, types);
+
             self.calculate_header_width(&mut w, types);
-            self.calculate_body_width(&mut w, &data, types.len(), types);
+            self.calculate_body_width(&mut w, &formatted);
 
             self.print_header(&w, types, indent);
-            self.print_body(&w, types, data, indent, last_separate)
+            self.print_body(&w, data, formatted, indent, last_separate)
         }
     }
 
@@ -359,7 +398,7 @@ impl PrettyPrinter {
         if data.len() > 0 {
             let max_name_width = data.keys().map(|n| n.len()).max().unwrap();
             for (name, value) in data.drain() {
-                let ss = value.to_pretty_string(&self.format_data, &ColumnFormat::None, false);
+                let ss = self.format(&value, &ColumnFormat::None, false);
                 if indent * 4 + max_name_width + ss.width() + 2 < self.printer.width() {
                     let mut line = " ".repeat(4 * indent);
                     line.push_str(&name);
@@ -372,14 +411,13 @@ impl PrettyPrinter {
                     line.push_str(&name);
                     line.push(':');
                     self.printer.line(&line);
-                    self.print_struct_value(value, indent + 1);
+                    self.print_struct_value(value, ss, indent + 1);
                 }
             }
         }
     }
 
-    fn print_struct_value(&self, value: Value, indent: usize) {
-        let ss = value.to_pretty_string(&self.format_data, &ColumnFormat::None, false);
+    fn print_struct_value(&self, value: Value, ss: String, indent: usize) {
         if ss.width() + 4 * indent < self.printer.width() {
             let mut line = " ".repeat(4 * indent);
             line.push_str(&ss);
@@ -408,7 +446,7 @@ impl PrettyPrinter {
         let mut items_per_column;
         let data = data
             .iter()
-            .map(|s| s.cells()[0].to_pretty_string(&self.format_data, &types[0].format, true))
+            .map(|s| self.format(&s.cells()[0], &types[0].format, true))
             .collect::<Vec<_>>();
 
         for cols in (2..50).rev() {
@@ -88,6 +88,48 @@ pub fn noop() -> (Printer, JoinHandle<()>) {
     )
 }
 
+/**
+A message written to a printer created with `with_callback`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrinterOutput {
+    Line(String),
+    Error(String),
+}
+
+/**
+Create a printer that passes everything written to it to a callback instead of the terminal, e.g.
+so that a program embedding Crush can capture it. The callback is called from the printer thread.
+ */
+pub fn with_callback(mut callback: impl FnMut(PrinterOutput) + Send + 'static) -> (Printer, JoinHandle<()>) {
+    let (sender, receiver) = bounded(128);
+    let (pong_sender, pong_receiver) = bounded(1);
+
+    (
+        Printer {
+            sender,
+            source: None,
+            pong_receiver,
+        },
+        thread::Builder::new()
+            .name("printer:callback".to_string())
+            .spawn(move || {
+                while let Ok(message) = receiver.recv() {
+                    match message {
+                        Ping => { let _ = pong_sender.send(()); }
+                        Error(err) => callback(PrinterOutput::Error(err)),
+                        CrushError(err) => callback(PrinterOutput::Error(match err.context() {
+                            Some(ctx) => format!("{}\n{}", err.message(), ctx),
+                            None => err.message(),
+                        })),
+                        Line(line) => callback(PrinterOutput::Line(line)),
+                    }
+                }
+            })
+            .unwrap(),
+    )
+}
+
 impl Printer {
     pub fn line(&self, line: &str) {
         self.handle_error(to_crush_error(
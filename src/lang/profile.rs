@@ -0,0 +1,193 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::cell::Cell;
+use std::time::Instant;
+use crate::lang::data::table::Row;
+use crate::lang::value::Value;
+
+thread_local! {
+    /** Whether the CPU time of the current thread is already being added to a stage. */
+    static MEASURING: Cell<bool> = const { Cell::new(false) };
+}
+
+/**
+Statistics for one stage of a profiled job. The stage updates them through the instrumented pipes
+and streams it reads from and writes to.
+ */
+#[derive(Debug)]
+pub struct StageStats {
+    name: String,
+    rows_in: AtomicU64,
+    rows_out: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    recv_nanos: AtomicU64,
+    send_nanos: AtomicU64,
+    cpu_nanos: AtomicU64,
+    peak_buffered: AtomicUsize,
+    /** The number of threads currently running this stage. */
+    running: Mutex<usize>,
+    /** Notified whenever the number of running threads drops to zero. */
+    idle: Condvar,
+}
+
+impl StageStats {
+    fn new(name: String) -> StageStats {
+        StageStats {
+            name,
+            rows_in: AtomicU64::new(0),
+            rows_out: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            recv_nanos: AtomicU64::new(0),
+            send_nanos: AtomicU64::new(0),
+            cpu_nanos: AtomicU64::new(0),
+            peak_buffered: AtomicUsize::new(0),
+            running: Mutex::new(0),
+            idle: Condvar::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rows_in(&self) -> u64 {
+        self.rows_in.load(Ordering::Relaxed)
+    }
+
+    pub fn rows_out(&self) -> u64 {
+        self.rows_out.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /** Nanoseconds spent blocked waiting for input. */
+    pub fn recv_nanos(&self) -> u64 {
+        self.recv_nanos.load(Ordering::Relaxed)
+    }
+
+    /** Nanoseconds spent blocked waiting for the next stage to accept output. */
+    pub fn send_nanos(&self) -> u64 {
+        self.send_nanos.load(Ordering::Relaxed)
+    }
+
+    pub fn cpu_nanos(&self) -> u64 {
+        self.cpu_nanos.load(Ordering::Relaxed)
+    }
+
+    /** The largest number of rows that were waiting in the output stream of this stage. */
+    pub fn peak_buffered(&self) -> usize {
+        self.peak_buffered.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self, row: &Row, start: Instant) {
+        self.recv_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.rows_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(row_size(row), Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, row_size: u64, start: Instant, buffered: usize) {
+        self.send_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.rows_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(row_size, Ordering::Relaxed);
+        self.peak_buffered.fetch_max(buffered, Ordering::Relaxed);
+    }
+
+    /** Account for time spent blocked on sending or receiving a value that isn't a row. */
+    pub fn waited(&self, start: Instant, sending: bool) {
+        let counter = if sending { &self.send_nanos } else { &self.recv_nanos };
+        counter.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /**
+    Run the stage, adding the CPU time used by the current thread to the statistics. If the
+    thread is already being measured, e.g. because a closure passes its output on to the last
+    command in it, the time is only counted once.
+     */
+    pub fn measure_cpu<T>(&self, f: impl FnOnce() -> T) -> T {
+        if MEASURING.with(|m| m.replace(true)) {
+            return f();
+        }
+        *self.running.lock().unwrap() += 1;
+        let start = thread_cpu_nanos();
+        let res = f();
+        self.cpu_nanos.fetch_add(thread_cpu_nanos().saturating_sub(start), Ordering::Relaxed);
+        let mut running = self.running.lock().unwrap();
+        *running -= 1;
+        if *running == 0 {
+            self.idle.notify_all();
+        }
+        drop(running);
+        MEASURING.with(|m| m.set(false));
+        res
+    }
+}
+
+/**
+The statistics of all stages of all jobs run while profiling, in the order they were started.
+ */
+#[derive(Clone)]
+pub struct Profile {
+    stages: Arc<Mutex<Vec<Arc<StageStats>>>>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            stages: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn stage(&self, name: String) -> Arc<StageStats> {
+        let stats = Arc::new(StageStats::new(name));
+        self.stages.lock().unwrap().push(stats.clone());
+        stats
+    }
+
+    /**
+    Return the statistics of all stages. Only the last stage of a job is waited for when running
+    it, so first wait for the other stages to finish updating their statistics.
+     */
+    pub fn stages(&self) -> Vec<Arc<StageStats>> {
+        let stages = self.stages.lock().unwrap().clone();
+        for stage in &stages {
+            let _idle = stage.idle.wait_while(stage.running.lock().unwrap(), |running| *running > 0).unwrap();
+        }
+        stages
+    }
+}
+
+fn thread_cpu_nanos() -> u64 {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // Safe, the call only writes to the timespec we pass in
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return 0;
+    }
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/**
+A rough estimate of the memory used by a row, used to compare how much data flows through
+different stages, not to measure anything exactly.
+ */
+pub fn row_size(row: &Row) -> u64 {
+    row.cells().iter().map(value_size).sum()
+}
+
+fn value_size(value: &Value) -> u64 {
+    let size = match value {
+        Value::String(s) => s.len(),
+        Value::Binary(b) => b.len(),
+        Value::File(f) => f.as_os_str().len(),
+        Value::List(l) => return l.iter().map(|v| value_size(&v)).sum::<u64>() + std::mem::size_of::<Value>() as u64,
+        _ => 0,
+    };
+    (size + std::mem::size_of::<Value>()) as u64
+}
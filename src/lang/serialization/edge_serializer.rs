@@ -0,0 +1,103 @@
+use super::super::errors::{error, CrushResult};
+use super::super::value::Value;
+use super::model;
+use super::model::{element, Element};
+use super::{DeserializationState, Serializable, SerializationState};
+use crate::lang::data::edge::{Edge, Graph};
+use crate::lang::data::table::{ColumnType, Row};
+use crate::util::identity_arc::Identity;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
+fn deserialize_graph(
+    id: usize,
+    elements: &[Element],
+    state: &mut DeserializationState,
+) -> CrushResult<Arc<Graph>> {
+    if let Some(graph) = state.graphs.get(&id) {
+        return Ok(graph.clone());
+    }
+    match elements[id].element.as_ref().unwrap() {
+        element::Element::Graph(g) => {
+            let types = g
+                .column_types
+                .iter()
+                .map(|t| ColumnType::deserialize(*t as usize, elements, state))
+                .collect::<CrushResult<Vec<_>>>()?;
+            let graph = Graph::new(types);
+            // The rows contain edges to the graph itself, so it must be registered before they
+            // are deserialized.
+            state.graphs.insert(id, graph.clone());
+            let rows = g
+                .rows
+                .iter()
+                .map(|r| Row::deserialize(*r as usize, elements, state))
+                .collect::<CrushResult<Vec<_>>>()?;
+            graph.set_rows(rows)?;
+            Ok(graph)
+        }
+        _ => error("Expected a graph"),
+    }
+}
+
+fn serialize_graph(
+    graph: &Arc<Graph>,
+    elements: &mut Vec<Element>,
+    state: &mut SerializationState,
+) -> CrushResult<usize> {
+    match state.with_id.entry(graph.id()) {
+        Entry::Occupied(o) => Ok(*o.get()),
+        Entry::Vacant(v) => {
+            let idx = elements.len();
+            elements.push(model::Element::default());
+            v.insert(idx);
+
+            let mut g = model::Graph::default();
+            for t in graph.types() {
+                g.column_types.push(t.serialize(elements, state)? as u64);
+            }
+            for r in graph.rows()? {
+                g.rows.push(r.serialize(elements, state)? as u64);
+            }
+            elements[idx].element = Some(element::Element::Graph(g));
+            Ok(idx)
+        }
+    }
+}
+
+impl Serializable<Edge> for Edge {
+    fn deserialize(
+        id: usize,
+        elements: &[Element],
+        state: &mut DeserializationState,
+    ) -> CrushResult<Edge> {
+        match elements[id].element.as_ref().unwrap() {
+            element::Element::Edge(e) => {
+                let graph = deserialize_graph(e.graph as usize, elements, state)?;
+                let value = Value::deserialize(e.value as usize, elements, state)?;
+                Ok(Edge::new(&graph, e.from as usize, e.to as usize, e.directed, value))
+            }
+            _ => error("Expected an edge"),
+        }
+    }
+
+    fn serialize(
+        &self,
+        elements: &mut Vec<Element>,
+        state: &mut SerializationState,
+    ) -> CrushResult<usize> {
+        let graph = serialize_graph(&self.graph()?, elements, state)? as u64;
+        let value = self.value().serialize(elements, state)? as u64;
+        let idx = elements.len();
+        elements.push(model::Element {
+            element: Some(element::Element::Edge(model::Edge {
+                graph,
+                from: self.from() as u64,
+                to: self.to() as u64,
+                directed: self.is_directed(),
+                value,
+            })),
+        });
+        Ok(idx)
+    }
+}
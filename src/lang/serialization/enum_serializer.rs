@@ -0,0 +1,54 @@
+use super::super::errors::{error, CrushResult};
+use super::super::value::{Value, ValueType};
+use super::model;
+use super::model::{element, Element};
+use super::{DeserializationState, Serializable, SerializationState};
+use crate::lang::data::r#enum::Enum;
+
+impl Serializable<Enum> for Enum {
+    fn deserialize(
+        id: usize,
+        elements: &[Element],
+        state: &mut DeserializationState,
+    ) -> CrushResult<Enum> {
+        match elements[id].element.as_ref().unwrap() {
+            element::Element::Enum(e) => {
+                let enum_type = match ValueType::deserialize(e.enum_type as usize, elements, state)? {
+                    ValueType::Enum(enum_type) => enum_type,
+                    _ => return error("Expected an enum type"),
+                };
+                if e.variant as usize >= enum_type.variants().len() {
+                    return error("Invalid enum variant");
+                }
+                let fields = e
+                    .fields
+                    .iter()
+                    .map(|field| Value::deserialize(*field as usize, elements, state))
+                    .collect::<CrushResult<Vec<_>>>()?;
+                Enum::new(enum_type, e.variant as usize, fields)
+            }
+            _ => error("Expected an enum"),
+        }
+    }
+
+    fn serialize(
+        &self,
+        elements: &mut Vec<Element>,
+        state: &mut SerializationState,
+    ) -> CrushResult<usize> {
+        let enum_type = ValueType::Enum(self.enum_type().clone()).serialize(elements, state)? as u64;
+        let mut fields = Vec::new();
+        for (_, value) in self.fields() {
+            fields.push(value.serialize(elements, state)? as u64);
+        }
+        let idx = elements.len();
+        elements.push(model::Element {
+            element: Some(element::Element::Enum(model::Enum {
+                enum_type,
+                variant: self.variant() as u64,
+                fields,
+            })),
+        });
+        Ok(idx)
+    }
+}
@@ -1,19 +1,28 @@
 use crate::lang::data::dict::Dict;
-use crate::lang::errors::{to_crush_error, CrushResult};
+use crate::lang::data::edge::Graph;
+use crate::lang::errors::{error, mandate, to_crush_error, CrushErrorType, CrushResult};
 use crate::lang::data::list::List;
 use crate::lang::data::r#struct::Struct;
+use crate::lang::data::set::Set;
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::pipe::{streams, InputStream, OutputStream};
+use crate::lang::state::global_state::GlobalState;
 use crate::lang::state::scope::Scope;
 use crate::lang::value::{Value, ValueType};
-use model::Element;
-use model::SerializedValue;
+use model::{stream_frame, stream_header};
+use model::{Element, RowBatch, SerializedValue, StreamFrame, StreamHeader};
 use prost::Message;
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::sync::Arc;
 
 mod dict_serializer;
+mod edge_serializer;
+mod enum_serializer;
 mod integer_serializer;
 mod list_serializer;
 mod scope_serializer;
+mod set_serializer;
 mod string_serializer;
 mod struct_serializer;
 mod table_serializer;
@@ -36,11 +45,14 @@ pub struct DeserializationState {
     pub lists: HashMap<usize, List>,
     pub types: HashMap<usize, ValueType>,
     pub dicts: HashMap<usize, Dict>,
+    pub sets: HashMap<usize, Set>,
     pub structs: HashMap<usize, Struct>,
     pub scopes: HashMap<usize, Scope>,
+    pub graphs: HashMap<usize, Arc<Graph>>,
 }
 
-pub fn serialize(value: &Value, buf: &mut Vec<u8>) -> CrushResult<()> {
+/** Serialize a value into a message that can be embedded in other messages. */
+pub fn serialized_value(value: &Value) -> CrushResult<SerializedValue> {
     let mut res = SerializedValue::default();
     let mut state = SerializationState {
         with_id: HashMap::new(),
@@ -50,7 +62,11 @@ pub fn serialize(value: &Value, buf: &mut Vec<u8>) -> CrushResult<()> {
         .clone()
         .materialize()?
         .serialize(&mut res.elements, &mut state)? as u64;
+    Ok(res)
+}
 
+pub fn serialize(value: &Value, buf: &mut Vec<u8>) -> CrushResult<()> {
+    let res = serialized_value(value)?;
     buf.reserve(res.encoded_len());
     res.encode(buf).unwrap();
     Ok(())
@@ -73,20 +89,70 @@ pub fn deserialize_reader(source: &mut dyn Read, env: &Scope) -> CrushResult<Val
     deserialize(&buf, env)
 }
 
-pub fn deserialize(buf: &Vec<u8>, env: &Scope) -> CrushResult<Value> {
-    let mut state = DeserializationState {
-        values: HashMap::new(),
-        types: HashMap::new(),
-        lists: HashMap::new(),
-        dicts: HashMap::new(),
-        structs: HashMap::new(),
-        scopes: HashMap::new(),
-        env: env.clone(),
-    };
+/**
+Write a single length-delimited frame. Frames are used to send multiple pup messages over the
+same byte stream, e.g. to a long lived worker process. A frame is a big endian 64-bit length
+followed by that many bytes of payload.
+ */
+pub fn write_frame(destination: &mut dyn Write, payload: &[u8]) -> CrushResult<()> {
+    to_crush_error(destination.write_all(&(payload.len() as u64).to_be_bytes()))?;
+    to_crush_error(destination.write_all(payload))?;
+    to_crush_error(destination.flush())
+}
+
+/**
+The largest frame `read_frame` accepts. The length of a frame comes from the other end of the
+stream, so it can not be trusted to allocate a buffer of that size.
+ */
+pub const MAX_FRAME_SIZE: u64 = 1 << 30;
+
+/**
+Read a single length-delimited frame, as written by `write_frame`. Returns None if the stream
+was closed before the start of a new frame.
+ */
+pub fn read_frame(source: &mut dyn Read) -> CrushResult<Option<Vec<u8>>> {
+    let mut len = [0u8; 8];
+    match source.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return error(e.to_string()),
+    }
+    let len = u64::from_be_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return error(format!(
+            "Invalid pup frame of {} bytes, the largest allowed size is {} bytes",
+            len, MAX_FRAME_SIZE));
+    }
+    // Read incrementally instead of allocating the whole frame up front, so that a truncated
+    // stream only costs as much memory as it actually contains
+    let mut payload = Vec::new();
+    to_crush_error(source.take(len).read_to_end(&mut payload))?;
+    if payload.len() as u64 != len {
+        return error("Truncated pup frame");
+    }
+    Ok(Some(payload))
+}
 
-    let res = SerializedValue::decode(&mut Cursor::new(buf)).unwrap();
+impl DeserializationState {
+    fn new(env: &Scope) -> DeserializationState {
+        DeserializationState {
+            values: HashMap::new(),
+            types: HashMap::new(),
+            lists: HashMap::new(),
+            dicts: HashMap::new(),
+            sets: HashMap::new(),
+            structs: HashMap::new(),
+            scopes: HashMap::new(),
+            graphs: HashMap::new(),
+            env: env.clone(),
+        }
+    }
+}
+
+pub fn deserialize(buf: &Vec<u8>, env: &Scope) -> CrushResult<Value> {
+    let mut state = DeserializationState::new(env);
 
-//    println!("AAA {:?}", res);
+    let res = to_crush_error(SerializedValue::decode(&mut Cursor::new(buf)))?;
 
     Ok(Value::deserialize(
         res.root as usize,
@@ -95,6 +161,199 @@ pub fn deserialize(buf: &Vec<u8>, env: &Scope) -> CrushResult<Value> {
     )?)
 }
 
+/**
+The maximum number of rows sent in a single frame of a pup stream. Rows are sent as soon as they
+are available, so batches are usually smaller than this.
+ */
+const STREAM_BATCH_SIZE: usize = 256;
+
+/**
+The first bytes of a pup stream. They tell a pup stream apart from a single pup-serialized value,
+e.g. the output of an older crush binary that doesn't support streaming.
+ */
+pub const STREAM_MAGIC: &[u8] = b"PUPS";
+
+fn write_stream_frame(destination: &mut dyn Write, frame: stream_frame::Frame) -> CrushResult<()> {
+    let frame = StreamFrame { frame: Some(frame) };
+    let mut buf = Vec::with_capacity(frame.encoded_len());
+    to_crush_error(frame.encode(&mut buf))?;
+    write_frame(destination, &buf)
+}
+
+fn read_stream_frame(source: &mut dyn Read) -> CrushResult<stream_frame::Frame> {
+    match read_frame(source)? {
+        None => error("Unexpected end of pup stream"),
+        Some(buf) => mandate(
+            to_crush_error(StreamFrame::decode(&buf[..]))?.frame,
+            "Invalid frame in pup stream"),
+    }
+}
+
+fn header(content: stream_header::Content) -> stream_frame::Frame {
+    stream_frame::Frame::Header(StreamHeader {
+        version: crate::version_string(),
+        content: Some(content),
+    })
+}
+
+fn send_rows(rows: Vec<Row>, destination: &mut dyn Write) -> CrushResult<()> {
+    let mut batch = RowBatch::default();
+    let mut state = SerializationState {
+        with_id: HashMap::new(),
+        values: HashMap::new(),
+    };
+    for row in rows {
+        let row = row.materialize()?;
+        batch.rows.push(row.serialize(&mut batch.elements, &mut state)? as u64);
+    }
+    write_stream_frame(destination, stream_frame::Frame::Rows(batch))
+}
+
+fn stream_rows(input: InputStream, destination: &mut dyn Write) -> CrushResult<()> {
+    write_stream_frame(
+        destination,
+        header(stream_header::Content::StreamType(
+            serialized_value(&Value::Type(ValueType::TableInputStream(ColumnType::materialize(input.types())?)))?)))?;
+    let mut rows = Vec::new();
+    while let Ok(row) = input.recv() {
+        rows.push(row);
+        while rows.len() < STREAM_BATCH_SIZE {
+            match input.try_recv() {
+                Ok(row) => rows.push(row),
+                Err(_) => break,
+            }
+        }
+        send_rows(std::mem::take(&mut rows), destination)?;
+    }
+    Ok(())
+}
+
+/**
+Write a value in the streaming variant of the pup format. Unlike `serialize`, table streams are
+not materialized. Instead, their rows are written in batches as they become available, so this
+function does not return until the stream has been exhausted.
+
+If the value is an error, the error is written to the stream, to be returned on the reading side.
+ */
+pub fn serialize_stream(value: CrushResult<Value>, destination: &mut dyn Write) -> CrushResult<()> {
+    serialize_stream_checked(value, destination, || Ok(()))
+}
+
+/**
+Like `serialize_stream`, but once the value has been written, `check` is called to find out if
+producing it failed part-way through, e.g. because the command writing a table stream returned an
+error after sending some rows. If so, the stream is terminated by that error instead of an end
+frame.
+ */
+pub fn serialize_stream_checked(
+    value: CrushResult<Value>,
+    destination: &mut dyn Write,
+    check: impl FnOnce() -> CrushResult<()>,
+) -> CrushResult<()> {
+    to_crush_error(destination.write_all(STREAM_MAGIC))?;
+    let res = match value {
+        Ok(Value::TableInputStream(input)) => stream_rows(input, destination),
+        Ok(value) => serialized_value(&value).and_then(|v| write_stream_frame(
+            destination,
+            header(stream_header::Content::Value(v)))),
+        Err(e) => Err(e),
+    }.and_then(|_| check());
+    match res {
+        Ok(()) => write_stream_frame(destination, stream_frame::Frame::End(true)),
+        Err(e) => write_stream_frame(destination, stream_frame::Frame::Error(e.message())),
+    }
+}
+
+fn deserialize_rows(batch: &RowBatch, env: &Scope, output: &OutputStream) -> CrushResult<()> {
+    let mut state = DeserializationState::new(env);
+    for idx in &batch.rows {
+        output.send(Row::deserialize(*idx as usize, &batch.elements, &mut state)?)?;
+    }
+    Ok(())
+}
+
+fn expect_end(source: &mut dyn Read) -> CrushResult<()> {
+    match read_stream_frame(source)? {
+        stream_frame::Frame::End(_) => Ok(()),
+        stream_frame::Frame::Error(e) => error(e),
+        _ => error("Expected end of pup stream"),
+    }
+}
+
+/**
+Read a value written by `serialize_stream`. If the value is a table stream, a thread is spawned
+that reads rows from the source and passes them on as they arrive, and the returned value is a
+table stream that is immediately available.
+
+Once the whole value has been read, `on_end` is called with the source, e.g. so that a connection
+can be reused for another value. If the stream is not read to completion, because of an error or
+because the reader of the table stream went away, the source is dropped instead.
+ */
+pub fn deserialize_stream<R: Read + Send + 'static>(
+    mut source: R,
+    env: &Scope,
+    global_state: &GlobalState,
+    on_end: impl FnOnce(R) + Send + 'static,
+) -> CrushResult<Value> {
+    let mut magic = [0u8; STREAM_MAGIC.len()];
+    if source.read_exact(&mut magic).is_err() || magic != STREAM_MAGIC {
+        return error("Not a pup stream. If it was written by crush on another host, that crush may be too old to support streaming");
+    }
+    let header = match read_stream_frame(&mut source)? {
+        stream_frame::Frame::Header(header) => header,
+        stream_frame::Frame::Error(e) => return error(e),
+        _ => return error("Expected pup stream header"),
+    };
+    if header.version != crate::version_string() {
+        return error(format!(
+            "Incompatible pup stream, written by {} but read by {}",
+            header.version,
+            crate::version_string()));
+    }
+    let mut state = DeserializationState::new(env);
+    match mandate(header.content, "Missing content in pup stream header")? {
+        stream_header::Content::Value(v) => {
+            let value = Value::deserialize(v.root as usize, &v.elements, &mut state)?;
+            expect_end(&mut source)?;
+            on_end(source);
+            Ok(value)
+        }
+        stream_header::Content::StreamType(t) => {
+            match ValueType::deserialize(t.root as usize, &t.elements, &mut state)? {
+                ValueType::TableInputStream(types) => {
+                    let (output, input) = streams(types);
+                    let my_env = env.clone();
+                    global_state.threads().spawn(
+                        "pup:stream",
+                        None,
+                        move || {
+                            loop {
+                                match read_stream_frame(&mut source)? {
+                                    stream_frame::Frame::Rows(batch) => {
+                                        match deserialize_rows(&batch, &my_env, &output) {
+                                            Ok(()) => {}
+                                            // The reader of the table stream went away
+                                            Err(e) if e.is(CrushErrorType::SendError) => return Ok(()),
+                                            Err(e) => return Err(e),
+                                        }
+                                    }
+                                    stream_frame::Frame::End(_) => {
+                                        on_end(source);
+                                        return Ok(());
+                                    }
+                                    stream_frame::Frame::Error(e) => return error(e),
+                                    stream_frame::Frame::Header(_) => return error("Unexpected header in pup stream"),
+                                }
+                            }
+                        })?;
+                    Ok(Value::TableInputStream(input))
+                }
+                _ => error("Expected the type of a table stream in pup stream header"),
+            }
+        }
+    }
+}
+
 pub trait Serializable<T> {
     fn deserialize(
         id: usize,
@@ -107,3 +366,48 @@ pub trait Serializable<T> {
         state: &mut SerializationState,
     ) -> CrushResult<usize>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"abc").unwrap();
+        let mut source = Cursor::new(buf);
+        assert_eq!(read_frame(&mut source).unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(read_frame(&mut source).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut source = Cursor::new(u64::MAX.to_be_bytes().to_vec());
+        assert!(read_frame(&mut source).is_err());
+    }
+
+    #[test]
+    fn streams_round_trip() {
+        let mut buf = Vec::new();
+        serialize_stream(Ok(Value::from("hello")), &mut buf).unwrap();
+        let global_state = GlobalState::new(crate::lang::printer::noop().0).unwrap();
+        let value = deserialize_stream(Cursor::new(buf), &Scope::create_root(), &global_state, |_| {}).unwrap();
+        assert!(value == Value::from("hello"));
+    }
+
+    #[test]
+    fn values_are_not_streams() {
+        let mut buf = Vec::new();
+        serialize(&Value::from("hello"), &mut buf).unwrap();
+        let global_state = GlobalState::new(crate::lang::printer::noop().0).unwrap();
+        let err = deserialize_stream(Cursor::new(buf), &Scope::create_root(), &global_state, |_| {}).err().unwrap();
+        assert!(err.message().contains("too old"), "{}", err.message());
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let mut buf = 10u64.to_be_bytes().to_vec();
+        buf.extend_from_slice(b"abc");
+        assert!(read_frame(&mut Cursor::new(buf)).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use super::super::errors::{error, CrushResult};
+use super::super::value::{Value, ValueType};
+use super::model;
+use super::model::{element, Element};
+use super::{DeserializationState, Serializable, SerializationState};
+use crate::lang::data::set::Set;
+use crate::util::identity_arc::Identity;
+use std::collections::hash_map::Entry;
+
+impl Serializable<Set> for Set {
+    fn deserialize(
+        id: usize,
+        elements: &[Element],
+        state: &mut DeserializationState,
+    ) -> CrushResult<Set> {
+        match state.sets.entry(id) {
+            Entry::Occupied(o) => Ok(o.get().clone()),
+            Entry::Vacant(_) => {
+                if let element::Element::Set(s) = elements[id].element.as_ref().unwrap() {
+                    let element_type =
+                        ValueType::deserialize(s.element_type as usize, elements, state)?;
+                    let set = Set::new(element_type)?;
+                    state.sets.insert(id, set.clone());
+
+                    for value in &s.elements {
+                        set.insert(Value::deserialize(*value as usize, elements, state)?)?;
+                    }
+                    Ok(set)
+                } else {
+                    error("Expected a set")
+                }
+            }
+        }
+    }
+
+    fn serialize(
+        &self,
+        elements: &mut Vec<Element>,
+        state: &mut SerializationState,
+    ) -> CrushResult<usize> {
+        let id = self.id();
+        match state.with_id.entry(id) {
+            Entry::Occupied(o) => Ok(*o.get()),
+            Entry::Vacant(v) => {
+                let idx = elements.len();
+                elements.push(model::Element::default());
+                v.insert(idx);
+
+                let mut ss = model::Set {
+                    element_type: Value::Type(self.element_type()).serialize(elements, state)? as u64,
+                    elements: Vec::with_capacity(self.len()),
+                };
+                for value in self.elements() {
+                    ss.elements.push(value.serialize(elements, state)? as u64);
+                }
+                elements[idx].element = Some(element::Element::Set(ss));
+
+                Ok(idx)
+            }
+        }
+    }
+}
@@ -3,6 +3,9 @@ use crate::lang::data::dict::Dict;
 use crate::lang::errors::{error, to_crush_error, CrushResult, mandate};
 use crate::lang::data::list::List;
 use crate::lang::data::r#struct::Struct;
+use crate::lang::data::r#enum::Enum;
+use crate::lang::data::edge::Edge;
+use crate::lang::data::set::Set;
 use crate::lang::state::scope::Scope;
 use crate::lang::serialization::model;
 use crate::lang::serialization::model::{element, Element};
@@ -80,6 +83,12 @@ impl Serializable<Value> for Value {
             element::Element::Struct(_) => {
                 Ok(Value::Struct(Struct::deserialize(id, elements, state)?))
             }
+            element::Element::Enum(_) => {
+                Ok(Value::Enum(Enum::deserialize(id, elements, state)?))
+            }
+            element::Element::Edge(_) => {
+                Ok(Value::Edge(Edge::deserialize(id, elements, state)?))
+            }
 
             element::Element::Command(_)
             | element::Element::BoundCommand(_)
@@ -91,12 +100,14 @@ impl Serializable<Value> for Value {
                 Ok(Value::Scope(Scope::deserialize(id, elements, state)?))
             }
             element::Element::Dict(_) => Ok(Dict::deserialize(id, elements, state)?.into()),
+            element::Element::Set(_) => Ok(Set::deserialize(id, elements, state)?.into()),
 
             element::Element::TrackedString(_)
             | element::Element::Strings(_)
             | element::Element::ColumnType(_)
             | element::Element::Row(_)
-            | element::Element::Member(_) => error("Not a value"),
+            | element::Element::Member(_)
+            | element::Element::Graph(_) => error("Not a value"),
         }
     }
 
@@ -139,7 +150,10 @@ impl Serializable<Value> for Value {
             Value::Table(t) => t.serialize(elements, state),
             Value::Command(c) => c.serialize(elements, state),
             Value::Struct(s) => s.serialize(elements, state),
+            Value::Enum(e) => e.serialize(elements, state),
+            Value::Edge(e) => e.serialize(elements, state),
             Value::Dict(d) => d.serialize(elements, state),
+            Value::Set(s) => s.serialize(elements, state),
             Value::Scope(s) => s.serialize(elements, state),
             Value::TableOutputStream(_) | Value::TableInputStream(_) |
             Value::BinaryInputStream(_) => error("Can't serialize streams"),
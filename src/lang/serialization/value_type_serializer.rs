@@ -3,9 +3,12 @@ use crate::lang::serialization::model;
 use crate::lang::serialization::model::{element, Element};
 use crate::lang::serialization::{DeserializationState, Serializable, SerializationState};
 use crate::lang::data::table::ColumnType;
+use crate::lang::data::r#enum::{EnumType, Variant};
+use crate::util::identity_arc::Identity;
 use crate::lang::value::ValueType;
 use model::r#type::SimpleTypeKind;
 use model::r#type::Type::SimpleType;
+use std::sync::Arc;
 
 impl Serializable<ValueType> for ValueType {
     fn deserialize(
@@ -32,6 +35,7 @@ impl Serializable<ValueType> for ValueType {
                     14 => ValueType::Time,
                     15 => ValueType::Struct,
                     16 => ValueType::Any,
+                    18 => ValueType::Edge,
                     _ => return error("Unrecognised type"),
                 }),
                 model::r#type::Type::ListType(l) => Ok(ValueType::List(Box::from(
@@ -49,6 +53,15 @@ impl Serializable<ValueType> for ValueType {
                         state,
                     )?),
                 )),
+                model::r#type::Type::SetType(s) => Ok(ValueType::Set(Box::from(
+                    ValueType::deserialize(s.element_type as usize, elements, state)?,
+                ))),
+                model::r#type::Type::UnionType(u) => Ok(ValueType::either(
+                    u.types
+                        .iter()
+                        .map(|t| ValueType::deserialize(*t as usize, elements, state))
+                        .collect::<CrushResult<Vec<_>>>()?,
+                )),
                 model::r#type::Type::TableType(tt) => Ok(ValueType::Table(
                     tt.column_types
                         .iter()
@@ -67,6 +80,30 @@ impl Serializable<ValueType> for ValueType {
                         .map(|t| ColumnType::deserialize(*t as usize, elements, state))
                         .collect::<CrushResult<Vec<_>>>()?,
                 )),
+                model::r#type::Type::EnumType(e) => {
+                    // Values of the same enum share the serialized type, so share the deserialized one too
+                    if let Some(t) = state.types.get(&id) {
+                        return Ok(t.clone());
+                    }
+                    let mut variants = Vec::new();
+                    for variant in &e.variants {
+                        let mut fields = Vec::new();
+                        for field in &variant.fields {
+                            fields.push((
+                                String::deserialize(field.name as usize, elements, state)?,
+                                ValueType::deserialize(field.r#type as usize, elements, state)?,
+                            ));
+                        }
+                        variants.push(Variant {
+                            name: String::deserialize(variant.name as usize, elements, state)?,
+                            fields,
+                        });
+                    }
+                    let name = String::deserialize(e.name as usize, elements, state)?;
+                    let res = ValueType::Enum(Arc::from(EnumType::new(name, variants)?));
+                    state.types.insert(id, res.clone());
+                    Ok(res)
+                }
             }
         } else {
             error("Invalid type")
@@ -95,6 +132,7 @@ impl Serializable<ValueType> for ValueType {
             ValueType::Any => SimpleTypeKind::Any,
             ValueType::Binary => SimpleTypeKind::Binary,
             ValueType::Type => SimpleTypeKind::Type,
+            ValueType::Edge => SimpleTypeKind::Edge,
             ValueType::List(t) => {
                 let l = model::ListType {
                     element_type: t.serialize(elements, state)? as u64,
@@ -120,6 +158,33 @@ impl Serializable<ValueType> for ValueType {
                 });
                 return Ok(idx);
             }
+            ValueType::Set(t) => {
+                let s = model::SetType {
+                    element_type: t.serialize(elements, state)? as u64,
+                };
+                let idx = elements.len();
+                elements.push(model::Element {
+                    element: Some(element::Element::Type(model::Type {
+                        r#type: Some(model::r#type::Type::SetType(s)),
+                    })),
+                });
+                return Ok(idx);
+            }
+            ValueType::Union(types) => {
+                let u = model::UnionType {
+                    types: types
+                        .iter()
+                        .map(|t| t.serialize(elements, state).map(|t| t as u64))
+                        .collect::<CrushResult<Vec<_>>>()?,
+                };
+                let idx = elements.len();
+                elements.push(model::Element {
+                    element: Some(element::Element::Type(model::Type {
+                        r#type: Some(model::r#type::Type::UnionType(u)),
+                    })),
+                });
+                return Ok(idx);
+            }
             ValueType::TableOutputStream(col) | ValueType::Table(col) |
             ValueType::TableInputStream(col) => {
                 let d = model::TableType {
@@ -141,6 +206,37 @@ impl Serializable<ValueType> for ValueType {
                 return Ok(idx);
             }
             ValueType::BinaryInputStream => SimpleTypeKind::BinaryStream,
+            ValueType::Enum(e) => {
+                if let Some(idx) = state.with_id.get(&e.id()) {
+                    return Ok(*idx);
+                }
+                let mut variants = Vec::new();
+                for variant in e.variants() {
+                    let mut fields = Vec::new();
+                    for (name, field_type) in &variant.fields {
+                        fields.push(model::EnumField {
+                            name: name.serialize(elements, state)? as u64,
+                            r#type: field_type.serialize(elements, state)? as u64,
+                        });
+                    }
+                    variants.push(model::EnumVariant {
+                        name: variant.name.serialize(elements, state)? as u64,
+                        fields,
+                    });
+                }
+                let et = model::EnumType {
+                    name: e.name().to_string().serialize(elements, state)? as u64,
+                    variants,
+                };
+                let idx = elements.len();
+                elements.push(model::Element {
+                    element: Some(element::Element::Type(model::Type {
+                        r#type: Some(model::r#type::Type::EnumType(et)),
+                    })),
+                });
+                state.with_id.insert(e.id(), idx);
+                return Ok(idx);
+            }
         };
 
         let idx = elements.len();
@@ -5,6 +5,7 @@ use crate::lang::state::global_state::{GlobalState, JobHandle};
 use crate::lang::pipe::{
     black_hole, empty_channel, ValueReceiver, ValueSender,
 };
+use crate::lang::profile::Profile;
 use crate::lang::value::Value;
 use std::mem::swap;
 use std::thread::ThreadId;
@@ -56,6 +57,8 @@ pub struct JobContext {
     pub scope: Scope,
     pub global_state: GlobalState,
     pub handle: Option<JobHandle>,
+    /** If set, every stage of the job records statistics into this profile. */
+    pub profile: Option<Profile>,
 }
 
 impl JobContext {
@@ -71,6 +74,7 @@ impl JobContext {
             scope: env,
             global_state,
             handle: None,
+            profile: None,
         }
     }
 
@@ -81,6 +85,7 @@ impl JobContext {
             scope: self.scope.clone(),
             global_state: self.global_state.clone(),
             handle: Some(self.global_state.job_begin(desc)),
+            profile: self.profile.clone(),
         }
     }
 
@@ -91,6 +96,14 @@ impl JobContext {
             scope: self.scope.clone(),
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
+            profile: self.profile.clone(),
+        }
+    }
+
+    pub fn with_profile(self, profile: Option<Profile>) -> JobContext {
+        JobContext {
+            profile,
+            ..self
         }
     }
 
@@ -103,6 +116,7 @@ impl JobContext {
             scope: self.scope.clone(),
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
+            profile: None,
         }
     }
 
@@ -127,6 +141,7 @@ pub struct CommandContext {
     pub this: Option<Value>,
     pub global_state: GlobalState,
     handle: Option<JobHandle>,
+    profile: Option<Profile>,
 }
 
 impl CommandContext {
@@ -142,6 +157,7 @@ impl CommandContext {
             this: None,
             global_state: state.clone(),
             handle: None,
+            profile: None,
         }
     }
 
@@ -168,6 +184,7 @@ impl CommandContext {
             this: None,
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
+            profile: None,
         }
     }
 
@@ -183,6 +200,7 @@ impl CommandContext {
             this,
             global_state: self.global_state,
             handle: self.handle,
+            profile: self.profile,
         }
     }
 
@@ -198,6 +216,7 @@ impl CommandContext {
             this: self.this,
             global_state: self.global_state,
             handle: self.handle,
+            profile: self.profile,
         }
     }
 
@@ -213,6 +232,7 @@ impl CommandContext {
             this: self.this,
             global_state: self.global_state,
             handle: self.handle,
+            profile: self.profile,
         }
     }
 
@@ -228,9 +248,25 @@ impl CommandContext {
             this: self.this,
             global_state: self.global_state,
             handle: self.handle.clone(),
+            profile: self.profile,
+        }
+    }
+
+    /**
+    Return a new Command context that is identical to this one but where the jobs of a closure
+    run in it are profiled.
+     */
+    pub fn with_profile(self, profile: Profile) -> CommandContext {
+        CommandContext {
+            profile: Some(profile),
+            ..self
         }
     }
 
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
     pub fn spawn<F>(&self, name: &str, f: F) -> CrushResult<ThreadId>
         where
             F: FnOnce() -> CrushResult<()>,
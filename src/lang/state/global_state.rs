@@ -1,12 +1,16 @@
 use crate::lang::command::Command;
+use crate::lang::debug::Debugger;
 use crate::lang::errors::{to_crush_error, CrushResult};
 use crate::lang::parser::Parser;
 use crate::lang::printer::Printer;
+use crate::lang::state::scope::Scope;
 use crate::lang::threads::ThreadStore;
+use crate::lang::trace::Tracer;
 use num_format::{Grouping, SystemLocale};
 use std::sync::{Arc, Mutex, MutexGuard};
 use rustyline::Editor;
-use rustyline::history::DefaultHistory;
+use crate::interactive::editor::EditorSettings;
+use crate::interactive::history::CrushHistory;
 use crate::interactive::rustyline_helper::RustylineHelper;
 use crate::lang::value::Value;
 use crate::util::byte_unit::ByteUnit;
@@ -81,14 +85,21 @@ impl FormatData {
 pub struct GlobalState {
     data: Arc<Mutex<StateData>>,
     threads: ThreadStore,
+    debugger: Debugger,
+    tracer: Tracer,
+    /** Every imported module, so that each module is only run once. */
+    modules: Scope,
     printer: Printer,
     parser: Parser,
-    editor: Arc<Mutex<Option<Editor<RustylineHelper, DefaultHistory>>>>,
+    editor: Arc<Mutex<Option<Editor<RustylineHelper, CrushHistory>>>>,
 }
 
 struct StateData {
     format_data: FormatData,
     prompt: Option<Command>,
+    suggest: bool,
+    editor_settings: EditorSettings,
+    history: Option<CrushHistory>,
     jobs: Vec<Option<LiveJob>>,
     exit_status: Option<i32>,
 }
@@ -169,15 +180,32 @@ impl GlobalState {
                 },
                 exit_status: None,
                 prompt: None,
+                suggest: true,
+                editor_settings: EditorSettings::default(),
+                history: None,
                 jobs: Vec::new(),
             })),
             threads: ThreadStore::new(),
+            debugger: Debugger::new(),
+            tracer: Tracer::new(),
+            modules: Scope::create(Some("modules".to_string()), Some("All imported modules.".to_string()), false, false, false),
             printer,
             parser: Parser::new(),
             editor: Arc::from(Mutex::new(None)),
         })
     }
 
+    /**
+    A copy of this state that shares everything except the printer, e.g. so that the output of a
+    single request can be captured.
+     */
+    pub fn with_printer(&self, printer: Printer) -> GlobalState {
+        GlobalState {
+            printer,
+            ..self.clone()
+        }
+    }
+
     pub fn parser(&self) -> &Parser {
         &self.parser
     }
@@ -186,6 +214,18 @@ impl GlobalState {
         &self.threads
     }
 
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    pub fn modules(&self) -> &Scope {
+        &self.modules
+    }
+
     pub fn printer(&self) -> &Printer {
         &self.printer
     }
@@ -231,17 +271,52 @@ impl GlobalState {
         data.prompt.as_ref().map(|a| a.clone())
     }
 
+    pub fn set_suggest(&self, suggest: bool) {
+        let mut data = self.data.lock().unwrap();
+        data.suggest = suggest;
+    }
+
+    pub fn suggest(&self) -> bool {
+        let data = self.data.lock().unwrap();
+        data.suggest
+    }
+
+    pub fn editor_settings(&self) -> EditorSettings {
+        let data = self.data.lock().unwrap();
+        data.editor_settings.clone()
+    }
+
+    pub fn set_editor_settings(&self, mut settings: EditorSettings) {
+        let mut data = self.data.lock().unwrap();
+        settings.version = data.editor_settings.version + 1;
+        data.editor_settings = settings;
+    }
+
+    pub fn set_history(&self, history: Option<CrushHistory>) {
+        let mut data = self.data.lock().unwrap();
+        data.history = history;
+    }
+
+    /**
+    The history of the interactive prompt. Unlike the editor, the history can be
+    used while a line is being read, e.g. from a key binding.
+     */
+    pub fn history(&self) -> Option<CrushHistory> {
+        let data = self.data.lock().unwrap();
+        data.history.clone()
+    }
+
     pub fn jobs(&self) -> Vec<LiveJob> {
         let data = self.data.lock().unwrap();
         data.jobs.iter().flat_map(|a| a.clone()).collect()
     }
 
-    pub fn set_editor(&self, editor: Option<Editor<RustylineHelper, DefaultHistory>>) {
+    pub fn set_editor(&self, editor: Option<Editor<RustylineHelper, CrushHistory>>) {
         let mut data = self.editor.lock().unwrap();
         *data = editor;
     }
 
-    pub fn editor(&self) -> MutexGuard<Option<Editor<RustylineHelper, DefaultHistory>>> {
+    pub fn editor(&self) -> MutexGuard<Option<Editor<RustylineHelper, CrushHistory>>> {
         self.editor.lock().unwrap()
     }
 
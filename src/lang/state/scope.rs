@@ -1,4 +1,5 @@
 use crate::lang::command::{Command, CrushCommand, OutputType, ArgumentDescription};
+use crate::lang::command_invocation::CommandInvocation;
 use crate::lang::errors::{error, mandate, CrushResult, argument_error_legacy, CrushError};
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::help::Help;
@@ -9,6 +10,7 @@ use ordered_map::OrderedMap;
 use std::cmp::max;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use chrono::Duration;
 use lazy_static::lazy_static;
 use crate::data::table::{ColumnType, Row};
@@ -191,6 +193,21 @@ pub struct ScopeData {
 
     pub name: Option<String>,
     description: Option<String>,
+
+    /** The command most recently started in this scope. Since the calling scopes make up the
+                   function stack, this is used by the debugger to show where each call in the stack is. */
+    position: Option<Arc<CommandInvocation>>,
+
+    /** If the code of a module is running in this scope, the file of that module. */
+    module: Option<PathBuf>,
+
+    /** The members that the module running in this scope exports. If empty, all members not
+                   starting with an underscore are exported. */
+    exports: Vec<String>,
+
+    /** Methods added to builtin types using `types:extend`, along with the type each one extends.
+    They are visible to code running in this scope and its children. */
+    extensions: Vec<(ValueType, String, Command)>,
     is_loaded: bool,
     loader: Option<Box<dyn Send + FnOnce(&mut ScopeLoader) -> CrushResult<()>>>,
 }
@@ -213,6 +230,10 @@ impl ScopeData {
             is_readonly: false,
             name,
             description,
+            position: None,
+            module: None,
+            exports: Vec::new(),
+            extensions: Vec::new(),
             is_loaded: true,
             loader: None,
         }
@@ -236,6 +257,10 @@ impl ScopeData {
             is_readonly: false,
             name,
             description,
+            position: None,
+            module: None,
+            exports: Vec::new(),
+            extensions: Vec::new(),
             is_loaded: false,
             loader: Some(loader),
         }
@@ -254,12 +279,21 @@ impl Clone for ScopeData {
             is_readonly: self.is_readonly,
             name: self.name.clone(),
             description: self.description.clone(),
+            position: self.position.clone(),
+            module: self.module.clone(),
+            exports: self.exports.clone(),
+            extensions: self.extensions.clone(),
             is_loaded: true,
             loader: None,
         }
     }
 }
 
+/** Whether two types are the same, ignoring type parameters like the element type of a list. */
+fn same_kind(a: &ValueType, b: &ValueType) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
 fn lookup(key: &str, data: &MutexGuard<ScopeData>) -> Option<Value> {
     data.mapping.get(key).map(|v| v.clone())
 }
@@ -295,6 +329,10 @@ impl Scope {
                 is_readonly,
                 name,
                 description,
+                position: None,
+                module: None,
+                exports: Vec::new(),
+                extensions: Vec::new(),
                 is_loaded: true,
                 loader: None,
             })),
@@ -313,6 +351,18 @@ impl Scope {
         }
     }
 
+    /**
+    Create a scope to run the code of a module in. The scope remembers the file of the module, so
+    that circular imports can be found by looking through the calling scopes.
+     */
+    pub fn create_module(&self, caller: &Scope, file: &Path) -> Scope {
+        let mut data = ScopeData::new(Some(self.clone()), Some(caller.clone()), false, None, None);
+        data.module = Some(file.to_path_buf());
+        Scope {
+            data: Arc::from(Mutex::new(data)),
+        }
+    }
+
     pub fn create_namespace(
         &self,
         name: &str,
@@ -430,6 +480,7 @@ impl Scope {
         let mut data = self.lock()?;
         data.mapping.clear();
         data.uses.clear();
+        data.extensions.clear();
         Ok(())
     }
 
@@ -744,6 +795,123 @@ impl Scope {
     pub fn set_calling(&self, calling: Option<Scope>) {
         self.lock().unwrap().calling_scope = calling;
     }
+
+    pub fn set_position(&self, position: Arc<CommandInvocation>) {
+        self.data.lock().unwrap().position = Some(position);
+    }
+
+    pub fn position(&self) -> Option<Arc<CommandInvocation>> {
+        self.data.lock().unwrap().position.clone()
+    }
+
+    /**
+    The files of all modules that are being imported in the call stack leading up to this scope,
+    innermost module first.
+     */
+    pub fn importing(&self) -> Vec<PathBuf> {
+        let mut res = Vec::new();
+        let mut current = Some(self.clone());
+        while let Some(scope) = current {
+            let data = scope.data.lock().unwrap();
+            res.extend(data.module.clone());
+            current = data.calling_scope.clone();
+        }
+        res
+    }
+
+    /**
+    Add to the members exported by the module whose code is running in this scope or one of
+    its calling scopes.
+     */
+    pub fn add_exports(&self, names: &[String]) -> CrushResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if data.module.is_some() {
+            data.exports.extend(names.iter().cloned());
+            return Ok(());
+        }
+        match data.calling_scope.clone() {
+            Some(caller) => {
+                drop(data);
+                caller.add_exports(names)
+            }
+            None => error("Members can only be exported from a module"),
+        }
+    }
+
+    pub fn exports(&self) -> Vec<String> {
+        self.data.lock().unwrap().exports.clone()
+    }
+
+    /**
+    Add a method to all values of the specified type, for code running in this scope or one of
+    its children. Parametrized types are extended regardless of their parameters, i.e. extending
+    `list` extends all lists.
+     */
+    pub fn extend(&self, value_type: ValueType, name: &str, method: Command) -> CrushResult<()> {
+        let mut data = self.lock()?;
+        if data.is_readonly {
+            return error("Scope is read only");
+        }
+        data.extensions.retain(|(t, n, _)| !(same_kind(t, &value_type) && n == name));
+        data.extensions.push((value_type, name.to_string(), method));
+        Ok(())
+    }
+
+    /**
+    The method with the specified name that was added to the specified type in this scope or the
+    closest of its parents.
+     */
+    pub fn extension(&self, value_type: &ValueType, name: &str) -> CrushResult<Option<Command>> {
+        let data = self.lock()?;
+        match data.extensions.iter().find(|(t, n, _)| same_kind(t, value_type) && n == name) {
+            Some((_, _, method)) => Ok(Some(method.clone())),
+            None => match data.parent_scope.clone() {
+                Some(parent) => {
+                    drop(data);
+                    parent.extension(value_type, name)
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /**
+    All methods added to the specified type that are visible in this scope, sorted by name.
+     */
+    pub fn extensions(&self, value_type: &ValueType) -> CrushResult<Vec<(String, Command)>> {
+        let mut res: Vec<(String, Command)> = Vec::new();
+        let mut current = Some(self.clone());
+        while let Some(scope) = current {
+            let data = scope.lock()?;
+            for (t, name, method) in &data.extensions {
+                if same_kind(t, value_type) && !res.iter().any(|(n, _)| n == name) {
+                    res.push((name.clone(), method.clone()));
+                }
+            }
+            current = data.parent_scope.clone();
+        }
+        res.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(res)
+    }
+
+    /**
+    The call stack leading up to this scope, innermost call first. Each element is a scope
+    along with the command most recently started in it.
+     */
+    pub fn call_stack(&self) -> Vec<(Scope, Arc<CommandInvocation>)> {
+        let mut res = Vec::new();
+        let mut current = Some(self.clone());
+        while let Some(scope) = current {
+            let data = scope.data.lock().unwrap();
+            let position = data.position.clone();
+            current = data.calling_scope.clone();
+            drop(data);
+            if let Some(position) = position {
+                res.push((scope, position));
+            }
+        }
+        res
+    }
 }
 
 impl Display for Scope {
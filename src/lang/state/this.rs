@@ -5,6 +5,7 @@ use std::mem::swap;
 use crate::{argument_error_legacy, CrushResult};
 use crate::data::dict::Dict;
 use crate::data::list::List;
+use crate::data::set::Set;
 use crate::data::r#struct::Struct;
 use crate::data::table::Table;
 use crate::lang::pipe::{InputStream, OutputStream};
@@ -39,6 +40,7 @@ macro_rules! this_method {
 pub trait This {
     fn list(&mut self) -> CrushResult<List>;
     fn dict(&mut self) -> CrushResult<Dict>;
+    fn set(&mut self) -> CrushResult<Set>;
     fn string(&mut self) -> CrushResult<String>;
     fn r#struct(&mut self) -> CrushResult<Struct>;
     fn file(&mut self) -> CrushResult<PathBuf>;
@@ -59,6 +61,7 @@ pub trait This {
 impl This for Option<Value> {
     this_method!(list, List, List, "list");
     this_method!(dict, Dict, Dict, "dict");
+    this_method!(set, Set, Set, "set");
 
     fn string(&mut self) -> CrushResult<String> {
         let mut this = None;
@@ -1,6 +1,6 @@
 use std::thread::{JoinHandle, ThreadId};
 use crate::lang::printer::Printer;
-use crate::lang::errors::{to_crush_error, CrushResult};
+use crate::lang::errors::{error, to_crush_error, CrushResult};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use crossbeam::channel::Sender;
@@ -31,10 +31,17 @@ pub struct ThreadDescription {
     pub job_id: Option<JobId>,
 }
 
-fn join_handle(handle: JoinHandle<CrushResult<()>>, printer: &Printer) {
+fn join_handle(handle: JoinHandle<CrushResult<()>>, printer: &Printer) -> bool {
     match handle.join() {
-        Ok(res) => { printer.handle_error(res) }
-        Err(_) => printer.error("Unknown error while waiting for command to exit"),
+        Ok(res) => {
+            let success = res.is_ok();
+            printer.handle_error(res);
+            success
+        }
+        Err(_) => {
+            printer.error("Unknown error while waiting for command to exit");
+            false
+        }
     }
 }
 
@@ -119,24 +126,35 @@ impl ThreadStore {
     }
 
     /**
-    Block calling thread until specified thread has exited
+    Block calling thread until specified thread has exited, and return whether it succeeded
     */
-    pub fn join_one(&self, id: ThreadId, printer: &Printer) {
-        let mut data = self.data.lock().unwrap();
-        let mut kill_idx = None;
-        for idx in 0..data.threads.len() {
-            if data.threads[idx].handle.thread().id() == id {
-                kill_idx = Some(idx);
-                break;
-            }
+    pub fn join_one(&self, id: ThreadId, printer: &Printer) -> bool {
+        match self.take(id) {
+            Some(h) => join_handle(h.handle, printer),
+            None => true,
         }
-        if let Some(idx) = kill_idx {
-            let h = data.threads.remove(idx);
-            drop(data);
-            join_handle(h.handle, printer);
+    }
+
+    /**
+    Block calling thread until specified thread has exited, and return its result instead of
+    reporting errors to the printer.
+    */
+    pub fn wait_for(&self, id: ThreadId) -> CrushResult<()> {
+        match self.take(id) {
+            Some(h) => match h.handle.join() {
+                Ok(res) => res,
+                Err(_) => error("Unknown error while waiting for command to exit"),
+            },
+            None => Ok(()),
         }
     }
 
+    fn take(&self, id: ThreadId) -> Option<ThreadData> {
+        let mut data = self.data.lock().unwrap();
+        let idx = data.threads.iter().position(|t| t.handle.thread().id() == id)?;
+        Some(data.threads.remove(idx))
+    }
+
     pub fn current(&self) -> CrushResult<Vec<ThreadDescription>> {
         let data = self.data.lock().unwrap();
         Ok(data.threads.iter()
@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use serde_json::json;
+use crate::lang::argument::Argument;
+use crate::lang::ast::location::Location;
+use crate::lang::check::line_and_column;
+use crate::lang::command::Command;
+use crate::lang::errors::{CrushErrorType, CrushResult};
+use crate::lang::printer::Printer;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::value::ValueDefinition;
+
+/** Arguments longer than this are shortened in the trace. */
+const MAX_ARGUMENT_LENGTH: usize = 40;
+
+struct TracerData {
+    output: Option<File>,
+    commands: Vec<String>,
+    source: Option<String>,
+}
+
+/**
+Execution tracing, like `set -x` in other shells. While tracing is turned on, every command is
+logged right before it runs, together with its evaluated arguments, and again with the time it
+took once it has finished.
+ */
+#[derive(Clone)]
+pub struct Tracer {
+    data: Arc<Mutex<TracerData>>,
+    /** Checked before every command, so that tracing costs nothing while it is turned off. */
+    enabled: Arc<AtomicBool>,
+}
+
+/**
+One traced command.
+ */
+struct Event {
+    name: String,
+    arguments: Vec<String>,
+    depth: usize,
+    location: Location,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer {
+            data: Arc::new(Mutex::new(TracerData {
+                output: None,
+                commands: Vec::new(),
+                source: None,
+            })),
+            enabled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /**
+    Turn tracing on. If an output file is given, the trace is written to it as JSON lines,
+    otherwise it is printed. If any commands are given, only those commands are traced.
+     */
+    pub fn start(&self, output: Option<File>, commands: Vec<String>) {
+        let mut data = self.data.lock().unwrap();
+        data.output = output;
+        data.commands = commands;
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /**
+    Turn tracing off and close the output file, if any.
+     */
+    pub fn stop(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.data.lock().unwrap().output = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /**
+    Set the source code that locations refer to, so that they can be shown as line numbers.
+     */
+    pub fn set_source(&self, source: String) {
+        self.data.lock().unwrap().source = Some(source);
+    }
+
+    /**
+    Run a command, tracing it if tracing is on. The definition is what the command was
+    called as, and is used as the name of the command in the trace.
+     */
+    pub fn eval(&self, definition: &ValueDefinition, command: &Command, context: CommandContext) -> CrushResult<()> {
+        if !self.is_enabled() {
+            return command.eval(context);
+        }
+        let name = match definition {
+            ValueDefinition::Value(_, _) => command.name().to_string(),
+            d => d.to_string(),
+        };
+        if !self.is_traced(&name, command.name()) {
+            return command.eval(context);
+        }
+
+        let event = Event {
+            name,
+            arguments: context.arguments.iter().map(format_argument).collect(),
+            depth: context.scope.call_stack().len().saturating_sub(1),
+            location: definition.location(),
+        };
+        let printer = context.global_state.printer().clone();
+        self.log_start(&event, &printer);
+        let start = Instant::now();
+        let res = command.eval(context);
+        self.log_end(&event, start, &res, &printer);
+        res
+    }
+
+    fn is_traced(&self, name: &str, short_name: &str) -> bool {
+        let data = self.data.lock().unwrap();
+        data.commands.is_empty() || data.commands.iter().any(|c| c == name || c == short_name)
+    }
+
+    fn log_start(&self, event: &Event, printer: &Printer) {
+        let mut data = self.data.lock().unwrap();
+        let (line, column) = match &data.source {
+            Some(source) => {
+                let (line, column) = line_and_column(source, event.location.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        match &mut data.output {
+            Some(file) => {
+                let line = json!({
+                    "event": "start",
+                    "command": event.name,
+                    "arguments": event.arguments,
+                    "depth": event.depth,
+                    "start": event.location.start,
+                    "end": event.location.end,
+                    "line": line,
+                    "column": column,
+                });
+                let _ = writeln!(file, "{}", line);
+            }
+            None => {
+                let position = match (line, column) {
+                    (Some(line), Some(column)) => format!("line {}, column {}", line, column),
+                    _ => format!("characters {}-{}", event.location.start, event.location.end),
+                };
+                let mut text = format!("{} {}", prefix(event.depth), event.name);
+                for argument in &event.arguments {
+                    text.push(' ');
+                    text.push_str(argument);
+                }
+                printer.line(&format!("{} ({})", text, position));
+                // External commands write straight to the terminal, so make sure the trace comes first
+                printer.ping();
+            }
+        }
+    }
+
+    fn log_end(&self, event: &Event, start: Instant, res: &CrushResult<()>, printer: &Printer) {
+        let elapsed = start.elapsed();
+        let mut data = self.data.lock().unwrap();
+        match &mut data.output {
+            Some(file) => {
+                let line = json!({
+                    "event": "end",
+                    "command": event.name,
+                    "depth": event.depth,
+                    "seconds": elapsed.as_secs_f64(),
+                    "success": succeeded(res),
+                });
+                let _ = writeln!(file, "{}", line);
+            }
+            None => {
+                let status = if succeeded(res) { "finished" } else { "failed" };
+                printer.line(&format!("{} {} {} in {:?}", prefix(event.depth), event.name, status, elapsed));
+            }
+        }
+    }
+}
+
+/** Commands that fail because nobody reads their output are not reported as errors elsewhere either. */
+fn succeeded(res: &CrushResult<()>) -> bool {
+    match res {
+        Ok(_) => true,
+        Err(e) => e.is(CrushErrorType::SendError),
+    }
+}
+
+/** Like `set -x`, the nesting depth is shown as the number of leading plus signs. */
+fn prefix(depth: usize) -> String {
+    "+".repeat(depth + 1)
+}
+
+fn format_argument(argument: &Argument) -> String {
+    let value = argument.value.to_string();
+    let value = if value.chars().count() > MAX_ARGUMENT_LENGTH {
+        format!("{}...", value.chars().take(MAX_ARGUMENT_LENGTH).collect::<String>())
+    } else {
+        value
+    };
+    match &argument.argument_type {
+        Some(name) => format!("{}={}", name, value),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn json_lines_output() {
+        let file = std::env::temp_dir().join(format!("crush-trace-{:016x}.jsonl", rand::random::<u64>()));
+        let source = "$f := {echo 1}\n$f\n";
+        let interpreter = Interpreter::with_output(|_| {}).unwrap();
+        let tracer = interpreter.global_state().tracer();
+        tracer.set_source(source.to_string());
+        tracer.start(Some(File::create(&file).unwrap()), vec![]);
+        interpreter.eval(source).unwrap();
+        tracer.stop();
+
+        let events = std::fs::read_to_string(&file).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let _ = std::fs::remove_file(&file);
+        let summary = events.iter()
+            .map(|event| format!("{} {} {}", event["event"], event["command"], event["depth"]))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            r#""start" "let" 0"#,
+            r#""end" "let" 0"#,
+            r#""start" "f" 0"#,
+            r#""start" "echo" 1"#,
+            r#""end" "echo" 1"#,
+            r#""end" "f" 0"#,
+        ]);
+        assert_eq!(events[2]["line"], 2);
+        assert_eq!(events[3]["arguments"], json!(["1"]));
+        assert_eq!(events[5]["success"], true);
+    }
+}
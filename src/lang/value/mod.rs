@@ -16,10 +16,13 @@ use regex::Regex;
 use crate::lang::errors::{argument_error_legacy, CrushResult, mandate};
 use crate::lang::data::r#struct::Struct;
 use crate::lang::data::r#struct::StructReader;
+use crate::lang::data::r#enum::Enum;
+use crate::lang::data::edge::Edge;
 use crate::lang::state::scope::Scope;
+use crate::lang::state::global_state::GlobalState;
 use crate::lang::pipe::{InputStream, OutputStream, Stream, streams};
 use crate::lang::data::{
-    binary::BinaryReader, dict::Dict, dict::DictReader, list::List,
+    binary::BinaryReader, dict::Dict, dict::DictReader, list::List, set::Set,
     table::ColumnType, table::TableReader,
 };
 use crate::util::time::duration_format;
@@ -33,6 +36,7 @@ use chrono::Duration;
 
 use crate::lang::command::{Command, CommandBinder};
 use crate::lang::help::Help;
+use crate::builtins::types;
 use crate::lang::pretty::format_buffer;
 use crate::lang::printer::Printer;
 use crate::util::regex::RegexFileMatcher;
@@ -65,12 +69,15 @@ pub enum Value {
     Struct(Struct),
     List(List),
     Dict(Dict),
+    Set(Set),
     Scope(Scope),
     Bool(bool),
     Float(f64),
     BinaryInputStream(Box<dyn BinaryReader + Send + Sync>),
     Binary(Arc<[u8]>),
     Type(ValueType),
+    Enum(Enum),
+    Edge(Edge),
 }
 
 impl Display for Value {
@@ -91,10 +98,13 @@ impl Display for Value {
             Value::Scope(env) => env.fmt(f),
             Value::Bool(v) => std::fmt::Display::fmt(if *v { "true" } else { "false" }, f),
             Value::Dict(d) => d.fmt(f),
+            Value::Set(s) => s.fmt(f),
             Value::Float(val) => std::fmt::Display::fmt(val, f),
             Value::Binary(v) => f.write_str(&format_buffer(v, true)),
             Value::Type(t) => std::fmt::Display::fmt(t, f),
             Value::Struct(s) => s.fmt(f),
+            Value::Enum(e) => e.fmt(f),
+            Value::Edge(e) => e.fmt(f),
             Value::Command(_) | Value::TableInputStream(_) | Value::TableOutputStream(_) |
             Value::Table(_) | Value::BinaryInputStream(_) | Value::Empty => {
                 f.write_str("<")?;
@@ -246,10 +256,17 @@ impl Value {
                     .get(name)
                     .map(|m| Value::Command(m.clone()))
             }),
-            Value::Type(t) => t
+            Value::Type(ValueType::Enum(e)) => match types::r#enum::variant(e, name)? {
+                Some(variant) => Some(variant),
+                None => ValueType::Type.fields().get(name).map(|m| Value::Command(m.clone())),
+            },
+            Value::Type(t) => ValueType::Type
                 .fields()
                 .get(name)
+                .or_else(|| t.fields().get(name))
                 .map(|m| Value::Command(m.clone())),
+            Value::Enum(e) => e.get(name),
+            Value::Edge(e) => e.field(name)?,
             _ => self
                 .value_type()
                 .fields()
@@ -266,7 +283,13 @@ impl Value {
                 res.append(&mut scope.dump_local().unwrap().iter().map(|(k, _)| k.to_string()).collect());
                 add_keys(self.value_type().fields(), &mut res);
             }
-            Value::Type(t) => add_keys(t.fields(), &mut res),
+            Value::Type(ValueType::Enum(e)) => res.extend(e.variants().iter().map(|v| v.name.clone())),
+            Value::Type(t) => {
+                add_keys(ValueType::Type.fields(), &mut res);
+                add_keys(t.fields(), &mut res);
+            }
+            Value::Enum(e) => res.extend(e.fields().into_iter().map(|(name, _)| name)),
+            Value::Edge(_) => res.append(&mut Edge::fields()),
             _ => add_keys(self.value_type().fields(), &mut res),
         }
         res.sort_by(|x, y| x.cmp(y));
@@ -274,6 +297,32 @@ impl Value {
         res
     }
 
+    /**
+    Like `field`, but also finds methods added to the type of this value using `types:extend`
+    that are visible in the specified scope. Builtin members take precedence over those.
+     */
+    pub fn field_with_extensions(&self, name: &str, scope: &Scope) -> CrushResult<Option<Value>> {
+        Ok(match self.field(name)? {
+            Some(value) => Some(value),
+            None => scope.extension(&self.value_type(), name)?.map(Value::Command),
+        })
+    }
+
+    /**
+    Like `fields`, but also includes methods added to the type of this value using `types:extend`
+    that are visible in the specified scope.
+     */
+    pub fn fields_with_extensions(&self, scope: &Scope) -> CrushResult<Vec<String>> {
+        let mut res = self.fields();
+        for (name, _) in scope.extensions(&self.value_type())? {
+            if !res.contains(&name) {
+                res.push(name);
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
     pub fn get_recursive(&self, path: &[String]) -> CrushResult<Value> {
         match path.len() {
             0 => error("Invalid path"),
@@ -308,6 +357,7 @@ impl Value {
             Value::Table(r) => Some(Box::from(TableReader::new(r.clone()))),
             Value::List(l) => Some(l.stream()),
             Value::Dict(d) => Some(Box::from(DictReader::new(d.clone()))),
+            Value::Set(s) => Some(s.stream()),
             Value::Struct(s) => Some(Box::from(StructReader::new(s.clone()))),
             Value::Scope(s) => Some(Box::from(ScopeReader::new(s.clone()))),
             Value::Glob(l) => {
@@ -339,11 +389,14 @@ impl Value {
             Value::Scope(_) => ValueType::Scope,
             Value::Bool(_) => ValueType::Bool,
             Value::Dict(d) => d.dict_type(),
+            Value::Set(s) => s.set_type(),
             Value::Float(_) => ValueType::Float,
             Value::Empty => ValueType::Empty,
             Value::BinaryInputStream(_) => ValueType::BinaryInputStream,
             Value::Binary(_) => ValueType::Binary,
             Value::Type(_) => ValueType::Type,
+            Value::Enum(e) => ValueType::Enum(e.enum_type().clone()),
+            Value::Edge(_) => ValueType::Edge,
         }
     }
 
@@ -397,7 +450,10 @@ impl Value {
             }
             Value::Table(r) => Value::Table(r.materialize()?),
             Value::Dict(d) => d.materialize()?.into(),
+            Value::Set(s) => s.materialize()?.into(),
             Value::Struct(r) => Value::Struct(r.materialize()?),
+            Value::Enum(e) => Value::Enum(e.materialize()?),
+            Value::Edge(e) => Value::Edge(e.materialize()?),
             Value::List(l) => l.materialize()?.into(),
             Value::TableOutputStream(_) =>
                 return error(
@@ -416,6 +472,18 @@ impl Value {
             return Ok(self);
         }
 
+        if let ValueType::Union(types) = &new_type {
+            if new_type.is(&self) {
+                return Ok(self);
+            }
+            for t in types {
+                if let Ok(value) = self.clone().convert(t.clone()) {
+                    return Ok(value);
+                }
+            }
+            return error(format!("Can't convert value of type {} to {}", self.value_type(), new_type));
+        }
+
         match (&self, &new_type) {
             (Value::Integer(i), ValueType::Bool) => return Ok(Value::Bool(*i != 0)),
             (Value::Float(f), ValueType::Integer) => return Ok(Value::Integer(*f as i128)),
@@ -452,11 +520,15 @@ impl Value {
             ValueType::Struct => error("invalid convert"),
             ValueType::List(_) => error("invalid convert"),
             ValueType::Dict(_, _) => error("invalid convert"),
+            ValueType::Set(_) => error("invalid convert"),
             ValueType::Scope => error("Invalid convert"),
             ValueType::Empty => error("Invalid convert"),
             ValueType::Any => error("Invalid convert"),
             ValueType::BinaryInputStream => error("invalid convert"),
             ValueType::Type => error("invalid convert"),
+            ValueType::Enum(_) => error("invalid convert"),
+            ValueType::Edge => error("invalid convert"),
+            ValueType::Union(_) => error("invalid convert"),
         }
     }
 
@@ -496,6 +568,51 @@ impl Value {
             _ => self.to_string(),
         }
     }
+
+    /**
+    True if this value is equal to the other one. Unlike `==`, this calls the `__eq__` method of
+    instances of classes that have one.
+     */
+    pub fn equals(&self, other: &Value, scope: &Scope, state: &GlobalState) -> CrushResult<bool> {
+        match (self, other) {
+            (Value::Struct(l), Value::Struct(_)) => match l.call_method("__eq__", vec![other.clone()], scope, state) {
+                Some(res) => match res? {
+                    Value::Bool(b) => Ok(b),
+                    v => argument_error_legacy(format!("__eq__ returned a value of type {}, expected a bool", v.value_type())),
+                },
+                None => Ok(self == other),
+            },
+            _ => Ok(self == other),
+        }
+    }
+
+    /**
+    The ordering of this value relative to the other one, if they can be compared. Unlike
+    `partial_cmp`, this calls the `__lt__` method of instances of classes that have one.
+     */
+    pub fn compare(&self, other: &Value, scope: &Scope, state: &GlobalState) -> CrushResult<Option<Ordering>> {
+        match (self, other) {
+            (Value::Struct(l), Value::Struct(r)) =>
+                match (less(l, other, scope, state)?, less(r, self, scope, state)?) {
+                    (Some(true), Some(_)) => Ok(Some(Ordering::Less)),
+                    (Some(false), Some(true)) => Ok(Some(Ordering::Greater)),
+                    (Some(false), Some(false)) => Ok(Some(Ordering::Equal)),
+                    _ => Ok(None),
+                },
+            _ => Ok(self.partial_cmp(other)),
+        }
+    }
+}
+
+/** Call the `__lt__` method of a struct, if its class has one. */
+fn less(s: &Struct, other: &Value, scope: &Scope, state: &GlobalState) -> CrushResult<Option<bool>> {
+    match s.call_method("__lt__", vec![other.clone()], scope, state) {
+        Some(res) => match res? {
+            Value::Bool(b) => Ok(Some(b)),
+            v => argument_error_legacy(format!("__lt__ returned a value of type {}, expected a bool", v.value_type())),
+        },
+        None => Ok(None),
+    }
 }
 
 fn has_non_printable(s: &str) -> bool {
@@ -526,11 +643,14 @@ impl Clone for Value {
             Value::Scope(e) => Value::Scope(e.clone()),
             Value::Bool(v) => Value::Bool(*v),
             Value::Dict(d) => d.clone().into(),
+            Value::Set(s) => s.clone().into(),
             Value::Float(f) => Value::Float(*f),
             Value::Empty => Value::Empty,
             Value::BinaryInputStream(v) => Value::BinaryInputStream(v.as_ref().clone()),
             Value::Binary(v) => Value::Binary(v.clone()),
             Value::Type(t) => Value::Type(t.clone()),
+            Value::Enum(e) => Value::Enum(e.clone()),
+            Value::Edge(e) => Value::Edge(e.clone()),
         }
     }
 }
@@ -566,8 +686,11 @@ impl std::hash::Hash for Value {
             Value::Bool(v) => v.hash(state),
             Value::Binary(v) => v.hash(state),
             Value::Struct(v) => v.hash(state),
+            Value::Enum(v) => v.hash(state),
+            Value::Edge(v) => v.hash(state),
             Value::Scope(_)
             | Value::Dict(_)
+            | Value::Set(_)
             | Value::Table(_)
             | Value::List(_)
             | Value::TableInputStream(_)
@@ -604,16 +727,21 @@ impl PartialEq for Value {
             (Value::File(val1), Value::String(val2)) => {
                 file_result_compare(&Path::new(&val2.to_string()), val1.as_ref())
             }
+            (Value::File(val1), Value::File(val2)) => val1 == val2,
             (Value::Table(val1), Value::Table(val2)) => match val1.partial_cmp(val2) {
                 None => false,
                 Some(o) => o == Ordering::Equal,
             },
             (Value::Struct(val1), Value::Struct(val2)) => val1 == val2,
+            (Value::Enum(val1), Value::Enum(val2)) => val1 == val2,
+            (Value::Edge(val1), Value::Edge(val2)) => val1 == val2,
             (Value::List(val1), Value::List(val2)) => val1 == val2,
             (Value::Dict(val1), Value::Dict(val2)) => val1 == val2,
+            (Value::Set(val1), Value::Set(val2)) => val1 == val2,
             (Value::Bool(val1), Value::Bool(val2)) => val1 == val2,
             (Value::Float(val1), Value::Float(val2)) => val1 == val2,
             (Value::Binary(val1), Value::Binary(val2)) => val1 == val2,
+            (Value::Type(val1), Value::Type(val2)) => val1 == val2,
             _ => false,
         }
     }
@@ -642,8 +770,11 @@ impl std::cmp::PartialOrd for Value {
             (Value::File(val1), Value::File(val2)) => Some(val1.cmp(val2)),
             (Value::Table(val1), Value::Table(val2)) => val1.partial_cmp(val2),
             (Value::Struct(val1), Value::Struct(val2)) => val1.partial_cmp(val2),
+            (Value::Enum(val1), Value::Enum(val2)) => val1.partial_cmp(val2),
+            (Value::Edge(val1), Value::Edge(val2)) => val1.partial_cmp(val2),
             (Value::List(val1), Value::List(val2)) => val1.partial_cmp(val2),
             (Value::Dict(val1), Value::Dict(val2)) => val1.partial_cmp(val2),
+            (Value::Set(val1), Value::Set(val2)) => val1.partial_cmp(val2),
             (Value::Bool(val1), Value::Bool(val2)) => Some(val1.cmp(val2)),
             (Value::Float(val1), Value::Float(val2)) => val1.partial_cmp(val2),
             (Value::Binary(val1), Value::Binary(val2)) => Some(val1.cmp(val2)),
@@ -50,10 +50,17 @@ impl ValueDefinition {
         Ok(match self {
             ValueDefinition::Value(v, _) => (None, v.clone()),
             ValueDefinition::JobDefinition(def) => {
+                // The job runs in the same scope as the command it is an argument to, so
+                // afterwards, the position of the scope is restored to that command.
+                let position = context.env.position();
                 let first_input = empty_channel();
                 let (last_output, last_input) = pipe();
                 def.eval(context.job_context(first_input, last_output))?;
-                (None, last_input.recv()?)
+                let value = last_input.recv()?;
+                if let Some(position) = position {
+                    context.env.set_position(position);
+                }
+                (None, value)
             }
             ValueDefinition::ClosureDefinition(name, p, c, _) => (
                 None,
@@ -87,8 +94,17 @@ impl ValueDefinition {
                 } else {
                     parent
                 };
+                let field = match (parent.field_with_extensions(&entry.string, &context.env)?, &parent) {
+                    (None, Value::Struct(s)) => s.call_method(
+                        "__getattr__",
+                        vec![Value::from(entry.string.as_str())],
+                        &context.env,
+                        &context.global_state,
+                    ).transpose()?,
+                    (field, _) => field,
+                };
                 let val = mandate(
-                    parent.field(&entry.string)?,
+                    field,
                     &format!(
                         "Missing field {} in value of type {}",
                         entry,
@@ -8,7 +8,8 @@ use ordered_map::OrderedMap;
 use regex::Regex;
 use std::cmp::max;
 use std::fmt::{Display, Formatter};
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
+use crate::lang::data::r#enum::EnumType;
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub enum ValueType {
@@ -26,6 +27,7 @@ pub enum ValueType {
     Struct,
     List(Box<ValueType>),
     Dict(Box<ValueType>, Box<ValueType>),
+    Set(Box<ValueType>),
     Scope,
     Bool,
     Float,
@@ -34,6 +36,9 @@ pub enum ValueType {
     BinaryInputStream,
     Binary,
     Type,
+    Enum(Arc<EnumType>),
+    Edge,
+    Union(Vec<ValueType>),
 }
 
 pub fn empty_methods() -> &'static OrderedMap<String, Command> {
@@ -42,14 +47,40 @@ pub fn empty_methods() -> &'static OrderedMap<String, Command> {
 }
 
 impl ValueType {
-    pub fn either(_options: Vec<ValueType>) -> ValueType {
-        ValueType::Any
+    /**
+    A type that matches values of any of the specified types. Nested unions are flattened and
+    duplicates removed, so that two unions of the same types are equal regardless of order.
+     */
+    pub fn either(options: Vec<ValueType>) -> ValueType {
+        let mut res = Vec::new();
+        for option in options {
+            match option {
+                ValueType::Any => return ValueType::Any,
+                ValueType::Union(inner) => res.extend(inner),
+                t => res.push(t),
+            }
+        }
+        res.sort();
+        res.dedup();
+        match res.len() {
+            0 => ValueType::Any,
+            1 => res.remove(0),
+            _ => ValueType::Union(res),
+        }
+    }
+
+    /**
+    A type that matches values of the specified type, or nothing.
+     */
+    pub fn optional(value_type: ValueType) -> ValueType {
+        ValueType::either(vec![value_type, ValueType::Empty])
     }
 
     pub fn fields(&self) -> &OrderedMap<String, Command> {
         match self {
             ValueType::List(_) => &types::list::methods(),
             ValueType::Dict(_, _) => &types::dict::methods(),
+            ValueType::Set(_) => &types::set::methods(),
             ValueType::String => &types::string::methods(),
             ValueType::File => &types::file::methods(),
             ValueType::Regex => &types::re::methods(),
@@ -63,16 +94,29 @@ impl ValueType {
             ValueType::TableOutputStream(_) => &types::table_output_stream::methods(),
             ValueType::Binary => &types::binary::methods(),
             ValueType::Scope => &types::scope::methods(),
+            ValueType::Type => &types::r#type::methods(),
             _ => empty_methods(),
         }
     }
 
     pub fn is(&self, value: &Value) -> bool {
-        (*self == ValueType::Any) || (*self == value.value_type())
+        match self {
+            ValueType::Any => true,
+            ValueType::Union(types) => types.iter().any(|t| t.is(value)),
+            t => *t == value.value_type(),
+        }
     }
 
+    /**
+    True if every value of the pattern type is also of this type.
+     */
     pub fn is_compatible_with(&self, pattern: &ValueType) -> bool {
-        (*self == ValueType::Any) || (self == pattern)
+        match (self, pattern) {
+            (ValueType::Any, _) => true,
+            (_, ValueType::Union(patterns)) => patterns.iter().all(|p| self.is_compatible_with(p)),
+            (ValueType::Union(types), p) => types.iter().any(|t| t.is_compatible_with(p)),
+            (t, p) => t == p,
+        }
     }
 
     pub fn materialize(&self) -> CrushResult<ValueType> {
@@ -92,6 +136,8 @@ impl ValueType {
             | ValueType::Binary
             | ValueType::Type
             | ValueType::Struct
+            | ValueType::Enum(_)
+            | ValueType::Edge
             | ValueType::Bool => self.clone(),
             ValueType::BinaryInputStream => ValueType::Binary,
             ValueType::TableInputStream(o) => ValueType::Table(ColumnType::materialize(o)?),
@@ -101,6 +147,9 @@ impl ValueType {
             ValueType::Dict(k, v) => {
                 ValueType::Dict(Box::from(k.materialize()?), Box::from(v.materialize()?))
             }
+            ValueType::Set(t) => ValueType::Set(Box::from(t.materialize()?)),
+            ValueType::Union(types) => ValueType::either(
+                types.iter().map(|t| t.materialize()).collect::<CrushResult<Vec<_>>>()?),
         })
     }
 
@@ -109,17 +158,24 @@ impl ValueType {
             ValueType::Scope
             | ValueType::List(_)
             | ValueType::Dict(_, _)
+            | ValueType::Set(_)
             | ValueType::Command
             | ValueType::BinaryInputStream
             | ValueType::TableInputStream(_)
             | ValueType::Struct
             | ValueType::Table(_) => false,
+            ValueType::Union(types) => types.iter().all(|t| t.is_hashable()),
+            ValueType::Enum(e) => e
+                .variants()
+                .iter()
+                .all(|v| v.fields.iter().all(|(_, t)| t.is_hashable())),
             _ => true,
         }
     }
 
     pub fn is_comparable(&self) -> bool {
-        self.is_hashable()
+        // Instances of classes are compared using their __lt__ method
+        matches!(self, ValueType::Struct) || self.is_hashable()
     }
 
     pub fn parse(&self, s: &str) -> CrushResult<Value> {
@@ -134,6 +190,11 @@ impl ValueType {
             ValueType::File => Ok(Value::from(s)),
             ValueType::Float => Ok(Value::Float(to_crush_error(s.parse::<f64>())?)),
             ValueType::Bool => Ok(Value::Bool(to_crush_error(s.parse::<bool>())?)),
+            ValueType::Union(types) => types
+                .iter()
+                .find_map(|t| t.parse(s).ok())
+                .map(Ok)
+                .unwrap_or_else(|| error(format!("Failed to parse cell as {}", self))),
             _ => error("Failed to parse cell"),
         }
     }
@@ -162,6 +223,7 @@ impl Help for ValueType {
             ValueType::Struct => "A mapping from name to value",
             ValueType::List(_) => "A mutable list of items, usually of the same type",
             ValueType::Dict(_, _) => "A mutable mapping from one set of values to another",
+            ValueType::Set(_) => "A mutable collection of unique values",
             ValueType::Scope => "A scope in the Crush namespace",
             ValueType::Bool => "True or false",
             ValueType::Float => {
@@ -172,6 +234,9 @@ impl Help for ValueType {
             ValueType::BinaryInputStream => "A stream of binary data",
             ValueType::Binary => "Binary data",
             ValueType::Type => "A type",
+            ValueType::Enum(_) => "A value that is one of a fixed set of variants",
+            ValueType::Edge => "A connection from one row of a table to another",
+            ValueType::Union(_) => "A value of any one of several types",
         }
             .to_string()
     }
@@ -181,6 +246,25 @@ impl Help for ValueType {
             ValueType::Time => {
                 vec!["    All time instances use the local time zone.\n".to_string()]
             }
+            ValueType::Enum(e) => {
+                let mut lines = vec!["    Variants:\n".to_string()];
+                for variant in e.variants() {
+                    lines.push(format!(
+                        "    * {}{}",
+                        variant.name,
+                        variant.fields.iter().map(|(name, t)| format!(" {}=({})", name, t)).collect::<String>()
+                    ));
+                }
+                lines
+            }
+            ValueType::Edge => {
+                vec![
+                    "    Members:\n".to_string(),
+                    "    * directed  True if this edge is directed".to_string(),
+                    "    * node      The row this edge points to, as a struct".to_string(),
+                    "    * value     The value associated with this edge".to_string(),
+                ]
+            }
             _ => { Vec::new() }
         };
 
@@ -253,6 +337,10 @@ impl Display for ValueType {
                 f.write_str(" ")?;
                 v.fmt(f)
             }
+            ValueType::Set(t) => {
+                f.write_str("set ")?;
+                t.fmt(f)
+            }
             ValueType::Scope => f.write_str("scope"),
             ValueType::Bool => f.write_str("bool"),
             ValueType::Float => f.write_str("float"),
@@ -261,6 +349,17 @@ impl Display for ValueType {
             ValueType::BinaryInputStream => f.write_str("binary_stream"),
             ValueType::Binary => f.write_str("binary"),
             ValueType::Type => f.write_str("type"),
+            ValueType::Enum(e) => f.write_str(e.name()),
+            ValueType::Edge => f.write_str("edge"),
+            ValueType::Union(types) => {
+                for (idx, t) in types.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_str("|")?;
+                    }
+                    t.fmt(f)?;
+                }
+                Ok(())
+            }
         }
     }
 }
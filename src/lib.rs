@@ -0,0 +1,27 @@
+/*!
+Crush is a command line shell that is also a powerful and modern programming language. Besides
+the `crush` binary, it can be embedded into other programs using the `Interpreter` type.
+ */
+#[macro_use]
+extern crate lalrpop_util;
+
+pub mod lang;
+pub mod builtins;
+pub mod util;
+mod interpreter;
+
+pub use interpreter::Interpreter;
+/** Used by the code generated by the signature macro. */
+pub use ordered_map;
+
+use lang::errors::{argument_error_legacy, to_crush_error, CrushResult};
+use lang::{data, interactive, state};
+use lang::printer::Printer;
+
+/**
+The version string printed by `crush --version`. It is also used by `remote:exec` to verify that
+the crush binary on a remote host is the same version as the local one.
+ */
+pub fn version_string() -> String {
+    format!("crush {}", env!("CARGO_PKG_VERSION"))
+}
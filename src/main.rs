@@ -1,51 +1,96 @@
-#[macro_use]
-extern crate lalrpop_util;
-
-mod lang;
-mod builtins;
-mod util;
-
-use crate::lang::errors::{argument_error_legacy, CrushResult, to_crush_error};
-use crate::lang::pretty::create_pretty_printer;
-use crate::lang::{execute, printer};
-use builtins::declare;
+use crush::lang::errors::{argument_error_legacy, CrushResult, to_crush_error};
+use crush::lang::pretty::create_pretty_printer;
+use crush::lang::{check, execute, lsp, printer};
+use crush::lang::ast::format::{format_files, FormatAction};
+use crush::builtins::{declare, stop_workers};
+use crush::version_string;
 use std::io::Read;
 use std::path::PathBuf;
-use lang::{data, state};
-use crate::lang::interactive;
-use lang::state::global_state::GlobalState;
-use crate::lang::printer::Printer;
+use crush::lang::state;
+use crush::lang::interactive;
+use crush::lang::state::global_state::GlobalState;
+use crush::lang::printer::Printer;
 
 #[derive(PartialEq, Eq)]
 enum Mode {
     Interactive,
     Pup,
+    PupWorker,
+    Lsp,
+    Fmt(Vec<PathBuf>, FormatAction),
+    Check(Vec<PathBuf>),
+    Serve(PathBuf),
+    Client(PathBuf, Option<PathBuf>),
     File(PathBuf),
+    Debug(Option<PathBuf>),
+    Trace(Option<PathBuf>),
     Help,
+    Version,
 }
 
 struct Config {
     mode: Mode,
 }
 
+fn socket_arg(args: &mut impl Iterator<Item = String>, name: &str) -> CrushResult<PathBuf> {
+    match args.next() {
+        Some(socket) => Ok(PathBuf::from(socket)),
+        None => argument_error_legacy(format!("Missing socket path for {}", name)),
+    }
+}
+
+fn file_mode(mode: Mode, file: &str) -> Mode {
+    match mode {
+        Mode::Client(socket, _) => Mode::Client(socket, Some(PathBuf::from(file))),
+        Mode::Fmt(mut files, action) => {
+            files.push(PathBuf::from(file));
+            Mode::Fmt(files, action)
+        }
+        Mode::Check(mut files) => {
+            files.push(PathBuf::from(file));
+            Mode::Check(files)
+        }
+        Mode::Debug(_) => Mode::Debug(Some(PathBuf::from(file))),
+        Mode::Trace(_) => Mode::Trace(Some(PathBuf::from(file))),
+        _ => Mode::File(PathBuf::from(file)),
+    }
+}
+
 fn parse_args() -> CrushResult<Config> {
-    let args = std::env::args().collect::<Vec<_>>();
+    let mut args = std::env::args().skip(1);
     let mut mode = Mode::Interactive;
     let mut all_files = false;
-    for arg in &args[1..] {
+    while let Some(arg) = args.next() {
         if all_files {
-            mode = Mode::File(PathBuf::from(arg))
+            mode = file_mode(mode, &arg)
         } else {
             match arg.as_str() {
                 "--pup" | "-p" => mode = Mode::Pup,
+                "--pup-worker" => mode = Mode::PupWorker,
+                "--lsp" => mode = Mode::Lsp,
+                "--fmt" => mode = Mode::Fmt(vec![], FormatAction::Print),
+                "--check" => mode = Mode::Check(vec![]),
+                "--list" => match mode {
+                    Mode::Fmt(files, _) => mode = Mode::Fmt(files, FormatAction::List),
+                    _ => return argument_error_legacy("--list can only be used together with --fmt"),
+                },
+                "--write" => match mode {
+                    Mode::Fmt(files, _) => mode = Mode::Fmt(files, FormatAction::Write),
+                    _ => return argument_error_legacy("--write can only be used together with --fmt"),
+                },
+                "--serve" => mode = Mode::Serve(socket_arg(&mut args, "--serve")?),
+                "--client" => mode = Mode::Client(socket_arg(&mut args, "--client")?, None),
+                "--debug" => mode = Mode::Debug(None),
+                "--trace" => mode = Mode::Trace(None),
                 "--interactive" | "-i" => mode = Mode::Interactive,
                 "--help" | "-h" => mode = Mode::Help,
+                "--version" | "-V" => mode = Mode::Version,
                 "--" => all_files = true,
                 file => {
                     if file.starts_with("-") {
                         return argument_error_legacy(format!("Unknown argument {}", file));
                     }
-                    mode = Mode::File(PathBuf::from(file))
+                    mode = file_mode(mode, file)
                 }
             }
         }
@@ -58,12 +103,37 @@ fn print_help(printer: &Printer) {
     printer.line("Run the Crush shell");
     printer.line("");
     printer.line("  -h, --help        Print this message and exit");
+    printer.line("  -V, --version     Print version information and exit");
     printer.line("  -i --interactive  Run in interactive mode (this is the default)");
     printer.line("  -p --pup          Read pup-serialized closure from standard input,");
-    printer.line("                      execute, and serialize output to pup-format");
-    printer.line("                      and send to standard output");
+    printer.line("                      execute, and serialize output to the streaming");
+    printer.line("                      pup-format and send to standard output");
+    printer.line("  --pup-worker      Like --pup, but read any number of length-delimited");
+    printer.line("                      closures from standard input and respond to each");
+    printer.line("                      in turn. Used by user:do to keep elevated processes");
+    printer.line("                      running between calls");
+    printer.line("  --lsp             Run as a language server, speaking the Language Server");
+    printer.line("                      Protocol on standard input and output");
+    printer.line("  --fmt             Format the specified files, or standard input if no");
+    printer.line("                      file is given, and print the result");
+    printer.line("  --fmt --list      Print the names of all files that are not formatted, and");
+    printer.line("                      exit with a non-zero status if there are any");
+    printer.line("  --fmt --write     Rewrite the specified files in place");
+    printer.line("  --check           Check the specified files, or standard input if no file");
+    printer.line("                      is given, for mistakes without running them, and exit");
+    printer.line("                      with a non-zero status if any errors are found");
+    printer.line("  --serve SOCKET    Listen for closures on the specified Unix socket and");
+    printer.line("                      respond to them like --pup-worker. The global scope");
+    printer.line("                      is kept between requests, but each connection declares");
+    printer.line("                      its variables in a scope of its own");
+    printer.line("  --client SOCKET   Run the specified file, or standard input if no file");
+    printer.line("                      is given, in a crush process started with --serve");
+    printer.line("  --debug FILE      Run the specified file in the debugger, pausing before");
+    printer.line("                      the first command");
+    printer.line("  --trace FILE      Run the specified file with tracing turned on, printing");
+    printer.line("                      every command before it runs");
     printer.line("");
-    printer.line("Crush can be run in three modes.");
+    printer.line("Crush can be run in four modes.");
     printer.line("");
     printer.line("- With no arguments, Crush starts in interactive mode, and commands will be read from");
     printer.line("  standard input.");
@@ -73,6 +143,9 @@ fn print_help(printer: &Printer) {
     printer.line("  standard input, and executed. The output of the closure will be written in pup-format");
     printer.line("  to standard output. This third mode is used by e.g. sudo and remote:exec to run");
     printer.line("  closures in a different process.");
+    printer.line("- With the argument \"--serve\", Crush runs as a daemon, executing closures sent to it over");
+    printer.line("  a Unix socket. Use \"--client\" to run a script in such a daemon, in order to avoid the");
+    printer.line("  startup cost of a new process.");
 }
 
 fn run() -> CrushResult<i32> {
@@ -81,14 +154,14 @@ fn run() -> CrushResult<i32> {
     let root_scope = state::scope::Scope::create_root();
     let local_scope = root_scope.create_child(&root_scope, false);
 
-    let (printer, print_handle) = if config.mode == Mode::Pup {
+    let (printer, print_handle) = if config.mode == Mode::Pup || config.mode == Mode::PupWorker || config.mode == Mode::Lsp {
         printer::noop()
     } else {
         printer::init()
     };
 
     let global_state = GlobalState::new(printer)?;
-    let pretty_printer = create_pretty_printer(global_state.printer().clone(), &global_state);
+    let pretty_printer = create_pretty_printer(global_state.printer().clone(), &root_scope, &global_state);
 
     declare(&root_scope)?;
 
@@ -109,6 +182,75 @@ fn run() -> CrushResult<i32> {
             )?;
         }
 
+        Mode::PupWorker => {
+            execute::pup_worker(
+                local_scope,
+                &mut std::io::stdin().lock(),
+                &mut std::io::stdout().lock(),
+                &global_state,
+            )?;
+        }
+
+        Mode::Lsp => {
+            lsp::run(
+                local_scope,
+                &mut std::io::stdin().lock(),
+                &mut std::io::stdout().lock(),
+                &global_state,
+            )?;
+        }
+
+        Mode::Fmt(files, action) => {
+            match format_files(&files, action, &global_state) {
+                Ok(true) => {}
+                Ok(false) => global_state.set_exit_status(1),
+                Err(e) => {
+                    global_state.printer().crush_error(e);
+                    global_state.set_exit_status(1);
+                }
+            }
+        }
+
+        Mode::Check(files) => {
+            match check::check_files(&files, &local_scope, &global_state) {
+                Ok(true) => {}
+                Ok(false) => global_state.set_exit_status(1),
+                Err(e) => {
+                    global_state.printer().crush_error(e);
+                    global_state.set_exit_status(1);
+                }
+            }
+        }
+
+        Mode::Serve(socket) => {
+            execute::serve(
+                local_scope,
+                socket.as_path(),
+                &global_state,
+            )?
+        }
+
+        Mode::Client(socket, file) => {
+            let source = match file {
+                Some(file) => to_crush_error(std::fs::read_to_string(file))?,
+                None => {
+                    let mut source = String::new();
+                    to_crush_error(std::io::stdin().read_to_string(&mut source))?;
+                    source
+                }
+            };
+            if let Err(e) = execute::client(
+                &root_scope,
+                socket.as_path(),
+                &source,
+                &pretty_printer,
+                &global_state,
+            ) {
+                global_state.printer().crush_error(e);
+                global_state.set_exit_status(1);
+            }
+        }
+
         Mode::File(f) => {
             execute::file(
                 &local_scope,
@@ -118,13 +260,50 @@ fn run() -> CrushResult<i32> {
             )?
         }
 
+        Mode::Debug(file) => {
+            let file = match file {
+                Some(file) => file,
+                None => return argument_error_legacy("Missing file for --debug"),
+            };
+            let source = to_crush_error(std::fs::read_to_string(&file))?;
+            global_state.debugger().set_source(source.clone());
+            global_state.debugger().start();
+            execute::string(
+                &local_scope,
+                &source,
+                &pretty_printer,
+                &global_state,
+            )?;
+        }
+
+        Mode::Trace(file) => {
+            let file = match file {
+                Some(file) => file,
+                None => return argument_error_legacy("Missing file for --trace"),
+            };
+            let source = to_crush_error(std::fs::read_to_string(&file))?;
+            global_state.tracer().set_source(source.clone());
+            global_state.tracer().start(None, vec![]);
+            execute::string(
+                &local_scope,
+                &source,
+                &pretty_printer,
+                &global_state,
+            )?;
+        }
+
         Mode::Help => {
             print_help(&global_state.printer())
         }
+
+        Mode::Version => {
+            global_state.printer().line(&version_string())
+        }
     }
     let status = global_state.exit_status().unwrap_or(0);
-    global_state.threads().join(global_state.printer());
+    stop_workers();
     drop(pretty_printer);
+    global_state.threads().join(global_state.printer());
     drop(global_state);
     root_scope.clear()?;
     drop(root_scope);
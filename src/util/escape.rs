@@ -31,6 +31,13 @@ pub fn escape(s: &str) -> String {
     res
 }
 
+/**
+Quote a string so that it is passed verbatim as a single argument by a POSIX shell.
+ */
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
 #[derive(Eq, PartialEq)]
 enum State {
     Normal,
@@ -7,6 +7,7 @@ pub mod hex;
 pub mod identity_arc;
 pub mod integer_formater;
 pub mod logins;
+pub mod privilege;
 pub mod regex;
 pub mod replace;
 pub mod time;
@@ -0,0 +1,169 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::process;
+use crate::util::escape::shell_quote;
+use crate::util::privilege::Elevation::{Doas, Pkexec, Su, Sudo};
+
+/**
+The different tools crush knows how to use in order to run a process as another user.
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Elevation {
+    Sudo,
+    Doas,
+    Su,
+    Pkexec,
+}
+
+/**
+The prompt we ask sudo to print when it wants a password, so that we can detect it on the
+standard error stream of the child process and ask the user ourselves.
+ */
+pub const PASSWORD_PROMPT_MARKER: &str = "[crush:password]";
+
+#[derive(Debug)]
+pub struct Error {
+    msg: String,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl TryFrom<&str> for Elevation {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "sudo" => Ok(Sudo),
+            "doas" => Ok(Doas),
+            "su" => Ok(Su),
+            "pkexec" => Ok(Pkexec),
+            _ => Err(Error { msg: format!("Unknown privilege elevation method {}", s) }),
+        }
+    }
+}
+
+impl Display for Elevation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sudo => f.write_str("sudo"),
+            Doas => f.write_str("doas"),
+            Su => f.write_str("su"),
+            Pkexec => f.write_str("pkexec"),
+        }
+    }
+}
+
+impl Elevation {
+    /**
+    True if this method reads the password from the standard input of the child process, prefixed
+    by a prompt containing PASSWORD_PROMPT_MARKER on standard error. doas and su have no way to read
+    the password from anywhere but the terminal, and pkexec asks the polkit agent, so crush can
+    only ask for the password itself when using sudo.
+     */
+    pub fn reads_password_from_stdin(&self) -> bool {
+        match self {
+            Sudo => true,
+            Doas | Su | Pkexec => false,
+        }
+    }
+
+    /**
+    True if this method needs to ask for a password on the terminal. su always does, unless it is
+    run by root.
+     */
+    pub fn needs_terminal(&self) -> bool {
+        match self {
+            Su => !nix::unistd::geteuid().is_root(),
+            Sudo | Doas | Pkexec => false,
+        }
+    }
+
+    /**
+    Create a command that runs the specified crush binary with the specified arguments as the
+    specified user.
+     */
+    pub fn command(&self, username: &str, binary: &Path, args: &[&str]) -> process::Command {
+        match self {
+            Sudo => {
+                let mut cmd = process::Command::new("sudo");
+                cmd.arg("--stdin")
+                    .arg("--prompt").arg(format!("{} %u: ", PASSWORD_PROMPT_MARKER))
+                    .arg("--user").arg(username)
+                    .arg("--")
+                    .arg(binary)
+                    .args(args);
+                cmd
+            }
+            Doas => {
+                let mut cmd = process::Command::new("doas");
+                cmd.arg("-u").arg(username).arg("--").arg(binary).args(args);
+                cmd
+            }
+            Su => {
+                let mut line = shell_quote(&binary.to_string_lossy());
+                for a in args {
+                    line.push(' ');
+                    line.push_str(&shell_quote(a));
+                }
+                let mut cmd = process::Command::new("su");
+                cmd.arg(username).arg("-c").arg(line);
+                cmd
+            }
+            Pkexec => {
+                let mut cmd = process::Command::new("pkexec");
+                cmd.arg("--user").arg(username).arg(binary).args(args);
+                cmd
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Return the program and the arguments of the command used to run crush as root. */
+    fn command_line(method: Elevation) -> Vec<String> {
+        let cmd = method.command("root", Path::new("/opt/my crush"), &["--pup-worker"]);
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sudo_command_reads_password_from_stdin() {
+        assert_eq!(
+            command_line(Sudo),
+            vec!["sudo", "--stdin", "--prompt", "[crush:password] %u: ", "--user", "root", "--", "/opt/my crush", "--pup-worker"]);
+    }
+
+    #[test]
+    fn doas_command() {
+        assert_eq!(command_line(Doas), vec!["doas", "-u", "root", "--", "/opt/my crush", "--pup-worker"]);
+    }
+
+    #[test]
+    fn su_command_quotes_arguments() {
+        assert_eq!(command_line(Su), vec!["su", "root", "-c", "'/opt/my crush' '--pup-worker'"]);
+    }
+
+    #[test]
+    fn pkexec_command() {
+        assert_eq!(command_line(Pkexec), vec!["pkexec", "--user", "root", "/opt/my crush", "--pup-worker"]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for method in [Sudo, Doas, Su, Pkexec] {
+            assert_eq!(Elevation::try_from(method.to_string().as_str()).unwrap(), method);
+        }
+        assert!(Elevation::try_from("runas").is_err());
+    }
+}
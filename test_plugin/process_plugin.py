@@ -0,0 +1,126 @@
+#!/usr/bin/env python3
+"""
+A plugin process used to test the plugin protocol in src/lang/plugin/process.rs. It declares
+two commands: echo, which returns its arguments as a struct, and fail, which always fails.
+
+It only implements as much of protobuf as it needs, so that it has no dependencies.
+"""
+import struct
+import sys
+
+
+def varint(n):
+    res = bytearray()
+    while True:
+        byte = n & 0x7f
+        n >>= 7
+        if n:
+            res.append(byte | 0x80)
+        else:
+            res.append(byte)
+            return bytes(res)
+
+
+def message_field(number, payload):
+    return varint(number << 3 | 2) + varint(len(payload)) + payload
+
+
+def string_field(number, s):
+    return message_field(number, s.encode())
+
+
+def parse(buf):
+    """Return a dict from field number to the list of values of that field."""
+    fields = {}
+    idx = 0
+    while idx < len(buf):
+        key, idx = read_varint(buf, idx)
+        number, wire_type = key >> 3, key & 7
+        if wire_type == 0:
+            value, idx = read_varint(buf, idx)
+        elif wire_type == 2:
+            length, idx = read_varint(buf, idx)
+            value = buf[idx:idx + length]
+            idx += length
+        else:
+            raise ValueError("Unsupported wire type {}".format(wire_type))
+        fields.setdefault(number, []).append(value)
+    return fields
+
+
+def read_varint(buf, idx):
+    res = 0
+    shift = 0
+    while True:
+        byte = buf[idx]
+        idx += 1
+        res |= (byte & 0x7f) << shift
+        shift += 7
+        if not byte & 0x80:
+            return res, idx
+
+
+def read_frame():
+    length = sys.stdin.buffer.read(8)
+    if len(length) < 8:
+        return None
+    return sys.stdin.buffer.read(struct.unpack(">Q", length)[0])
+
+
+def read_magic():
+    """Read the bytes every pup stream starts with."""
+    if sys.stdin.buffer.read(4) != b"PUPS":
+        raise ValueError("Not a pup stream")
+
+
+def write_magic():
+    sys.stdout.buffer.write(b"PUPS")
+
+
+def write_frame(payload):
+    sys.stdout.buffer.write(struct.pack(">Q", len(payload)) + payload)
+    sys.stdout.buffer.flush()
+
+
+def argument(name, value_type, description):
+    return string_field(1, name) + string_field(2, value_type) + string_field(3, description)
+
+
+def declaration():
+    echo = (string_field(1, "echo")
+            + string_field(2, "Return the arguments as a struct")
+            + message_field(4, argument("text", "string", "any text.")))
+    fail = string_field(1, "fail") + string_field(2, "Always fail")
+    return message_field(1, echo) + message_field(1, fail)
+
+
+def main():
+    write_frame(declaration())
+    while True:
+        request = read_frame()
+        if request is None:
+            return
+        request = parse(request)
+        command = request[1][0].decode()
+        arguments = request[2][0]
+
+        # Read the input, and copy the version from its header
+        read_magic()
+        version = ""
+        while True:
+            frame = parse(read_frame())
+            if 1 in frame:
+                version = parse(frame[1][0])[1][0].decode()
+            if 3 in frame or 4 in frame:
+                break
+
+        write_magic()
+        if command == "echo":
+            header = string_field(1, version) + message_field(2, arguments)
+            write_frame(message_field(1, header))
+            write_frame(varint(3 << 3) + varint(1))
+        else:
+            write_frame(string_field(4, "Failed on purpose"))
+
+
+main()
@@ -0,0 +1,132 @@
+/*!
+A native plugin used to test the plugin ABI in `src/lang/plugin/native.rs`. It defines the structs
+of the ABI itself instead of using the ones in Crush, just like a plugin written in another
+language would, so that the tests notice if the layout of the ABI changes by accident.
+
+It registers two namespaces. `fixture` has the commands `identity`, which returns its arguments,
+`fail`, which always fails, and `garbage`, which returns a result that isn't valid pup.
+`fixture_extra` is empty, and is used to test what happens when
+one of the namespaces of a plugin can't be declared.
+ */
+use std::ffi::{c_char, c_void};
+
+type WriteFn = unsafe extern "C" fn(result: *mut c_void, data: *const u8, len: usize);
+type CommandFn = unsafe extern "C" fn(
+    arguments: *const u8,
+    arguments_len: usize,
+    result: *mut c_void,
+    write_result: WriteFn,
+) -> i32;
+
+#[repr(C)]
+struct ArgumentDefinition {
+    name: *const c_char,
+    value_type: *const c_char,
+    description: *const c_char,
+}
+
+#[repr(C)]
+struct CommandDefinition {
+    namespace: *const c_char,
+    name: *const c_char,
+    short_help: *const c_char,
+    long_help: *const c_char,
+    arguments: *const ArgumentDefinition,
+    argument_count: usize,
+    call: CommandFn,
+}
+
+#[repr(C)]
+pub struct PluginApi {
+    version: u32,
+    registrar: *mut c_void,
+    register_namespace: unsafe extern "C" fn(
+        registrar: *mut c_void,
+        name: *const c_char,
+        description: *const c_char,
+    ) -> i32,
+    register_command: unsafe extern "C" fn(
+        registrar: *mut c_void,
+        command: *const CommandDefinition,
+    ) -> i32,
+}
+
+fn c(s: &'static [u8]) -> *const c_char {
+    s.as_ptr() as *const c_char
+}
+
+/** The arguments are already a pup encoded struct, so they can be written back as they are. */
+unsafe extern "C" fn identity(arguments: *const u8, arguments_len: usize, result: *mut c_void, write_result: WriteFn) -> i32 {
+    write_result(result, arguments, arguments_len);
+    0
+}
+
+unsafe extern "C" fn fail(_arguments: *const u8, _arguments_len: usize, result: *mut c_void, write_result: WriteFn) -> i32 {
+    let message = b"Failed on purpose";
+    write_result(result, message.as_ptr(), message.len());
+    1
+}
+
+unsafe extern "C" fn garbage(_arguments: *const u8, _arguments_len: usize, result: *mut c_void, write_result: WriteFn) -> i32 {
+    let data = [0x0f, 0, 0, 0, 0, 0];
+    write_result(result, data.as_ptr(), data.len());
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn crush_plugin_abi_version() -> u32 {
+    1
+}
+
+/// # Safety
+///
+/// The api must point to a valid `PluginApi`.
+#[no_mangle]
+pub unsafe extern "C" fn crush_plugin_init(api: *const PluginApi) -> i32 {
+    let api = &*api;
+    if (api.register_namespace)(api.registrar, c(b"fixture\0"), c(b"Commands for testing plugins\0")) != 0 ||
+        (api.register_namespace)(api.registrar, c(b"fixture_extra\0"), c(b"An empty namespace\0")) != 0 {
+        return 1;
+    }
+
+    let arguments = [ArgumentDefinition {
+        name: c(b"text\0"),
+        value_type: c(b"string\0"),
+        description: c(b"any text.\0"),
+    }];
+    let commands = [
+        CommandDefinition {
+            namespace: c(b"fixture\0"),
+            name: c(b"identity\0"),
+            short_help: c(b"Return the arguments as a struct\0"),
+            long_help: std::ptr::null(),
+            arguments: arguments.as_ptr(),
+            argument_count: arguments.len(),
+            call: identity,
+        },
+        CommandDefinition {
+            namespace: c(b"fixture\0"),
+            name: c(b"fail\0"),
+            short_help: c(b"Always fail\0"),
+            long_help: std::ptr::null(),
+            arguments: std::ptr::null(),
+            argument_count: 0,
+            call: fail,
+        },
+        CommandDefinition {
+            namespace: c(b"fixture\0"),
+            name: c(b"garbage\0"),
+            short_help: c(b"Return a malformed result\0"),
+            long_help: std::ptr::null(),
+            arguments: std::ptr::null(),
+            argument_count: 0,
+            call: garbage,
+        },
+    ];
+    for command in &commands {
+        if (api.register_command)(api.registrar, command) != 0 {
+            return 1;
+        }
+    }
+    0
+}
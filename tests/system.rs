@@ -1,13 +1,23 @@
+use std::env;
 use std::fs;
 use std::path::Path;
+use std::process;
 use std::process::Command;
 use test_finder::test_finder;
 
 fn run_system_test(name: &Path) {
+    // Tests that need to write files do so in a fresh directory, found in $crush:env:CRUSH_TEST_DIR
+    let test_dir = env::temp_dir().join(format!(
+        "crush-test-{}-{}",
+        process::id(),
+        name.file_stem().unwrap().to_str().unwrap()));
+    fs::create_dir_all(&test_dir).expect("failed to create test directory");
     let output = Command::new("./target/debug/crush")
         .args(&[name.to_str().unwrap()])
+        .env("CRUSH_TEST_DIR", &test_dir)
         .output()
         .expect("failed to execute process");
+    let _ = fs::remove_dir_all(&test_dir);
     let output_name = name.with_extension("crush.output");
     let expected_output = fs::read_to_string(output_name.to_str().unwrap())
         .expect(format!("failed to read output file {}", output_name.to_str().unwrap()).as_str());